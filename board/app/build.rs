@@ -3,563 +3,186 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use math::common::RotationDirection;
 use proc_macro2::Span;
 use quote::quote;
-use stepper::{motion::Positioning, stepper::SteppingMode};
+use stepper::{probe::ProbeType, stepper::MAX_MOTORS};
 use syn::Ident;
 
-mod external {
-    use std::ops::Not;
-
-    use serde_derive::{Deserialize, Serialize};
-
-    fn get_string_value(s: String) -> Option<String> {
-        s.is_empty().not().then_some(s)
-    }
-
-    #[derive(Default, Debug, Serialize, Deserialize, Clone)]
-    pub struct PinConfig {
-        pin: String,
-    }
-
-    impl PinConfig {
-        pub fn get_pin(&self) -> Option<String> {
-            get_string_value(self.pin.clone())
-        }
-    }
-
-    #[derive(Default, Debug, Serialize, Deserialize, Clone)]
-    pub struct PeripheralConfig {
-        peripheral: String,
-    }
-
-    impl PeripheralConfig {
-        pub fn get_peripheral(&self) -> Option<String> {
-            get_string_value(self.peripheral.clone())
-        }
-    }
-
-    //     [motion]
-    // arc_unit_length = 0.0
-    // feedrate = 0.0
-    // positioning = "absolute"
-
-    // [motion.retraction]
-    // feedrate = 0.0
-    // length = 0.0
-    // z_lift = 0.0
-
-    // [motion.recover]
-    // feedrate = 0.0
-    // length = 0.0
-
-    #[derive(Default, Debug, Serialize, Deserialize, Clone, Copy)]
-    pub struct RecoverMotionConfig {
-        feedrate: f64,
-        length: f64,
-    }
-
-    impl RecoverMotionConfig {
-        pub fn get_feedrate(&self) -> f64 {
-            self.feedrate
-        }
-
-        pub fn get_length(&self) -> f64 {
-            self.length
-        }
-    }
-
-    #[derive(Default, Debug, Serialize, Deserialize, Clone, Copy)]
-    pub struct RetractionMotionConfig {
-        feedrate: f64,
-        length: f64,
-        z_lift: f64,
-    }
-
-    impl RetractionMotionConfig {
-        pub fn get_feedrate(&self) -> f64 {
-            self.feedrate
-        }
-
-        pub fn get_length(&self) -> f64 {
-            self.length
-        }
-
-        pub fn get_zlift(&self) -> f64 {
-            self.z_lift
-        }
-    }
-
-    #[derive(Default, Debug, Serialize, Deserialize, Clone)]
-    pub struct EndstopPartConfig {
-        pin: String,
-        exti: String,
-    }
-
-    impl EndstopPartConfig {
-        pub fn get_pin(&self) -> Option<String> {
-            get_string_value(self.pin.clone())
-        }
-
-        pub fn get_exti(&self) -> Option<String> {
-            get_string_value(self.exti.clone())
-        }
-    }
-
-    #[derive(Default, Debug, Serialize, Deserialize, Clone)]
-    pub struct EndstopsConfig {
-        x: EndstopPartConfig,
-        y: EndstopPartConfig,
-        z: EndstopPartConfig,
-    }
-
-    impl EndstopsConfig {
-        pub fn get_x(&self) -> EndstopPartConfig {
-            self.x.clone()
-        }
-
-        pub fn get_y(&self) -> EndstopPartConfig {
-            self.y.clone()
-        }
-
-        pub fn get_z(&self) -> EndstopPartConfig {
-            self.z.clone()
-        }
-    }
-
-    #[derive(Default, Debug, Serialize, Deserialize, Clone)]
-    pub struct MotionConfig {
-        arc_unit_length: f64,
-        feedrate: f64,
-        positioning: String,
-        e_positioning: String,
-        feedrate_multiplier: f64,
-        retraction: RetractionMotionConfig,
-        recover: RecoverMotionConfig,
-        endstops: EndstopsConfig,
-    }
-
-    impl MotionConfig {
-        pub fn get_arc_unit_length(&self) -> f64 {
-            self.arc_unit_length
-        }
-
-        pub fn get_feedrate(&self) -> f64 {
-            self.feedrate
-        }
-
-        pub fn get_positioning(&self) -> Option<String> {
-            get_string_value(self.positioning.clone())
-        }
-
-        pub fn get_e_positioning(&self) -> Option<String> {
-            get_string_value(self.e_positioning.clone())
-        }
-
-        pub fn get_retraction(&self) -> RetractionMotionConfig {
-            self.retraction
-        }
-
-        pub fn get_recover(&self) -> RecoverMotionConfig {
-            self.recover
-        }
-
-        pub fn get_endstops(&self) -> EndstopsConfig {
-            self.endstops.clone()
-        }
-
-        pub fn get_feedrate_multiplier(&self) -> f64 {
-            self.feedrate_multiplier
-        }
-    }
-
-    #[derive(Default, Debug, Serialize, Deserialize, Clone, Copy)]
-    pub struct StepperBounds {
-        pub min: f64,
-        pub max: f64,
-    }
-    /* stepper */
-    #[derive(Default, Debug, Serialize, Deserialize, Clone)]
-    pub struct StepperConfig {
-        step: PinConfig,
-        dir: PinConfig,
-        stepping_mode: String,
-        distance_per_step: f64,
-        steps_per_revolution: u64,
-        bounds: StepperBounds,
-        positive_direction: String,
-    }
-
-    impl StepperConfig {
-        pub fn get_step(&self) -> PinConfig {
-            self.step.clone()
-        }
-        pub fn get_dir(&self) -> PinConfig {
-            self.dir.clone()
-        }
-        pub fn get_stepping_mode(&self) -> String {
-            self.stepping_mode.clone()
-        }
-        pub fn get_distance_per_step(&self) -> f64 {
-            self.distance_per_step
-        }
-        pub fn get_steps_per_revolution(&self) -> u64 {
-            self.steps_per_revolution
-        }
-        pub fn get_bounds(&self) -> StepperBounds {
-            self.bounds
-        }
-        pub fn get_positive_direction(&self) -> String {
-            self.positive_direction.clone()
-        }
-    }
-
-    #[derive(Default, Debug, Serialize, Deserialize, Clone)]
-    pub struct StepperConfigs {
-        x: StepperConfig,
-        y: StepperConfig,
-        z: StepperConfig,
-        e: StepperConfig,
-    }
-
-    impl StepperConfigs {
-        pub fn get_x(&self) -> StepperConfig {
-            self.x.clone()
-        }
-        pub fn get_y(&self) -> StepperConfig {
-            self.y.clone()
-        }
-        pub fn get_z(&self) -> StepperConfig {
-            self.z.clone()
-        }
-        pub fn get_e(&self) -> StepperConfig {
-            self.e.clone()
-        }
-    }
-
-    /* UART */
-    #[derive(Default, Debug, Serialize, Deserialize, Clone)]
-    pub struct UartPartConfig {
-        pin: String,
-        dma: PeripheralConfig,
-    }
-
-    impl UartPartConfig {
-        pub fn get_pin(&self) -> Option<String> {
-            get_string_value(self.pin.clone())
-        }
-
-        pub fn get_dma(&self) -> PeripheralConfig {
-            self.dma.clone()
-        }
-    }
-
-    #[derive(Default, Debug, Serialize, Deserialize, Clone)]
-    pub struct UartConfig {
-        peripheral: String,
-        baudrate: u64,
-        rx: UartPartConfig,
-        tx: UartPartConfig,
-    }
-
-    impl UartConfig {
-        pub fn get_peripheral(&self) -> Option<String> {
-            get_string_value(self.peripheral.clone())
-        }
-
-        pub fn get_baudrate(&self) -> u64 {
-            self.baudrate
-        }
-
-        pub fn get_tx(&self) -> UartPartConfig {
-            self.tx.clone()
-        }
-
-        pub fn get_rx(&self) -> UartPartConfig {
-            self.rx.clone()
-        }
-    }
-
-    /* ADC */
-    // [ThermalActuator.adc]
-    // peripheral = "ADC1"
-
-    // [ThermalActuator.adc.input]
-    // pin = "PA1"
-
-    // [ThermalActuator.adc.dma]
-    // peripheral = ""
-    #[derive(Default, Debug, Serialize, Deserialize, Clone)]
-    pub struct AdcConfig {
-        pub peripheral: String,
-        pub dma: PeripheralConfig,
-    }
-
-    impl AdcConfig {
-        pub fn get_peripheral(&self) -> Option<String> {
-            get_string_value(self.peripheral.clone())
-        }
-
-        pub fn get_dma(&self) -> PeripheralConfig {
-            self.dma.clone()
-        }
-    }
-
-    // [ThermalActuator.pwm]
-    // frequency=0
-
-    // [ThermalActuator.pwm.timer]
-    // peripheral = ""
-
-    // [ThermalActuator.pwm.channel0]
-    // pin = ""
-
-    // [ThermalActuator.pwm.channel1]
-    // pin = ""
-
-    // [ThermalActuator.pwm.channel2]
-    // pin = ""
-
-    // [ThermalActuator.pwm.channel3]
-    // pin = ""
-    #[derive(Default, Debug, Serialize, Deserialize, Clone)]
-    pub struct PwmConfig {
-        frequency: u64,
-        timer: String,
-        ch1: String,
-        ch2: String,
-        ch3: String,
-    }
-
-    impl PwmConfig {
-        pub fn get_frequency(&self) -> u64 {
-            self.frequency
-        }
-
-        pub fn get_timer(&self) -> Option<String> {
-            get_string_value(self.timer.clone())
-        }
-
-        pub fn get_ch1(&self) -> Option<String> {
-            get_string_value(self.ch1.clone())
-        }
-
-        pub fn get_ch2(&self) -> Option<String> {
-            get_string_value(self.ch2.clone())
-        }
-
-        pub fn get_ch3(&self) -> Option<String> {
-            get_string_value(self.ch3.clone())
-        }
-    }
-
-    #[derive(Default, Debug, Serialize, Deserialize, Clone, Copy)]
-    pub struct PwmOutputConfig {
-        channel: u8,
-    }
-
-    impl PwmOutputConfig {
-        pub fn get_channel(&self) -> u8 {
-            self.channel
-        }
-    }
-
-    #[derive(Default, Debug, Serialize, Deserialize, Clone)]
-    pub struct SpiConfig {
-        peripheral: String,
-        clk: PeripheralConfig,
-        mosi: PinConfig,
-        miso: PinConfig,
-        cs: PinConfig,
-    }
-
-    impl SpiConfig {
-        pub fn get_peripheral(&self) -> Option<String> {
-            get_string_value(self.peripheral.clone())
-        }
-
-        pub fn get_clk(&self) -> &PeripheralConfig {
-            &self.clk
-        }
-
-        pub fn get_mosi(&self) -> &PinConfig {
-            &self.mosi
-        }
-        pub fn get_miso(&self) -> &PinConfig {
-            &self.miso
-        }
-        pub fn get_cs(&self) -> &PinConfig {
-            &self.cs
-        }
-    }
-
-    // [ThermalActuator.heater.pid]
-    // k_p = 0
-    // k_i = 0
-    // k_d = 0
-    #[derive(Default, Debug, Serialize, Deserialize, Clone, Copy)]
-    pub struct PidConfig {
-        k_p: f64,
-        k_i: f64,
-        k_d: f64,
-    }
-
-    impl PidConfig {
-        pub fn get_k_p(&self) -> f64 {
-            self.k_p
-        }
-
-        pub fn get_k_i(&self) -> f64 {
-            self.k_i
-        }
-        pub fn get_k_d(&self) -> f64 {
-            self.k_d
-        }
-    }
-
-    #[derive(Default, Debug, Serialize, Deserialize, Clone)]
-    pub struct ThermalActuatorConfig {
-        pub thermistor: ThermistorConfig,
-        pub heater: HeaterConfig,
-    }
-
-    impl ThermalActuatorConfig {
-        pub fn get_thermistor(&self) -> ThermistorConfig {
-            self.thermistor.clone()
-        }
-
-        pub fn get_heater(&self) -> HeaterConfig {
-            self.heater.clone()
-        }
-    }
-
-    #[derive(Default, Debug, Serialize, Deserialize, Clone)]
-    pub struct HeaterConfig {
-        pub pwm: PwmOutputConfig,
-        pub pid: PidConfig,
-        pub min_temperature_limit: f64,
-        pub max_temperature_limit: f64,
-    }
-
-    impl HeaterConfig {
-        pub fn get_pid(&self) -> PidConfig {
-            self.pid
-        }
-        pub fn get_max_temperature_limit(&self) -> f64 {
-            self.max_temperature_limit
-        }
-        pub fn get_min_temperature_limit(&self) -> f64 {
-            self.min_temperature_limit
-        }
-        pub fn get_pwm(&self) -> PwmOutputConfig {
-            self.pwm
-        }
-    }
-
-    #[derive(Default, Debug, Serialize, Deserialize, Clone)]
-    pub struct ThermistorConfig {
-        pub r_series: f64,
-        pub r0: f64,
-        pub b: f64,
-        pub samples: u64,
-        pub adc: PinConfig,
-    }
-
-    impl ThermistorConfig {
-        pub fn get_r_series(&self) -> f64 {
-            self.r_series
-        }
-
-        pub fn get_r0(&self) -> f64 {
-            self.r0
-        }
-
-        pub fn get_b(&self) -> f64 {
-            self.b
-        }
-
-        pub fn get_samples(&self) -> u64 {
-            self.samples
-        }
-
-        pub fn get_adc(&self) -> PinConfig {
-            self.adc.clone()
+fn homing_config(conf: config::HomingConfig) -> proc_macro2::TokenStream {
+    let direction = conf.get_direction().expect("Homing direction is missing");
+    let direction = direction.as_str();
+    let fast_speed = conf.get_fast_speed();
+    let slow_speed = conf.get_slow_speed();
+    let bump = conf.get_bump();
+    let sensorless = conf.get_sensorless();
+    let min_travel = conf.get_min_travel();
+    let backoff = conf.get_backoff();
+    quote! {
+        HomingConfig {
+            direction: HomingDirection::from(#direction),
+            fast_speed: Speed::from_meters_per_second(#fast_speed / (1000.0 * 60.0)),
+            slow_speed: Speed::from_meters_per_second(#slow_speed / (1000.0 * 60.0)),
+            bump: Distance::from_millimeters(#bump),
+            sensorless: #sensorless,
+            min_travel: Distance::from_millimeters(#min_travel),
+            backoff: Distance::from_millimeters(#backoff),
         }
     }
+}
 
-    #[derive(Default, Debug, Serialize, Deserialize, Clone)]
-    pub struct FanConfig {
-        max_speed: f64,
-        pwm: PwmOutputConfig,
+fn shaper_config(conf: config::ShaperConfig) -> proc_macro2::TokenStream {
+    let shaper = conf.get_shaper().expect("Input shaper is missing");
+    let shaper = shaper.as_str();
+    let frequency = conf.get_frequency();
+    let damping = conf.get_damping();
+    quote! {
+        InputShaper::new(ShaperType::from(#shaper), #frequency, #damping)
     }
+}
 
-    impl FanConfig {
-        pub fn get_pwm(&self) -> PwmOutputConfig {
-            self.pwm
-        }
-        pub fn get_max_speed(&self) -> f64 {
-            self.max_speed
-        }
+// a driver without run current isn't there, the stepper has a plain STEP/DIR driver
+fn driver_config(
+    conf: config::DriverConfig,
+    stepper: config::StepperConfig,
+) -> proc_macro2::TokenStream {
+    if conf.get_run_current() == 0.0 {
+        return quote! { None };
     }
-
-    #[derive(Default, Debug, Serialize, Deserialize, Clone)]
-    pub struct SdCardConfig {
-        pub spi: SpiConfig,
+    let address = conf.get_address();
+    if address > 3 {
+        panic!("TMC driver address must be between 0 and 3");
     }
-
-    impl SdCardConfig {
-        pub fn get_spi(&self) -> SpiConfig {
-            self.spi.clone()
-        }
+    let r_sense = conf.get_r_sense();
+    let run_current = conf.get_run_current();
+    let hold_multiplier = conf.get_hold_multiplier();
+    let stealth_chop = conf.get_stealth_chop();
+    let stall_threshold = conf.get_stall_threshold();
+    let stepping_mode = stepper.get_stepping_mode();
+    let stepping_mode = stepping_mode.as_str();
+    let distance_per_step = stepper.get_distance_per_step();
+    let hybrid_threshold = match conf.get_hybrid_threshold() {
+        Some(v) => quote! { Some(Speed::from_meters_per_second(#v / 1000.0)) },
+        None => quote! { None },
+    };
+    let homing_current = match conf.get_homing_current() {
+        Some(v) => quote! { Some(Current::from_milliamperes(#v)) },
+        None => quote! { None },
+    };
+    quote! {
+        Some(TmcConfig {
+            address: #address,
+            r_sense: Resistance::from_ohms(#r_sense),
+            run_current: Current::from_milliamperes(#run_current),
+            hold_multiplier: #hold_multiplier,
+            stepping_mode: SteppingMode::from(#stepping_mode),
+            stealth_chop: #stealth_chop,
+            hybrid_threshold: #hybrid_threshold,
+            distance_per_step: Distance::from_millimeters(#distance_per_step),
+            stall_threshold: #stall_threshold,
+            homing_current: #homing_current,
+        })
     }
+}
 
-    #[derive(Default, Debug, Serialize, Deserialize, Clone)]
-    pub struct DebugConfig {
-        alive_led: PinConfig,
+// the pins of the additional motors are degraded, so that any number of them fits the config
+fn motors_config(axis: &str, conf: config::StepperConfig) -> proc_macro2::TokenStream {
+    let motors = conf.get_motors();
+    if motors.len() >= MAX_MOTORS {
+        panic!(
+            "Stepper {} can have at most {} additional motors",
+            axis,
+            MAX_MOTORS - 1
+        );
     }
-
-    impl DebugConfig {
-        pub fn get_alive_led(&self) -> PinConfig {
-            self.alive_led.clone()
+    let motors = motors.iter().map(|motor| {
+        let step = motor
+            .get_step()
+            .get_pin()
+            .unwrap_or_else(|| panic!("Stepper {} motor step pin is missing", axis));
+        let step = Ident::new(step.as_str(), Span::call_site());
+        let dir = motor
+            .get_dir()
+            .get_pin()
+            .unwrap_or_else(|| panic!("Stepper {} motor dir pin is missing", axis));
+        let dir = Ident::new(dir.as_str(), Span::call_site());
+        let endstop = motor.get_endstop();
+        let endstop = match (endstop.get_pin(), endstop.get_exti()) {
+            (Some(pin), Some(exti)) => {
+                let pin = Ident::new(pin.as_str(), Span::call_site());
+                let exti = Ident::new(exti.as_str(), Span::call_site());
+                quote! {
+                    Some(EndstopPartConfig {
+                        pin: p.#pin.degrade(),
+                        exti: p.#exti.degrade(),
+                    })
+                }
+            }
+            (None, None) => quote! { None },
+            _ => panic!("Stepper {} motor endstop needs both pin and EXTI", axis),
+        };
+        quote! {
+            MotorConfig {
+                step_pin: p.#step.degrade(),
+                dir_pin: p.#dir.degrade(),
+                endstop: #endstop,
+            }
         }
-    }
-
-    #[derive(Default, Debug, Serialize, Deserialize, Clone)]
-    pub struct MyConfig {
-        pub steppers: StepperConfigs,
-        pub pwm: PwmConfig,
-        pub uart: UartConfig,
-        pub adc: AdcConfig,
-        pub hotend: ThermalActuatorConfig,
-        pub heatbed: ThermalActuatorConfig,
-        pub fan: FanConfig,
-        pub sdcard: SdCardConfig,
-        pub motion: MotionConfig,
-        pub debug: DebugConfig,
+    });
+    quote! {
+        [#(#motors),*].into_iter().collect()
     }
 }
 
 fn main() {
     println!("cargo::rerun-if-changed=config/config.toml");
     let path = Path::new("config/config.toml");
-    let conf = confy::load_path::<external::MyConfig>(path).expect("Error reading config file");
-
-    let motion_arc_unit_len = conf.motion.get_arc_unit_length();
+    // the values are checked by the config crate, the same way the host tools do
+    let conf = config::Config::load(path)
+        .unwrap_or_else(|e| panic!("Error reading config file: {}", e));
+
+    let motion_arcs_tolerance = conf.motion.get_arcs().get_tolerance();
+    let motion_arcs_min_segment_len = conf.motion.get_arcs().get_min_segment_length();
+    let motion_arcs_max_segment_len = conf.motion.get_arcs().get_max_segment_length();
+    let motion_arcs_min_segment_duration =
+        (conf.motion.get_arcs().get_min_segment_duration() * 1000.0) as u64;
     let motion_feedrate = conf.motion.get_feedrate();
     let motion_positioning = conf
         .motion
         .get_positioning()
         .expect("Motion positioning is missing");
     let motion_positioning = motion_positioning.as_str();
-    let _ = Positioning::from(motion_positioning);
     let motion_e_positioning = conf
         .motion
         .get_e_positioning()
         .expect("Motion positioning is missing");
     let motion_e_positioning = motion_e_positioning.as_str();
-    let _ = Positioning::from(motion_e_positioning);
     let motion_feedrate_multiplier = conf.motion.get_feedrate_multiplier();
+    let motion_flow_multiplier = conf.motion.get_flow_multiplier();
+    // E is a length without the diameter of the filament
+    let motion_filament_diameter = match conf.motion.get_filament_diameter() {
+        d if d == 0.0 => quote! { None },
+        d => quote! { Some(Distance::from_millimeters(#d)) },
+    };
+
+    let motion_limits = conf.motion.get_limits();
+    let axes =
+        |axes: config::AxesConfig| [axes.get_x(), axes.get_y(), axes.get_z(), axes.get_e()];
+    let [
+        motion_max_feedrate_x,
+        motion_max_feedrate_y,
+        motion_max_feedrate_z,
+        motion_max_feedrate_e,
+    ] = axes(motion_limits.get_max_feedrate());
+    let [
+        motion_max_acceleration_x,
+        motion_max_acceleration_y,
+        motion_max_acceleration_z,
+        motion_max_acceleration_e,
+    ] = axes(motion_limits.get_max_acceleration());
+    let [motion_jerk_x, motion_jerk_y, motion_jerk_z, motion_jerk_e] = axes(motion_limits.get_jerk());
+    let motion_print_acceleration = motion_limits.get_print_acceleration();
+    let motion_retract_acceleration = motion_limits.get_retract_acceleration();
+    let motion_travel_acceleration = motion_limits.get_travel_acceleration();
 
     let motion_retraction_z_lift = conf.motion.get_retraction().get_zlift();
     let motion_retraction_feedrate = conf.motion.get_retraction().get_feedrate();
@@ -567,6 +190,73 @@ fn main() {
     let motion_recover_feedrate = conf.motion.get_recover().get_feedrate();
     let motion_recover_len = conf.motion.get_recover().get_length();
 
+    let motion_homing_x = homing_config(conf.motion.get_homing().get_x());
+    let motion_homing_y = homing_config(conf.motion.get_homing().get_y());
+    let motion_homing_z = homing_config(conf.motion.get_homing().get_z());
+    let motion_homing_x_offset = conf.motion.get_homing().get_x().get_offset();
+    let motion_homing_y_offset = conf.motion.get_homing().get_y().get_offset();
+    let motion_homing_z_offset = conf.motion.get_homing().get_z().get_offset();
+    let motion_homing_z_lift = conf.motion.get_homing().get_z_lift();
+    let motion_homing_required = conf.motion.get_homing().get_required();
+    let motion_software_endstops_enabled = conf.motion.get_software_endstops().get_enabled();
+    let motion_software_endstops_clip = conf.motion.get_software_endstops().get_clip();
+    let motion_leveling = conf.motion.get_leveling();
+    let motion_leveling_points_x = motion_leveling.get_points().get_x();
+    let motion_leveling_points_y = motion_leveling.get_points().get_y();
+    let motion_leveling_min_x = motion_leveling.get_min().get_x();
+    let motion_leveling_min_y = motion_leveling.get_min().get_y();
+    let motion_leveling_max_x = motion_leveling.get_max().get_x();
+    let motion_leveling_max_y = motion_leveling.get_max().get_y();
+    let motion_leveling_fade_height = motion_leveling.get_fade_height();
+
+    let motion_probe_config = conf.motion.get_probe();
+    let motion_probe_offset_x = motion_probe_config.get_offset().get_x();
+    let motion_probe_offset_y = motion_probe_config.get_offset().get_y();
+    let motion_probe_offset_z = motion_probe_config.get_offset().get_z();
+    let motion_probe_clearance = motion_probe_config.get_clearance();
+    let motion_probe_max_depth = motion_probe_config.get_max_depth();
+    let motion_probe_feedrate = motion_probe_config.get_feedrate();
+    let motion_probe_travel_feedrate = motion_probe_config.get_travel_feedrate();
+    let motion_probe_samples = motion_probe_config.get_samples();
+    let motion_probe_retract = motion_probe_config.get_retract();
+
+    let motion_alignment = conf.motion.get_alignment();
+    let motion_alignment_points = motion_alignment.get_points();
+    let motion_alignment_points_x = motion_alignment_points.iter().map(|p| p.get_x());
+    let motion_alignment_points_y = motion_alignment_points.iter().map(|p| p.get_y());
+    let motion_alignment_iterations = motion_alignment.get_iterations();
+    let motion_alignment_accuracy = motion_alignment.get_accuracy();
+
+    let motion_backlash = conf.motion.get_backlash();
+    let motion_backlash_x = motion_backlash.get_x();
+    let motion_backlash_y = motion_backlash.get_y();
+    let motion_backlash_z = motion_backlash.get_z();
+    let motion_backlash_correction = motion_backlash.get_correction();
+    let motion_backlash_smoothing = motion_backlash.get_smoothing();
+
+    let motion_skew = conf.motion.get_skew();
+    let motion_skew_xy = motion_skew.get_xy();
+    let motion_skew_xz = motion_skew.get_xz();
+    let motion_skew_yz = motion_skew.get_yz();
+
+    let motion_input_shaping_x = shaper_config(conf.motion.get_input_shaping().get_x());
+    let motion_input_shaping_y = shaper_config(conf.motion.get_input_shaping().get_y());
+
+    let motion_linear_advance = conf.motion.get_linear_advance();
+    let motion_linear_advance_k = motion_linear_advance.get_k();
+    let motion_linear_advance_max_feedrate = motion_linear_advance.get_max_feedrate();
+
+    let motion_homing_order = conf
+        .motion
+        .get_homing()
+        .get_order()
+        .expect("Homing order is missing");
+    let motion_homing_order = motion_homing_order
+        .to_uppercase()
+        .chars()
+        .map(|c| Ident::new(c.to_string().as_str(), Span::call_site()))
+        .collect::<Vec<Ident>>();
+
     let motion_endstop_x = conf
         .motion
         .get_endstops()
@@ -615,6 +305,34 @@ fn main() {
         .expect("Endstop z EXTI is missing");
     let motion_endstop_z_exti = Ident::new(motion_endstop_z_exti.as_str(), Span::call_site());
 
+    let motion_probe = conf
+        .motion
+        .get_probe()
+        .get_pin()
+        .expect("Probe pin is missing");
+    let motion_probe = Ident::new(motion_probe.as_str(), Span::call_site());
+
+    let motion_probe_exti = conf
+        .motion
+        .get_probe()
+        .get_exti()
+        .expect("Probe EXTI is missing");
+    let motion_probe_exti = Ident::new(motion_probe_exti.as_str(), Span::call_site());
+
+    let motion_probe_kind = conf
+        .motion
+        .get_probe()
+        .get_kind()
+        .expect("Probe kind is missing");
+    let motion_probe_kind = motion_probe_kind.as_str();
+    let _ = ProbeType::from(motion_probe_kind);
+    let motion_probe_active_low = conf.motion.get_probe().get_active_low();
+    let motion_probe_delay = conf.motion.get_probe().get_delay();
+    if motion_probe_delay < 0.0 {
+        panic!("Probe delay can't be negative");
+    }
+    let motion_probe_delay = (motion_probe_delay * 1000.0) as u64;
+
     let steppers_x_step_pin = conf
         .steppers
         .get_x()
@@ -632,7 +350,6 @@ fn main() {
     let steppers_x_dir_pin = Ident::new(steppers_x_dir_pin.as_str(), Span::call_site());
     let steppers_x_stepping_mode = conf.steppers.get_x().get_stepping_mode();
     let steppers_x_stepping_mode = steppers_x_stepping_mode.as_str();
-    let _ = SteppingMode::from(steppers_x_stepping_mode);
 
     let steppers_x_distance_per_step = conf.steppers.get_x().get_distance_per_step();
     let steppers_x_steps_per_revolution = conf.steppers.get_x().get_steps_per_revolution();
@@ -641,7 +358,6 @@ fn main() {
     let steppers_x_bounds_max = steppers_x_bounds.max;
     let steppers_x_positive_direction = conf.steppers.get_x().get_positive_direction();
     let steppers_x_positive_direction = steppers_x_positive_direction.as_str();
-    let _ = RotationDirection::from(steppers_x_positive_direction);
 
     let steppers_y_step_pin = conf
         .steppers
@@ -661,7 +377,6 @@ fn main() {
 
     let steppers_y_stepping_mode = conf.steppers.get_y().get_stepping_mode();
     let steppers_y_stepping_mode = steppers_y_stepping_mode.as_str();
-    let _ = SteppingMode::from(steppers_y_stepping_mode);
 
     let steppers_y_distance_per_step = conf.steppers.get_y().get_distance_per_step();
     let steppers_y_steps_per_revolution = conf.steppers.get_y().get_steps_per_revolution();
@@ -670,7 +385,6 @@ fn main() {
     let steppers_y_bounds_max = steppers_y_bounds.max;
     let steppers_y_positive_direction = conf.steppers.get_y().get_positive_direction();
     let steppers_y_positive_direction = steppers_y_positive_direction.as_str();
-    let _ = RotationDirection::from(steppers_y_positive_direction);
 
     let steppers_z_step_pin = conf
         .steppers
@@ -689,7 +403,6 @@ fn main() {
     let steppers_z_dir_pin = Ident::new(steppers_z_dir_pin.as_str(), Span::call_site());
     let steppers_z_stepping_mode = conf.steppers.get_z().get_stepping_mode();
     let steppers_z_stepping_mode = steppers_z_stepping_mode.as_str();
-    let _ = SteppingMode::from(steppers_z_stepping_mode);
     let steppers_z_distance_per_step = conf.steppers.get_z().get_distance_per_step();
     let steppers_z_steps_per_revolution = conf.steppers.get_z().get_steps_per_revolution();
     let steppers_z_bounds = conf.steppers.get_z().get_bounds();
@@ -697,7 +410,6 @@ fn main() {
     let steppers_z_bounds_max = steppers_z_bounds.max;
     let steppers_z_positive_direction = conf.steppers.get_z().get_positive_direction();
     let steppers_z_positive_direction = steppers_z_positive_direction.as_str();
-    let _ = RotationDirection::from(steppers_z_positive_direction);
 
     let steppers_e_step_pin = conf
         .steppers
@@ -717,7 +429,6 @@ fn main() {
 
     let steppers_e_stepping_mode = conf.steppers.get_e().get_stepping_mode();
     let steppers_e_stepping_mode = steppers_e_stepping_mode.as_str();
-    let _ = SteppingMode::from(steppers_e_stepping_mode);
     let steppers_e_distance_per_step = conf.steppers.get_e().get_distance_per_step();
     let steppers_e_steps_per_revolution = conf.steppers.get_e().get_steps_per_revolution();
     let steppers_e_bounds = conf.steppers.get_e().get_bounds();
@@ -725,7 +436,13 @@ fn main() {
     let steppers_e_bounds_max = steppers_e_bounds.max;
     let steppers_e_positive_direction = conf.steppers.get_e().get_positive_direction();
     let steppers_e_positive_direction = steppers_e_positive_direction.as_str();
-    let _ = RotationDirection::from(steppers_e_positive_direction);
+
+    let steppers_x_motors = motors_config("x", conf.steppers.get_x());
+    let steppers_y_motors = motors_config("y", conf.steppers.get_y());
+    let steppers_z_motors = motors_config("z", conf.steppers.get_z());
+    if !conf.steppers.get_e().get_motors().is_empty() {
+        panic!("Stepper e can't have additional motors");
+    }
 
     let pwm_timer = conf
         .pwm
@@ -778,6 +495,73 @@ fn main() {
         .expect("UART TX pin is missing");
     let uart_tx_dma = Ident::new(uart_tx_dma.as_str(), Span::call_site());
 
+    // the drivers are optional, without the UART to talk to them there are none. The code
+    // using the UART is built only with the tmc_drivers cfg
+    println!("cargo::rustc-check-cfg=cfg(tmc_drivers)");
+    let drivers_uart = conf.drivers.get_uart();
+    let (drivers_irq, drivers_types, drivers) = match drivers_uart.get_peripheral() {
+        Some(drivers_uart_peripheral) => {
+            println!("cargo::rustc-cfg=tmc_drivers");
+            let drivers_uart_peripheral =
+                Ident::new(drivers_uart_peripheral.as_str(), Span::call_site());
+            let drivers_uart_baudrate = drivers_uart.get_baudrate();
+            let drivers_uart_pin = drivers_uart.get_pin().expect("Drivers UART pin is missing");
+            let drivers_uart_pin = Ident::new(drivers_uart_pin.as_str(), Span::call_site());
+            let drivers_uart_rx_dma = drivers_uart
+                .get_rx()
+                .get_dma()
+                .get_peripheral()
+                .expect("Drivers UART RX DMA is missing");
+            let drivers_uart_rx_dma = Ident::new(drivers_uart_rx_dma.as_str(), Span::call_site());
+            let drivers_uart_tx_dma = drivers_uart
+                .get_tx()
+                .get_dma()
+                .get_peripheral()
+                .expect("Drivers UART TX DMA is missing");
+            let drivers_uart_tx_dma = Ident::new(drivers_uart_tx_dma.as_str(), Span::call_site());
+            let drivers_x = driver_config(conf.drivers.get_x(), conf.steppers.get_x());
+            let drivers_y = driver_config(conf.drivers.get_y(), conf.steppers.get_y());
+            let drivers_z = driver_config(conf.drivers.get_z(), conf.steppers.get_z());
+            let drivers_e = driver_config(conf.drivers.get_e(), conf.steppers.get_e());
+            (
+                quote! {
+                    #drivers_uart_peripheral => embassy_stm32::usart::InterruptHandler<#drivers_uart_peripheral>;
+                },
+                quote! {
+                    pub type DriversUartPeripheral = #drivers_uart_peripheral;
+                    pub type DriversUartPin = #drivers_uart_pin;
+                    pub type DriversUartRxDma = #drivers_uart_rx_dma;
+                    pub type DriversUartTxDma = #drivers_uart_tx_dma;
+                },
+                quote! {
+                    Some(DriversConfig{
+                        uart: DriversUartConfig{
+                            peripheral: p.#drivers_uart_peripheral,
+                            baudrate: #drivers_uart_baudrate,
+                            pin: p.#drivers_uart_pin,
+                            rx_dma: p.#drivers_uart_rx_dma,
+                            tx_dma: p.#drivers_uart_tx_dma,
+                        },
+                        x: #drivers_x,
+                        y: #drivers_y,
+                        z: #drivers_z,
+                        e: #drivers_e,
+                    })
+                },
+            )
+        }
+        None => (
+            quote! {},
+            quote! {
+                pub type DriversUartPeripheral = ();
+                pub type DriversUartPin = ();
+                pub type DriversUartRxDma = ();
+                pub type DriversUartTxDma = ();
+            },
+            quote! { None },
+        ),
+    };
+
     let adc_peripheral = conf
         .adc
         .get_peripheral()
@@ -834,6 +618,21 @@ fn main() {
     let fan_pwm_output_channel = conf.fan.get_pwm().get_channel();
     let fan_max_speed = conf.fan.get_max_speed();
 
+    let servo = conf.servo.clone().expect("Servo is missing");
+    let servo_timer = servo.get_timer().expect("Servo timer is missing");
+    if servo_timer == conf.pwm.get_timer().unwrap_or_default() {
+        panic!("Servo timer must not be the PWM timer");
+    }
+    let servo_timer = Ident::new(servo_timer.as_str(), Span::call_site());
+    let servo_pin = servo.get_pin().expect("Servo pin is missing");
+    let servo_pin = Ident::new(servo_pin.as_str(), Span::call_site());
+    let servo_min_pulse = servo.get_min_pulse();
+    let servo_max_pulse = servo.get_max_pulse();
+    let servo_max_angle = servo.get_max_angle();
+
+    let preflight_enabled = conf.preflight.get_enabled();
+    let preflight_min_extrusion_temperature = conf.preflight.get_min_extrusion_temperature();
+
     let sdcard_spi_peripheral = conf
         .sdcard
         .get_spi()
@@ -892,15 +691,26 @@ fn main() {
 
     let tokens = quote! {
         use embassy_stm32::peripherals::*;
-        use math::measurements::{Speed, Length, Distance, Resistance, Temperature, AngularVelocity};
+        use math::measurements::{Acceleration, Speed, Length, Distance, Resistance, Temperature, AngularVelocity, Current};
         use math::common::RotationDirection;
         use stepper::motion::Positioning;
         use stepper::stepper::SteppingMode;
-        use stepper::planner::{MotionConfig, RecoverMotionConfig, RetractionMotionConfig};
+        use stepper::probe::ProbeType;
+        use math::Axis;
+        use stepper::motion::{HomingConfig, HomingDirection};
+        use embassy_stm32::exti::Channel as _;
+        use embassy_stm32::gpio::Pin as _;
+        use stepper::planner::{AlignmentMotionConfig, ArcMotionConfig, BacklashMotionConfig, ExtrusionMotionConfig, HomingMotionConfig, InputShapingMotionConfig, LevelingMotionConfig, LimitsMotionConfig, MotionConfig, ProbeMotionConfig, RecoverMotionConfig, RetractionMotionConfig, SoftwareEndstopsConfig};
+        use stepper::tmc::TmcConfig;
+        use stepper::skew::Skew;
+        use stepper::shaper::{InputShaper, ShaperType};
+        use stepper::advance::LinearAdvance;
+        use stepper::preflight::PreflightLimits;
         use crate::config::*;
 
         embassy_stm32::bind_interrupts!(pub struct Irqs {
             #uart_peripheral => embassy_stm32::usart::InterruptHandler<#uart_peripheral>;
+            #drivers_irq
         });
 
         pub type XStepPin = #steppers_x_step_pin;
@@ -915,6 +725,8 @@ fn main() {
         pub type PwmCh1Pin = #pwm_ch1;
         pub type PwmCh2Pin = #pwm_ch2;
         pub type PwmCh3Pin = #pwm_ch3;
+        pub type ServoTimer = #servo_timer;
+        pub type ServoPin = #servo_pin;
         pub type UartPeripheral = #uart_peripheral;
         pub type UartRxPin = #uart_rx_pin;
         pub type UartRxDma = #uart_rx_dma;
@@ -935,7 +747,10 @@ fn main() {
         pub type YEndstopExti = #motion_endstop_y_exti;
         pub type ZEndstopPin = #motion_endstop_z;
         pub type ZEndstopExti = #motion_endstop_z_exti;
+        pub type ProbePin = #motion_probe;
+        pub type ProbeExti = #motion_probe_exti;
         pub type DebugAliveLedPin = #debug_alive_led;
+        #drivers_types
 
         pub fn peripherals_init(p: embassy_stm32::Peripherals) -> PrinterConfig<
             XStepPin,
@@ -950,6 +765,8 @@ fn main() {
             PwmCh1Pin,
             PwmCh2Pin,
             PwmCh3Pin,
+            ServoTimer,
+            ServoPin,
             UartPeripheral,
             UartRxPin,
             UartRxDma,
@@ -970,15 +787,53 @@ fn main() {
             YEndstopExti,
             ZEndstopPin,
             ZEndstopExti,
+            ProbePin,
+            ProbeExti,
             DebugAliveLedPin,
+            DriversUartPeripheral,
+            DriversUartPin,
+            DriversUartRxDma,
+            DriversUartTxDma,
         >{
             PrinterConfig{
                 motion: MotionConfig{
-                    arc_unit_length: Length::from_millimeters(#motion_arc_unit_len),
+                    arcs: ArcMotionConfig{
+                        tolerance: Length::from_millimeters(#motion_arcs_tolerance),
+                        min_segment_length: Length::from_millimeters(#motion_arcs_min_segment_len),
+                        max_segment_length: Length::from_millimeters(#motion_arcs_max_segment_len),
+                        min_segment_duration: core::time::Duration::from_micros(#motion_arcs_min_segment_duration),
+                    },
                     feedrate: Speed::from_meters_per_second(#motion_feedrate / (1000.0 * 60.0)),
                     positioning: Positioning::from(#motion_positioning),
                     e_positioning: Positioning::from(#motion_e_positioning),
                     feedrate_multiplier: #motion_feedrate_multiplier,
+                    extrusion: ExtrusionMotionConfig{
+                        flow_multiplier: #motion_flow_multiplier,
+                        filament_diameter: #motion_filament_diameter,
+                    },
+                    limits: LimitsMotionConfig{
+                        max_feedrate: (
+                            Speed::from_meters_per_second(#motion_max_feedrate_x / (1000.0 * 60.0)),
+                            Speed::from_meters_per_second(#motion_max_feedrate_y / (1000.0 * 60.0)),
+                            Speed::from_meters_per_second(#motion_max_feedrate_z / (1000.0 * 60.0)),
+                            Speed::from_meters_per_second(#motion_max_feedrate_e / (1000.0 * 60.0)),
+                        ),
+                        max_acceleration: (
+                            Acceleration::from_meters_per_second_per_second(#motion_max_acceleration_x / 1000.0),
+                            Acceleration::from_meters_per_second_per_second(#motion_max_acceleration_y / 1000.0),
+                            Acceleration::from_meters_per_second_per_second(#motion_max_acceleration_z / 1000.0),
+                            Acceleration::from_meters_per_second_per_second(#motion_max_acceleration_e / 1000.0),
+                        ),
+                        print_acceleration: Acceleration::from_meters_per_second_per_second(#motion_print_acceleration / 1000.0),
+                        retract_acceleration: Acceleration::from_meters_per_second_per_second(#motion_retract_acceleration / 1000.0),
+                        travel_acceleration: Acceleration::from_meters_per_second_per_second(#motion_travel_acceleration / 1000.0),
+                        jerk: (
+                            Speed::from_meters_per_second(#motion_jerk_x / (1000.0 * 60.0)),
+                            Speed::from_meters_per_second(#motion_jerk_y / (1000.0 * 60.0)),
+                            Speed::from_meters_per_second(#motion_jerk_z / (1000.0 * 60.0)),
+                            Speed::from_meters_per_second(#motion_jerk_e / (1000.0 * 60.0)),
+                        ),
+                    },
                     retraction: RetractionMotionConfig{
                         feedrate: Speed::from_meters_per_second(#motion_retraction_feedrate / (1000.0 * 60.0)),
                         length: Length::from_millimeters(#motion_retraction_len),
@@ -988,6 +843,72 @@ fn main() {
                         feedrate: Speed::from_meters_per_second(#motion_recover_feedrate / (1000.0 * 60.0)),
                         length: Length::from_millimeters(#motion_recover_len),
                     },
+                    homing: HomingMotionConfig{
+                        axes: (#motion_homing_x, #motion_homing_y, #motion_homing_z),
+                        order: [#(Axis::#motion_homing_order),*],
+                        z_lift: Length::from_millimeters(#motion_homing_z_lift),
+                        required: #motion_homing_required,
+                        offset: (
+                            Distance::from_millimeters(#motion_homing_x_offset),
+                            Distance::from_millimeters(#motion_homing_y_offset),
+                            Distance::from_millimeters(#motion_homing_z_offset),
+                        ),
+                    },
+                    software_endstops: SoftwareEndstopsConfig{
+                        enabled: #motion_software_endstops_enabled,
+                        clip: #motion_software_endstops_clip,
+                    },
+                    leveling: LevelingMotionConfig{
+                        points: (#motion_leveling_points_x, #motion_leveling_points_y),
+                        min: (
+                            Distance::from_millimeters(#motion_leveling_min_x),
+                            Distance::from_millimeters(#motion_leveling_min_y),
+                        ),
+                        max: (
+                            Distance::from_millimeters(#motion_leveling_max_x),
+                            Distance::from_millimeters(#motion_leveling_max_y),
+                        ),
+                        fade_height: Length::from_millimeters(#motion_leveling_fade_height),
+                    },
+                    probe: ProbeMotionConfig{
+                        offset: (
+                            Distance::from_millimeters(#motion_probe_offset_x),
+                            Distance::from_millimeters(#motion_probe_offset_y),
+                            Distance::from_millimeters(#motion_probe_offset_z),
+                        ),
+                        clearance: Length::from_millimeters(#motion_probe_clearance),
+                        max_depth: Length::from_millimeters(#motion_probe_max_depth),
+                        feedrate: Speed::from_meters_per_second(#motion_probe_feedrate / (1000.0 * 60.0)),
+                        travel_feedrate: Speed::from_meters_per_second(#motion_probe_travel_feedrate / (1000.0 * 60.0)),
+                        samples: #motion_probe_samples,
+                        retract: Length::from_millimeters(#motion_probe_retract),
+                    },
+                    alignment: AlignmentMotionConfig{
+                        points: [#((
+                            Distance::from_millimeters(#motion_alignment_points_x),
+                            Distance::from_millimeters(#motion_alignment_points_y),
+                        )),*].into_iter().collect(),
+                        iterations: #motion_alignment_iterations,
+                        accuracy: Length::from_millimeters(#motion_alignment_accuracy),
+                    },
+                    backlash: BacklashMotionConfig{
+                        distance: (
+                            Distance::from_millimeters(#motion_backlash_x),
+                            Distance::from_millimeters(#motion_backlash_y),
+                            Distance::from_millimeters(#motion_backlash_z),
+                        ),
+                        correction: #motion_backlash_correction,
+                        smoothing: Length::from_millimeters(#motion_backlash_smoothing),
+                    },
+                    skew: Skew::new(#motion_skew_xy, #motion_skew_xz, #motion_skew_yz),
+                    shaping: InputShapingMotionConfig{
+                        x: #motion_input_shaping_x,
+                        y: #motion_input_shaping_y,
+                    },
+                    advance: LinearAdvance::new(
+                        #motion_linear_advance_k,
+                        Speed::from_meters_per_second(#motion_linear_advance_max_feedrate / (1000.0 * 60.0)),
+                    ),
                 },
                 endstops: EndstopsConfig{
                     x: EndstopPartConfig {
@@ -1002,6 +923,13 @@ fn main() {
                         pin: p.#motion_endstop_z,
                         exti: p.#motion_endstop_z_exti,
                     },
+                    probe: ProbeConfig {
+                        pin: p.#motion_probe,
+                        exti: p.#motion_probe_exti,
+                        kind: ProbeType::from(#motion_probe_kind),
+                        active_low: #motion_probe_active_low,
+                        delay: core::time::Duration::from_micros(#motion_probe_delay),
+                    },
                 },
                 steppers: SteppersConfig{
                     x: StepperConfig{
@@ -1012,6 +940,7 @@ fn main() {
                         steps_per_revolution: #steppers_x_steps_per_revolution,
                         bounds: (Distance::from_millimeters(#steppers_x_bounds_min), Distance::from_millimeters(#steppers_x_bounds_max)),
                         positive_direction: RotationDirection::from(#steppers_x_positive_direction),
+                        motors: #steppers_x_motors,
                     },
                     y: StepperConfig{
                         step_pin: p.#steppers_y_step_pin,
@@ -1021,6 +950,7 @@ fn main() {
                         steps_per_revolution: #steppers_y_steps_per_revolution,
                         bounds: (Distance::from_millimeters(#steppers_y_bounds_min), Distance::from_millimeters(#steppers_y_bounds_max)),
                         positive_direction: RotationDirection::from(#steppers_y_positive_direction),
+                        motors: #steppers_y_motors,
                     },
                     z: StepperConfig{
                         step_pin: p.#steppers_z_step_pin,
@@ -1030,6 +960,7 @@ fn main() {
                         steps_per_revolution: #steppers_z_steps_per_revolution,
                         bounds: (Distance::from_millimeters(#steppers_z_bounds_min), Distance::from_millimeters(#steppers_z_bounds_max)),
                         positive_direction: RotationDirection::from(#steppers_z_positive_direction),
+                        motors: #steppers_z_motors,
                     },
                     e: StepperConfig{
                         step_pin: p.#steppers_e_step_pin,
@@ -1039,6 +970,7 @@ fn main() {
                         steps_per_revolution: #steppers_e_steps_per_revolution,
                         bounds: (Distance::from_millimeters(#steppers_e_bounds_min), Distance::from_millimeters(#steppers_e_bounds_max)),
                         positive_direction: RotationDirection::from(#steppers_e_positive_direction),
+                        motors: heapless::Vec::new(),
                     },
                 },
                 pwm: PwmConfig{
@@ -1121,6 +1053,15 @@ fn main() {
                         channel: #fan_pwm_output_channel,
                     }
                 },
+                servo: ServoOutputConfig {
+                    timer: p.#servo_timer,
+                    pin: p.#servo_pin,
+                    config: ServoOptionsConfig {
+                        min_pulse: core::time::Duration::from_micros(#servo_min_pulse),
+                        max_pulse: core::time::Duration::from_micros(#servo_max_pulse),
+                        max_angle: #servo_max_angle,
+                    },
+                },
                 sdcard: SdCardConfig {
                     spi: SpiConfig {
                         peripheral: p.#sdcard_spi_peripheral,
@@ -1132,7 +1073,14 @@ fn main() {
                 },
                 debug: DebugConfig{
                     alive_led: p.#debug_alive_led
-                }
+                },
+                preflight: PreflightConfig{
+                    enabled: #preflight_enabled,
+                    limits: PreflightLimits{
+                        min_extrusion_temperature: Temperature::from_celsius(#preflight_min_extrusion_temperature),
+                    },
+                },
+                drivers: #drivers
             }
         }
     };
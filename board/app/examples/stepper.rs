@@ -28,6 +28,15 @@ impl TimerBase for StepperTimer {
         let duration = embassy_time::Duration::from_micros(duration.as_micros() as u64);
         Timer::after(duration).await
     }
+
+    fn now() -> core::time::Duration {
+        core::time::Duration::from_micros(embassy_time::Instant::now().as_micros())
+    }
+
+    async fn at(instant: core::time::Duration) {
+        let instant = embassy_time::Instant::from_micros(instant.as_micros() as u64);
+        Timer::at(instant).await
+    }
 }
 
 #[embassy_executor::main]
@@ -1,22 +1,39 @@
+use embassy_stm32::exti::AnyChannel;
+use embassy_stm32::gpio::AnyPin;
 use math::{
     common::RotationDirection,
     measurements::{AngularVelocity, Distance, Length, Temperature},
 };
 pub use stepper::planner::MotionConfig;
-use stepper::stepper::SteppingMode;
+use stepper::preflight::PreflightLimits;
+use stepper::probe::ProbeType;
+use stepper::stepper::{SteppingMode, MAX_MOTORS};
+use stepper::tmc::TmcConfig;
 
 pub type ThermistorOptionsConfig = thermal_actuator::thermistor::ThermistorConfig;
 pub type PidConfig = common::PidConfig;
+pub type ServoOptionsConfig = servo::ServoConfig;
 
 pub struct EndstopPartConfig<P, E> {
     pub pin: P,
     pub exti: E,
 }
 
-pub struct EndstopsConfig<XP, XE, YP, YE, ZP, ZE> {
+// a BLTouch takes the servo output, active_low is for the inductive probes and delay is the
+// time the pin of a BLTouch takes to move
+pub struct ProbeConfig<P, E> {
+    pub pin: P,
+    pub exti: E,
+    pub kind: ProbeType,
+    pub active_low: bool,
+    pub delay: core::time::Duration,
+}
+
+pub struct EndstopsConfig<XP, XE, YP, YE, ZP, ZE, PP, PE> {
     pub x: EndstopPartConfig<XP, XE>,
     pub y: EndstopPartConfig<YP, YE>,
     pub z: EndstopPartConfig<ZP, ZE>,
+    pub probe: ProbeConfig<PP, PE>,
 }
 
 pub struct StepperConfig<S, D> {
@@ -27,6 +44,14 @@ pub struct StepperConfig<S, D> {
     pub steps_per_revolution: u64,
     pub bounds: (Distance, Distance),
     pub positive_direction: RotationDirection,
+    pub motors: heapless::Vec<MotorConfig, { MAX_MOTORS - 1 }>,
+}
+
+// a motor driving the axis along with the main one, with its own endstop to square the gantry
+pub struct MotorConfig {
+    pub step_pin: AnyPin,
+    pub dir_pin: AnyPin,
+    pub endstop: Option<EndstopPartConfig<AnyPin, AnyChannel>>,
 }
 
 pub struct UartPartConfig<P, D> {
@@ -41,6 +66,23 @@ pub struct UartConfig<P, RXP, RXD, TXP, TXD> {
     pub tx: UartPartConfig<TXP, TXD>,
 }
 
+pub struct DriversUartConfig<P, T, RXD, TXD> {
+    pub peripheral: P,
+    pub baudrate: u64,
+    pub pin: T,
+    pub rx_dma: RXD,
+    pub tx_dma: TXD,
+}
+
+// the steppers without a driver of their own are None
+pub struct DriversConfig<P, T, RXD, TXD> {
+    pub uart: DriversUartConfig<P, T, RXD, TXD>,
+    pub x: Option<TmcConfig>,
+    pub y: Option<TmcConfig>,
+    pub z: Option<TmcConfig>,
+    pub e: Option<TmcConfig>,
+}
+
 pub struct SteppersConfig<XP, XD, YP, YD, ZP, ZD, EP, ED> {
     pub x: StepperConfig<XP, XD>,
     pub y: StepperConfig<YP, YD>,
@@ -61,6 +103,8 @@ pub struct PrinterConfig<
     CH1,
     CH2,
     CH3,
+    SVT,
+    SVP,
     UP,
     RXP,
     RXD,
@@ -81,7 +125,13 @@ pub struct PrinterConfig<
     YEE,
     ZEP,
     ZEE,
+    PRP,
+    PRE,
     LED,
+    DUP,
+    DUT,
+    DURXD,
+    DUTXD,
 > {
     pub steppers: SteppersConfig<XP, XD, YP, YD, ZP, ZD, EP, ED>,
     pub pwm: PwmConfig<PWMT, CH1, CH2, CH3>,
@@ -90,10 +140,13 @@ pub struct PrinterConfig<
     pub hotend: ThermalActuatorConfig<HOI>,
     pub heatbed: ThermalActuatorConfig<HEI>,
     pub fan: FanConfig,
+    pub servo: ServoOutputConfig<SVT, SVP>,
     pub sdcard: SdCardConfig<SPIP, SPIT, SPIMO, SPIMI, SPICS>,
     pub motion: MotionConfig,
-    pub endstops: EndstopsConfig<XEP, XEE, YEP, YEE, ZEP, ZEE>,
+    pub endstops: EndstopsConfig<XEP, XEE, YEP, YEE, ZEP, ZEE, PRP, PRE>,
     pub debug: DebugConfig<LED>,
+    pub drivers: Option<DriversConfig<DUP, DUT, DURXD, DUTXD>>,
+    pub preflight: PreflightConfig,
 }
 
 pub struct AdcConfig<I, D> {
@@ -142,6 +195,13 @@ pub struct FanConfig {
     pub pwm: PwmOutputConfig,
 }
 
+// the pin is the channel 1 of the timer
+pub struct ServoOutputConfig<T, P> {
+    pub timer: T,
+    pub pin: P,
+    pub config: ServoOptionsConfig,
+}
+
 pub struct SdCardConfig<SPIP, SPIT, SPIMO, SPIMI, SPICS> {
     pub spi: SpiConfig<SPIP, SPIT, SPIMO, SPIMI, SPICS>,
 }
@@ -149,3 +209,8 @@ pub struct SdCardConfig<SPIP, SPIT, SPIMO, SPIMI, SPICS> {
 pub struct DebugConfig<A> {
     pub alive_led: A,
 }
+
+pub struct PreflightConfig {
+    pub enabled: bool,
+    pub limits: PreflightLimits,
+}
@@ -3,14 +3,19 @@
 
 use core::fmt::Display;
 
-use common::{AdcBase, ExtiInputPinBase, OutputPinBase, PwmBase, TimerBase};
+use common::{
+    AdcBase, ExtiInputPinBase, HalfDuplexSerialBase, OutputPinBase, PwmBase, SpiBase, TimerBase,
+};
 use embassy_stm32::{
     adc::{Adc, AnyAdcChannel, Instance, Resolution, RxDma, SampleTime},
     exti::ExtiInput,
     gpio::Output,
+    mode::Async,
+    spi::{self, Spi},
     timer::{simple_pwm::SimplePwm, Channel, GeneralInstance4Channel},
+    usart::{self, Uart},
 };
-use embassy_time::{Duration, Instant, Timer};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
 use embedded_sdmmc::{TimeSource, Timestamp};
 use math::measurements::Temperature;
 use stepper::stepper::StepperError;
@@ -129,6 +134,15 @@ impl TimerBase for StepperTimer {
         let duration = embassy_time::Duration::from_micros(duration.as_micros() as u64);
         Timer::after(duration).await
     }
+
+    fn now() -> core::time::Duration {
+        core::time::Duration::from_micros(embassy_time::Instant::now().as_micros())
+    }
+
+    async fn at(instant: core::time::Duration) {
+        let instant = embassy_time::Instant::from_micros(instant.as_micros() as u64);
+        Timer::at(instant).await
+    }
 }
 
 #[macro_export]
@@ -190,6 +204,65 @@ macro_rules! task_write {
     };
 }
 
+#[derive(Clone, Copy, Debug)]
+pub enum HalfDuplexSerialError {
+    Usart(usart::Error),
+    Timeout,
+}
+
+pub struct HalfDuplexSerialWrapper<'a> {
+    inner: Uart<'a, Async>,
+    timeout: Duration,
+}
+
+impl<'a> HalfDuplexSerialWrapper<'a> {
+    pub fn new(inner: Uart<'a, Async>, timeout: Duration) -> Self {
+        Self { inner, timeout }
+    }
+}
+
+impl HalfDuplexSerialBase for HalfDuplexSerialWrapper<'_> {
+    type Error = HalfDuplexSerialError;
+
+    // the UART is configured without readback, so the echo of the request never reaches the RX buffer
+    async fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.inner
+            .write(data)
+            .await
+            .map_err(HalfDuplexSerialError::Usart)
+    }
+
+    async fn read(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
+        match with_timeout(self.timeout, self.inner.read(data)).await {
+            Ok(r) => r.map_err(HalfDuplexSerialError::Usart),
+            Err(_) => Err(HalfDuplexSerialError::Timeout),
+        }
+    }
+}
+
+pub struct SpiWrapper<'a> {
+    inner: Spi<'a, Async>,
+    cs: Output<'a>,
+}
+
+impl<'a> SpiWrapper<'a> {
+    pub fn new(inner: Spi<'a, Async>, mut cs: Output<'a>) -> Self {
+        cs.set_high();
+        Self { inner, cs }
+    }
+}
+
+impl SpiBase for SpiWrapper<'_> {
+    type Error = spi::Error;
+
+    async fn transfer(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
+        self.cs.set_low();
+        let result = self.inner.transfer_in_place(data).await;
+        self.cs.set_high();
+        result
+    }
+}
+
 pub struct SimplePwmWrapper<'a, T: GeneralInstance4Channel> {
     inner: SimplePwm<'a, T>,
 }
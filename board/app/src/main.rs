@@ -6,13 +6,14 @@ use core::fmt::Write;
 use core::str::FromStr;
 
 use app::config::{
-    EndstopsConfig, FanConfig, MotionConfig, SdCardConfig, SteppersConfig, ThermalActuatorConfig,
+    DriversConfig, EndstopsConfig, FanConfig, MotionConfig, SdCardConfig, ServoOutputConfig,
+    SteppersConfig, ThermalActuatorConfig,
 };
 use app::ext::*;
 use app::{init_input_pin, init_output_pin, init_stepper, timer_channel, PrinterEvent};
 use app::{task_write, Clock, ExtiInputPinWrapper, OutputPinWrapper, StepperTimer};
-use app::{AdcWrapper, ResolutionWrapper, SimplePwmWrapper};
-use common::PwmBase;
+use app::{AdcWrapper, HalfDuplexSerialWrapper, ResolutionWrapper, SimplePwmWrapper};
+use common::{PwmBase, ServoBase};
 use embassy_embedded_hal::shared_bus::blocking::spi::SpiDevice;
 use embassy_executor::{InterruptExecutor, Spawner};
 use embassy_futures::join::join;
@@ -44,11 +45,16 @@ use embassy_time::{Delay, Duration, Timer};
 use embedded_sdmmc::{SdCard, VolumeIdx, VolumeManager};
 use fan::FanController;
 use heapless::{String, Vec};
-use math::{measurements::Temperature, DistanceUnit};
+use math::{measurements::Temperature, Axis, DistanceUnit};
 use parser::gcode::{GCodeParser, GCommand};
+use servo::{Servo, SERVO_FREQUENCY};
 use static_cell::{ConstStaticCell, StaticCell};
+use stepper::dispatch::{dispatch, is_planner_command};
 use stepper::planner::Planner;
+use stepper::preflight::Preflight;
+use stepper::probe::{AnyProbe, BlTouch, BlTouchCommand, InductiveProbe, ProbeType, SwitchProbe};
 use stepper::stepper::{StepperAttachment, StepperOptions};
+use stepper::tmc::{Tmc2209, TmcDrivers};
 use thermal_actuator::{
     controller::ThermalActuator, heater::Heater, thermistor, thermistor::Thermistor,
 };
@@ -291,20 +297,10 @@ async fn command_dispatcher_task() {
                 GCommand::G20 => parser.set_distance_unit(DistanceUnit::Inch),
                 GCommand::G21 => parser.set_distance_unit(DistanceUnit::Millimeter),
                 GCommand::M149 { u } => parser.set_temperature_unit(u),
-                GCommand::G0 { .. }
-                | GCommand::G1 { .. }
-                | GCommand::G2 { .. }
-                | GCommand::G3 { .. }
-                | GCommand::G4 { .. }
-                | GCommand::G10
-                | GCommand::G11
-                | GCommand::G28 { .. }
-                | GCommand::G90
-                | GCommand::G91
-                | GCommand::G92 { .. }
-                | GCommand::M207 { .. }
-                | GCommand::M208 { .. }
-                | GCommand::M220 { .. } => {
+                GCommand::M280 { .. } => {
+                    destination = 1u8 << u8::from(TaskId::Planner);
+                }
+                _ if is_planner_command(&cmd) => {
                     destination = 1u8 << u8::from(TaskId::Planner);
                 }
                 GCommand::M104 { .. }
@@ -670,6 +666,7 @@ async fn sdcard_handler(
         SdCardSpiMisoPin,
         SdCardSpiCsPin,
     >,
+    mut preflight: Option<Preflight>,
 ) {
     static SPI_BUS: StaticCell<NoopMutex<RefCell<Spi<'static, Blocking>>>> = StaticCell::new();
     let spi = spi::Spi::new_blocking(
@@ -693,6 +690,8 @@ async fn sdcard_handler(
     let mut working_file = None;
     let mut working_volume = None;
     let mut running = false;
+    // the working file passed the preflight checks, so resuming it doesn't check it again
+    let mut checked = false;
     let mut msg: String<MAX_MESSAGE_LEN> = String::new();
     let mut tmp: [u8; 128] = [0u8; 128];
     let mut clock = Clock::new();
@@ -731,6 +730,7 @@ async fn sdcard_handler(
                 info!("Volume closed");
             }
             running = false;
+            checked = false;
         }
 
         if let Some(cmd) = watch_receiver.try_changed() {
@@ -798,12 +798,60 @@ async fn sdcard_handler(
                             Ok(f) => Some(f),
                             Err(_) => panic!("File not found"),
                         };
+                        checked = false;
                         #[cfg(feature = "defmt-log")]
                         info!("Working file set");
                     }
                     // ignore the parameters of M24, just start/resume the print
                     GCommand::M24 { .. } => {
-                        if !running {
+                        if !running && !checked {
+                            checked = true;
+                            if let (Some(preflight), Some(wf)) = (preflight.as_mut(), working_file)
+                            {
+                                // read the lines the same way the print does
+                                preflight.reset();
+                                msg.clear();
+                                loop {
+                                    let n = volume_manager
+                                        .read(wf, &mut tmp)
+                                        .expect("Something went wrong during the SD-Card file reading");
+                                    // the last line may not end with a newline
+                                    let end = n == 0;
+                                    if end && msg.is_empty() {
+                                        break;
+                                    }
+                                    let bytes: &[u8] = if end { b"\n" } else { &tmp[0..n] };
+                                    for b in bytes {
+                                        if *b == b'\n' {
+                                            if let Err(issue) = preflight.check(msg.as_str()) {
+                                                report.clear();
+                                                task_write!(&mut report, SD_CARD_LABEL, "{}", issue)
+                                                    .unwrap();
+                                                FEEDBACK_CHANNEL.send(report.clone()).await;
+                                                checked = false;
+                                            }
+                                            msg.clear();
+                                        } else if msg.push((*b).into()).is_err() {
+                                            msg.clear();
+                                        }
+                                    }
+                                    if end {
+                                        break;
+                                    }
+                                }
+                                msg.clear();
+                                volume_manager
+                                    .file_seek_from_start(wf, 0)
+                                    .expect("Cannot rewind the file");
+                                if !checked {
+                                    report.clear();
+                                    task_write!(&mut report, SD_CARD_LABEL, "Preflight failed")
+                                        .unwrap();
+                                    FEEDBACK_CHANNEL.try_send(report.clone()).unwrap_or(());
+                                }
+                            }
+                        }
+                        if !running && checked {
                             clock.start();
                             running = true;
                             // event_channel_publisher
@@ -850,6 +898,15 @@ async fn sdcard_handler(
                 .read(working_file.unwrap(), &mut tmp)
                 .expect("Something went wrong during the SD-Card file reading");
             if n == 0 {
+                // the last line may not end with a newline
+                if !msg.is_empty() {
+                    let cmd = TaskMessage {
+                        msg: msg.clone(),
+                        priority: TaskMessagePriority::Low,
+                    };
+                    COMMAND_DISPATCHER_CHANNEL.send(cmd).await;
+                    msg.clear();
+                }
                 event_channel_publisher.publish(PrinterEvent::EOF).await;
             } else {
                 for b in &tmp[0..n] {
@@ -896,11 +953,33 @@ async fn planner_handler(
         YEndstopExti,
         ZEndstopPin,
         ZEndstopExti,
+        ProbePin,
+        ProbeExti,
+    >,
+    drivers_config: Option<
+        DriversConfig<DriversUartPeripheral, DriversUartPin, DriversUartRxDma, DriversUartTxDma>,
     >,
+    servo_config: ServoOutputConfig<ServoTimer, ServoPin>,
 ) {
     let mut report: String<MAX_MESSAGE_LEN> = String::new();
     let mut debug = false;
 
+    // the servo has a timer of its own, running at 50Hz
+    let servo_pwm = SimplePwm::new(
+        servo_config.timer,
+        Some(PwmPin::new_ch1(servo_config.pin, OutputType::PushPull)),
+        None,
+        None,
+        None,
+        hz(SERVO_FREQUENCY as u32),
+        CountingMode::EdgeAlignedUp,
+    );
+    let servo = Servo::new(
+        SimplePwmWrapper::new(servo_pwm),
+        embassy_stm32::timer::Channel::Ch1,
+        servo_config.config,
+    );
+
     let x_stepper = init_stepper!(
         config.x.step_pin,
         config.x.dir_pin,
@@ -972,15 +1051,102 @@ async fn planner_handler(
 
     let endstops = (Some(x_endstop), Some(y_endstop), Some(z_endstop), None);
 
-    let mut planner: Planner<OutputPinWrapper<'_>, StepperTimer, ExtiInputPinWrapper> =
-        Planner::new(
-            x_stepper,
-            y_stepper,
-            z_stepper,
-            e_stepper,
-            motion_config,
-            endstops,
-        );
+    let probe_config = endstops_config.probe;
+    let probe = ExtiInput::new(probe_config.pin, probe_config.exti, Pull::Down);
+    let probe = init_input_pin!(probe);
+    // a BLTouch takes the servo, M280 sends it its commands instead
+    let (probe, mut servo) = match probe_config.kind {
+        ProbeType::Switch => (AnyProbe::Switch(SwitchProbe::new(probe)), Some(servo)),
+        ProbeType::Inductive => (
+            AnyProbe::Inductive(InductiveProbe::new(probe, probe_config.active_low)),
+            Some(servo),
+        ),
+        ProbeType::BlTouch => (
+            AnyProbe::BlTouch(BlTouch::new(probe, servo, probe_config.delay)),
+            None,
+        ),
+    };
+
+    // without the UART of the drivers in the config its types are placeholders
+    #[cfg(tmc_drivers)]
+    let drivers = drivers_config.map(|drivers_config| {
+        let mut uart_config = embassy_stm32::usart::Config::default();
+        uart_config.baudrate = drivers_config.uart.baudrate as u32;
+
+        let drivers_uart = Uart::new_half_duplex(
+            drivers_config.uart.peripheral,
+            drivers_config.uart.pin,
+            Irqs,
+            drivers_config.uart.tx_dma,
+            drivers_config.uart.rx_dma,
+            uart_config,
+            usart::HalfDuplexReadback::NoReadback,
+            usart::HalfDuplexConfig::OpenDrainInternal,
+        )
+        .expect("Drivers UART configuration not valid");
+        let drivers_uart = HalfDuplexSerialWrapper::new(drivers_uart, Duration::from_millis(10));
+
+        TmcDrivers::new(
+            drivers_uart,
+            (
+                drivers_config.x.map(Tmc2209::new),
+                drivers_config.y.map(Tmc2209::new),
+                drivers_config.z.map(Tmc2209::new),
+                drivers_config.e.map(Tmc2209::new),
+            ),
+        )
+    });
+    #[cfg(not(tmc_drivers))]
+    let drivers = {
+        let _ = drivers_config;
+        None
+    };
+
+    let mut planner: Planner<
+        OutputPinWrapper<'_>,
+        StepperTimer,
+        ExtiInputPinWrapper,
+        HalfDuplexSerialWrapper<'_>,
+        AnyProbe<ExtiInputPinWrapper, Servo<SimplePwmWrapper<'_, ServoTimer>>, StepperTimer>,
+    > = Planner::new(
+        x_stepper,
+        y_stepper,
+        z_stepper,
+        e_stepper,
+        motion_config,
+        endstops,
+        drivers,
+    );
+
+    planner.set_probe(Some(probe));
+
+    // the additional motors of the axes, each one stops on its own endstop while homing
+    let motors = [
+        (Axis::X, config.x.motors),
+        (Axis::Y, config.y.motors),
+        (Axis::Z, config.z.motors),
+    ];
+    for (axis, motors) in motors {
+        for motor in motors {
+            let endstop = motor.endstop.map(|endstop| {
+                init_input_pin!(ExtiInput::new(endstop.pin, endstop.exti, Pull::Down))
+            });
+            planner
+                .add_motor(
+                    axis,
+                    init_output_pin!(motor.step_pin),
+                    init_output_pin!(motor.dir_pin),
+                    endstop,
+                )
+                .expect("Too many motors");
+        }
+    }
+
+    if let Err(e) = planner.init_drivers().await {
+        report.clear();
+        task_write!(&mut report, PLANNER_LABEL, "{}", e).unwrap();
+        FEEDBACK_CHANNEL.try_send(report.clone()).unwrap_or(());
+    }
 
     let dt = Duration::from_millis(20);
     let mut event_channel_subscriber = EVENT_CHANNEL
@@ -1009,46 +1175,54 @@ async fn planner_handler(
             #[cfg(feature = "defmt-log")]
             info!("[PLANNER HANDLER] command received");
             match cmd.cmd {
-                GCommand::G0 { .. }
-                | GCommand::G1 { .. }
-                | GCommand::G2 { .. }
-                | GCommand::G3 { .. }
-                | GCommand::G4 { .. }
-                | GCommand::G10
-                | GCommand::G11
-                | GCommand::G28 { .. }
-                | GCommand::G90
-                | GCommand::G91
-                | GCommand::G92 { .. }
-                | GCommand::M207 { .. }
-                | GCommand::M208 { .. }
-                | GCommand::M220 { .. } => {
-                    if let Err(e) = planner.execute(cmd.cmd.clone()).await {
+                // there's a single servo. Its angle is reported without s
+                GCommand::M280 { p, s } => {
+                    report.clear();
+                    match (servo.as_mut(), p) {
+                        (_, p) if p != 0 => {
+                            task_write!(&mut report, PLANNER_LABEL, "Servo {} not found", p).unwrap()
+                        }
+                        // the servo of a BLTouch, the angle is the command sent to it
+                        (None, p) => {
+                            let bltouch = planner.get_probe_mut().and_then(AnyProbe::as_bltouch);
+                            match (bltouch, s.map(BlTouchCommand::from_angle)) {
+                                (Some(bltouch), Some(Some(command))) => bltouch.execute(command).await,
+                                (Some(_), Some(None)) => {
+                                    task_write!(&mut report, PLANNER_LABEL, "Invalid BLTouch command").unwrap()
+                                }
+                                _ => task_write!(&mut report, PLANNER_LABEL, "Servo {} used by the probe", p)
+                                    .unwrap(),
+                            }
+                        }
+                        (Some(servo), _) => {
+                            if let Some(angle) = s {
+                                servo.set_angle(angle);
+                            } else {
+                                match servo.get_angle() {
+                                    Some(angle) => {
+                                        task_write!(&mut report, PLANNER_LABEL, "Servo {} angle: {:.1}", p, angle).unwrap()
+                                    }
+                                    None => task_write!(&mut report, PLANNER_LABEL, "Servo {} detached", p).unwrap(),
+                                }
+                            }
+                        }
+                    }
+                    if !report.is_empty() {
+                        FEEDBACK_CHANNEL.try_send(report.clone()).unwrap_or(());
+                    }
+                }
+                command => {
+                    let mut feedback = |args: core::fmt::Arguments| {
+                        report.clear();
+                        task_write!(&mut report, PLANNER_LABEL, "{}", args).unwrap();
+                        FEEDBACK_CHANNEL.try_send(report.clone()).unwrap_or(());
+                    };
+                    if let Err(e) = dispatch(&mut planner, command, &mut feedback).await {
                         event_channel_publisher
                             .publish(PrinterEvent::Stepper(e))
                             .await;
-                        report.clear();
-                        task_write!(&mut report, PLANNER_LABEL, "{}", e).unwrap();
-                        FEEDBACK_CHANNEL.try_send(report.clone()).unwrap_or(());
                     }
                 }
-                GCommand::M114 => {
-                    report.clear();
-                    task_write!(
-                        &mut report,
-                        PLANNER_LABEL,
-                        "Head position: [X:{}] [Y:{}] [Z:{}]",
-                        planner.get_x_position(),
-                        planner.get_y_position(),
-                        planner.get_z_position(),
-                    )
-                    .unwrap();
-                    FEEDBACK_CHANNEL.try_send(report.clone()).unwrap_or(());
-                }
-                _ => {
-                    // #[cfg(feature = "defmt-log")]
-                    // error!("[PLANNER HANDLER] command not handled")
-                }
             }
             SIGNAL.signal(TaskId::Planner);
         }
@@ -1183,6 +1357,21 @@ async fn main(spawner: Spawner) {
         .spawn(hotend_handler(printer_config.hotend, printer_config.fan))
         .unwrap();
 
+    let steppers = &printer_config.steppers;
+    let preflight = if printer_config.preflight.enabled {
+        Some(Preflight::new(
+            printer_config.preflight.limits,
+            &printer_config.motion,
+            [
+                steppers.x.bounds,
+                steppers.y.bounds,
+                steppers.z.bounds,
+            ],
+        ))
+    } else {
+        None
+    };
+
     interrupt::TIM2.set_priority(interrupt::Priority::P6);
     let planner_spawner = EXECUTOR_HIGH.start(interrupt::TIM2);
 
@@ -1191,11 +1380,13 @@ async fn main(spawner: Spawner) {
             printer_config.steppers,
             printer_config.motion,
             printer_config.endstops,
+            printer_config.drivers,
+            printer_config.servo,
         ))
         .unwrap();
 
     spawner
-        .spawn(sdcard_handler(printer_config.sdcard))
+        .spawn(sdcard_handler(printer_config.sdcard, preflight))
         .unwrap();
 
     let mut alive_led = init_output_pin!(printer_config.debug.alive_led);
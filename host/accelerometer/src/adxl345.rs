@@ -0,0 +1,282 @@
+use core::fmt::Display;
+
+use common::SpiBase;
+use math::measurements::Acceleration;
+
+// https://www.analog.com/media/en/technical-documentation/data-sheets/ADXL345.pdf
+pub mod register {
+    pub const DEVID: u8 = 0x00;
+    pub const BW_RATE: u8 = 0x2C;
+    pub const POWER_CTL: u8 = 0x2D;
+    pub const DATA_FORMAT: u8 = 0x31;
+    pub const DATAX0: u8 = 0x32;
+    pub const FIFO_CTL: u8 = 0x38;
+    pub const FIFO_STATUS: u8 = 0x39;
+}
+
+const DEVICE_ID: u8 = 0xE5;
+
+// first byte of a frame: read bit, multiple bytes bit and address
+const READ_FLAG: u8 = 0x80;
+const MULTIPLE_BYTES_FLAG: u8 = 0x40;
+
+const POWER_CTL_MEASURE: u8 = 1 << 3;
+const DATA_FORMAT_FULL_RES: u8 = 1 << 3;
+// the FIFO keeps the last 32 samples, the oldest ones are overwritten
+const FIFO_CTL_STREAM: u8 = 0b10 << 6;
+const FIFO_STATUS_ENTRIES_MASK: u8 = 0x3F;
+
+// samples held by the FIFO
+pub const FIFO_SIZE: usize = 32;
+
+// in full resolution a LSB is 3.9mg whatever the range
+const SCALE: f64 = 0.0039 * 9.80665;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Adxl345Error {
+    Spi,
+    // the device id isn't the one of an ADXL345
+    InvalidDevice,
+}
+
+impl Display for Adxl345Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match &self {
+            Adxl345Error::Spi => core::write!(f, "SPI communication failed"),
+            Adxl345Error::InvalidDevice => core::write!(f, "Accelerometer not found"),
+        }
+    }
+}
+
+// output data rate
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DataRate {
+    Hz100,
+    Hz200,
+    Hz400,
+    Hz800,
+    Hz1600,
+    Hz3200,
+}
+
+impl DataRate {
+    pub fn frequency(&self) -> f64 {
+        match self {
+            DataRate::Hz100 => 100.0,
+            DataRate::Hz200 => 200.0,
+            DataRate::Hz400 => 400.0,
+            DataRate::Hz800 => 800.0,
+            DataRate::Hz1600 => 1600.0,
+            DataRate::Hz3200 => 3200.0,
+        }
+    }
+
+    fn code(&self) -> u8 {
+        match self {
+            DataRate::Hz100 => 0x0A,
+            DataRate::Hz200 => 0x0B,
+            DataRate::Hz400 => 0x0C,
+            DataRate::Hz800 => 0x0D,
+            DataRate::Hz1600 => 0x0E,
+            DataRate::Hz3200 => 0x0F,
+        }
+    }
+}
+
+// full scale, in g
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Range {
+    G2,
+    G4,
+    G8,
+    G16,
+}
+
+impl Range {
+    fn code(&self) -> u8 {
+        match self {
+            Range::G2 => 0,
+            Range::G4 => 1,
+            Range::G8 => 2,
+            Range::G16 => 3,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Adxl345Config {
+    pub rate: DataRate,
+    pub range: Range,
+}
+
+impl Default for Adxl345Config {
+    fn default() -> Self {
+        Self {
+            rate: DataRate::Hz3200,
+            range: Range::G16,
+        }
+    }
+}
+
+// 3-axis accelerometer on SPI (mode 3, up to 5MHz). Once initialized it samples continuously
+// into its FIFO, which has to be read before it fills up
+pub struct Adxl345 {
+    config: Adxl345Config,
+}
+
+impl Adxl345 {
+    pub fn new(config: Adxl345Config) -> Self {
+        Self { config }
+    }
+
+    pub fn get_config(&self) -> Adxl345Config {
+        self.config
+    }
+
+    // the registers are written in standby, the measurement starts last
+    pub async fn init<S: SpiBase>(&mut self, spi: &mut S) -> Result<(), Adxl345Error> {
+        if self.read_register(spi, register::DEVID).await? != DEVICE_ID {
+            return Err(Adxl345Error::InvalidDevice);
+        }
+        self.write_register(spi, register::POWER_CTL, 0).await?;
+        self.write_register(spi, register::BW_RATE, self.config.rate.code())
+            .await?;
+        self.write_register(
+            spi,
+            register::DATA_FORMAT,
+            DATA_FORMAT_FULL_RES | self.config.range.code(),
+        )
+        .await?;
+        self.write_register(spi, register::FIFO_CTL, FIFO_CTL_STREAM)
+            .await?;
+        self.write_register(spi, register::POWER_CTL, POWER_CTL_MEASURE)
+            .await
+    }
+
+    // back to standby
+    pub async fn stop<S: SpiBase>(&mut self, spi: &mut S) -> Result<(), Adxl345Error> {
+        self.write_register(spi, register::POWER_CTL, 0).await
+    }
+
+    pub async fn write_register<S: SpiBase>(
+        &mut self,
+        spi: &mut S,
+        register: u8,
+        value: u8,
+    ) -> Result<(), Adxl345Error> {
+        let mut frame = [register, value];
+        spi.transfer(&mut frame)
+            .await
+            .map_err(|_| Adxl345Error::Spi)
+    }
+
+    pub async fn read_register<S: SpiBase>(
+        &mut self,
+        spi: &mut S,
+        register: u8,
+    ) -> Result<u8, Adxl345Error> {
+        let mut frame = [READ_FLAG | register, 0];
+        spi.transfer(&mut frame)
+            .await
+            .map_err(|_| Adxl345Error::Spi)?;
+        Ok(frame[1])
+    }
+
+    // samples waiting in the FIFO
+    pub async fn get_fifo_entries<S: SpiBase>(
+        &mut self,
+        spi: &mut S,
+    ) -> Result<usize, Adxl345Error> {
+        let status = self.read_register(spi, register::FIFO_STATUS).await?;
+        Ok(usize::from(status & FIFO_STATUS_ENTRIES_MASK))
+    }
+
+    // the oldest sample of the FIFO, which is removed from it
+    pub async fn read_acceleration<S: SpiBase>(
+        &mut self,
+        spi: &mut S,
+    ) -> Result<(Acceleration, Acceleration, Acceleration), Adxl345Error> {
+        let mut frame = [0u8; 7];
+        frame[0] = READ_FLAG | MULTIPLE_BYTES_FLAG | register::DATAX0;
+        spi.transfer(&mut frame)
+            .await
+            .map_err(|_| Adxl345Error::Spi)?;
+        let axis = |n: usize| {
+            let raw = i16::from_le_bytes([frame[1 + 2 * n], frame[2 + 2 * n]]);
+            Acceleration::from_meters_per_second_per_second(f64::from(raw) * SCALE)
+        };
+        Ok((axis(0), axis(1), axis(2)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use sim::{block_on, SimSpi};
+
+    #[test]
+    fn test_adxl345_init() {
+        let mut spi = SimSpi::new();
+        spi.queue_response(&[0x00, 0xE5]);
+        for _ in 0..5 {
+            spi.queue_response(&[0x00, 0x00]);
+        }
+        let mut adxl = Adxl345::new(Adxl345Config {
+            rate: DataRate::Hz1600,
+            range: Range::G8,
+        });
+        assert_eq!(block_on(adxl.init(&mut spi)), Ok(()));
+        assert_eq!(
+            spi.written(),
+            &[
+                vec![0x80, 0x00],
+                vec![0x2D, 0x00],
+                vec![0x2C, 0x0E],
+                vec![0x31, 0x0A],
+                vec![0x38, 0x80],
+                vec![0x2D, 0x08],
+            ]
+        );
+
+        // a board without the accelerometer reads 0xFF
+        let mut spi = SimSpi::new();
+        spi.queue_response(&[0xFF, 0xFF]);
+        assert_eq!(
+            block_on(adxl.init(&mut spi)),
+            Err(Adxl345Error::InvalidDevice)
+        );
+        assert_eq!(block_on(adxl.init(&mut spi)), Err(Adxl345Error::Spi));
+    }
+
+    #[test]
+    fn test_adxl345_read() {
+        let mut spi = SimSpi::new();
+        spi.queue_response(&[0x00, 0x9E]);
+        // resting flat: 1g on Z, a little tilt on X and Y
+        spi.queue_response(&[0x00, 0x10, 0x00, 0xF0, 0xFF, 0x00, 0x01]);
+        let mut adxl = Adxl345::new(Adxl345Config::default());
+        // the trigger bit isn't part of the entries
+        assert_eq!(block_on(adxl.get_fifo_entries(&mut spi)), Ok(30));
+        let (x, y, z) = block_on(adxl.read_acceleration(&mut spi)).unwrap();
+        assert_eq!(
+            spi.written()[1],
+            vec![0xF2, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+        assert_abs_diff_eq!(
+            x.as_meters_per_second_per_second(),
+            0.611935,
+            epsilon = 0.000001
+        );
+        assert_abs_diff_eq!(
+            y.as_meters_per_second_per_second(),
+            -0.611935,
+            epsilon = 0.000001
+        );
+        assert_abs_diff_eq!(
+            z.as_meters_per_second_per_second(),
+            9.790959,
+            epsilon = 0.000001
+        );
+    }
+}
@@ -35,6 +35,15 @@ pub trait AdcBase {
 
 pub trait TimerBase {
     fn after(duration: Duration) -> impl Future<Output = ()>;
+    // monotonic time elapsed since an arbitrary origin
+    fn now() -> Duration;
+    // wait until now() reaches the instant, returns immediately if it's already past
+    fn at(instant: Duration) -> impl Future<Output = ()>;
+}
+
+// hobby servo, the angle is in degrees
+pub trait ServoBase {
+    fn set_angle(&mut self, angle: f64);
 }
 
 pub trait OutputPinBase {
@@ -48,3 +57,20 @@ pub trait ExtiInputPinBase {
     fn wait_for_high(&mut self) -> impl Future<Output = ()>;
     fn wait_for_low(&mut self) -> impl Future<Output = ()>;
 }
+
+// single-wire UART (e.g. TMC2209 PDN_UART): the implementation is responsible
+// for discarding the echo of the bytes written on the shared line
+pub trait HalfDuplexSerialBase {
+    type Error;
+
+    fn write(&mut self, data: &[u8]) -> impl Future<Output = Result<(), Self::Error>>;
+    fn read(&mut self, data: &mut [u8]) -> impl Future<Output = Result<(), Self::Error>>;
+}
+
+// full-duplex SPI, the implementation drives the chip select around each transfer
+pub trait SpiBase {
+    type Error;
+
+    // the bytes of data are sent and replaced by the ones received
+    fn transfer(&mut self, data: &mut [u8]) -> impl Future<Output = Result<(), Self::Error>>;
+}
@@ -0,0 +1,474 @@
+use std::time::Duration;
+
+use math::measurements::{Acceleration, Distance, Length, Resistance, Speed, Temperature};
+use math::Axis;
+use stepper::advance::LinearAdvance;
+use stepper::leveling::MAX_MESH_POINTS;
+use stepper::motion;
+use stepper::planner::{
+    self, AlignmentMotionConfig, ArcMotionConfig, BacklashMotionConfig, ExtrusionMotionConfig,
+    HomingMotionConfig, InputShapingMotionConfig, LevelingMotionConfig, LimitsMotionConfig,
+    ProbeMotionConfig, RecoverMotionConfig, RetractionMotionConfig, SoftwareEndstopsConfig,
+};
+use stepper::preflight::{Preflight, PreflightLimits};
+use stepper::shaper::InputShaper;
+use stepper::skew::Skew;
+use stepper::stepper::{StepperAttachment, StepperOptions, MAX_MOTORS};
+use thermal_actuator::thermistor;
+
+use crate::{
+    AlignmentConfig, AxesConfig, BacklashConfig, Config, HeaterConfig, HomingConfig,
+    HomingConfigs, LevelingConfig, LimitsConfig, LinearAdvanceConfig, PreflightConfig,
+    ProbeConfig, ServoConfig, ShaperConfig, StepperConfig, ThermistorConfig,
+};
+
+// the values of the firmware types. Feedrates and speeds are in mm/min, the same conversions
+// end up in the code build.rs generates
+
+fn speed_from_mm_per_minute(value: f64) -> Speed {
+    Speed::from_meters_per_second(value / (1000.0 * 60.0))
+}
+
+// the From<&str> conversions panic on invalid values
+fn check_str<T: for<'a> From<&'a str>>(
+    value: &str,
+    valid: &[&str],
+    name: &str,
+) -> Result<T, String> {
+    if valid.contains(&value) {
+        Ok(T::from(value))
+    } else {
+        Err(format!("Invalid {}: {}", name, value))
+    }
+}
+
+impl Config {
+    // the values that don't make a valid printer. The pins and the peripherals are left to
+    // build.rs
+    pub fn check(&self) -> Result<(), String> {
+        self.motion_config()?;
+        for stepper in [
+            &self.steppers.x,
+            &self.steppers.y,
+            &self.steppers.z,
+            &self.steppers.e,
+        ] {
+            stepper.options()?;
+        }
+        if let Some(servo) = &self.servo {
+            servo.config()?;
+        }
+        Ok(())
+    }
+
+    pub fn motion_config(&self) -> Result<planner::MotionConfig, String> {
+        let motion = &self.motion;
+        let positioning = ["absolute", "relative"];
+        let homing = &motion.homing;
+        if motion.flow_multiplier <= 0.0 || motion.filament_diameter < 0.0 {
+            return Err(String::from("Invalid extrusion"));
+        }
+        Ok(planner::MotionConfig {
+            arcs: ArcMotionConfig {
+                tolerance: Length::from_millimeters(motion.arcs.tolerance),
+                min_segment_length: Length::from_millimeters(motion.arcs.min_segment_length),
+                max_segment_length: Length::from_millimeters(motion.arcs.max_segment_length),
+                min_segment_duration: Duration::from_micros(
+                    (motion.arcs.min_segment_duration * 1000.0) as u64,
+                ),
+            },
+            feedrate: speed_from_mm_per_minute(motion.feedrate),
+            positioning: check_str(&motion.positioning, &positioning, "positioning")?,
+            e_positioning: check_str(&motion.e_positioning, &positioning, "positioning")?,
+            feedrate_multiplier: motion.feedrate_multiplier,
+            extrusion: ExtrusionMotionConfig {
+                flow_multiplier: motion.flow_multiplier,
+                filament_diameter: (motion.filament_diameter > 0.0)
+                    .then(|| Distance::from_millimeters(motion.filament_diameter)),
+            },
+            limits: motion.limits.config()?,
+            retraction: RetractionMotionConfig {
+                feedrate: speed_from_mm_per_minute(motion.retraction.feedrate),
+                length: Length::from_millimeters(motion.retraction.length),
+                z_lift: Length::from_millimeters(motion.retraction.z_lift),
+            },
+            recover: RecoverMotionConfig {
+                feedrate: speed_from_mm_per_minute(motion.recover.feedrate),
+                length: Length::from_millimeters(motion.recover.length),
+            },
+            homing: HomingMotionConfig {
+                axes: (homing.x.config()?, homing.y.config()?, homing.z.config()?),
+                order: homing.order()?,
+                z_lift: Length::from_millimeters(homing.z_lift),
+                required: homing.required,
+                offset: (
+                    Distance::from_millimeters(homing.x.offset),
+                    Distance::from_millimeters(homing.y.offset),
+                    Distance::from_millimeters(homing.z.offset),
+                ),
+            },
+            software_endstops: SoftwareEndstopsConfig {
+                enabled: motion.software_endstops.enabled,
+                clip: motion.software_endstops.clip,
+            },
+            leveling: motion.leveling.config()?,
+            probe: motion.probe.config()?,
+            alignment: motion.alignment.config()?,
+            backlash: motion.backlash.config()?,
+            skew: Skew::new(motion.skew.xy, motion.skew.xz, motion.skew.yz),
+            shaping: InputShapingMotionConfig {
+                x: motion.input_shaping.x.config()?,
+                y: motion.input_shaping.y.config()?,
+            },
+            advance: motion.linear_advance.config()?,
+        })
+    }
+
+    // the preflight checks of the printer, whether the config enables them before a print or not
+    pub fn preflight(&self) -> Result<Preflight, String> {
+        let steppers = &self.steppers;
+        let bounds = [&steppers.x, &steppers.y, &steppers.z].map(|stepper| {
+            (
+                Distance::from_millimeters(stepper.bounds.min),
+                Distance::from_millimeters(stepper.bounds.max),
+            )
+        });
+        Ok(Preflight::new(
+            self.preflight.limits(),
+            &self.motion_config()?,
+            bounds,
+        ))
+    }
+}
+
+impl PreflightConfig {
+    pub fn limits(&self) -> PreflightLimits {
+        PreflightLimits {
+            min_extrusion_temperature: Temperature::from_celsius(self.min_extrusion_temperature),
+        }
+    }
+}
+
+impl LevelingConfig {
+    pub fn config(&self) -> Result<LevelingMotionConfig, String> {
+        let range = 2..=MAX_MESH_POINTS;
+        if !range.contains(&self.points.x) || !range.contains(&self.points.y) {
+            return Err(format!(
+                "Invalid mesh points: {}x{}",
+                self.points.x, self.points.y
+            ));
+        }
+        if self.max.x <= self.min.x || self.max.y <= self.min.y {
+            return Err(String::from("Invalid mesh area"));
+        }
+        Ok(LevelingMotionConfig {
+            points: (self.points.x, self.points.y),
+            min: (
+                Distance::from_millimeters(self.min.x),
+                Distance::from_millimeters(self.min.y),
+            ),
+            max: (
+                Distance::from_millimeters(self.max.x),
+                Distance::from_millimeters(self.max.y),
+            ),
+            fade_height: Length::from_millimeters(self.fade_height),
+        })
+    }
+}
+
+impl ProbeConfig {
+    pub fn config(&self) -> Result<ProbeMotionConfig, String> {
+        if self.samples == 0 {
+            return Err(String::from("Invalid probe samples: 0"));
+        }
+        if self.max_depth <= 0.0 {
+            return Err(format!("Invalid probe max depth: {}", self.max_depth));
+        }
+        Ok(ProbeMotionConfig {
+            offset: (
+                Distance::from_millimeters(self.offset.x),
+                Distance::from_millimeters(self.offset.y),
+                Distance::from_millimeters(self.offset.z),
+            ),
+            clearance: Length::from_millimeters(self.clearance),
+            max_depth: Length::from_millimeters(self.max_depth),
+            feedrate: speed_from_mm_per_minute(self.feedrate),
+            travel_feedrate: speed_from_mm_per_minute(self.travel_feedrate),
+            samples: self.samples,
+            retract: Length::from_millimeters(self.retract),
+        })
+    }
+}
+
+impl AlignmentConfig {
+    pub fn config(&self) -> Result<AlignmentMotionConfig, String> {
+        if self.accuracy <= 0.0 {
+            return Err(format!("Invalid alignment accuracy: {}", self.accuracy));
+        }
+        let mut points = heapless::Vec::new();
+        for point in &self.points {
+            let point = (
+                Distance::from_millimeters(point.x),
+                Distance::from_millimeters(point.y),
+            );
+            points.push(point).map_err(|_| {
+                format!(
+                    "Invalid alignment points: {}, at most {}",
+                    self.points.len(),
+                    MAX_MOTORS
+                )
+            })?;
+        }
+        Ok(AlignmentMotionConfig {
+            points,
+            iterations: self.iterations,
+            accuracy: Length::from_millimeters(self.accuracy),
+        })
+    }
+}
+
+impl BacklashConfig {
+    pub fn config(&self) -> Result<BacklashMotionConfig, String> {
+        if !(0.0..=1.0).contains(&self.correction) {
+            return Err(format!("Invalid backlash correction: {}", self.correction));
+        }
+        if [self.x, self.y, self.z, self.smoothing].iter().any(|v| *v < 0.0) {
+            return Err(String::from("Invalid backlash distance"));
+        }
+        Ok(BacklashMotionConfig {
+            distance: (
+                Distance::from_millimeters(self.x),
+                Distance::from_millimeters(self.y),
+                Distance::from_millimeters(self.z),
+            ),
+            correction: self.correction,
+            smoothing: Length::from_millimeters(self.smoothing),
+        })
+    }
+}
+
+impl ShaperConfig {
+    pub fn config(&self) -> Result<InputShaper, String> {
+        let shaper_type = check_str(
+            &self.shaper,
+            &["zv", "mzv", "zvd", "ei", "2hump_ei"],
+            "shaper",
+        )?;
+        if self.frequency < 0.0 || !(0.0..1.0).contains(&self.damping) {
+            return Err(String::from("Invalid input shaping"));
+        }
+        Ok(InputShaper::new(shaper_type, self.frequency, self.damping))
+    }
+}
+
+impl LimitsConfig {
+    pub fn config(&self) -> Result<LimitsMotionConfig, String> {
+        let (feedrate, acceleration, jerk) = (self.max_feedrate, self.max_acceleration, self.jerk);
+        let accelerations = [
+            self.print_acceleration,
+            self.retract_acceleration,
+            self.travel_acceleration,
+        ];
+        if feedrate.values().iter().any(|v| *v <= 0.0)
+            || acceleration.values().iter().chain(&accelerations).any(|v| *v <= 0.0)
+            || jerk.values().iter().any(|v| *v < 0.0)
+        {
+            return Err(String::from("Invalid limits"));
+        }
+        let acceleration_from_mm =
+            |a: f64| Acceleration::from_meters_per_second_per_second(a / 1000.0);
+        Ok(LimitsMotionConfig {
+            max_feedrate: feedrate.map(speed_from_mm_per_minute),
+            max_acceleration: acceleration.map(acceleration_from_mm),
+            print_acceleration: acceleration_from_mm(self.print_acceleration),
+            retract_acceleration: acceleration_from_mm(self.retract_acceleration),
+            travel_acceleration: acceleration_from_mm(self.travel_acceleration),
+            jerk: jerk.map(speed_from_mm_per_minute),
+        })
+    }
+}
+
+impl AxesConfig {
+    fn values(&self) -> [f64; 4] {
+        [self.x, self.y, self.z, self.e]
+    }
+
+    fn map<T>(&self, f: impl Fn(f64) -> T) -> (T, T, T, T) {
+        (f(self.x), f(self.y), f(self.z), f(self.e))
+    }
+}
+
+impl LinearAdvanceConfig {
+    pub fn config(&self) -> Result<LinearAdvance, String> {
+        if self.k < 0.0 || self.max_feedrate <= 0.0 {
+            return Err(String::from("Invalid linear advance"));
+        }
+        Ok(LinearAdvance::new(
+            self.k,
+            speed_from_mm_per_minute(self.max_feedrate),
+        ))
+    }
+}
+
+impl ServoConfig {
+    pub fn config(&self) -> Result<servo::ServoConfig, String> {
+        let config = servo::ServoConfig {
+            min_pulse: Duration::from_micros(self.min_pulse),
+            max_pulse: Duration::from_micros(self.max_pulse),
+            max_angle: self.max_angle,
+        };
+        if !config.is_valid() {
+            return Err(String::from("Invalid servo pulses or max angle"));
+        }
+        Ok(config)
+    }
+}
+
+impl HomingConfigs {
+    // every axis among x, y and z exactly once
+    pub fn order(&self) -> Result<[Axis; 3], String> {
+        let mut order = [Axis::X; 3];
+        let mut chars = self.order.chars();
+        for axis in order.iter_mut() {
+            *axis = match chars.next() {
+                Some('x') => Axis::X,
+                Some('y') => Axis::Y,
+                Some('z') => Axis::Z,
+                _ => return Err(format!("Invalid homing order: {}", self.order)),
+            };
+        }
+        if chars.next().is_some()
+            || order[0] == order[1]
+            || order[0] == order[2]
+            || order[1] == order[2]
+        {
+            return Err(format!("Invalid homing order: {}", self.order));
+        }
+        Ok(order)
+    }
+}
+
+impl HomingConfig {
+    pub fn config(&self) -> Result<motion::HomingConfig, String> {
+        Ok(motion::HomingConfig {
+            direction: check_str(&self.direction, &["min", "max"], "homing direction")?,
+            fast_speed: speed_from_mm_per_minute(self.fast_speed),
+            slow_speed: speed_from_mm_per_minute(self.slow_speed),
+            bump: Distance::from_millimeters(self.bump),
+            sensorless: self.sensorless,
+            min_travel: Distance::from_millimeters(self.min_travel),
+            backoff: Distance::from_millimeters(self.backoff),
+        })
+    }
+}
+
+impl StepperConfig {
+    pub fn options(&self) -> Result<StepperOptions, String> {
+        Ok(StepperOptions {
+            steps_per_revolution: self.steps_per_revolution,
+            stepping_mode: check_str(
+                &self.stepping_mode,
+                &["full", "half", "quarter", "eighth", "sixteenth"],
+                "stepping mode",
+            )?,
+            bounds: Some((
+                Distance::from_millimeters(self.bounds.min),
+                Distance::from_millimeters(self.bounds.max),
+            )),
+            positive_direction: check_str(
+                &self.positive_direction,
+                &["clockwise", "counterclockwise"],
+                "rotation direction",
+            )?,
+            acceleration: None,
+        })
+    }
+
+    pub fn attachment(&self) -> StepperAttachment {
+        StepperAttachment {
+            distance_per_step: Distance::from_millimeters(self.distance_per_step),
+        }
+    }
+}
+
+impl HeaterConfig {
+    pub fn pid_config(&self) -> common::PidConfig {
+        common::PidConfig {
+            k_p: self.pid.k_p,
+            k_i: self.pid.k_i,
+            k_d: self.pid.k_d,
+        }
+    }
+
+    pub fn temperature_limit(&self) -> (Temperature, Temperature) {
+        (
+            Temperature::from_celsius(self.min_temperature_limit),
+            Temperature::from_celsius(self.max_temperature_limit),
+        )
+    }
+}
+
+impl ThermistorConfig {
+    pub fn config(&self) -> thermistor::ThermistorConfig {
+        thermistor::ThermistorConfig {
+            r_series: Resistance::from_ohms(self.r_series),
+            r0: Resistance::from_ohms(self.r0),
+            // same conversion as build.rs
+            b: Temperature::from_celsius(self.b),
+            samples: self.samples,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use math::common::RotationDirection;
+    use std::path::PathBuf;
+    use stepper::stepper::SteppingMode;
+
+    #[test]
+    fn test_board_config() {
+        let path =
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../../board/app/config/config.toml");
+        let config = Config::load(&path).unwrap();
+        let motion = config.motion_config().unwrap();
+        assert_eq!(motion.homing.order, [Axis::X, Axis::Y, Axis::Z]);
+        assert_eq!(config.hotend.get_heater().get_pwm().get_channel(), 1);
+        let options = config.steppers.get_x().options().unwrap();
+        assert_eq!(options.stepping_mode, SteppingMode::QuarterStep);
+        assert_eq!(
+            options.positive_direction,
+            RotationDirection::CounterClockwise
+        );
+        assert_eq!(config.steppers.get_x().get_step().get_pin().as_deref(), Some("PC11"));
+        assert!(config.preflight.get_enabled());
+        let mut preflight = config.preflight().unwrap();
+        assert!(preflight.check("G28").is_ok());
+        let servo = config.servo.unwrap().config().unwrap();
+        assert_eq!(servo.max_pulse, Duration::from_micros(2400));
+    }
+
+    #[test]
+    fn test_homing_order() {
+        let axis = |direction: &str| HomingConfig {
+            direction: String::from(direction),
+            ..Default::default()
+        };
+        let mut homing = HomingConfigs {
+            order: String::from("zxy"),
+            z_lift: 0.0,
+            required: false,
+            x: axis("max"),
+            y: axis("max"),
+            z: axis("min"),
+        };
+        assert_eq!(homing.order(), Ok([Axis::Z, Axis::X, Axis::Y]));
+        homing.order = String::from("xx");
+        assert!(homing.order().is_err());
+        homing.order = String::from("xyzx");
+        assert!(homing.order().is_err());
+        homing.order = String::from("xyx");
+        assert!(homing.order().is_err());
+    }
+}
@@ -0,0 +1,1273 @@
+// the config.toml of the board, read by build.rs to generate the firmware config and by the
+// host tools. The pins and the peripherals only matter to build.rs, the host configs can leave
+// them out
+use std::{fs, ops::Not, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+mod convert;
+
+fn get_string_value(s: String) -> Option<String> {
+    s.is_empty().not().then_some(s)
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct PinConfig {
+    pin: String,
+}
+
+impl PinConfig {
+    pub fn get_pin(&self) -> Option<String> {
+        get_string_value(self.pin.clone())
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct PeripheralConfig {
+    peripheral: String,
+}
+
+impl PeripheralConfig {
+    pub fn get_peripheral(&self) -> Option<String> {
+        get_string_value(self.peripheral.clone())
+    }
+}
+
+//     [motion]
+// feedrate = 0.0
+// positioning = "absolute"
+
+// [motion.retraction]
+// feedrate = 0.0
+// length = 0.0
+// z_lift = 0.0
+
+// [motion.recover]
+// feedrate = 0.0
+// length = 0.0
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct RecoverMotionConfig {
+    feedrate: f64,
+    length: f64,
+}
+
+impl RecoverMotionConfig {
+    pub fn get_feedrate(&self) -> f64 {
+        self.feedrate
+    }
+
+    pub fn get_length(&self) -> f64 {
+        self.length
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct RetractionMotionConfig {
+    feedrate: f64,
+    length: f64,
+    z_lift: f64,
+}
+
+impl RetractionMotionConfig {
+    pub fn get_feedrate(&self) -> f64 {
+        self.feedrate
+    }
+
+    pub fn get_length(&self) -> f64 {
+        self.length
+    }
+
+    pub fn get_zlift(&self) -> f64 {
+        self.z_lift
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct EndstopPartConfig {
+    pin: String,
+    exti: String,
+}
+
+impl EndstopPartConfig {
+    pub fn get_pin(&self) -> Option<String> {
+        get_string_value(self.pin.clone())
+    }
+
+    pub fn get_exti(&self) -> Option<String> {
+        get_string_value(self.exti.clone())
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct EndstopsConfig {
+    x: EndstopPartConfig,
+    y: EndstopPartConfig,
+    z: EndstopPartConfig,
+}
+
+impl EndstopsConfig {
+    pub fn get_x(&self) -> EndstopPartConfig {
+        self.x.clone()
+    }
+
+    pub fn get_y(&self) -> EndstopPartConfig {
+        self.y.clone()
+    }
+
+    pub fn get_z(&self) -> EndstopPartConfig {
+        self.z.clone()
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct HomingConfig {
+    direction: String,
+    fast_speed: f64,
+    slow_speed: f64,
+    bump: f64,
+    offset: f64,
+    sensorless: bool,
+    min_travel: f64,
+    backoff: f64,
+}
+
+impl HomingConfig {
+    pub fn get_direction(&self) -> Option<String> {
+        get_string_value(self.direction.clone())
+    }
+
+    pub fn get_fast_speed(&self) -> f64 {
+        self.fast_speed
+    }
+
+    pub fn get_slow_speed(&self) -> f64 {
+        self.slow_speed
+    }
+
+    pub fn get_bump(&self) -> f64 {
+        self.bump
+    }
+
+    pub fn get_offset(&self) -> f64 {
+        self.offset
+    }
+
+    pub fn get_sensorless(&self) -> bool {
+        self.sensorless
+    }
+
+    pub fn get_min_travel(&self) -> f64 {
+        self.min_travel
+    }
+
+    pub fn get_backoff(&self) -> f64 {
+        self.backoff
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct HomingConfigs {
+    order: String,
+    z_lift: f64,
+    required: bool,
+    x: HomingConfig,
+    y: HomingConfig,
+    z: HomingConfig,
+}
+
+impl HomingConfigs {
+    pub fn get_order(&self) -> Option<String> {
+        get_string_value(self.order.clone())
+    }
+
+    pub fn get_z_lift(&self) -> f64 {
+        self.z_lift
+    }
+
+    pub fn get_required(&self) -> bool {
+        self.required
+    }
+
+    pub fn get_x(&self) -> HomingConfig {
+        self.x.clone()
+    }
+
+    pub fn get_y(&self) -> HomingConfig {
+        self.y.clone()
+    }
+
+    pub fn get_z(&self) -> HomingConfig {
+        self.z.clone()
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct MotionConfig {
+    feedrate: f64,
+    positioning: String,
+    e_positioning: String,
+    feedrate_multiplier: f64,
+    flow_multiplier: f64,
+    filament_diameter: f64,
+    limits: LimitsConfig,
+    retraction: RetractionMotionConfig,
+    recover: RecoverMotionConfig,
+    homing: HomingConfigs,
+    #[serde(default)]
+    endstops: EndstopsConfig,
+    software_endstops: SoftwareEndstopsConfig,
+    arcs: ArcsConfig,
+    leveling: LevelingConfig,
+    probe: ProbeConfig,
+    alignment: AlignmentConfig,
+    backlash: BacklashConfig,
+    skew: SkewConfig,
+    input_shaping: InputShapingConfig,
+    linear_advance: LinearAdvanceConfig,
+}
+
+impl MotionConfig {
+    pub fn get_feedrate(&self) -> f64 {
+        self.feedrate
+    }
+
+    pub fn get_positioning(&self) -> Option<String> {
+        get_string_value(self.positioning.clone())
+    }
+
+    pub fn get_e_positioning(&self) -> Option<String> {
+        get_string_value(self.e_positioning.clone())
+    }
+
+    pub fn get_retraction(&self) -> RetractionMotionConfig {
+        self.retraction
+    }
+
+    pub fn get_recover(&self) -> RecoverMotionConfig {
+        self.recover
+    }
+
+    pub fn get_homing(&self) -> HomingConfigs {
+        self.homing.clone()
+    }
+
+    pub fn get_endstops(&self) -> EndstopsConfig {
+        self.endstops.clone()
+    }
+
+    pub fn get_feedrate_multiplier(&self) -> f64 {
+        self.feedrate_multiplier
+    }
+
+    pub fn get_flow_multiplier(&self) -> f64 {
+        self.flow_multiplier
+    }
+
+    pub fn get_filament_diameter(&self) -> f64 {
+        self.filament_diameter
+    }
+
+    pub fn get_limits(&self) -> LimitsConfig {
+        self.limits
+    }
+
+    pub fn get_software_endstops(&self) -> SoftwareEndstopsConfig {
+        self.software_endstops
+    }
+
+    pub fn get_arcs(&self) -> ArcsConfig {
+        self.arcs
+    }
+
+    pub fn get_leveling(&self) -> LevelingConfig {
+        self.leveling
+    }
+
+    pub fn get_probe(&self) -> ProbeConfig {
+        self.probe.clone()
+    }
+
+    pub fn get_alignment(&self) -> AlignmentConfig {
+        self.alignment.clone()
+    }
+
+    pub fn get_backlash(&self) -> BacklashConfig {
+        self.backlash
+    }
+
+    pub fn get_skew(&self) -> SkewConfig {
+        self.skew
+    }
+
+    pub fn get_input_shaping(&self) -> InputShapingConfig {
+        self.input_shaping.clone()
+    }
+
+    pub fn get_linear_advance(&self) -> LinearAdvanceConfig {
+        self.linear_advance
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct LinearAdvanceConfig {
+    k: f64,
+    max_feedrate: f64,
+}
+
+impl LinearAdvanceConfig {
+    pub fn get_k(&self) -> f64 {
+        self.k
+    }
+
+    pub fn get_max_feedrate(&self) -> f64 {
+        self.max_feedrate
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct InputShapingConfig {
+    x: ShaperConfig,
+    y: ShaperConfig,
+}
+
+impl InputShapingConfig {
+    pub fn get_x(&self) -> ShaperConfig {
+        self.x.clone()
+    }
+
+    pub fn get_y(&self) -> ShaperConfig {
+        self.y.clone()
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct ShaperConfig {
+    shaper: String,
+    frequency: f64,
+    damping: f64,
+}
+
+impl ShaperConfig {
+    pub fn get_shaper(&self) -> Option<String> {
+        get_string_value(self.shaper.clone())
+    }
+
+    pub fn get_frequency(&self) -> f64 {
+        self.frequency
+    }
+
+    pub fn get_damping(&self) -> f64 {
+        self.damping
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct AxesConfig {
+    x: f64,
+    y: f64,
+    z: f64,
+    e: f64,
+}
+
+impl AxesConfig {
+    pub fn get_x(&self) -> f64 {
+        self.x
+    }
+
+    pub fn get_y(&self) -> f64 {
+        self.y
+    }
+
+    pub fn get_z(&self) -> f64 {
+        self.z
+    }
+
+    pub fn get_e(&self) -> f64 {
+        self.e
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct LimitsConfig {
+    max_feedrate: AxesConfig,
+    max_acceleration: AxesConfig,
+    print_acceleration: f64,
+    retract_acceleration: f64,
+    travel_acceleration: f64,
+    jerk: AxesConfig,
+}
+
+impl LimitsConfig {
+    pub fn get_max_feedrate(&self) -> AxesConfig {
+        self.max_feedrate
+    }
+
+    pub fn get_max_acceleration(&self) -> AxesConfig {
+        self.max_acceleration
+    }
+
+    pub fn get_print_acceleration(&self) -> f64 {
+        self.print_acceleration
+    }
+
+    pub fn get_retract_acceleration(&self) -> f64 {
+        self.retract_acceleration
+    }
+
+    pub fn get_travel_acceleration(&self) -> f64 {
+        self.travel_acceleration
+    }
+
+    pub fn get_jerk(&self) -> AxesConfig {
+        self.jerk
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct SkewConfig {
+    xy: f64,
+    xz: f64,
+    yz: f64,
+}
+
+impl SkewConfig {
+    pub fn get_xy(&self) -> f64 {
+        self.xy
+    }
+
+    pub fn get_xz(&self) -> f64 {
+        self.xz
+    }
+
+    pub fn get_yz(&self) -> f64 {
+        self.yz
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct BacklashConfig {
+    x: f64,
+    y: f64,
+    z: f64,
+    correction: f64,
+    smoothing: f64,
+}
+
+impl BacklashConfig {
+    pub fn get_x(&self) -> f64 {
+        self.x
+    }
+
+    pub fn get_y(&self) -> f64 {
+        self.y
+    }
+
+    pub fn get_z(&self) -> f64 {
+        self.z
+    }
+
+    pub fn get_correction(&self) -> f64 {
+        self.correction
+    }
+
+    pub fn get_smoothing(&self) -> f64 {
+        self.smoothing
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct AlignmentConfig {
+    points: Vec<LevelingPointConfig>,
+    iterations: usize,
+    accuracy: f64,
+}
+
+impl AlignmentConfig {
+    pub fn get_points(&self) -> Vec<LevelingPointConfig> {
+        self.points.clone()
+    }
+
+    pub fn get_iterations(&self) -> usize {
+        self.iterations
+    }
+
+    pub fn get_accuracy(&self) -> f64 {
+        self.accuracy
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct LevelingPointsConfig {
+    x: usize,
+    y: usize,
+}
+
+impl LevelingPointsConfig {
+    pub fn get_x(&self) -> usize {
+        self.x
+    }
+
+    pub fn get_y(&self) -> usize {
+        self.y
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct LevelingPointConfig {
+    x: f64,
+    y: f64,
+}
+
+impl LevelingPointConfig {
+    pub fn get_x(&self) -> f64 {
+        self.x
+    }
+
+    pub fn get_y(&self) -> f64 {
+        self.y
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct LevelingConfig {
+    points: LevelingPointsConfig,
+    min: LevelingPointConfig,
+    max: LevelingPointConfig,
+    fade_height: f64,
+}
+
+impl LevelingConfig {
+    pub fn get_points(&self) -> LevelingPointsConfig {
+        self.points
+    }
+
+    pub fn get_min(&self) -> LevelingPointConfig {
+        self.min
+    }
+
+    pub fn get_max(&self) -> LevelingPointConfig {
+        self.max
+    }
+
+    pub fn get_fade_height(&self) -> f64 {
+        self.fade_height
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct ProbeOffsetConfig {
+    x: f64,
+    y: f64,
+    z: f64,
+}
+
+impl ProbeOffsetConfig {
+    pub fn get_x(&self) -> f64 {
+        self.x
+    }
+
+    pub fn get_y(&self) -> f64 {
+        self.y
+    }
+
+    pub fn get_z(&self) -> f64 {
+        self.z
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct ProbeConfig {
+    #[serde(default)]
+    pin: String,
+    #[serde(default)]
+    exti: String,
+    #[serde(default)]
+    kind: String,
+    #[serde(default)]
+    active_low: bool,
+    #[serde(default)]
+    delay: f64,
+    offset: ProbeOffsetConfig,
+    clearance: f64,
+    max_depth: f64,
+    feedrate: f64,
+    travel_feedrate: f64,
+    samples: usize,
+    retract: f64,
+}
+
+impl ProbeConfig {
+    pub fn get_pin(&self) -> Option<String> {
+        get_string_value(self.pin.clone())
+    }
+
+    pub fn get_exti(&self) -> Option<String> {
+        get_string_value(self.exti.clone())
+    }
+
+    pub fn get_kind(&self) -> Option<String> {
+        get_string_value(self.kind.clone())
+    }
+
+    pub fn get_active_low(&self) -> bool {
+        self.active_low
+    }
+
+    pub fn get_delay(&self) -> f64 {
+        self.delay
+    }
+
+    pub fn get_offset(&self) -> ProbeOffsetConfig {
+        self.offset
+    }
+
+    pub fn get_clearance(&self) -> f64 {
+        self.clearance
+    }
+
+    pub fn get_max_depth(&self) -> f64 {
+        self.max_depth
+    }
+
+    pub fn get_feedrate(&self) -> f64 {
+        self.feedrate
+    }
+
+    pub fn get_travel_feedrate(&self) -> f64 {
+        self.travel_feedrate
+    }
+
+    pub fn get_samples(&self) -> usize {
+        self.samples
+    }
+
+    pub fn get_retract(&self) -> f64 {
+        self.retract
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct SoftwareEndstopsConfig {
+    enabled: bool,
+    clip: bool,
+}
+
+impl SoftwareEndstopsConfig {
+    pub fn get_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn get_clip(&self) -> bool {
+        self.clip
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct ArcsConfig {
+    tolerance: f64,
+    min_segment_length: f64,
+    max_segment_length: f64,
+    // ms
+    min_segment_duration: f64,
+}
+
+impl ArcsConfig {
+    pub fn get_tolerance(&self) -> f64 {
+        self.tolerance
+    }
+
+    pub fn get_min_segment_length(&self) -> f64 {
+        self.min_segment_length
+    }
+
+    pub fn get_max_segment_length(&self) -> f64 {
+        self.max_segment_length
+    }
+
+    pub fn get_min_segment_duration(&self) -> f64 {
+        self.min_segment_duration
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct StepperBounds {
+    pub min: f64,
+    pub max: f64,
+}
+/* stepper */
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct StepperConfig {
+    #[serde(default)]
+    step: PinConfig,
+    #[serde(default)]
+    dir: PinConfig,
+    stepping_mode: String,
+    distance_per_step: f64,
+    steps_per_revolution: u64,
+    bounds: StepperBounds,
+    positive_direction: String,
+    #[serde(default)]
+    motors: Vec<StepperMotorConfig>,
+}
+
+impl StepperConfig {
+    pub fn get_step(&self) -> PinConfig {
+        self.step.clone()
+    }
+    pub fn get_dir(&self) -> PinConfig {
+        self.dir.clone()
+    }
+    pub fn get_stepping_mode(&self) -> String {
+        self.stepping_mode.clone()
+    }
+    pub fn get_distance_per_step(&self) -> f64 {
+        self.distance_per_step
+    }
+    pub fn get_steps_per_revolution(&self) -> u64 {
+        self.steps_per_revolution
+    }
+    pub fn get_bounds(&self) -> StepperBounds {
+        self.bounds
+    }
+    pub fn get_positive_direction(&self) -> String {
+        self.positive_direction.clone()
+    }
+    pub fn get_motors(&self) -> Vec<StepperMotorConfig> {
+        self.motors.clone()
+    }
+}
+
+// a motor driving the axis along with the main one, the endstop is optional
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct StepperMotorConfig {
+    step: PinConfig,
+    dir: PinConfig,
+    #[serde(default)]
+    endstop: EndstopPartConfig,
+}
+
+impl StepperMotorConfig {
+    pub fn get_step(&self) -> PinConfig {
+        self.step.clone()
+    }
+    pub fn get_dir(&self) -> PinConfig {
+        self.dir.clone()
+    }
+    pub fn get_endstop(&self) -> EndstopPartConfig {
+        self.endstop.clone()
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct StepperConfigs {
+    x: StepperConfig,
+    y: StepperConfig,
+    z: StepperConfig,
+    e: StepperConfig,
+}
+
+impl StepperConfigs {
+    pub fn get_x(&self) -> StepperConfig {
+        self.x.clone()
+    }
+    pub fn get_y(&self) -> StepperConfig {
+        self.y.clone()
+    }
+    pub fn get_z(&self) -> StepperConfig {
+        self.z.clone()
+    }
+    pub fn get_e(&self) -> StepperConfig {
+        self.e.clone()
+    }
+}
+
+/* UART */
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct UartPartConfig {
+    pin: String,
+    dma: PeripheralConfig,
+}
+
+impl UartPartConfig {
+    pub fn get_pin(&self) -> Option<String> {
+        get_string_value(self.pin.clone())
+    }
+
+    pub fn get_dma(&self) -> PeripheralConfig {
+        self.dma.clone()
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct UartConfig {
+    peripheral: String,
+    baudrate: u64,
+    rx: UartPartConfig,
+    tx: UartPartConfig,
+}
+
+impl UartConfig {
+    pub fn get_peripheral(&self) -> Option<String> {
+        get_string_value(self.peripheral.clone())
+    }
+
+    pub fn get_baudrate(&self) -> u64 {
+        self.baudrate
+    }
+
+    pub fn get_tx(&self) -> UartPartConfig {
+        self.tx.clone()
+    }
+
+    pub fn get_rx(&self) -> UartPartConfig {
+        self.rx.clone()
+    }
+}
+
+/* TMC drivers */
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct DmaConfig {
+    dma: PeripheralConfig,
+}
+
+impl DmaConfig {
+    pub fn get_dma(&self) -> PeripheralConfig {
+        self.dma.clone()
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct DriversUartConfig {
+    peripheral: String,
+    baudrate: u64,
+    pin: String,
+    rx: DmaConfig,
+    tx: DmaConfig,
+}
+
+impl DriversUartConfig {
+    pub fn get_peripheral(&self) -> Option<String> {
+        get_string_value(self.peripheral.clone())
+    }
+
+    pub fn get_baudrate(&self) -> u64 {
+        self.baudrate
+    }
+
+    pub fn get_pin(&self) -> Option<String> {
+        get_string_value(self.pin.clone())
+    }
+
+    pub fn get_rx(&self) -> DmaConfig {
+        self.rx.clone()
+    }
+
+    pub fn get_tx(&self) -> DmaConfig {
+        self.tx.clone()
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct DriverConfig {
+    address: u8,
+    r_sense: f64,
+    run_current: f64,
+    hold_multiplier: f64,
+    stealth_chop: bool,
+    hybrid_threshold: f64,
+    stall_threshold: u8,
+    homing_current: f64,
+}
+
+impl DriverConfig {
+    pub fn get_address(&self) -> u8 {
+        self.address
+    }
+
+    pub fn get_r_sense(&self) -> f64 {
+        self.r_sense
+    }
+
+    pub fn get_run_current(&self) -> f64 {
+        self.run_current
+    }
+
+    pub fn get_hold_multiplier(&self) -> f64 {
+        self.hold_multiplier
+    }
+
+    pub fn get_stealth_chop(&self) -> bool {
+        self.stealth_chop
+    }
+
+    // 0 disables the hybrid mode
+    pub fn get_hybrid_threshold(&self) -> Option<f64> {
+        (self.hybrid_threshold > 0.0).then_some(self.hybrid_threshold)
+    }
+
+    pub fn get_stall_threshold(&self) -> u8 {
+        self.stall_threshold
+    }
+
+    // 0 keeps the run current while homing
+    pub fn get_homing_current(&self) -> Option<f64> {
+        (self.homing_current > 0.0).then_some(self.homing_current)
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct DriversConfig {
+    uart: DriversUartConfig,
+    x: DriverConfig,
+    y: DriverConfig,
+    z: DriverConfig,
+    e: DriverConfig,
+}
+
+impl DriversConfig {
+    pub fn get_uart(&self) -> DriversUartConfig {
+        self.uart.clone()
+    }
+    pub fn get_x(&self) -> DriverConfig {
+        self.x
+    }
+    pub fn get_y(&self) -> DriverConfig {
+        self.y
+    }
+    pub fn get_z(&self) -> DriverConfig {
+        self.z
+    }
+    pub fn get_e(&self) -> DriverConfig {
+        self.e
+    }
+}
+
+/* ADC */
+// [ThermalActuator.adc]
+// peripheral = "ADC1"
+
+// [ThermalActuator.adc.input]
+// pin = "PA1"
+
+// [ThermalActuator.adc.dma]
+// peripheral = ""
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct AdcConfig {
+    pub peripheral: String,
+    pub dma: PeripheralConfig,
+}
+
+impl AdcConfig {
+    pub fn get_peripheral(&self) -> Option<String> {
+        get_string_value(self.peripheral.clone())
+    }
+
+    pub fn get_dma(&self) -> PeripheralConfig {
+        self.dma.clone()
+    }
+}
+
+// [ThermalActuator.pwm]
+// frequency=0
+
+// [ThermalActuator.pwm.timer]
+// peripheral = ""
+
+// [ThermalActuator.pwm.channel0]
+// pin = ""
+
+// [ThermalActuator.pwm.channel1]
+// pin = ""
+
+// [ThermalActuator.pwm.channel2]
+// pin = ""
+
+// [ThermalActuator.pwm.channel3]
+// pin = ""
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct PwmConfig {
+    frequency: u64,
+    timer: String,
+    ch1: String,
+    ch2: String,
+    ch3: String,
+}
+
+impl PwmConfig {
+    pub fn get_frequency(&self) -> u64 {
+        self.frequency
+    }
+
+    pub fn get_timer(&self) -> Option<String> {
+        get_string_value(self.timer.clone())
+    }
+
+    pub fn get_ch1(&self) -> Option<String> {
+        get_string_value(self.ch1.clone())
+    }
+
+    pub fn get_ch2(&self) -> Option<String> {
+        get_string_value(self.ch2.clone())
+    }
+
+    pub fn get_ch3(&self) -> Option<String> {
+        get_string_value(self.ch3.clone())
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct PwmOutputConfig {
+    channel: u8,
+}
+
+impl PwmOutputConfig {
+    pub fn get_channel(&self) -> u8 {
+        self.channel
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct SpiConfig {
+    peripheral: String,
+    clk: PeripheralConfig,
+    mosi: PinConfig,
+    miso: PinConfig,
+    cs: PinConfig,
+}
+
+impl SpiConfig {
+    pub fn get_peripheral(&self) -> Option<String> {
+        get_string_value(self.peripheral.clone())
+    }
+
+    pub fn get_clk(&self) -> &PeripheralConfig {
+        &self.clk
+    }
+
+    pub fn get_mosi(&self) -> &PinConfig {
+        &self.mosi
+    }
+    pub fn get_miso(&self) -> &PinConfig {
+        &self.miso
+    }
+    pub fn get_cs(&self) -> &PinConfig {
+        &self.cs
+    }
+}
+
+// [ThermalActuator.heater.pid]
+// k_p = 0
+// k_i = 0
+// k_d = 0
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct PidConfig {
+    k_p: f64,
+    k_i: f64,
+    k_d: f64,
+}
+
+impl PidConfig {
+    pub fn get_k_p(&self) -> f64 {
+        self.k_p
+    }
+
+    pub fn get_k_i(&self) -> f64 {
+        self.k_i
+    }
+    pub fn get_k_d(&self) -> f64 {
+        self.k_d
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct ThermalActuatorConfig {
+    pub thermistor: ThermistorConfig,
+    pub heater: HeaterConfig,
+}
+
+impl ThermalActuatorConfig {
+    pub fn get_thermistor(&self) -> ThermistorConfig {
+        self.thermistor.clone()
+    }
+
+    pub fn get_heater(&self) -> HeaterConfig {
+        self.heater.clone()
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct HeaterConfig {
+    pub pwm: PwmOutputConfig,
+    pub pid: PidConfig,
+    pub min_temperature_limit: f64,
+    pub max_temperature_limit: f64,
+}
+
+impl HeaterConfig {
+    pub fn get_pid(&self) -> PidConfig {
+        self.pid
+    }
+    pub fn get_max_temperature_limit(&self) -> f64 {
+        self.max_temperature_limit
+    }
+    pub fn get_min_temperature_limit(&self) -> f64 {
+        self.min_temperature_limit
+    }
+    pub fn get_pwm(&self) -> PwmOutputConfig {
+        self.pwm
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct ThermistorConfig {
+    pub r_series: f64,
+    pub r0: f64,
+    pub b: f64,
+    pub samples: u64,
+    #[serde(default)]
+    pub adc: PinConfig,
+}
+
+impl ThermistorConfig {
+    pub fn get_r_series(&self) -> f64 {
+        self.r_series
+    }
+
+    pub fn get_r0(&self) -> f64 {
+        self.r0
+    }
+
+    pub fn get_b(&self) -> f64 {
+        self.b
+    }
+
+    pub fn get_samples(&self) -> u64 {
+        self.samples
+    }
+
+    pub fn get_adc(&self) -> PinConfig {
+        self.adc.clone()
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct FanConfig {
+    max_speed: f64,
+    pwm: PwmOutputConfig,
+}
+
+impl FanConfig {
+    pub fn get_pwm(&self) -> PwmOutputConfig {
+        self.pwm
+    }
+    pub fn get_max_speed(&self) -> f64 {
+        self.max_speed
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct ServoConfig {
+    #[serde(default)]
+    timer: String,
+    #[serde(default)]
+    pin: String,
+    min_pulse: u64,
+    max_pulse: u64,
+    max_angle: f64,
+}
+
+impl ServoConfig {
+    pub fn get_timer(&self) -> Option<String> {
+        get_string_value(self.timer.clone())
+    }
+    pub fn get_pin(&self) -> Option<String> {
+        get_string_value(self.pin.clone())
+    }
+    pub fn get_min_pulse(&self) -> u64 {
+        self.min_pulse
+    }
+    pub fn get_max_pulse(&self) -> u64 {
+        self.max_pulse
+    }
+    pub fn get_max_angle(&self) -> f64 {
+        self.max_angle
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct SdCardConfig {
+    pub spi: SpiConfig,
+}
+
+impl SdCardConfig {
+    pub fn get_spi(&self) -> SpiConfig {
+        self.spi.clone()
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct PreflightConfig {
+    enabled: bool,
+    min_extrusion_temperature: f64,
+}
+
+impl PreflightConfig {
+    pub fn get_enabled(&self) -> bool {
+        self.enabled
+    }
+    pub fn get_min_extrusion_temperature(&self) -> f64 {
+        self.min_extrusion_temperature
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct DebugConfig {
+    alive_led: PinConfig,
+}
+
+impl DebugConfig {
+    pub fn get_alive_led(&self) -> PinConfig {
+        self.alive_led.clone()
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct Config {
+    pub steppers: StepperConfigs,
+    #[serde(default)]
+    pub pwm: PwmConfig,
+    #[serde(default)]
+    pub uart: UartConfig,
+    #[serde(default)]
+    pub adc: AdcConfig,
+    pub hotend: ThermalActuatorConfig,
+    pub heatbed: ThermalActuatorConfig,
+    #[serde(default)]
+    pub fan: FanConfig,
+    #[serde(default)]
+    pub servo: Option<ServoConfig>,
+    #[serde(default)]
+    pub sdcard: SdCardConfig,
+    pub motion: MotionConfig,
+    #[serde(default)]
+    pub debug: DebugConfig,
+    #[serde(default)]
+    pub drivers: DriversConfig,
+    pub preflight: PreflightConfig,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        Self::parse(&content)
+    }
+
+    // the values are checked as well, see Config::check
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let config: Config = toml::from_str(content).map_err(|e| e.to_string())?;
+        config.check()?;
+        Ok(config)
+    }
+}
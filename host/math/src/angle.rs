@@ -1,4 +1,5 @@
 pub use measurements::Angle;
+#[cfg(not(test))]
 use micromath::F32Ext;
 
 /*
@@ -0,0 +1,218 @@
+use measurements::Distance;
+
+use crate::{common::precise_sqrt, vector::Vector2D};
+
+// the flattening never takes a step shorter than this, so that a zero tolerance still ends
+const MIN_STEP: f64 = 1.0 / 4096.0;
+
+// cubic Bézier curve from start to end, pulled by the two control points
+#[derive(Clone, Copy)]
+pub struct CubicBezier {
+    start: Vector2D<Distance>,
+    control_1: Vector2D<Distance>,
+    control_2: Vector2D<Distance>,
+    end: Vector2D<Distance>,
+}
+
+impl CubicBezier {
+    pub fn new(
+        start: Vector2D<Distance>,
+        control_1: Vector2D<Distance>,
+        control_2: Vector2D<Distance>,
+        end: Vector2D<Distance>,
+    ) -> Self {
+        Self {
+            start,
+            control_1,
+            control_2,
+            end,
+        }
+    }
+
+    pub fn get_start(&self) -> Vector2D<Distance> {
+        self.start
+    }
+
+    pub fn get_end(&self) -> Vector2D<Distance> {
+        self.end
+    }
+
+    // point of the curve at t, from 0 (start) to 1 (end)
+    pub fn point_at(&self, t: f64) -> Vector2D<Distance> {
+        let u = 1.0 - t;
+        self.start * (u * u * u)
+            + self.control_1 * (3.0 * u * u * t)
+            + self.control_2 * (3.0 * u * t * t)
+            + self.end * (t * t * t)
+    }
+
+    // length of the second derivative at t, in mm
+    fn second_derivative(&self, t: f64) -> f64 {
+        let a = self.control_2 - self.control_1 * 2.0 + self.start;
+        let b = self.end - self.control_2 * 2.0 + self.control_1;
+        let v = (a * (1.0 - t) + b * t) * 6.0;
+        let (x, y) = (v.get_x().as_millimeters(), v.get_y().as_millimeters());
+        precise_sqrt(x * x + y * y)
+    }
+
+    // the chord from t to t + step is at most step^2 / 8 times the largest second derivative
+    // along it away from the curve. The second derivative is linear in t, its largest value on the
+    // step is at one of its ends
+    fn step(&self, t: f64, tolerance: f64) -> f64 {
+        let max_step = 1.0 - t;
+        let step_for = |acceleration: f64| {
+            if acceleration > 0.0 {
+                precise_sqrt(8.0 * tolerance / acceleration).min(max_step)
+            } else {
+                max_step
+            }
+        };
+        let a0 = self.second_derivative(t);
+        let step = step_for(a0);
+        let a1 = self.second_derivative(t + step);
+        step_for(a0.max(a1)).max(MIN_STEP.min(max_step))
+    }
+
+    // the points ending the chords the curve is split into, each one within tolerance from the
+    // curve. Straight parts get long chords, tight bends short ones. The start is not included,
+    // the last point is the end
+    pub fn flatten(&self, tolerance: Distance) -> Flatten {
+        Flatten {
+            curve: *self,
+            tolerance: tolerance.as_millimeters(),
+            t: 0.0,
+        }
+    }
+}
+
+pub struct Flatten {
+    curve: CubicBezier,
+    tolerance: f64,
+    t: f64,
+}
+
+impl Iterator for Flatten {
+    // parameter of the point and the point
+    type Item = (f64, Vector2D<Distance>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.t >= 1.0 {
+            return None;
+        }
+        self.t = (self.t + self.curve.step(self.t, self.tolerance)).min(1.0);
+        if self.t >= 1.0 {
+            Some((1.0, self.curve.end))
+        } else {
+            Some((self.t, self.curve.point_at(self.t)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_abs_diff_eq;
+    use measurements::Distance;
+
+    use super::CubicBezier;
+    use crate::vector::Vector2D;
+
+    fn point(x: f64, y: f64) -> Vector2D<Distance> {
+        Vector2D::new(Distance::from_millimeters(x), Distance::from_millimeters(y))
+    }
+
+    // B(t) = (1-t)^3 P0 + 3(1-t)^2 t P1 + 3(1-t) t^2 P2 + t^3 P3, with P0 (0, 0), P1 (0, 40),
+    // P2 (60, 40) and P3 (60, 0)
+    fn analytic(t: f64) -> (f64, f64) {
+        let x = 3.0 * (1.0 - t) * t * t * 60.0 + t * t * t * 60.0;
+        let y = 3.0 * (1.0 - t) * (1.0 - t) * t * 40.0 + 3.0 * (1.0 - t) * t * t * 40.0;
+        (x, y)
+    }
+
+    fn curve() -> CubicBezier {
+        CubicBezier::new(
+            point(0.0, 0.0),
+            point(0.0, 40.0),
+            point(60.0, 40.0),
+            point(60.0, 0.0),
+        )
+    }
+
+    // distance of p from the segment from a to b
+    fn distance_from_chord(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let length = dx * dx + dy * dy;
+        let t = if length > 0.0 {
+            (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / length).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let (x, y) = (a.0 + t * dx, a.1 + t * dy);
+        ((p.0 - x).powi(2) + (p.1 - y).powi(2)).sqrt()
+    }
+
+    #[test]
+    fn test_bezier_point_at() {
+        let curve = curve();
+        for n in 0..=10 {
+            let t = n as f64 / 10.0;
+            let (x, y) = analytic(t);
+            let p = curve.point_at(t);
+            assert_abs_diff_eq!(p.get_x().as_millimeters(), x, epsilon = 1e-9);
+            assert_abs_diff_eq!(p.get_y().as_millimeters(), y, epsilon = 1e-9);
+        }
+        assert_abs_diff_eq!(
+            curve.point_at(0.5).get_y().as_millimeters(),
+            30.0,
+            epsilon = 1e-9
+        );
+    }
+
+    #[test]
+    fn test_bezier_flatten() {
+        let curve = curve();
+        let mut counts = Vec::new();
+        for tolerance in [0.1, 0.01, 0.001] {
+            let mut previous = (0.0, (0.0, 0.0));
+            let mut segments = 0;
+            for (t, p) in curve.flatten(Distance::from_millimeters(tolerance)) {
+                let p = (p.get_x().as_millimeters(), p.get_y().as_millimeters());
+                // the points are on the curve
+                let (x, y) = analytic(t);
+                assert_abs_diff_eq!(p.0, x, epsilon = 1e-9);
+                assert_abs_diff_eq!(p.1, y, epsilon = 1e-9);
+                assert!(t > previous.0);
+                // the curve between them stays within tolerance from the chord
+                for n in 1..20 {
+                    let s = previous.0 + (t - previous.0) * n as f64 / 20.0;
+                    assert!(distance_from_chord(analytic(s), previous.1, p) <= tolerance);
+                }
+                previous = (t, p);
+                segments += 1;
+            }
+            assert_eq!(previous.0, 1.0);
+            assert_eq!(previous.1, (60.0, 0.0));
+            counts.push(segments as f64);
+        }
+        // the chords get shorter as the tolerance gets tighter, by about sqrt(10) each time
+        for pair in counts.windows(2) {
+            assert!((2.8..3.5).contains(&(pair[1] / pair[0])));
+        }
+        // a zero tolerance still ends
+        let points = curve.flatten(Distance::from_millimeters(0.0)).count();
+        assert_eq!(points, 4096);
+    }
+
+    #[test]
+    fn test_bezier_flatten_straight() {
+        // control points along the line, the curve is the line itself
+        let curve = CubicBezier::new(
+            point(0.0, 0.0),
+            point(10.0, 10.0),
+            point(20.0, 20.0),
+            point(30.0, 30.0),
+        );
+        let points: Vec<_> = curve.flatten(Distance::from_millimeters(0.01)).collect();
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0].0, 1.0);
+    }
+}
@@ -1,5 +1,6 @@
 use core::{f64::consts::PI, time::Duration};
 use measurements::{AngularVelocity, Distance, Resistance, Speed, Temperature};
+#[cfg(not(test))]
 use micromath::F32Ext;
 
 use crate::{
@@ -51,10 +52,56 @@ pub fn abs(value: f64) -> f64 {
     v
 }
 
+// round half away from zero, f64::round is not available without std
+pub fn round(value: f64) -> f64 {
+    let truncated = value as i64 as f64;
+    let fraction = value - truncated;
+    if fraction >= 0.5 {
+        truncated + 1.0
+    } else if fraction <= -0.5 {
+        truncated - 1.0
+    } else {
+        truncated
+    }
+}
+
 pub fn sqrt(value: f64) -> f64 {
     (value as f32).sqrt() as f64
 }
 
+// the square root above is off by a few percent, which is too much for geometry. A few Newton
+// iterations make it exact enough
+pub fn precise_sqrt(value: f64) -> f64 {
+    let mut root = sqrt(value);
+    for _ in 0..3 {
+        if root > 0.0 {
+            root = (root + value / root) / 2.0;
+        }
+    }
+    root
+}
+
+// the exponential of micromath is as coarse as its square root. The series converges fast for
+// small values, the larger ones are halved down and the result squared back up
+pub fn exp(value: f64) -> f64 {
+    let mut reduced = value;
+    let mut squarings = 0;
+    while abs(reduced) > 0.5 && squarings < 64 {
+        reduced /= 2.0;
+        squarings += 1;
+    }
+    let mut term = 1.0;
+    let mut result = 1.0;
+    for n in 1..16 {
+        term *= reduced / n as f64;
+        result += term;
+    }
+    for _ in 0..squarings {
+        result *= result;
+    }
+    result
+}
+
 // get distance per step from pulley's radius
 // used for X/Y axis
 pub fn dps_from_radius(r: Distance, steps_per_revolution: u64) -> Option<Distance> {
@@ -170,7 +217,7 @@ pub fn compute_arc_destination(
         return start;
     }
 
-    let l = -1.0 * f64::from(i8::from(direction)) * arc_length;
+    let l = -f64::from(i8::from(direction)) * arc_length;
 
     let angle = Angle::from_radians(l / radius);
 
@@ -204,7 +251,7 @@ mod tests {
 
     use crate::{
         common::{
-            angular_velocity_from_steps, compute_arc_length, compute_step_duration,
+            angular_velocity_from_steps, compute_arc_length, compute_step_duration, exp, round,
             speed_from_angular_velocity, RotationDirection,
         },
         vector::Vector2D,
@@ -537,6 +584,22 @@ mod tests {
         let l = compute_arc_length(start, center, end, RotationDirection::Clockwise, true);
         assert_abs_diff_eq!(l.as_millimeters(), 2.0 * PI, epsilon = 0.000001);
     }
+
+    #[test]
+    fn test_round() {
+        assert_eq!(round(2.4), 2.0);
+        assert_eq!(round(2.5), 3.0);
+        assert_eq!(round(-2.5), -3.0);
+        assert_eq!(round(-2.4), -2.0);
+        assert_eq!(round(0.0), 0.0);
+    }
+
+    #[test]
+    fn test_exp() {
+        for value in [-20.0, -2.5, -0.3, 0.0, 0.1, 1.0, 7.0] {
+            assert_abs_diff_eq!(exp(value) / f64::exp(value), 1.0, epsilon = 0.000000001);
+        }
+    }
 }
 
 // pub struct StopWatch {
@@ -3,6 +3,7 @@
 pub use measurements;
 
 pub mod angle;
+pub mod bezier;
 pub mod common;
 pub mod pid;
 pub mod vector;
@@ -25,3 +26,22 @@ pub enum DistanceUnit {
     Millimeter,
     Inch,
 }
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+    E,
+}
+
+impl core::fmt::Display for Axis {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match &self {
+            Axis::X => core::write!(f, "X"),
+            Axis::Y => core::write!(f, "Y"),
+            Axis::Z => core::write!(f, "Z"),
+            Axis::E => core::write!(f, "E"),
+        }
+    }
+}
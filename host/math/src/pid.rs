@@ -1,5 +1,10 @@
 use core::time::Duration;
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PidError {
+    TargetNotSet,
+}
+
 pub struct PID {
     kp: f64,
     ki: f64,
@@ -39,8 +44,8 @@ impl PID {
         self.bounds = Some((min, max));
     }
 
-    pub fn update(&mut self, current: f64, dt: Duration) -> Result<f64, ()> {
-        let target = self.target.ok_or(())?;
+    pub fn update(&mut self, current: f64, dt: Duration) -> Result<f64, PidError> {
+        let target = self.target.ok_or(PidError::TargetNotSet)?;
         let error = target - current;
 
         // Proportional term
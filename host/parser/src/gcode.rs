@@ -1,12 +1,8 @@
-use core::{
-    fmt::Write,
-    str::FromStr,
-    time::Duration,
-};
+use core::{str::FromStr, time::Duration};
 
 use heapless::{LinearMap, String, Vec};
 use math::{
-    measurements::{Distance, Speed, Temperature},
+    measurements::{Acceleration, Current, Distance, Speed, Temperature},
     DistanceUnit, DurationUnit, TemperatureUnit,
 };
 
@@ -40,7 +36,10 @@ pub enum GCommand {
         f: Option<Speed>,
         i: Option<Distance>,
         j: Option<Distance>,
+        k: Option<Distance>,
         r: Option<Distance>,
+        // full circles added to the arc
+        p: Option<u8>,
     },
     G3 {
         x: Option<Distance>,
@@ -50,17 +49,55 @@ pub enum GCommand {
         f: Option<Speed>,
         i: Option<Distance>,
         j: Option<Distance>,
+        k: Option<Distance>,
         r: Option<Distance>,
+        // full circles added to the arc
+        p: Option<u8>,
     },
     // dwell
     G4 {
         p: Option<Duration>,
         s: Option<Duration>,
     },
+    // cubic Bézier curve, i j are the offsets of the first control point from the start and
+    // p q the ones of the second control point from the end
+    G5 {
+        x: Option<Distance>,
+        y: Option<Distance>,
+        e: Option<Distance>,
+        f: Option<Speed>,
+        i: Option<Distance>,
+        j: Option<Distance>,
+        p: Option<Distance>,
+        q: Option<Distance>,
+    },
     // retract
     G10,
+    // set the offsets of the coordinate system p, from 1 (G54) to 6 (G59) or 0 for the one in
+    // use, so that its origin is at x y z in machine coordinates
+    #[allow(non_camel_case_types)]
+    G10_L2 {
+        p: Option<u8>,
+        x: Option<Distance>,
+        y: Option<Distance>,
+        z: Option<Distance>,
+    },
+    // set the offsets of the coordinate system p so that the head is at x y z in it
+    #[allow(non_camel_case_types)]
+    G10_L20 {
+        p: Option<u8>,
+        x: Option<Distance>,
+        y: Option<Distance>,
+        z: Option<Distance>,
+    },
     // recover
     G11,
+    // select the XY plane for arcs
+    G17,
+    // select the ZX plane for arcs
+    G18,
+    // select the YZ plane for arcs
+    G19,
     // set distance unit to inches
     G20,
     // set distance unit to millimeters
@@ -71,17 +108,48 @@ pub enum GCommand {
         y: bool,
         z: bool,
     },
+    // probe the bed mesh, print it instead with t
+    G29 {
+        t: bool,
+    },
+    // probe the bed once at x y, at the current position without them
+    G30 {
+        x: Option<Distance>,
+        y: Option<Distance>,
+    },
+    // align the Z motors, at most i times until their heights are within t
+    G34 {
+        i: Option<u8>,
+        t: Option<Distance>,
+    },
+    // linear move in machine coordinates, G53 G0 X10 or G53 G1 X10. The offsets are ignored
+    G53 {
+        x: Option<Distance>,
+        y: Option<Distance>,
+        z: Option<Distance>,
+        f: Option<Speed>,
+    },
+    // select the coordinate systems from 1 to 6
+    G54,
+    G55,
+    G56,
+    G57,
+    G58,
+    G59,
     // set positioning as absolute
     G90,
     // set positioning as relative
     G91,
-    // set steppers position
+    // set the position of the head in the coordinate system in use, and the one of E
     G92{
         x: Option<Distance>,
         y: Option<Distance>,
         z: Option<Distance>,
         e: Option<Distance>,
     },
+    // clear the offsets set by G92
+    #[allow(non_camel_case_types)]
+    G92_1,
     // List SD Card files
     M20,
     // Init SD card (mount fs)
@@ -103,6 +171,12 @@ pub enum GCommand {
     M27,
     // report print time
     M31,
+    // probe repeatability test: probe p times at x y and report the statistics
+    M48 {
+        p: Option<u8>,
+        x: Option<Distance>,
+        y: Option<Distance>,
+    },
     // E absolute
     M82,
     // E relative
@@ -126,6 +200,8 @@ pub enum GCommand {
     },
     // report position
     M114,
+    // report stepper drivers status
+    M122,
     // report fan speed
     // optional duration s
     M123 {
@@ -156,12 +232,43 @@ pub enum GCommand {
         r: Temperature,
         s: Temperature,
     },
-    // [future] set max feedrate
+    // set the diameter d of the filament, E is then a volume in mm³ (0 makes it a length again)
+    M200 {
+        d: Option<Distance>,
+    },
+    // set the max acceleration of the axes, in units/s²
+    M201 {
+        x: Option<Acceleration>,
+        y: Option<Acceleration>,
+        z: Option<Acceleration>,
+        e: Option<Acceleration>,
+    },
+    // set the max feedrate of the axes, in units/s
     M203 {
-        x: Speed,
-        y: Speed,
-        z: Speed,
-        e: Speed,
+        x: Option<Speed>,
+        y: Option<Speed>,
+        z: Option<Speed>,
+        e: Option<Speed>,
+    },
+    // set the acceleration of the printing moves (p), of the retractions (r) and of the travels
+    // (t), in units/s²
+    M204 {
+        p: Option<Acceleration>,
+        r: Option<Acceleration>,
+        t: Option<Acceleration>,
+    },
+    // set the jerk of the axes, in units/s
+    M205 {
+        x: Option<Speed>,
+        y: Option<Speed>,
+        z: Option<Speed>,
+        e: Option<Speed>,
+    },
+    // set home offsets, added to the machine position once the axis is homed
+    M206 {
+        x: Option<Distance>,
+        y: Option<Distance>,
+        z: Option<Distance>,
     },
     // firmware retraction settings
     M207 {
@@ -174,16 +281,99 @@ pub enum GCommand {
         f: Speed,
         s: Distance,
     },
+    // enable (s = true) or disable (s = false) the software endstops, report them without s
+    M211 {
+        s: Option<bool>,
+    },
     // set feedrate multiplier
     M220 {
         s: f64,
     },
-    // set flow multiplier
+    // set flow multiplier, given as a percentage by S
     M221 {
         s: f64,
     },
+    // move the servo p to the angle s, in degrees. Its angle is reported without s
+    M280 {
+        p: u8,
+        s: Option<f64>,
+    },
+    // enable (s = true) or disable (s = false) the bed leveling, z sets the fade height.
+    // The state of the leveling is reported
+    M420 {
+        s: Option<bool>,
+        z: Option<Distance>,
+    },
+    // set the backlash of the axes, the correction factor f (0 to 1) and the smoothing distance s.
+    // The backlash is reported
+    M425 {
+        f: Option<f64>,
+        s: Option<Distance>,
+        x: Option<Distance>,
+        y: Option<Distance>,
+        z: Option<Distance>,
+    },
+    // report the motion settings
+    M503,
     // abort sd print
     M524,
+    // set stepper driver chopper mode: StealthChop (s = true) or SpreadCycle (s = false)
+    M569 {
+        s: bool,
+        x: bool,
+        y: bool,
+        z: bool,
+        e: bool,
+    },
+    // set the input shaping of x and/or y (both without them): the type t (0 ZV, 1 MZV, 2 ZVD,
+    // 3 EI, 4 2HUMP_EI), the frequency f in Hz (0 disables it) and the damping ratio d.
+    // The input shaping is reported
+    M593 {
+        d: Option<f64>,
+        f: Option<f64>,
+        t: Option<u8>,
+        x: bool,
+        y: bool,
+    },
+    // set the position of the probe from the nozzle, report it without arguments
+    M851 {
+        x: Option<Distance>,
+        y: Option<Distance>,
+        z: Option<Distance>,
+    },
+    // set the skew factors of the XY (i, or s), XZ (j) and YZ (k) planes, report them without
+    // arguments
+    M852 {
+        i: Option<f64>,
+        j: Option<f64>,
+        k: Option<f64>,
+    },
+    // set the linear advance factor k of the extruder, in seconds (0 disables it). The linear
+    // advance is reported
+    M900 {
+        k: Option<f64>,
+    },
+    // set stepper driver run current
+    M906 {
+        x: Option<Current>,
+        y: Option<Current>,
+        z: Option<Current>,
+        e: Option<Current>,
+    },
+    // set stepper driver hybrid threshold (StealthChop to SpreadCycle)
+    M913 {
+        x: Option<Speed>,
+        y: Option<Speed>,
+        z: Option<Speed>,
+        e: Option<Speed>,
+    },
+    // set stepper driver stall sensitivity, used by sensorless homing
+    // 0 to 255, 255 -> most sensitive
+    M914 {
+        x: Option<u8>,
+        y: Option<u8>,
+        z: Option<u8>,
+    },
 }
 
 fn extract_speed(cmd: &LinearMap<char, Option<&str>, 16>, key: char, unit: DistanceUnit) -> Option<Speed> {
@@ -191,6 +381,31 @@ fn extract_speed(cmd: &LinearMap<char, Option<&str>, 16>, key: char, unit: Dista
     Some(Speed::from_meters_per_second(distance.as_meters() / 60.0))
 }
 
+fn extract_speed_per_second(
+    cmd: &LinearMap<char, Option<&str>, 16>,
+    key: char,
+    unit: DistanceUnit,
+) -> Option<Speed> {
+    let distance = extract_distance(cmd, key, unit)?;
+    Some(Speed::from_meters_per_second(distance.as_meters()))
+}
+
+fn extract_acceleration(
+    cmd: &LinearMap<char, Option<&str>, 16>,
+    key: char,
+    unit: DistanceUnit,
+) -> Option<Acceleration> {
+    let distance = extract_distance(cmd, key, unit)?;
+    Some(Acceleration::from_meters_per_second_per_second(
+        distance.as_meters(),
+    ))
+}
+
+fn extract_current(cmd: &LinearMap<char, Option<&str>, 16>, key: char) -> Option<Current> {
+    let value = extract_token_as_number(cmd, key)?;
+    Some(Current::from_milliamperes(value))
+}
+
 fn extract_distance(
     cmd: &LinearMap<char, Option<&str>, 16>,
     key: char,
@@ -235,6 +450,15 @@ fn extract_token_as_number(cmd: &LinearMap<char, Option<&str>, 16>, key: char) -
     }
 }
 
+fn extract_token_as_u8(cmd: &LinearMap<char, Option<&str>, 16>, key: char) -> Option<u8> {
+    let value = extract_token_as_number(cmd, key)?;
+    if (0f64..=255f64).contains(&value) {
+        Some(value as u8)
+    } else {
+        None
+    }
+}
+
 fn extract_token_as_string<'a>(cmd: &'a LinearMap<char, Option<&str>, 16>, key: char) -> Option<&'a str> {
     match cmd.get(&key) {
         Some(t) => *t,
@@ -242,6 +466,26 @@ fn extract_token_as_string<'a>(cmd: &'a LinearMap<char, Option<&str>, 16>, key:
     }
 }
 
+// the line holds no command, only blanks and comments
+pub fn is_blank(line: &str) -> bool {
+    let mut state = ParserState::ReadingCommand;
+    for b in line.chars() {
+        match state {
+            ParserState::ReadingCommand => match b {
+                ';' | '(' => state = ParserState::ReadingComment(b),
+                _ if b.is_whitespace() => (),
+                _ => return false,
+            },
+            ParserState::ReadingComment(start) => {
+                if (start == '(' && b == ')') || (start == ';' && b == ';') {
+                    state = ParserState::ReadingCommand;
+                }
+            }
+        }
+    }
+    true
+}
+
 #[derive(Clone, Copy)]
 enum ParserState {
     ReadingCommand,
@@ -325,9 +569,14 @@ impl GCodeParser {
         if cmd_type.len() < 2 {
             return None;
         }
+        // the code can be followed by a subcode, as in G92.1
+        let (code, subcode) = match cmd_type.get(1..)?.split_once('.') {
+            Some((code, subcode)) => (code, Some(subcode.parse::<u64>().ok()?)),
+            None => (cmd_type.get(1..)?, None),
+        };
         let (prefix, code) = {
             let key = cmd_type.get(0..1)?.chars().next()?.to_ascii_uppercase();
-            let value = cmd_type.get(1..)?.parse::<u64>().ok()?;
+            let value = code.parse::<u64>().ok()?;
             match key {
                 'G' => (GCommandType::G, value),
                 'M' => (GCommandType::M, value),
@@ -335,6 +584,13 @@ impl GCodeParser {
             }
         };
 
+        if let Some(subcode) = subcode {
+            return match (prefix, code, subcode) {
+                (GCommandType::G, 92, 1) => Some(GCommand::G92_1),
+                _ => None,
+            };
+        }
+
         let mut args: LinearMap<char, Option<&str>, 16> = LinearMap::new();
 
         for t in tokens {
@@ -367,7 +623,9 @@ impl GCodeParser {
                 let f = extract_speed(&args, 'F', self.distance_unit);
                 let i = extract_distance(&args, 'I', self.distance_unit);
                 let j = extract_distance(&args, 'J', self.distance_unit);
+                let k = extract_distance(&args, 'K', self.distance_unit);
                 let r = extract_distance(&args, 'R', self.distance_unit);
+                let p = extract_token_as_u8(&args, 'P');
                 Some(GCommand::G2 {
                     x,
                     y,
@@ -376,7 +634,9 @@ impl GCodeParser {
                     f,
                     i,
                     j,
+                    k,
                     r,
+                    p,
                 })
             }
             (GCommandType::G, 3) => {
@@ -387,7 +647,9 @@ impl GCodeParser {
                 let f = extract_speed(&args, 'F', self.distance_unit);
                 let i = extract_distance(&args, 'I', self.distance_unit);
                 let j = extract_distance(&args, 'J', self.distance_unit);
+                let k = extract_distance(&args, 'K', self.distance_unit);
                 let r = extract_distance(&args, 'R', self.distance_unit);
+                let p = extract_token_as_u8(&args, 'P');
                 Some(GCommand::G3 {
                     x,
                     y,
@@ -396,7 +658,9 @@ impl GCodeParser {
                     f,
                     i,
                     j,
+                    k,
                     r,
+                    p,
                 })
             }
             (GCommandType::G, 4) => {
@@ -404,8 +668,43 @@ impl GCodeParser {
                 let s = extract_duration(&args, 'S', DurationUnit::Second);
                 Some(GCommand::G4 { p, s })
             }
-            (GCommandType::G, 10) => Some(GCommand::G10),
+            (GCommandType::G, 5) => {
+                let x = extract_distance(&args, 'X', self.distance_unit);
+                let y = extract_distance(&args, 'Y', self.distance_unit);
+                let e = extract_distance(&args, 'E', self.distance_unit);
+                let f = extract_speed(&args, 'F', self.distance_unit);
+                let i = extract_distance(&args, 'I', self.distance_unit);
+                let j = extract_distance(&args, 'J', self.distance_unit);
+                let p = extract_distance(&args, 'P', self.distance_unit);
+                let q = extract_distance(&args, 'Q', self.distance_unit);
+                Some(GCommand::G5 {
+                    x,
+                    y,
+                    e,
+                    f,
+                    i,
+                    j,
+                    p,
+                    q,
+                })
+            }
+            (GCommandType::G, 10) => {
+                let p = extract_token_as_u8(&args, 'P');
+                let x = extract_distance(&args, 'X', self.distance_unit);
+                let y = extract_distance(&args, 'Y', self.distance_unit);
+                let z = extract_distance(&args, 'Z', self.distance_unit);
+                // without L it's a retraction
+                match args.get(&'L') {
+                    None => Some(GCommand::G10),
+                    Some(Some("2")) => Some(GCommand::G10_L2 { p, x, y, z }),
+                    Some(Some("20")) => Some(GCommand::G10_L20 { p, x, y, z }),
+                    _ => None,
+                }
+            }
             (GCommandType::G, 11) => Some(GCommand::G11),
+            (GCommandType::G, 17) => Some(GCommand::G17),
+            (GCommandType::G, 18) => Some(GCommand::G18),
+            (GCommandType::G, 19) => Some(GCommand::G19),
             (GCommandType::G, 20) => Some(GCommand::G20),
             (GCommandType::G, 21) => Some(GCommand::G21),
             (GCommandType::G, 28) => {
@@ -424,6 +723,38 @@ impl GCodeParser {
                 }
                 Some(GCommand::G28 { x, y, z })
             }
+            (GCommandType::G, 29) => {
+                let t = args.contains_key(&'T');
+                Some(GCommand::G29 { t })
+            }
+            (GCommandType::G, 30) => {
+                let x = extract_distance(&args, 'X', self.distance_unit);
+                let y = extract_distance(&args, 'Y', self.distance_unit);
+                Some(GCommand::G30 { x, y })
+            }
+            (GCommandType::G, 34) => {
+                let i = extract_token_as_u8(&args, 'I');
+                let t = extract_distance(&args, 'T', self.distance_unit);
+                Some(GCommand::G34 { i, t })
+            }
+            (GCommandType::G, 53) => {
+                // the move that follows on the same line
+                match args.get(&'G') {
+                    None | Some(Some("0")) | Some(Some("1")) => (),
+                    _ => return None,
+                }
+                let x = extract_distance(&args, 'X', self.distance_unit);
+                let y = extract_distance(&args, 'Y', self.distance_unit);
+                let z = extract_distance(&args, 'Z', self.distance_unit);
+                let f = extract_speed(&args, 'F', self.distance_unit);
+                Some(GCommand::G53 { x, y, z, f })
+            }
+            (GCommandType::G, 54) => Some(GCommand::G54),
+            (GCommandType::G, 55) => Some(GCommand::G55),
+            (GCommandType::G, 56) => Some(GCommand::G56),
+            (GCommandType::G, 57) => Some(GCommand::G57),
+            (GCommandType::G, 58) => Some(GCommand::G58),
+            (GCommandType::G, 59) => Some(GCommand::G59),
             (GCommandType::G, 90) => Some(GCommand::G90),
             (GCommandType::G, 91) => Some(GCommand::G91),
             (GCommandType::G, 92) => {
@@ -448,6 +779,12 @@ impl GCodeParser {
             }),
             (GCommandType::M, 25) => Some(GCommand::M25),
             (GCommandType::M, 31) => Some(GCommand::M31),
+            (GCommandType::M, 48) => {
+                let p = extract_token_as_u8(&args, 'P');
+                let x = extract_distance(&args, 'X', self.distance_unit);
+                let y = extract_distance(&args, 'Y', self.distance_unit);
+                Some(GCommand::M48 { p, x, y })
+            }
             (GCommandType::M, 82) => Some(GCommand::M82),
             (GCommandType::M, 83) => Some(GCommand::M83),
             (GCommandType::M, 104) => {
@@ -471,6 +808,7 @@ impl GCodeParser {
                 Some(GCommand::M109 { s })
             }
             (GCommandType::M, 114) => Some(GCommand::M114),
+            (GCommandType::M, 122) => Some(GCommand::M122),
             (GCommandType::M, 123) => {
                 let s = extract_duration(&args, 'S', DurationUnit::Second);
                 Some(GCommand::M123 { s })
@@ -501,6 +839,43 @@ impl GCodeParser {
                 let s = extract_temperature(&args, 'S', self.temperature_unit)?;
                 Some(GCommand::M190 { s })
             }
+            (GCommandType::M, 200) => {
+                let d = extract_distance(&args, 'D', self.distance_unit);
+                Some(GCommand::M200 { d })
+            }
+            (GCommandType::M, 201) => {
+                let x = extract_acceleration(&args, 'X', self.distance_unit);
+                let y = extract_acceleration(&args, 'Y', self.distance_unit);
+                let z = extract_acceleration(&args, 'Z', self.distance_unit);
+                let e = extract_acceleration(&args, 'E', self.distance_unit);
+                Some(GCommand::M201 { x, y, z, e })
+            }
+            (GCommandType::M, 203) => {
+                let x = extract_speed_per_second(&args, 'X', self.distance_unit);
+                let y = extract_speed_per_second(&args, 'Y', self.distance_unit);
+                let z = extract_speed_per_second(&args, 'Z', self.distance_unit);
+                let e = extract_speed_per_second(&args, 'E', self.distance_unit);
+                Some(GCommand::M203 { x, y, z, e })
+            }
+            (GCommandType::M, 204) => {
+                let p = extract_acceleration(&args, 'P', self.distance_unit);
+                let r = extract_acceleration(&args, 'R', self.distance_unit);
+                let t = extract_acceleration(&args, 'T', self.distance_unit);
+                Some(GCommand::M204 { p, r, t })
+            }
+            (GCommandType::M, 205) => {
+                let x = extract_speed_per_second(&args, 'X', self.distance_unit);
+                let y = extract_speed_per_second(&args, 'Y', self.distance_unit);
+                let z = extract_speed_per_second(&args, 'Z', self.distance_unit);
+                let e = extract_speed_per_second(&args, 'E', self.distance_unit);
+                Some(GCommand::M205 { x, y, z, e })
+            }
+            (GCommandType::M, 206) => {
+                let x = extract_distance(&args, 'X', self.distance_unit);
+                let y = extract_distance(&args, 'Y', self.distance_unit);
+                let z = extract_distance(&args, 'Z', self.distance_unit);
+                Some(GCommand::M206 { x, y, z })
+            }
             (GCommandType::M, 207) => {
                 let f = extract_speed(&args, 'F', self.distance_unit)?;
                 let s = extract_distance(&args, 'S', self.distance_unit)?;
@@ -512,16 +887,107 @@ impl GCodeParser {
                 let s = extract_distance(&args, 'S', self.distance_unit)?;
                 Some(GCommand::M208 { f, s })
             }
+            (GCommandType::M, 211) => {
+                let s = extract_token_as_number(&args, 'S').map(|s| s != 0.0);
+                Some(GCommand::M211 { s })
+            }
             // set feedrate multiplier
             (GCommandType::M, 220) => {
                 let s = extract_token_as_number(&args, 'S')?;
                 Some(GCommand::M220 { s })
             }
             (GCommandType::M, 221) => {
-                let s = extract_token_as_number(&args, 'S')?;
+                let s = extract_token_as_number(&args, 'S')? / 100.0;
                 Some(GCommand::M221 { s })
             }
+            (GCommandType::M, 280) => {
+                let p = extract_token_as_u8(&args, 'P')?;
+                let s = extract_token_as_number(&args, 'S');
+                Some(GCommand::M280 { p, s })
+            }
+            (GCommandType::M, 420) => {
+                let s = extract_token_as_number(&args, 'S').map(|s| s != 0.0);
+                let z = extract_distance(&args, 'Z', self.distance_unit);
+                Some(GCommand::M420 { s, z })
+            }
+            (GCommandType::M, 425) => {
+                let f = extract_token_as_number(&args, 'F');
+                let s = extract_distance(&args, 'S', self.distance_unit);
+                let x = extract_distance(&args, 'X', self.distance_unit);
+                let y = extract_distance(&args, 'Y', self.distance_unit);
+                let z = extract_distance(&args, 'Z', self.distance_unit);
+                Some(GCommand::M425 { f, s, x, y, z })
+            }
+            (GCommandType::M, 503) => Some(GCommand::M503),
             (GCommandType::M, 524) => Some(GCommand::M524),
+            (GCommandType::M, 569) => {
+                let s = extract_token_as_number(&args, 'S')? != 0.0;
+                let (x, y, z, e) = (
+                    args.contains_key(&'X'),
+                    args.contains_key(&'Y'),
+                    args.contains_key(&'Z'),
+                    args.contains_key(&'E'),
+                );
+                // no axis means every axis
+                if !(x || y || z || e) {
+                    return Some(GCommand::M569 {
+                        s,
+                        x: true,
+                        y: true,
+                        z: true,
+                        e: true,
+                    });
+                }
+                Some(GCommand::M569 { s, x, y, z, e })
+            }
+            (GCommandType::M, 593) => {
+                let d = extract_token_as_number(&args, 'D');
+                let f = extract_token_as_number(&args, 'F');
+                let t = extract_token_as_u8(&args, 'T');
+                let (x, y) = (args.contains_key(&'X'), args.contains_key(&'Y'));
+                // no axis means both axes
+                let (x, y) = if x || y { (x, y) } else { (true, true) };
+                Some(GCommand::M593 { d, f, t, x, y })
+            }
+            (GCommandType::M, 851) => {
+                let x = extract_distance(&args, 'X', self.distance_unit);
+                let y = extract_distance(&args, 'Y', self.distance_unit);
+                let z = extract_distance(&args, 'Z', self.distance_unit);
+                Some(GCommand::M851 { x, y, z })
+            }
+            (GCommandType::M, 852) => {
+                let i = extract_token_as_number(&args, 'I')
+                    .or_else(|| extract_token_as_number(&args, 'S'));
+                let j = extract_token_as_number(&args, 'J');
+                let k = extract_token_as_number(&args, 'K');
+                Some(GCommand::M852 { i, j, k })
+            }
+            (GCommandType::M, 900) => {
+                let k = extract_token_as_number(&args, 'K');
+                Some(GCommand::M900 { k })
+            }
+            // currents are expressed in mA
+            (GCommandType::M, 906) => {
+                let x = extract_current(&args, 'X');
+                let y = extract_current(&args, 'Y');
+                let z = extract_current(&args, 'Z');
+                let e = extract_current(&args, 'E');
+                Some(GCommand::M906 { x, y, z, e })
+            }
+            // speeds are expressed in mm/s
+            (GCommandType::M, 913) => {
+                let x = extract_speed_per_second(&args, 'X', self.distance_unit);
+                let y = extract_speed_per_second(&args, 'Y', self.distance_unit);
+                let z = extract_speed_per_second(&args, 'Z', self.distance_unit);
+                let e = extract_speed_per_second(&args, 'E', self.distance_unit);
+                Some(GCommand::M913 { x, y, z, e })
+            }
+            (GCommandType::M, 914) => {
+                let x = extract_token_as_u8(&args, 'X');
+                let y = extract_token_as_u8(&args, 'Y');
+                let z = extract_token_as_u8(&args, 'Z');
+                Some(GCommand::M914 { x, y, z })
+            }
             _ => None,
         }
     }
@@ -532,6 +998,17 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_is_blank() {
+        assert!(is_blank(""));
+        assert!(is_blank("  \t"));
+        assert!(is_blank("; comment"));
+        assert!(is_blank("(comment) ; another one"));
+        assert!(!is_blank("G28 ; home"));
+        assert!(!is_blank("(comment) G28"));
+        assert!(!is_blank("hello"));
+    }
+
     #[test]
     fn test_parse_line_g0_complete() {
         let parser = GCodeParser::new();
@@ -803,4 +1280,439 @@ mod tests {
         assert!(res2.unwrap() == GCommand::G21);
     }
 
+    #[test]
+    fn test_parse_line_m906() {
+        let parser = GCodeParser::new();
+        let line = "M906 X800 E650.5";
+        let command = parser.parse_line(line);
+        assert!(command.is_some());
+        assert!(
+            command.unwrap()
+                == GCommand::M906 {
+                    x: Some(Current::from_milliamperes(800.0)),
+                    y: None,
+                    z: None,
+                    e: Some(Current::from_milliamperes(650.5)),
+                }
+        );
+    }
+
+    #[test]
+    fn test_parse_line_m569() {
+        let parser = GCodeParser::new();
+        let line = "M569 S0 X Z";
+        let command = parser.parse_line(line);
+        assert!(command.is_some());
+        assert!(
+            command.unwrap()
+                == GCommand::M569 {
+                    s: false,
+                    x: true,
+                    y: false,
+                    z: true,
+                    e: false,
+                }
+        );
+    }
+
+    #[test]
+    fn test_parse_line_m569_all() {
+        let parser = GCodeParser::new();
+        let line = "M569 S1";
+        let command = parser.parse_line(line);
+        assert!(command.is_some());
+        assert!(
+            command.unwrap()
+                == GCommand::M569 {
+                    s: true,
+                    x: true,
+                    y: true,
+                    z: true,
+                    e: true,
+                }
+        );
+    }
+
+    #[test]
+    fn test_parse_line_m569_invalid() {
+        let parser = GCodeParser::new();
+        let line = "M569 X";
+        let command = parser.parse_line(line);
+        assert!(command.is_none());
+    }
+
+    #[test]
+    fn test_parse_line_m913() {
+        let parser = GCodeParser::new();
+        let line = "M913 X100 Y50";
+        let command = parser.parse_line(line);
+        assert!(command.is_some());
+        assert!(
+            command.unwrap()
+                == GCommand::M913 {
+                    x: Some(Speed::from_meters_per_second(0.1)),
+                    y: Some(Speed::from_meters_per_second(0.05)),
+                    z: None,
+                    e: None,
+                }
+        );
+    }
+
+    #[test]
+    fn test_parse_line_m914() {
+        let parser = GCodeParser::new();
+        let line = "M914 X100 Z300";
+        let command = parser.parse_line(line);
+        assert!(command.is_some());
+        assert!(
+            command.unwrap()
+                == GCommand::M914 {
+                    x: Some(100),
+                    y: None,
+                    z: None,
+                }
+        );
+    }
+
+    #[test]
+    fn test_parse_line_m206() {
+        let parser = GCodeParser::new();
+        let line = "M206 X-2.5 Z0.3";
+        let command = parser.parse_line(line);
+        assert!(command.is_some());
+        assert!(
+            command.unwrap()
+                == GCommand::M206 {
+                    x: Some(Distance::from_millimeters(-2.5)),
+                    y: None,
+                    z: Some(Distance::from_millimeters(0.3)),
+                }
+        );
+    }
+
+    #[test]
+    fn test_parse_line_m211() {
+        let parser = GCodeParser::new();
+        assert!(parser.parse_line("M211 S0").unwrap() == GCommand::M211 { s: Some(false) });
+        assert!(parser.parse_line("M211 S1").unwrap() == GCommand::M211 { s: Some(true) });
+        assert!(parser.parse_line("M211").unwrap() == GCommand::M211 { s: None });
+    }
+
+    #[test]
+    fn test_parse_line_g2_helical() {
+        let parser = GCodeParser::new();
+        assert!(
+            parser.parse_line("G2 X10 Z5 K2.5 R-4 P2").unwrap()
+                == GCommand::G2 {
+                    x: Some(Distance::from_millimeters(10.0)),
+                    y: None,
+                    z: Some(Distance::from_millimeters(5.0)),
+                    e: None,
+                    f: None,
+                    i: None,
+                    j: None,
+                    k: Some(Distance::from_millimeters(2.5)),
+                    r: Some(Distance::from_millimeters(-4.0)),
+                    p: Some(2),
+                }
+        );
+    }
+
+    #[test]
+    fn test_parse_line_plane() {
+        let parser = GCodeParser::new();
+        assert!(parser.parse_line("G17").unwrap() == GCommand::G17);
+        assert!(parser.parse_line("G18").unwrap() == GCommand::G18);
+        assert!(parser.parse_line("G19").unwrap() == GCommand::G19);
+    }
+
+    #[test]
+    fn test_parse_line_g5() {
+        let parser = GCodeParser::new();
+        assert!(
+            parser.parse_line("G5 X20 Y10 I0 J10 P-5 Q0 E1.5").unwrap()
+                == GCommand::G5 {
+                    x: Some(Distance::from_millimeters(20.0)),
+                    y: Some(Distance::from_millimeters(10.0)),
+                    e: Some(Distance::from_millimeters(1.5)),
+                    f: None,
+                    i: Some(Distance::from_millimeters(0.0)),
+                    j: Some(Distance::from_millimeters(10.0)),
+                    p: Some(Distance::from_millimeters(-5.0)),
+                    q: Some(Distance::from_millimeters(0.0)),
+                }
+        );
+    }
+
+    #[test]
+    fn test_parse_line_leveling() {
+        let parser = GCodeParser::new();
+        assert!(parser.parse_line("G29").unwrap() == GCommand::G29 { t: false });
+        assert!(parser.parse_line("G29 T").unwrap() == GCommand::G29 { t: true });
+        assert!(
+            parser.parse_line("M420 S1 Z10").unwrap()
+                == GCommand::M420 {
+                    s: Some(true),
+                    z: Some(Distance::from_millimeters(10.0)),
+                }
+        );
+        assert!(parser.parse_line("M420").unwrap() == GCommand::M420 { s: None, z: None });
+    }
+
+    #[test]
+    fn test_parse_line_servo() {
+        let parser = GCodeParser::new();
+        assert!(
+            parser.parse_line("M280 P1 S90").unwrap()
+                == GCommand::M280 {
+                    p: 1,
+                    s: Some(90.0),
+                }
+        );
+        assert!(parser.parse_line("M280 P0").unwrap() == GCommand::M280 { p: 0, s: None });
+        assert!(parser.parse_line("M280 S90").is_none());
+    }
+
+    #[test]
+    fn test_parse_line_g34() {
+        let parser = GCodeParser::new();
+        assert!(
+            parser.parse_line("G34 I5 T0.01").unwrap()
+                == GCommand::G34 {
+                    i: Some(5),
+                    t: Some(Distance::from_millimeters(0.01)),
+                }
+        );
+        assert!(parser.parse_line("G34").unwrap() == GCommand::G34 { i: None, t: None });
+    }
+
+    #[test]
+    fn test_parse_line_probe() {
+        let parser = GCodeParser::new();
+        assert!(
+            parser.parse_line("G30 X10 Y-5").unwrap()
+                == GCommand::G30 {
+                    x: Some(Distance::from_millimeters(10.0)),
+                    y: Some(Distance::from_millimeters(-5.0)),
+                }
+        );
+        assert!(parser.parse_line("G30").unwrap() == GCommand::G30 { x: None, y: None });
+        assert!(
+            parser.parse_line("M48 P10 X20").unwrap()
+                == GCommand::M48 {
+                    p: Some(10),
+                    x: Some(Distance::from_millimeters(20.0)),
+                    y: None,
+                }
+        );
+        assert!(
+            parser.parse_line("M851 X-30 Z-1.5").unwrap()
+                == GCommand::M851 {
+                    x: Some(Distance::from_millimeters(-30.0)),
+                    y: None,
+                    z: Some(Distance::from_millimeters(-1.5)),
+                }
+        );
+    }
+
+    #[test]
+    fn test_parse_line_skew() {
+        let parser = GCodeParser::new();
+        assert!(
+            parser.parse_line("M852 I0.01 K-0.002").unwrap()
+                == GCommand::M852 {
+                    i: Some(0.01),
+                    j: None,
+                    k: Some(-0.002),
+                }
+        );
+        assert!(
+            parser.parse_line("M852 S0.02").unwrap()
+                == GCommand::M852 {
+                    i: Some(0.02),
+                    j: None,
+                    k: None,
+                }
+        );
+    }
+
+    #[test]
+    fn test_parse_line_limits() {
+        let mut parser = GCodeParser::new();
+        let acceleration = Acceleration::from_meters_per_second_per_second;
+        assert!(
+            parser.parse_line("M201 X3000 Z100").unwrap()
+                == GCommand::M201 {
+                    x: Some(acceleration(3.0)),
+                    y: None,
+                    z: Some(acceleration(0.1)),
+                    e: None,
+                }
+        );
+        assert!(
+            parser.parse_line("M203 X300 E25").unwrap()
+                == GCommand::M203 {
+                    x: Some(Speed::from_meters_per_second(0.3)),
+                    y: None,
+                    z: None,
+                    e: Some(Speed::from_meters_per_second(0.025)),
+                }
+        );
+        assert!(
+            parser.parse_line("M204 P1000 T1500").unwrap()
+                == GCommand::M204 {
+                    p: Some(acceleration(1.0)),
+                    r: None,
+                    t: Some(acceleration(1.5)),
+                }
+        );
+        assert!(
+            parser.parse_line("M205 Y10").unwrap()
+                == GCommand::M205 {
+                    x: None,
+                    y: Some(Speed::from_meters_per_second(0.01)),
+                    z: None,
+                    e: None,
+                }
+        );
+        parser.set_distance_unit(DistanceUnit::Inch);
+        assert!(
+            parser.parse_line("M203 Z1").unwrap()
+                == GCommand::M203 {
+                    x: None,
+                    y: None,
+                    z: Some(Speed::from_meters_per_second(0.0254)),
+                    e: None,
+                }
+        );
+    }
+
+    #[test]
+    fn test_parse_line_extrusion() {
+        let mut parser = GCodeParser::new();
+        assert!(parser.parse_line("M221 S95").unwrap() == GCommand::M221 { s: 0.95 });
+        assert!(parser.parse_line("M221").is_none());
+        assert!(
+            parser.parse_line("M200 D1.75").unwrap()
+                == GCommand::M200 {
+                    d: Some(Distance::from_millimeters(1.75))
+                }
+        );
+        assert!(parser.parse_line("M200").unwrap() == GCommand::M200 { d: None });
+        parser.set_distance_unit(DistanceUnit::Inch);
+        assert!(
+            parser.parse_line("M200 D0.1").unwrap()
+                == GCommand::M200 {
+                    d: Some(Distance::from_inches(0.1))
+                }
+        );
+        assert!(parser.parse_line("M503").unwrap() == GCommand::M503);
+    }
+
+    #[test]
+    fn test_parse_line_workspaces() {
+        let parser = GCodeParser::new();
+        let g53 = |x: Option<f64>, z: Option<f64>| GCommand::G53 {
+            x: x.map(Distance::from_millimeters),
+            y: None,
+            z: z.map(Distance::from_millimeters),
+            f: None,
+        };
+        assert!(parser.parse_line("G53 G0 X10 Z2").unwrap() == g53(Some(10.0), Some(2.0)));
+        assert!(parser.parse_line("G53 G1 X-5").unwrap() == g53(Some(-5.0), None));
+        assert!(parser.parse_line("G53").unwrap() == g53(None, None));
+        assert!(parser.parse_line("G53 G2 X10").is_none());
+        for (line, cmd) in [
+            ("G54", GCommand::G54),
+            ("G55", GCommand::G55),
+            ("G56", GCommand::G56),
+            ("G57", GCommand::G57),
+            ("G58", GCommand::G58),
+            ("G59", GCommand::G59),
+            ("G92.1", GCommand::G92_1),
+        ] {
+            assert!(parser.parse_line(line).unwrap() == cmd);
+        }
+        let mm = |v: f64| Some(Distance::from_millimeters(v));
+        assert!(
+            parser.parse_line("G10 L2 P2 X10 Z-1").unwrap()
+                == GCommand::G10_L2 {
+                    p: Some(2),
+                    x: mm(10.0),
+                    y: None,
+                    z: mm(-1.0),
+                }
+        );
+        assert!(
+            parser.parse_line("G10 L20 Y5").unwrap()
+                == GCommand::G10_L20 {
+                    p: None,
+                    x: None,
+                    y: mm(5.0),
+                    z: None,
+                }
+        );
+        assert!(parser.parse_line("G10").unwrap() == GCommand::G10);
+        assert!(parser.parse_line("G10 L1 P1").is_none());
+        assert!(parser.parse_line("G92.2").is_none());
+        assert!(parser.parse_line("G92.").is_none());
+        assert!(parser.parse_line("G1.5 X1").is_none());
+    }
+
+    #[test]
+    fn test_parse_line_linear_advance() {
+        let parser = GCodeParser::new();
+        assert!(parser.parse_line("M900 K0.05").unwrap() == GCommand::M900 { k: Some(0.05) });
+        assert!(parser.parse_line("M900").unwrap() == GCommand::M900 { k: None });
+    }
+
+    #[test]
+    fn test_parse_line_input_shaping() {
+        let parser = GCodeParser::new();
+        assert!(
+            parser.parse_line("M593 X F40.5 D0.1").unwrap()
+                == GCommand::M593 {
+                    d: Some(0.1),
+                    f: Some(40.5),
+                    t: None,
+                    x: true,
+                    y: false,
+                }
+        );
+        assert!(
+            parser.parse_line("M593 T2").unwrap()
+                == GCommand::M593 {
+                    d: None,
+                    f: None,
+                    t: Some(2),
+                    x: true,
+                    y: true,
+                }
+        );
+    }
+
+    #[test]
+    fn test_parse_line_backlash() {
+        let parser = GCodeParser::new();
+        assert!(
+            parser.parse_line("M425 F0.5 S3 Z0.08").unwrap()
+                == GCommand::M425 {
+                    f: Some(0.5),
+                    s: Some(Distance::from_millimeters(3.0)),
+                    x: None,
+                    y: None,
+                    z: Some(Distance::from_millimeters(0.08)),
+                }
+        );
+        assert!(
+            parser.parse_line("M425").unwrap()
+                == GCommand::M425 {
+                    f: None,
+                    s: None,
+                    x: None,
+                    y: None,
+                    z: None,
+                }
+        );
+    }
 }
@@ -0,0 +1,141 @@
+#![cfg_attr(not(test), no_std)]
+
+use core::time::Duration;
+
+use common::{PwmBase, ServoBase};
+
+// hobby servos expect a pulse every 20ms, the PWM timer driving them must run at this frequency
+pub const SERVO_FREQUENCY: u64 = 50;
+const SERVO_PERIOD: Duration = Duration::from_millis(20);
+
+// the width of the pulse goes linearly from min_pulse at 0° to max_pulse at max_angle
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ServoConfig {
+    pub min_pulse: Duration,
+    pub max_pulse: Duration,
+    pub max_angle: f64,
+}
+
+impl Default for ServoConfig {
+    fn default() -> Self {
+        Self {
+            min_pulse: Duration::from_micros(544),
+            max_pulse: Duration::from_micros(2400),
+            max_angle: 180.0,
+        }
+    }
+}
+
+impl ServoConfig {
+    pub fn is_valid(&self) -> bool {
+        self.min_pulse < self.max_pulse && self.max_pulse <= SERVO_PERIOD && self.max_angle > 0.0
+    }
+}
+
+// servo driven by a channel of a PWM timer. The timer runs at 50Hz, so it can't be shared with
+// the heaters and the fans
+pub struct Servo<P: PwmBase> {
+    pwm: P,
+    ch: P::Channel,
+    config: ServoConfig,
+    // None until the first angle is set, the servo gets no pulses in the meantime
+    angle: Option<f64>,
+}
+
+impl<P: PwmBase> Servo<P> {
+    pub fn new(pwm: P, ch: P::Channel, config: ServoConfig) -> Self {
+        let mut pwm = pwm;
+        pwm.disable(ch);
+        Self {
+            pwm,
+            ch,
+            config,
+            angle: None,
+        }
+    }
+
+    pub fn get_angle(&self) -> Option<f64> {
+        self.angle
+    }
+
+    pub fn get_config(&self) -> ServoConfig {
+        self.config
+    }
+
+    // the angle is clamped between 0 and the max angle
+    pub fn pulse_width(&self, angle: f64) -> Duration {
+        let ratio = angle.clamp(0.0, self.config.max_angle) / self.config.max_angle;
+        let (min, max) = (self.config.min_pulse, self.config.max_pulse);
+        min + (max - min).mul_f64(ratio)
+    }
+
+    // stop the pulses, the servo doesn't hold its position anymore
+    pub fn detach(&mut self) {
+        self.pwm.disable(self.ch);
+        self.angle = None;
+    }
+}
+
+impl<P: PwmBase> ServoBase for Servo<P> {
+    fn set_angle(&mut self, angle: f64) {
+        let angle = angle.clamp(0.0, self.config.max_angle);
+        let ratio = self.pulse_width(angle).as_secs_f64() / SERVO_PERIOD.as_secs_f64();
+        let duty_cycle = (self.pwm.get_max_duty() as f64 * ratio) as u64;
+        self.pwm.set_duty(self.ch, duty_cycle);
+        self.pwm.enable(self.ch);
+        self.angle = Some(angle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sim::SimPwm;
+
+    fn config() -> ServoConfig {
+        ServoConfig {
+            min_pulse: Duration::from_micros(1000),
+            max_pulse: Duration::from_micros(2000),
+            max_angle: 180.0,
+        }
+    }
+
+    #[test]
+    fn test_servo_pulse_width() {
+        let servo = Servo::new(SimPwm::new(1, 20000), 0, config());
+        assert_eq!(servo.pulse_width(0.0), Duration::from_micros(1000));
+        assert_eq!(servo.pulse_width(90.0), Duration::from_micros(1500));
+        assert_eq!(servo.pulse_width(180.0), Duration::from_micros(2000));
+        // out of range angles are clamped
+        assert_eq!(servo.pulse_width(-10.0), Duration::from_micros(1000));
+        assert_eq!(servo.pulse_width(200.0), Duration::from_micros(2000));
+    }
+
+    #[test]
+    fn test_servo_duty_cycle() {
+        // a step of the duty cycle is 1us long
+        let mut servo = Servo::new(SimPwm::new(2, 20000), 1, config());
+        assert!(!servo.pwm.is_enabled(1));
+        assert_eq!(servo.get_angle(), None);
+        servo.set_angle(90.0);
+        assert!(servo.pwm.is_enabled(1));
+        assert_eq!(servo.pwm.get_duty(1), 1500);
+        assert_eq!(servo.get_angle(), Some(90.0));
+        servo.set_angle(270.0);
+        assert_eq!(servo.pwm.get_duty(1), 2000);
+        assert_eq!(servo.get_angle(), Some(180.0));
+        servo.detach();
+        assert!(!servo.pwm.is_enabled(1));
+        assert_eq!(servo.get_angle(), None);
+    }
+
+    #[test]
+    fn test_servo_config() {
+        assert!(ServoConfig::default().is_valid());
+        let mut config = config();
+        config.max_pulse = Duration::from_micros(500);
+        assert!(!config.is_valid());
+        config.max_pulse = Duration::from_millis(25);
+        assert!(!config.is_valid());
+    }
+}
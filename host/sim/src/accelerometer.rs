@@ -0,0 +1,138 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::clock;
+use crate::spi::SimSpi;
+
+const DEVID: u8 = 0x00;
+const BW_RATE: u8 = 0x2C;
+const POWER_CTL: u8 = 0x2D;
+const DATAX0: u8 = 0x32;
+const FIFO_STATUS: u8 = 0x39;
+
+const FIFO_SIZE: usize = 32;
+// m/s² per LSB in full resolution, saturating at 16g
+const SCALE: f64 = 0.0039 * 9.80665;
+const MAX_RAW: f64 = 4095.0;
+
+struct Adxl345 {
+    registers: [u8; 64],
+    // when the measurement started and the samples taken since
+    measuring: Option<(Duration, u64)>,
+    fifo: VecDeque<[i16; 3]>,
+}
+
+impl Adxl345 {
+    fn new() -> Self {
+        Self {
+            registers: [0; 64],
+            measuring: None,
+            fifo: VecDeque::new(),
+        }
+    }
+
+    fn rate(&self) -> f64 {
+        3200.0 / f64::from(1u32 << (0x0F - (self.registers[BW_RATE as usize] & 0x0F).min(0x0F)))
+    }
+
+    // samples up to the current time, the FIFO keeps the latest ones
+    fn sample(&mut self, acceleration: &mut impl FnMut(Duration) -> [f64; 3]) {
+        let rate = self.rate();
+        let Some((start, taken)) = self.measuring.as_mut() else {
+            return;
+        };
+        loop {
+            let time = *start + Duration::from_secs_f64(*taken as f64 / rate);
+            if time > clock::now() {
+                break;
+            }
+            let raw =
+                acceleration(time).map(|a| (a / SCALE).round().clamp(-MAX_RAW, MAX_RAW) as i16);
+            if self.fifo.len() == FIFO_SIZE {
+                self.fifo.pop_front();
+            }
+            self.fifo.push_back(raw);
+            *taken += 1;
+        }
+    }
+
+    fn transfer(&mut self, frame: &[u8]) -> Vec<u8> {
+        let read = frame[0] & 0x80 != 0;
+        let register = frame[0] & 0x3F;
+        let mut response = vec![0; frame.len()];
+        if !read {
+            self.registers[register as usize] = frame[1];
+            if register == POWER_CTL {
+                self.fifo.clear();
+                self.measuring = (frame[1] & 0x08 != 0).then(|| (clock::now(), 0));
+            }
+            return response;
+        }
+        match register {
+            DEVID => response[1] = 0xE5,
+            FIFO_STATUS => response[1] = self.fifo.len() as u8,
+            DATAX0 => {
+                let sample = self.fifo.pop_front().unwrap_or_default();
+                for (n, value) in sample.iter().enumerate() {
+                    let [low, high] = value.to_le_bytes();
+                    response[1 + 2 * n] = low;
+                    response[2 + 2 * n] = high;
+                }
+            }
+            _ => response[1] = self.registers[register as usize],
+        }
+        response
+    }
+}
+
+// ADXL345 on SPI, sampling the acceleration of X, Y and Z in m/s² at the rate it's set to,
+// from when the measurement is turned on. The acceleration is asked for at each sample time,
+// in order, when the samples are due
+pub fn adxl345(mut acceleration: impl FnMut(Duration) -> [f64; 3] + 'static) -> SimSpi {
+    let device = Rc::new(RefCell::new(Adxl345::new()));
+    SimSpi::from_fn(move |frame| {
+        let mut device = device.borrow_mut();
+        device.sample(&mut acceleration);
+        device.transfer(frame)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{advance, block_on};
+    use common::SpiBase;
+
+    fn transfer(spi: &mut SimSpi, frame: &[u8]) -> Vec<u8> {
+        let mut data = frame.to_vec();
+        block_on(spi.transfer(&mut data)).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_adxl345() {
+        clock::reset();
+        let mut spi = adxl345(|t| [t.as_secs_f64(), -1.0, 9.80665]);
+        assert_eq!(transfer(&mut spi, &[0x80, 0]), vec![0, 0xE5]);
+        // 800Hz
+        transfer(&mut spi, &[BW_RATE, 0x0D]);
+        assert_eq!(transfer(&mut spi, &[0x80 | BW_RATE, 0]), vec![0, 0x0D]);
+        advance(Duration::from_millis(10));
+        assert_eq!(transfer(&mut spi, &[0x80 | FIFO_STATUS, 0]), vec![0, 0]);
+
+        transfer(&mut spi, &[POWER_CTL, 0x08]);
+        advance(Duration::from_millis(10));
+        // a sample at 0, then one each 1.25ms
+        assert_eq!(transfer(&mut spi, &[0x80 | FIFO_STATUS, 0]), vec![0, 9]);
+        let mut frame = vec![0xF2];
+        frame.extend([0; 6]);
+        assert_eq!(transfer(&mut spi, &frame), vec![0, 0, 0, 0xE6, 0xFF, 0, 1]);
+        advance(Duration::from_secs(1));
+        assert_eq!(transfer(&mut spi, &[0x80 | FIFO_STATUS, 0]), vec![0, 32]);
+        // the oldest samples are overwritten
+        let sample = transfer(&mut spi, &frame);
+        assert_eq!(i16::from_le_bytes([sample[1], sample[2]]), 26);
+    }
+}
@@ -0,0 +1,127 @@
+use core::time::Duration;
+
+use common::AdcBase;
+
+use crate::clock;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SimAdcResolution(pub u8);
+
+impl From<SimAdcResolution> for u64 {
+    fn from(value: SimAdcResolution) -> Self {
+        1 << value.0
+    }
+}
+
+// what an ADC pin reads
+pub enum AdcSource {
+    Constant(u16),
+    // every read returns the next value, the last one is kept once the sequence is over
+    Sequence(Vec<u16>, usize),
+    // value over the virtual time
+    Function(Box<dyn FnMut(Duration) -> u16>),
+}
+
+impl AdcSource {
+    pub fn sequence(values: Vec<u16>) -> Self {
+        AdcSource::Sequence(values, 0)
+    }
+
+    pub fn function(f: impl FnMut(Duration) -> u16 + 'static) -> Self {
+        AdcSource::Function(Box::new(f))
+    }
+
+    fn sample(&mut self) -> u16 {
+        match self {
+            AdcSource::Constant(value) => *value,
+            AdcSource::Sequence(values, index) => {
+                let value = values[(*index).min(values.len() - 1)];
+                *index += 1;
+                value
+            }
+            AdcSource::Function(f) => f(clock::now()),
+        }
+    }
+}
+
+// ADC whose pins are identified by their index, every pin reads from its own source
+pub struct SimAdc {
+    resolution: SimAdcResolution,
+    sample_time: u32,
+    sources: Vec<AdcSource>,
+}
+
+impl SimAdc {
+    pub fn new(resolution: SimAdcResolution) -> Self {
+        Self {
+            resolution,
+            sample_time: 0,
+            sources: Vec::new(),
+        }
+    }
+
+    // source of the next pin, returns its index
+    pub fn add_source(&mut self, source: AdcSource) -> usize {
+        self.sources.push(source);
+        self.sources.len() - 1
+    }
+
+    pub fn set_source(&mut self, pin: usize, source: AdcSource) {
+        self.sources[pin] = source;
+    }
+}
+
+impl AdcBase for SimAdc {
+    type PinType = usize;
+    type SampleTime = u32;
+    type Resolution = SimAdcResolution;
+
+    fn set_sample_time(&mut self, sample_time: Self::SampleTime) {
+        self.sample_time = sample_time;
+    }
+
+    fn sample_time(&self) -> Self::SampleTime {
+        self.sample_time
+    }
+
+    fn set_resolution(&mut self, resolution: Self::Resolution) {
+        self.resolution = resolution;
+    }
+
+    fn resolution(&self) -> Self::Resolution {
+        self.resolution
+    }
+
+    async fn read(&mut self, pin: &mut Self::PinType, readings: &mut [u16]) {
+        let source = &mut self.sources[*pin];
+        for reading in readings.iter_mut() {
+            *reading = source.sample();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::block_on;
+
+    #[test]
+    fn test_adc_sources() {
+        clock::reset();
+        let mut adc = SimAdc::new(SimAdcResolution(12));
+        let mut constant = adc.add_source(AdcSource::Constant(2000));
+        let mut sequence = adc.add_source(AdcSource::sequence(vec![1, 2]));
+        let mut ramp = adc.add_source(AdcSource::function(|t| t.as_millis() as u16));
+        let mut readings = [0u16; 1];
+        block_on(adc.read(&mut constant, &mut readings));
+        assert_eq!(readings[0], 2000);
+        for expected in [1, 2, 2] {
+            block_on(adc.read(&mut sequence, &mut readings));
+            assert_eq!(readings[0], expected);
+        }
+        clock::advance(Duration::from_millis(300));
+        block_on(adc.read(&mut ramp, &mut readings));
+        assert_eq!(readings[0], 300);
+        assert_eq!(u64::from(adc.resolution()), 4096);
+    }
+}
@@ -0,0 +1,138 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::pin::{Recorder, SimInputPin};
+
+// step and direction pins of an axis, as named in the recorder. A rising edge of the step pin
+// moves the axis by the resolution, forward while the direction pin is high
+#[derive(Clone)]
+pub struct SimAxis {
+    pub step: String,
+    pub dir: String,
+    // mm per step
+    pub resolution: f64,
+}
+
+impl SimAxis {
+    pub fn new(step: &str, dir: &str, resolution: f64) -> Self {
+        Self {
+            step: step.to_string(),
+            dir: dir.to_string(),
+            resolution,
+        }
+    }
+}
+
+struct Tracker {
+    recorder: Recorder,
+    axes: [SimAxis; 3],
+    // events already taken into account
+    cursor: usize,
+    forward: [bool; 3],
+    position: [f64; 3],
+}
+
+impl Tracker {
+    fn update(&mut self) -> [f64; 3] {
+        let events = self.recorder.events_from(self.cursor);
+        self.cursor += events.len();
+        for event in events {
+            for (n, axis) in self.axes.iter().enumerate() {
+                if event.pin == axis.dir {
+                    self.forward[n] = event.high;
+                } else if event.pin == axis.step && event.high {
+                    let sign = if self.forward[n] { 1.0 } else { -1.0 };
+                    self.position[n] += sign * axis.resolution;
+                }
+            }
+        }
+        self.position
+    }
+}
+
+// bed whose height changes along X and Y, touched by the nozzle of a simulated printer. The
+// position of the nozzle is rebuilt from the pins of the X, Y and Z steppers, starting from 0
+#[derive(Clone)]
+pub struct SimBed {
+    tracker: Rc<RefCell<Tracker>>,
+    height: Rc<dyn Fn(f64, f64) -> f64>,
+}
+
+impl SimBed {
+    pub fn new(
+        recorder: Recorder,
+        axes: [SimAxis; 3],
+        height: impl Fn(f64, f64) -> f64 + 'static,
+    ) -> Self {
+        Self {
+            tracker: Rc::new(RefCell::new(Tracker {
+                recorder,
+                axes,
+                cursor: 0,
+                forward: [false; 3],
+                position: [0.0; 3],
+            })),
+            height: Rc::new(height),
+        }
+    }
+
+    // position of the nozzle in mm
+    pub fn position(&self) -> [f64; 3] {
+        self.tracker.borrow_mut().update()
+    }
+
+    // height of the bed in mm
+    pub fn height(&self, x: f64, y: f64) -> f64 {
+        (self.height)(x, y)
+    }
+
+    // high while the nozzle is on the bed or below it
+    pub fn probe(&self) -> SimInputPin {
+        let bed = self.clone();
+        SimInputPin::from_fn(move || {
+            let [x, y, z] = bed.position();
+            z <= bed.height(x, y)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::reset;
+    use common::{ExtiInputPinBase, OutputPinBase};
+
+    #[test]
+    fn test_bed_probe() {
+        reset();
+        let recorder = Recorder::new();
+        let axes = [
+            SimAxis::new("x_step", "x_dir", 0.5),
+            SimAxis::new("y_step", "y_dir", 0.5),
+            SimAxis::new("z_step", "z_dir", 0.125),
+        ];
+        let bed = SimBed::new(recorder.clone(), axes, |x, _| -x / 8.0);
+        let probe = bed.probe();
+        let (mut x_step, mut x_dir) = (recorder.output_pin("x_step"), recorder.output_pin("x_dir"));
+        let (mut z_step, mut z_dir) = (recorder.output_pin("z_step"), recorder.output_pin("z_dir"));
+        assert!(probe.is_high());
+
+        x_dir.set_high();
+        for _ in 0..20 {
+            x_step.set_high();
+            x_step.set_low();
+        }
+        // the bed is 1.25mm lower at X10
+        z_dir.set_low();
+        for _ in 0..9 {
+            z_step.set_high();
+            z_step.set_low();
+        }
+        assert!(!probe.is_high());
+        z_step.set_high();
+        z_step.set_low();
+        assert!(probe.is_high());
+        let [x, y, z] = bed.position();
+        assert_eq!((x, y, z), (10.0, 0.0, -1.25));
+    }
+}
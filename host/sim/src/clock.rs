@@ -0,0 +1,231 @@
+use core::future::Future;
+use core::pin::{pin, Pin};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use std::cell::RefCell;
+
+use common::TimerBase;
+
+struct Clock {
+    now: Duration,
+    // deadlines of the timers that are currently waited, identified by the id of their future
+    timers: Vec<(u64, Duration)>,
+    next_id: u64,
+}
+
+thread_local! {
+    // every test runs in its own thread, so each one gets its own clock
+    static CLOCK: RefCell<Clock> = const {
+        RefCell::new(Clock {
+            now: Duration::ZERO,
+            timers: Vec::new(),
+            next_id: 0,
+        })
+    };
+}
+
+pub fn now() -> Duration {
+    CLOCK.with(|c| c.borrow().now)
+}
+
+pub fn advance(duration: Duration) {
+    CLOCK.with(|c| c.borrow_mut().now += duration);
+}
+
+// the clock never goes back, advancing to an instant in the past does nothing
+pub fn advance_to(instant: Duration) {
+    CLOCK.with(|c| {
+        let mut c = c.borrow_mut();
+        c.now = c.now.max(instant);
+    });
+}
+
+// the earliest deadline among the timers that are currently waited
+pub fn next_deadline() -> Option<Duration> {
+    CLOCK.with(|c| c.borrow().timers.iter().map(|t| t.1).min())
+}
+
+pub fn reset() {
+    CLOCK.with(|c| {
+        let mut c = c.borrow_mut();
+        c.now = Duration::ZERO;
+        c.timers.clear();
+    });
+}
+
+// completes once the clock reaches the deadline
+pub struct Sleep {
+    deadline: Duration,
+    id: Option<u64>,
+}
+
+impl Sleep {
+    pub fn until(deadline: Duration) -> Self {
+        Self { deadline, id: None }
+    }
+
+    pub fn deadline(&self) -> Duration {
+        self.deadline
+    }
+
+    fn unregister(&mut self) {
+        if let Some(id) = self.id.take() {
+            CLOCK.with(|c| c.borrow_mut().timers.retain(|t| t.0 != id));
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if now() >= self.deadline {
+            self.unregister();
+            return Poll::Ready(());
+        }
+        if self.id.is_none() {
+            let deadline = self.deadline;
+            let id = CLOCK.with(|c| {
+                let mut c = c.borrow_mut();
+                let id = c.next_id;
+                c.next_id += 1;
+                c.timers.push((id, deadline));
+                id
+            });
+            self.id = Some(id);
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.unregister();
+    }
+}
+
+pub struct SimTimer {}
+
+impl TimerBase for SimTimer {
+    fn after(duration: Duration) -> impl Future<Output = ()> {
+        Sleep::until(now() + duration)
+    }
+
+    fn now() -> Duration {
+        now()
+    }
+
+    fn at(instant: Duration) -> impl Future<Output = ()> {
+        Sleep::until(instant)
+    }
+}
+
+// run the future to completion. Whenever it can't make progress, the clock jumps to the
+// next deadline, so a move that takes minutes completes in the time needed to compute it.
+// Panics if the future waits for something that isn't a timer, as nothing could ever wake it
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        match next_deadline() {
+            Some(deadline) => advance_to(deadline),
+            None => panic!("the future is pending but no timer is running"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_advance() {
+        reset();
+        advance(Duration::from_millis(10));
+        assert_eq!(now(), Duration::from_millis(10));
+        advance_to(Duration::from_millis(5));
+        assert_eq!(now(), Duration::from_millis(10));
+        advance_to(Duration::from_millis(15));
+        assert_eq!(now(), Duration::from_millis(15));
+    }
+
+    #[test]
+    fn test_block_on_after() {
+        reset();
+        block_on(async {
+            SimTimer::after(Duration::from_secs(60)).await;
+            SimTimer::after(Duration::from_secs(60)).await;
+        });
+        assert_eq!(now(), Duration::from_secs(120));
+        assert_eq!(next_deadline(), None);
+    }
+
+    #[test]
+    fn test_block_on_at_past() {
+        reset();
+        advance(Duration::from_secs(1));
+        block_on(SimTimer::at(Duration::from_millis(500)));
+        assert_eq!(now(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_block_on_concurrent() {
+        reset();
+        let a = async {
+            SimTimer::after(Duration::from_millis(30)).await;
+            now()
+        };
+        let b = async {
+            SimTimer::after(Duration::from_millis(10)).await;
+            SimTimer::after(Duration::from_millis(10)).await;
+            now()
+        };
+        let (a, b) = block_on(async {
+            let mut a = pin!(a);
+            let mut b = pin!(b);
+            let (mut ra, mut rb) = (None, None);
+            core::future::poll_fn(|cx| {
+                if ra.is_none() {
+                    if let Poll::Ready(v) = a.as_mut().poll(cx) {
+                        ra = Some(v);
+                    }
+                }
+                if rb.is_none() {
+                    if let Poll::Ready(v) = b.as_mut().poll(cx) {
+                        rb = Some(v);
+                    }
+                }
+                match (ra, rb) {
+                    (Some(a), Some(b)) => Poll::Ready((a, b)),
+                    _ => Poll::Pending,
+                }
+            })
+            .await
+        });
+        assert_eq!(a, Duration::from_millis(30));
+        assert_eq!(b, Duration::from_millis(20));
+        assert_eq!(now(), Duration::from_millis(30));
+    }
+
+    #[test]
+    fn test_dropped_timer() {
+        reset();
+        {
+            let mut sleep = pin!(Sleep::until(Duration::from_secs(1)));
+            let mut cx = Context::from_waker(Waker::noop());
+            assert!(sleep.as_mut().poll(&mut cx).is_pending());
+            assert_eq!(next_deadline(), Some(Duration::from_secs(1)));
+        }
+        assert_eq!(next_deadline(), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_block_on_stuck() {
+        reset();
+        block_on(core::future::pending::<()>());
+    }
+}
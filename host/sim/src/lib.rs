@@ -0,0 +1,20 @@
+// implementations of the common traits for host tests and tools. Nothing waits for real time:
+// every component refers to a virtual clock that only moves when it is advanced, either manually
+// or by block_on when every task is waiting for a timer
+pub mod accelerometer;
+pub mod adc;
+pub mod bed;
+pub mod clock;
+pub mod pin;
+pub mod pwm;
+pub mod serial;
+pub mod spi;
+
+pub use accelerometer::adxl345;
+pub use adc::{AdcSource, SimAdc, SimAdcResolution};
+pub use bed::{SimAxis, SimBed};
+pub use clock::{block_on, SimTimer};
+pub use pin::{PinEvent, Recorder, SimInputPin, SimOutputPin};
+pub use pwm::SimPwm;
+pub use serial::{SimSerial, SimSerialError};
+pub use spi::{SimSpi, SimSpiError};
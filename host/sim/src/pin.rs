@@ -0,0 +1,324 @@
+use core::future::{poll_fn, Future};
+use core::task::Poll;
+use core::time::Duration;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use common::{ExtiInputPinBase, OutputPinBase};
+
+use crate::clock::{self, Sleep};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PinEvent {
+    pub time: Duration,
+    pub pin: String,
+    pub high: bool,
+}
+
+// collects the level changes of the output pins attached to it. Clones share the same events
+#[derive(Clone, Default)]
+pub struct Recorder {
+    events: Rc<RefCell<Vec<PinEvent>>>,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn output_pin(&self, name: &str) -> SimOutputPin {
+        SimOutputPin {
+            state: false,
+            name: name.to_string(),
+            recorder: Some(self.clone()),
+            latency: Duration::ZERO,
+        }
+    }
+
+    pub fn events(&self) -> Vec<PinEvent> {
+        self.events.borrow().clone()
+    }
+
+    // instants at which the pin went high
+    pub fn rising_edges(&self, pin: &str) -> Vec<Duration> {
+        self.events
+            .borrow()
+            .iter()
+            .filter(|e| e.pin == pin && e.high)
+            .map(|e| e.time)
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.events.borrow_mut().clear();
+    }
+
+    // events recorded from the given index on
+    pub fn events_from(&self, index: usize) -> Vec<PinEvent> {
+        self.events
+            .borrow()
+            .get(index..)
+            .map_or_else(Vec::new, <[PinEvent]>::to_vec)
+    }
+
+    fn record(&self, pin: &str, high: bool) {
+        self.events.borrow_mut().push(PinEvent {
+            time: clock::now(),
+            pin: pin.to_string(),
+            high,
+        });
+    }
+}
+
+#[derive(Default)]
+pub struct SimOutputPin {
+    state: bool,
+    name: String,
+    recorder: Option<Recorder>,
+    // time spent by every level change, to mimic the cost of driving a real GPIO
+    latency: Duration,
+}
+
+impl SimOutputPin {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    fn set(&mut self, high: bool) {
+        clock::advance(self.latency);
+        self.state = high;
+        if let Some(recorder) = &self.recorder {
+            recorder.record(&self.name, high);
+        }
+    }
+}
+
+impl OutputPinBase for SimOutputPin {
+    fn set_high(&mut self) {
+        self.set(true);
+    }
+
+    fn set_low(&mut self) {
+        self.set(false);
+    }
+
+    fn is_high(&self) -> bool {
+        self.state
+    }
+}
+
+#[derive(Default)]
+struct InputState {
+    initial: bool,
+    // level changes sorted by time, the level at an instant is given by the last change before it
+    changes: Vec<(Duration, bool)>,
+    // level computed when the pin is read, it takes the place of the changes
+    source: Option<Rc<dyn Fn() -> bool>>,
+}
+
+impl InputState {
+    fn level(&self, instant: Duration) -> bool {
+        if let Some(source) = &self.source {
+            return source();
+        }
+        self.changes
+            .iter()
+            .rev()
+            .find(|c| c.0 <= instant)
+            .map_or(self.initial, |c| c.1)
+    }
+
+    fn next_change(&self, instant: Duration, level: bool) -> Option<Duration> {
+        self.changes
+            .iter()
+            .find(|c| c.0 > instant && c.1 == level)
+            .map(|c| c.0)
+    }
+
+    fn schedule(&mut self, instant: Duration, level: bool) {
+        let index = self.changes.partition_point(|c| c.0 <= instant);
+        self.changes.insert(index, (instant, level));
+    }
+}
+
+// input pin (e.g. an endstop) whose level can be scripted over the virtual time or changed by
+// hand. Clones share the same level, so a test can keep a handle to a pin owned by the planner
+#[derive(Clone, Default)]
+pub struct SimInputPin {
+    state: Rc<RefCell<InputState>>,
+}
+
+impl SimInputPin {
+    pub fn new(high: bool) -> Self {
+        Self {
+            state: Rc::new(RefCell::new(InputState {
+                initial: high,
+                changes: Vec::new(),
+                source: None,
+            })),
+        }
+    }
+
+    // the level is given by the function every time the pin is read, e.g. a probe that follows
+    // the simulated position of the nozzle. Waiting for a level doesn't wake the task by itself,
+    // the pin is read again whenever something else does
+    pub fn from_fn(level: impl Fn() -> bool + 'static) -> Self {
+        Self {
+            state: Rc::new(RefCell::new(InputState {
+                initial: false,
+                changes: Vec::new(),
+                source: Some(Rc::new(level)),
+            })),
+        }
+    }
+
+    // the pin goes high at the given instant
+    pub fn high_at(self, instant: Duration) -> Self {
+        self.state.borrow_mut().schedule(instant, true);
+        self
+    }
+
+    // the pin goes low at the given instant
+    pub fn low_at(self, instant: Duration) -> Self {
+        self.state.borrow_mut().schedule(instant, false);
+        self
+    }
+
+    pub fn set_high(&self) {
+        self.state.borrow_mut().schedule(clock::now(), true);
+    }
+
+    pub fn set_low(&self) {
+        self.state.borrow_mut().schedule(clock::now(), false);
+    }
+
+    fn wait_for(&self, level: bool) -> impl Future<Output = ()> + '_ {
+        let mut sleep: Option<Sleep> = None;
+        poll_fn(move |cx| {
+            let now = clock::now();
+            let state = self.state.borrow();
+            if state.level(now) == level {
+                return Poll::Ready(());
+            }
+            // wake up when the level is expected to change
+            let next = state.next_change(now, level);
+            if sleep.as_ref().map(Sleep::deadline) != next {
+                sleep = next.map(Sleep::until);
+            }
+            if let Some(sleep) = sleep.as_mut() {
+                let _ = core::pin::Pin::new(sleep).poll(cx);
+            }
+            Poll::Pending
+        })
+    }
+}
+
+impl ExtiInputPinBase for SimInputPin {
+    fn is_high(&self) -> bool {
+        self.state.borrow().level(clock::now())
+    }
+
+    fn wait_for_high(&mut self) -> impl Future<Output = ()> {
+        self.wait_for(true)
+    }
+
+    fn wait_for_low(&mut self) -> impl Future<Output = ()> {
+        self.wait_for(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{block_on, reset};
+    use crate::SimTimer;
+    use common::TimerBase;
+
+    #[test]
+    fn test_output_pin_recording() {
+        reset();
+        let recorder = Recorder::new();
+        let mut pin = recorder.output_pin("x_step");
+        pin.set_high();
+        clock::advance(Duration::from_micros(10));
+        pin.set_low();
+        assert!(!pin.is_high());
+        assert_eq!(
+            recorder.events(),
+            vec![
+                PinEvent {
+                    time: Duration::ZERO,
+                    pin: String::from("x_step"),
+                    high: true
+                },
+                PinEvent {
+                    time: Duration::from_micros(10),
+                    pin: String::from("x_step"),
+                    high: false
+                },
+            ]
+        );
+        assert_eq!(recorder.rising_edges("x_step"), vec![Duration::ZERO]);
+        assert!(recorder.rising_edges("y_step").is_empty());
+    }
+
+    #[test]
+    fn test_output_pin_latency() {
+        reset();
+        let mut pin = SimOutputPin::new().with_latency(Duration::from_micros(3));
+        pin.set_high();
+        pin.set_low();
+        assert_eq!(clock::now(), Duration::from_micros(6));
+    }
+
+    #[test]
+    fn test_input_pin_script() {
+        reset();
+        let pin = SimInputPin::new(false)
+            .high_at(Duration::from_millis(10))
+            .low_at(Duration::from_millis(20));
+        assert!(!pin.is_high());
+        clock::advance(Duration::from_millis(10));
+        assert!(pin.is_high());
+        clock::advance(Duration::from_millis(10));
+        assert!(!pin.is_high());
+    }
+
+    #[test]
+    fn test_input_pin_wait_for_high() {
+        reset();
+        let mut pin = SimInputPin::new(false).high_at(Duration::from_millis(25));
+        block_on(pin.wait_for_high());
+        assert_eq!(clock::now(), Duration::from_millis(25));
+        // already high
+        block_on(pin.wait_for_high());
+        assert_eq!(clock::now(), Duration::from_millis(25));
+    }
+
+    #[test]
+    fn test_input_pin_shared() {
+        reset();
+        let mut pin = SimInputPin::new(false);
+        let handle = pin.clone();
+        let trigger = async {
+            SimTimer::after(Duration::from_millis(5)).await;
+            handle.set_high();
+        };
+        block_on(async {
+            let mut wait = core::pin::pin!(pin.wait_for_high());
+            let mut trigger = core::pin::pin!(trigger);
+            poll_fn(|cx| {
+                let _ = trigger.as_mut().poll(cx);
+                wait.as_mut().poll(cx)
+            })
+            .await
+        });
+        assert_eq!(clock::now(), Duration::from_millis(5));
+        assert!(pin.is_high());
+    }
+}
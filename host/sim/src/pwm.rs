@@ -0,0 +1,95 @@
+use core::time::Duration;
+
+use common::PwmBase;
+
+use crate::clock;
+
+#[derive(Clone, Copy, Default)]
+struct PwmChannel {
+    enabled: bool,
+    duty_cycle: u64,
+}
+
+// PWM timer with a given number of channels, a channel is identified by its index
+pub struct SimPwm {
+    channels: Vec<PwmChannel>,
+    max_duty: u64,
+    // every duty cycle set, with the instant and the channel
+    history: Vec<(Duration, usize, u64)>,
+}
+
+impl SimPwm {
+    pub fn new(channels: usize, max_duty: u64) -> Self {
+        Self {
+            channels: vec![PwmChannel::default(); channels],
+            max_duty,
+            history: Vec::new(),
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels.len()
+    }
+
+    pub fn is_enabled(&self, channel: usize) -> bool {
+        self.channels[channel].enabled
+    }
+
+    pub fn get_duty(&self, channel: usize) -> u64 {
+        self.channels[channel].duty_cycle
+    }
+
+    pub fn history(&self) -> &[(Duration, usize, u64)] {
+        &self.history
+    }
+}
+
+impl PwmBase for SimPwm {
+    type Channel = usize;
+
+    fn enable(&mut self, channel: Self::Channel) {
+        self.channels[channel].enabled = true;
+    }
+
+    fn disable(&mut self, channel: Self::Channel) {
+        self.channels[channel].enabled = false;
+    }
+
+    fn get_max_duty(&self) -> u64 {
+        self.max_duty
+    }
+
+    fn set_duty(&mut self, channel: Self::Channel, duty_cycle: u64) {
+        self.channels[channel].duty_cycle = duty_cycle;
+        self.history.push((clock::now(), channel, duty_cycle));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pwm() {
+        clock::reset();
+        let mut pwm = SimPwm::new(4, 4096);
+        pwm.enable(1);
+        pwm.set_duty(1, 2048);
+        clock::advance(Duration::from_millis(1));
+        pwm.set_duty(1, 1024);
+        assert!(pwm.is_enabled(1));
+        assert!(!pwm.is_enabled(0));
+        assert_eq!(pwm.get_duty(1), 1024);
+        assert_eq!(pwm.get_max_duty(), 4096);
+        assert_eq!(
+            pwm.history(),
+            &[
+                (Duration::ZERO, 1, 2048),
+                (Duration::from_millis(1), 1, 1024)
+            ]
+        );
+        pwm.disable(1);
+        assert!(!pwm.is_enabled(1));
+        assert_eq!(pwm.channels(), 4);
+    }
+}
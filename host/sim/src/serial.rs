@@ -0,0 +1,70 @@
+use std::collections::VecDeque;
+
+use common::HalfDuplexSerialBase;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SimSerialError {
+    // nothing has been queued to be read
+    Timeout,
+    // the queued response doesn't have the length of the read
+    Length,
+}
+
+// records every write and answers the reads with the queued responses, in order
+#[derive(Default)]
+pub struct SimSerial {
+    written: Vec<Vec<u8>>,
+    responses: VecDeque<Vec<u8>>,
+}
+
+impl SimSerial {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn queue_response(&mut self, data: &[u8]) {
+        self.responses.push_back(data.to_vec());
+    }
+
+    pub fn written(&self) -> &[Vec<u8>] {
+        &self.written
+    }
+}
+
+impl HalfDuplexSerialBase for SimSerial {
+    type Error = SimSerialError;
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.written.push(data.to_vec());
+        Ok(())
+    }
+
+    async fn read(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
+        let response = self.responses.pop_front().ok_or(SimSerialError::Timeout)?;
+        if response.len() != data.len() {
+            return Err(SimSerialError::Length);
+        }
+        data.copy_from_slice(&response);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::block_on;
+
+    #[test]
+    fn test_serial() {
+        let mut serial = SimSerial::new();
+        serial.queue_response(&[1, 2, 3]);
+        assert!(block_on(serial.write(&[0x05, 0x00])).is_ok());
+        assert_eq!(serial.written(), &[vec![0x05, 0x00]]);
+        let mut data = [0u8; 2];
+        assert_eq!(block_on(serial.read(&mut data)), Err(SimSerialError::Length));
+        assert_eq!(block_on(serial.read(&mut data)), Err(SimSerialError::Timeout));
+        serial.queue_response(&[4, 5]);
+        assert!(block_on(serial.read(&mut data)).is_ok());
+        assert_eq!(data, [4, 5]);
+    }
+}
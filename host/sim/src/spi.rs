@@ -0,0 +1,83 @@
+use std::collections::VecDeque;
+
+use common::SpiBase;
+
+// gets each frame and returns the bytes clocked back
+type Device = Box<dyn FnMut(&[u8]) -> Vec<u8>>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SimSpiError {
+    // nothing has been queued to answer the transfer
+    Timeout,
+    // the queued response doesn't have the length of the transfer
+    Length,
+}
+
+// records the frame sent by every transfer and answers it with the queued responses, in order,
+// or with the responses of a device when there is one
+#[derive(Default)]
+pub struct SimSpi {
+    written: Vec<Vec<u8>>,
+    responses: VecDeque<Vec<u8>>,
+    device: Option<Device>,
+}
+
+impl SimSpi {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_fn(device: impl FnMut(&[u8]) -> Vec<u8> + 'static) -> Self {
+        Self {
+            device: Some(Box::new(device)),
+            ..Self::default()
+        }
+    }
+
+    pub fn queue_response(&mut self, data: &[u8]) {
+        self.responses.push_back(data.to_vec());
+    }
+
+    pub fn written(&self) -> &[Vec<u8>] {
+        &self.written
+    }
+}
+
+impl SpiBase for SimSpi {
+    type Error = SimSpiError;
+
+    async fn transfer(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
+        self.written.push(data.to_vec());
+        let response = match self.device.as_mut() {
+            Some(device) => device(data),
+            None => self.responses.pop_front().ok_or(SimSpiError::Timeout)?,
+        };
+        if response.len() != data.len() {
+            return Err(SimSpiError::Length);
+        }
+        data.copy_from_slice(&response);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::block_on;
+
+    #[test]
+    fn test_spi() {
+        let mut spi = SimSpi::new();
+        spi.queue_response(&[0, 0xE5]);
+        let mut data = [0x80, 0x00];
+        assert!(block_on(spi.transfer(&mut data)).is_ok());
+        assert_eq!(data, [0, 0xE5]);
+        assert_eq!(spi.written(), &[vec![0x80, 0x00]]);
+        assert_eq!(block_on(spi.transfer(&mut data)), Err(SimSpiError::Timeout));
+
+        let mut spi = SimSpi::from_fn(|frame| frame.iter().map(|b| b + 1).collect());
+        let mut data = [1, 2, 3];
+        assert!(block_on(spi.transfer(&mut data)).is_ok());
+        assert_eq!(data, [2, 3, 4]);
+    }
+}
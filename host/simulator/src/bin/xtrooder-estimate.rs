@@ -0,0 +1,128 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use math::measurements::{Density, Distance};
+use simulator::estimate::format_duration;
+use simulator::{Config, PrintEstimator};
+use stepper::estimator::filament_mass;
+
+const USAGE: &str = "usage: xtrooder-estimate [--config <config.toml>] [--diameter <mm>] [--density <g/cm3>] [<file.gcode>]
+
+Estimates the print time and the filament used by the G-code of the file, or of the standard
+input, running it on the planner of the printer without moving it. The time spent heating up
+isn't taken into account. The filament is 1.75mm PLA (1.24g/cm3) by default";
+
+struct Args {
+    config: PathBuf,
+    gcode: Option<PathBuf>,
+    diameter: f64,
+    density: f64,
+}
+
+fn parse_number(value: &str, name: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(v) if v > 0.0 => Ok(v),
+        _ => Err(format!("Invalid {}: {}", name, value)),
+    }
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        config: PathBuf::from("config.toml"),
+        gcode: None,
+        diameter: 1.75,
+        density: 1.24,
+    };
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--config" | "--diameter" | "--density" => {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| format!("Missing value of {}", arg))?;
+                match arg.as_str() {
+                    "--config" => args.config = PathBuf::from(value),
+                    "--diameter" => args.diameter = parse_number(&value, "diameter")?,
+                    _ => args.density = parse_number(&value, "density")?,
+                }
+            }
+            "-h" | "--help" => return Err(String::from(USAGE)),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option: {}", arg)),
+            _ if args.gcode.is_none() => args.gcode = Some(PathBuf::from(arg)),
+            _ => return Err(String::from(USAGE)),
+        }
+    }
+    Ok(args)
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let config = Config::load(&args.config)?;
+    let mut estimator = PrintEstimator::new(&config)?;
+    let input: Box<dyn BufRead> = match &args.gcode {
+        Some(path) => Box::new(BufReader::new(
+            File::open(path).map_err(|e| format!("Cannot open {}: {}", path.display(), e))?,
+        )),
+        None => Box::new(io::stdin().lock()),
+    };
+    for (n, line) in input.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        // the firmware skips the commands it can't execute as well
+        if let Err(e) = estimator.execute(&line) {
+            eprintln!("line {}: {}", n + 1, e);
+        }
+    }
+
+    let estimate = estimator.get_estimate();
+    let mass = filament_mass(
+        estimate.filament,
+        Distance::from_millimeters(args.diameter),
+        // g/cm3 and kg/dm3 are the same
+        Density::from_kilograms_per_cubic_meter(args.density * 1000.0),
+    );
+    println!(
+        "Print time: {} ({:.1}s)",
+        format_duration(estimate.duration),
+        estimate.duration.as_secs_f64()
+    );
+    println!(
+        "Filament: {:.1}mm ({:.2}g)",
+        estimate.filament.as_millimeters(),
+        mass.as_grams()
+    );
+    match estimate.bounds {
+        Some((min, max)) => println!(
+            "Bounding box: X {:.2}..{:.2} Y {:.2}..{:.2} Z {:.2}..{:.2}",
+            min.get_x().as_millimeters(),
+            max.get_x().as_millimeters(),
+            min.get_y().as_millimeters(),
+            max.get_y().as_millimeters(),
+            min.get_z().as_millimeters(),
+            max.get_z().as_millimeters()
+        ),
+        None => println!("Bounding box: no extrusion"),
+    }
+    println!("Layers: {}", estimate.layers);
+    for (n, layer) in estimator.get_layers().iter().enumerate() {
+        println!(
+            "{:>5} Z{:.2} {} {:.1}mm",
+            n + 1,
+            layer.z.as_millimeters(),
+            format_duration(layer.duration),
+            layer.filament.as_millimeters()
+        );
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let result = parse_args().and_then(run);
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
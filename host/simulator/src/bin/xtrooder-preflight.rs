@@ -0,0 +1,75 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use simulator::Config;
+
+const USAGE: &str = "usage: xtrooder-preflight [--config <config.toml>] [<file.gcode>]
+
+Checks the G-code of the file, or of the standard input, against the configuration of the
+printer before printing it. Every move out of the bounds of the axes, every feedrate above the
+limits, every extrusion with a cold hotend and every command the firmware doesn't support is
+reported along with its line";
+
+struct Args {
+    config: PathBuf,
+    gcode: Option<PathBuf>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        config: PathBuf::from("config.toml"),
+        gcode: None,
+    };
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--config" => {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| format!("Missing value of {}", arg))?;
+                args.config = PathBuf::from(value);
+            }
+            "-h" | "--help" => return Err(String::from(USAGE)),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option: {}", arg)),
+            _ if args.gcode.is_none() => args.gcode = Some(PathBuf::from(arg)),
+            _ => return Err(String::from(USAGE)),
+        }
+    }
+    Ok(args)
+}
+
+// number of issues found
+fn run(args: Args) -> Result<usize, String> {
+    let config = Config::load(&args.config)?;
+    let mut preflight = config.preflight()?;
+    let input: Box<dyn BufRead> = match &args.gcode {
+        Some(path) => Box::new(BufReader::new(
+            File::open(path).map_err(|e| format!("Cannot open {}: {}", path.display(), e))?,
+        )),
+        None => Box::new(io::stdin().lock()),
+    };
+    let mut issues = 0;
+    for line in input.lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        if let Err(issue) = preflight.check(&line) {
+            println!("{}", issue);
+            issues += 1;
+        }
+    }
+    println!("{} lines checked, {} issues", preflight.get_line(), issues);
+    Ok(issues)
+}
+
+fn main() -> ExitCode {
+    let result = parse_args().and_then(run);
+    match result {
+        Ok(0) => ExitCode::SUCCESS,
+        Ok(_) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use simulator::{Config, Printer, TraceFormat, TraceWriter};
+
+const USAGE: &str = "usage: xtrooder-sim [--config <config.toml>] [--trace <file>] [--format csv|json] [<file.gcode>]
+
+Runs the G-code of the file, or of the standard input, on a virtual printer and prints the
+feedback of the firmware. The trace holds the steps of every axis and the position reported
+after every command. Its format is given by --format or by the extension of the file (csv by
+default). The exit status is a failure when any of the commands fails";
+
+struct Args {
+    config: PathBuf,
+    gcode: Option<PathBuf>,
+    trace: Option<PathBuf>,
+    format: Option<TraceFormat>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        config: PathBuf::from("config.toml"),
+        gcode: None,
+        trace: None,
+        format: None,
+    };
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--config" | "--trace" | "--format" => {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| format!("Missing value of {}", arg))?;
+                match arg.as_str() {
+                    "--config" => args.config = PathBuf::from(value),
+                    "--trace" => args.trace = Some(PathBuf::from(value)),
+                    _ => args.format = Some(TraceFormat::try_from(value.as_str())?),
+                }
+            }
+            "-h" | "--help" => return Err(String::from(USAGE)),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option: {}", arg)),
+            _ if args.gcode.is_none() => args.gcode = Some(PathBuf::from(arg)),
+            _ => return Err(String::from(USAGE)),
+        }
+    }
+    Ok(args)
+}
+
+fn trace_format(path: &Path, format: Option<TraceFormat>) -> TraceFormat {
+    format.unwrap_or(match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => TraceFormat::Json,
+        _ => TraceFormat::Csv,
+    })
+}
+
+// number of commands that failed
+fn run(args: Args) -> Result<usize, String> {
+    let config = Config::load(&args.config)?;
+    let mut printer = Printer::new(&config)?;
+    let input: Box<dyn BufRead> = match &args.gcode {
+        Some(path) => Box::new(BufReader::new(
+            File::open(path).map_err(|e| format!("Cannot open {}: {}", path.display(), e))?,
+        )),
+        None => Box::new(io::stdin().lock()),
+    };
+    let mut trace = match &args.trace {
+        Some(path) => {
+            let file = File::create(path)
+                .map_err(|e| format!("Cannot create {}: {}", path.display(), e))?;
+            let format = trace_format(path, args.format);
+            Some(TraceWriter::new(BufWriter::new(file), format).map_err(|e| e.to_string())?)
+        }
+        None => None,
+    };
+    let mut stdout = io::stdout().lock();
+    let mut errors = 0;
+    for (n, line) in input.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        match printer.execute(&line) {
+            Ok(feedback) => {
+                for msg in feedback {
+                    writeln!(stdout, "{}", msg).map_err(|e| e.to_string())?;
+                }
+            }
+            // the firmware skips the invalid commands as well
+            Err(e) => {
+                eprintln!("line {}: {}", n + 1, e);
+                errors += 1;
+            }
+        }
+        let events = printer.take_trace();
+        if let Some(trace) = trace.as_mut() {
+            for event in events.iter() {
+                trace.write(event).map_err(|e| e.to_string())?;
+            }
+        }
+    }
+    if let Some(trace) = trace {
+        trace.finish().map_err(|e| e.to_string())?;
+    }
+    eprintln!("Simulated time: {:.3}s", printer.now().as_secs_f64());
+    if errors > 0 {
+        eprintln!("{} commands failed", errors);
+    }
+    Ok(errors)
+}
+
+fn main() -> ExitCode {
+    let result = parse_args().and_then(run);
+    match result {
+        Ok(0) => ExitCode::SUCCESS,
+        Ok(_) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
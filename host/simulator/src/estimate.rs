@@ -0,0 +1,131 @@
+use core::time::Duration;
+
+use config::{Config, StepperConfig};
+use math::DistanceUnit;
+use parser::gcode::{is_blank, GCodeParser, GCommand};
+use sim::{SimInputPin, SimOutputPin, SimSerial, SimTimer};
+use stepper::estimator::{Estimate, Estimator, Layer};
+use stepper::probe::SwitchProbe;
+use stepper::planner::Planner;
+use stepper::stepper::{Attached, Stepper};
+
+pub type SimEstimator =
+    Estimator<SimOutputPin, SimTimer, SimInputPin, SimSerial, SwitchProbe<SimInputPin>>;
+
+fn dry_run_stepper(config: &StepperConfig) -> Result<Stepper<SimOutputPin, Attached>, String> {
+    Ok(Stepper::new_with_attachment(
+        SimOutputPin::new(),
+        SimOutputPin::new(),
+        config.options()?,
+        config.attachment(),
+    ))
+}
+
+// estimation of a G-code program on the planner of the printer described by the config. The
+// planner runs in dry run, so there are no endstops nor drivers to simulate
+pub struct PrintEstimator {
+    estimator: SimEstimator,
+    parser: GCodeParser,
+    layers: Vec<Layer>,
+}
+
+impl PrintEstimator {
+    pub fn new(config: &Config) -> Result<Self, String> {
+        let steppers = &config.steppers;
+        let planner = Planner::new(
+            dry_run_stepper(&steppers.get_x())?,
+            dry_run_stepper(&steppers.get_y())?,
+            dry_run_stepper(&steppers.get_z())?,
+            dry_run_stepper(&steppers.get_e())?,
+            config.motion_config()?,
+            (None, None, None, None),
+            None,
+        );
+        Ok(Self {
+            estimator: Estimator::new(planner),
+            parser: GCodeParser::new(),
+            layers: Vec::new(),
+        })
+    }
+
+    // blank lines and comments are skipped, the lines that aren't valid commands and the moves
+    // the planner refuses are returned as error
+    pub fn execute(&mut self, line: &str) -> Result<(), String> {
+        let command = match self.parser.parse(line) {
+            Some(command) => command,
+            None if is_blank(line) => return Ok(()),
+            None => return Err(format!("Invalid command: {}", line.trim())),
+        };
+        match command {
+            GCommand::G20 => self.parser.set_distance_unit(DistanceUnit::Inch),
+            GCommand::G21 => self.parser.set_distance_unit(DistanceUnit::Millimeter),
+            GCommand::M149 { u } => self.parser.set_temperature_unit(u),
+            command => {
+                let layer = sim::block_on(self.estimator.execute(command))
+                    .map_err(|e| format!("{}", e))?;
+                self.layers.extend(layer);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_estimate(&self) -> Estimate {
+        self.estimator.get_estimate()
+    }
+
+    // completed layers, followed by the one in progress
+    pub fn get_layers(&self) -> Vec<Layer> {
+        let mut layers = self.layers.clone();
+        layers.extend(self.estimator.get_layer());
+        layers
+    }
+}
+
+// 1h 02m 03s
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, minutes, seconds) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("{}h {:02}m {:02}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m {:02}s", minutes, seconds)
+    } else {
+        format!("{:.1}s", duration.as_secs_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_print_estimator() {
+        let path =
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../../board/app/config/config.toml");
+        let config = Config::load(&path).unwrap();
+        let mut estimator = PrintEstimator::new(&config).unwrap();
+        let program = "G28 ; home\nM104 S200\nG21\nM83\nG1 Z0.2 F600\nG1 X10 E1\nG1 Z0.4\n\nG1 X0 E1\n";
+        for line in program.lines() {
+            estimator.execute(line).unwrap();
+        }
+        assert!(estimator.execute("G1 X1000 E1").is_err());
+        assert!(estimator.execute("hello").is_err());
+        let layers = estimator.get_layers();
+        assert_eq!(layers.len(), 2);
+        assert_abs_diff_eq!(layers[0].z.as_millimeters(), 0.2, epsilon = 0.01);
+        assert_abs_diff_eq!(layers[1].z.as_millimeters(), 0.4, epsilon = 0.01);
+        let estimate = estimator.get_estimate();
+        assert_eq!(estimate.layers, 2);
+        assert!(estimate.duration >= layers[0].duration + layers[1].duration);
+        assert!(!estimate.duration.is_zero());
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_millis(12_340)), "12.3s");
+        assert_eq!(format_duration(Duration::from_secs(125)), "2m 05s");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "1h 02m 03s");
+    }
+}
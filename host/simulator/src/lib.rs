@@ -0,0 +1,12 @@
+// virtual printer: the firmware planner and heaters running on the virtual clock of the sim
+// crate, with the axes, endstops and heated blocks modeled on the host
+pub mod estimate;
+pub mod machine;
+pub mod printer;
+pub mod thermal;
+pub mod trace;
+
+pub use config::Config;
+pub use estimate::PrintEstimator;
+pub use printer::Printer;
+pub use trace::{TraceEvent, TraceFormat, TraceKind, TraceWriter};
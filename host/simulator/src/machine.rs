@@ -0,0 +1,262 @@
+use core::future::{poll_fn, Future};
+use core::task::Poll;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use common::{ExtiInputPinBase, OutputPinBase};
+use math::common::RotationDirection;
+use math::measurements::Distance;
+use math::Axis;
+use sim::clock;
+use stepper::motion::HomingDirection;
+
+use crate::trace::{Trace, TraceEvent, TraceKind};
+
+// physical state of an axis, moved by the pulses on its step pin. It's kept apart from the
+// position tracked by the firmware, so the two can be compared
+struct AxisState {
+    axis: Axis,
+    // distance covered by a pulse
+    pulse_distance: Distance,
+    positive_direction: RotationDirection,
+    direction: RotationDirection,
+    pulses: i64,
+    // the endstop, if any, is pressed once the axis reaches this position
+    endstop: Option<(HomingDirection, Distance)>,
+    trace: Trace,
+}
+
+impl AxisState {
+    fn position(&self) -> Distance {
+        self.pulse_distance * self.pulses as f64
+    }
+
+    fn step(&mut self) {
+        let dir = i8::from(self.positive_direction) * i8::from(self.direction);
+        self.pulses += i64::from(dir);
+        let event = TraceEvent {
+            time: clock::now(),
+            kind: TraceKind::Step,
+            axis: self.axis,
+            position: self.position(),
+        };
+        self.trace.borrow_mut().push(event);
+    }
+
+    fn endstop_pressed(&self) -> bool {
+        self.pressed(self.endstop)
+    }
+
+    fn pressed(&self, trigger: Option<(HomingDirection, Distance)>) -> bool {
+        // half a pulse of tolerance, the position is a multiple of the pulse distance
+        let tolerance = self.pulse_distance.as_millimeters() / 2.0;
+        let position = self.position().as_millimeters();
+        match trigger {
+            Some((HomingDirection::Min, p)) => position <= p.as_millimeters() + tolerance,
+            Some((HomingDirection::Max, p)) => position >= p.as_millimeters() - tolerance,
+            None => false,
+        }
+    }
+}
+
+// an axis of the printer: the step and dir pins of its stepper and its endstop share the state
+#[derive(Clone)]
+pub struct SimAxis {
+    state: Rc<RefCell<AxisState>>,
+}
+
+impl SimAxis {
+    pub fn new(
+        axis: Axis,
+        pulse_distance: Distance,
+        positive_direction: RotationDirection,
+        trace: Trace,
+    ) -> Self {
+        Self {
+            state: Rc::new(RefCell::new(AxisState {
+                axis,
+                pulse_distance,
+                positive_direction,
+                direction: RotationDirection::CounterClockwise,
+                pulses: 0,
+                endstop: None,
+                trace,
+            })),
+        }
+    }
+
+    pub fn with_endstop(self, side: HomingDirection, position: Distance) -> Self {
+        self.state.borrow_mut().endstop = Some((side, position));
+        self
+    }
+
+    pub fn position(&self) -> Distance {
+        self.state.borrow().position()
+    }
+
+    pub fn pulses(&self) -> i64 {
+        self.state.borrow().pulses
+    }
+
+    pub fn step_pin(&self) -> SimAxisPin {
+        SimAxisPin {
+            state: self.state.clone(),
+            role: PinRole::Step,
+            high: false,
+        }
+    }
+
+    pub fn dir_pin(&self) -> SimAxisPin {
+        SimAxisPin {
+            state: self.state.clone(),
+            role: PinRole::Dir,
+            high: false,
+        }
+    }
+
+    pub fn endstop(&self) -> SimEndstop {
+        SimEndstop {
+            state: self.state.clone(),
+            bed: None,
+        }
+    }
+
+    // probe touching a flat bed at the given position, meant for the Z axis
+    pub fn probe(&self, bed: Distance) -> SimEndstop {
+        SimEndstop {
+            state: self.state.clone(),
+            bed: Some(bed),
+        }
+    }
+}
+
+enum PinRole {
+    Step,
+    Dir,
+}
+
+pub struct SimAxisPin {
+    state: Rc<RefCell<AxisState>>,
+    role: PinRole,
+    high: bool,
+}
+
+impl OutputPinBase for SimAxisPin {
+    fn set_high(&mut self) {
+        match self.role {
+            // the driver steps on the rising edge
+            PinRole::Step if !self.high => self.state.borrow_mut().step(),
+            PinRole::Step => {}
+            PinRole::Dir => self.state.borrow_mut().direction = RotationDirection::Clockwise,
+        }
+        self.high = true;
+    }
+
+    fn set_low(&mut self) {
+        if let PinRole::Dir = self.role {
+            self.state.borrow_mut().direction = RotationDirection::CounterClockwise;
+        }
+        self.high = false;
+    }
+
+    fn is_high(&self) -> bool {
+        self.high
+    }
+}
+
+// switch that is pressed while the axis is at its position or beyond, or a probe pressed
+// while the axis is on the bed or below it
+pub struct SimEndstop {
+    state: Rc<RefCell<AxisState>>,
+    bed: Option<Distance>,
+}
+
+impl SimEndstop {
+    fn wait_for(&self, level: bool) -> impl Future<Output = ()> + '_ {
+        // the level changes only when the axis steps, so whoever moves it polls again
+        poll_fn(move |_| {
+            if self.is_high() == level {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+    }
+}
+
+impl ExtiInputPinBase for SimEndstop {
+    fn is_high(&self) -> bool {
+        let state = self.state.borrow();
+        match self.bed {
+            Some(bed) => state.pressed(Some((HomingDirection::Min, bed))),
+            None => state.endstop_pressed(),
+        }
+    }
+
+    fn wait_for_high(&mut self) -> impl Future<Output = ()> {
+        self.wait_for(true)
+    }
+
+    fn wait_for_low(&mut self) -> impl Future<Output = ()> {
+        self.wait_for(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::new_trace;
+
+    #[test]
+    fn test_axis_pulses() {
+        clock::reset();
+        let trace = new_trace();
+        let axis = SimAxis::new(
+            Axis::X,
+            Distance::from_millimeters(0.5),
+            RotationDirection::CounterClockwise,
+            trace.clone(),
+        );
+        let mut step = axis.step_pin();
+        let mut dir = axis.dir_pin();
+        // counterclockwise is the positive direction
+        dir.set_low();
+        for _ in 0..4 {
+            step.set_high();
+            step.set_low();
+        }
+        dir.set_high();
+        step.set_high();
+        step.set_low();
+        assert_eq!(axis.pulses(), 3);
+        assert_eq!(axis.position(), Distance::from_millimeters(1.5));
+        assert_eq!(trace.borrow().len(), 5);
+        assert_eq!(trace.borrow()[4].position, Distance::from_millimeters(1.5));
+    }
+
+    #[test]
+    fn test_axis_endstop() {
+        clock::reset();
+        let axis = SimAxis::new(
+            Axis::Z,
+            Distance::from_millimeters(1.0),
+            RotationDirection::Clockwise,
+            new_trace(),
+        )
+        .with_endstop(HomingDirection::Min, Distance::from_millimeters(-2.0));
+        let endstop = axis.endstop();
+        let mut step = axis.step_pin();
+        let mut dir = axis.dir_pin();
+        dir.set_low();
+        step.set_high();
+        step.set_low();
+        assert!(!endstop.is_high());
+        step.set_high();
+        step.set_low();
+        assert!(endstop.is_high());
+        dir.set_high();
+        step.set_high();
+        step.set_low();
+        assert!(!endstop.is_high());
+    }
+}
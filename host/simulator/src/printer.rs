@@ -0,0 +1,857 @@
+use core::time::Duration;
+
+use math::measurements::{Distance, Temperature};
+use math::{Axis, DistanceUnit};
+use parser::gcode::{is_blank, GCodeParser, GCommand};
+use common::ServoBase;
+use config::{Config, StepperConfig};
+use servo::Servo;
+use sim::{block_on, clock, SimAdc, SimAdcResolution, SimPwm, SimSerial, SimTimer};
+use stepper::motion::{HomingConfig, HomingDirection};
+use stepper::dispatch::{dispatch, is_planner_command};
+use stepper::planner::Planner;
+use stepper::probe::SwitchProbe;
+use stepper::stepper::{Attached, Stepper};
+
+use crate::machine::{SimAxis, SimAxisPin, SimEndstop};
+use crate::thermal::{SimHeater, ThermalModel};
+use crate::trace::{new_trace, Trace, TraceEvent, TraceKind};
+
+// same labels as the firmware feedback
+const HOTEND_LABEL: &str = "HOTEND";
+const HEATBED_LABEL: &str = "HEATBED";
+const PLANNER_LABEL: &str = "PLANNER";
+
+// period of the heater loops of the firmware
+const HEATER_DT: Duration = Duration::from_millis(100);
+// M109 and M190 give up after this long, the firmware would wait forever
+const HEATING_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+pub type SimPlanner =
+    Planner<SimAxisPin, SimTimer, SimEndstop, SimSerial, SwitchProbe<SimEndstop>>;
+
+#[derive(Clone, Copy)]
+enum HeaterId {
+    Hotend,
+    Heatbed,
+}
+
+// the printer running the firmware planner and heaters on the virtual clock. The G-code
+// lines are executed one at a time, each one returns the feedback the firmware would send
+pub struct Printer {
+    planner: SimPlanner,
+    parser: GCodeParser,
+    axes: [SimAxis; 4],
+    trace: Trace,
+    pwm: SimPwm,
+    adc: SimAdc,
+    hotend: SimHeater,
+    heatbed: SimHeater,
+    // M280 P0, when the config has a servo
+    servo: Option<Servo<SimPwm>>,
+    // instant of the last iteration of the heater loops
+    last_update: Duration,
+    temperature_report: Option<Duration>,
+    report_counter: Duration,
+    feedback: Vec<String>,
+}
+
+fn sim_axis(axis: Axis, config: &StepperConfig, trace: &Trace) -> Result<SimAxis, String> {
+    let options = config.options()?;
+    let pulse_distance = Distance::from_millimeters(
+        config.get_distance_per_step() / f64::from(u8::from(options.stepping_mode)),
+    );
+    Ok(SimAxis::new(
+        axis,
+        pulse_distance,
+        options.positive_direction,
+        trace.clone(),
+    ))
+}
+
+fn sim_stepper(
+    sim_axis: &SimAxis,
+    config: &StepperConfig,
+) -> Result<Stepper<SimAxisPin, Attached>, String> {
+    Ok(Stepper::new_with_attachment(
+        sim_axis.step_pin(),
+        sim_axis.dir_pin(),
+        config.options()?,
+        config.attachment(),
+    ))
+}
+
+// the endstop sits on the bound of the homing side, where homing sets the position
+fn with_endstop(sim_axis: SimAxis, config: &StepperConfig, homing: &HomingConfig) -> SimAxis {
+    let position = match homing.direction {
+        HomingDirection::Min => config.get_bounds().min,
+        HomingDirection::Max => config.get_bounds().max,
+    };
+    sim_axis.with_endstop(homing.direction, Distance::from_millimeters(position))
+}
+
+impl Printer {
+    // the virtual clock is reset, so the print starts at 0
+    pub fn new(config: &Config) -> Result<Self, String> {
+        clock::reset();
+        let motion = config.motion_config()?;
+        let steppers = &config.steppers;
+        let (x_config, y_config) = (steppers.get_x(), steppers.get_y());
+        let (z_config, e_config) = (steppers.get_z(), steppers.get_e());
+        let trace = new_trace();
+        let homing = motion.homing.axes;
+        let x = with_endstop(
+            sim_axis(Axis::X, &x_config, &trace)?,
+            &x_config,
+            &homing.0,
+        );
+        let y = with_endstop(
+            sim_axis(Axis::Y, &y_config, &trace)?,
+            &y_config,
+            &homing.1,
+        );
+        let z = with_endstop(
+            sim_axis(Axis::Z, &z_config, &trace)?,
+            &z_config,
+            &homing.2,
+        );
+        let e = sim_axis(Axis::E, &e_config, &trace)?;
+        // the board has no endstop on E and sensorless homing needs the drivers, which
+        // aren't simulated
+        let endstops = (
+            Some(x.endstop()),
+            Some(y.endstop()),
+            Some(z.endstop()),
+            None,
+        );
+        let mut planner = Planner::new(
+            sim_stepper(&x, &x_config)?,
+            sim_stepper(&y, &y_config)?,
+            sim_stepper(&z, &z_config)?,
+            sim_stepper(&e, &e_config)?,
+            motion,
+            endstops,
+            None,
+        );
+        // the bed is flat, at Z 0
+        planner.set_probe(Some(SwitchProbe::new(z.probe(Distance::from_millimeters(0.0)))));
+        let pwm = SimPwm::new(4, 4096);
+        let mut adc = SimAdc::new(SimAdcResolution(12));
+        let hotend = SimHeater::new(&config.hotend, ThermalModel::hotend(), &pwm, &mut adc)?;
+        let heatbed = SimHeater::new(&config.heatbed, ThermalModel::heatbed(), &pwm, &mut adc)?;
+        // a step of the duty cycle of the servo timer is 1us long
+        let servo = match &config.servo {
+            Some(servo) => Some(Servo::new(SimPwm::new(1, 20000), 0, servo.config()?)),
+            None => None,
+        };
+        Ok(Self {
+            planner,
+            parser: GCodeParser::new(),
+            axes: [x, y, z, e],
+            trace,
+            pwm,
+            adc,
+            hotend,
+            heatbed,
+            servo,
+            last_update: Duration::ZERO,
+            temperature_report: None,
+            report_counter: Duration::ZERO,
+            feedback: Vec::new(),
+        })
+    }
+
+    pub fn get_planner(&self) -> &SimPlanner {
+        &self.planner
+    }
+
+    // physical position of the axis, given by the pulses received by its driver
+    pub fn get_axis_position(&self, axis: Axis) -> Distance {
+        self.axis(axis).position()
+    }
+
+    pub fn get_axis_pulses(&self, axis: Axis) -> i64 {
+        self.axis(axis).pulses()
+    }
+
+    pub fn get_hotend(&self) -> &SimHeater {
+        &self.hotend
+    }
+
+    pub fn get_heatbed(&self) -> &SimHeater {
+        &self.heatbed
+    }
+
+    pub fn now(&self) -> Duration {
+        clock::now()
+    }
+
+    // events recorded since the last call
+    pub fn take_trace(&mut self) -> Vec<TraceEvent> {
+        core::mem::take(&mut *self.trace.borrow_mut())
+    }
+
+    fn axis(&self, axis: Axis) -> &SimAxis {
+        match axis {
+            Axis::X => &self.axes[0],
+            Axis::Y => &self.axes[1],
+            Axis::Z => &self.axes[2],
+            Axis::E => &self.axes[3],
+        }
+    }
+
+    fn report(&mut self, label: &str, msg: String) {
+        self.report_at(clock::now(), label, msg);
+    }
+
+    fn report_at(&mut self, time: Duration, label: &str, msg: String) {
+        self.feedback
+            .push(format!("[{}] [{}] {}", time.as_millis(), label, msg));
+    }
+
+    // execute a G-code line and return the feedback. Blank lines and comments are skipped,
+    // the lines that aren't valid commands are returned as error
+    pub fn execute(&mut self, line: &str) -> Result<Vec<String>, String> {
+        let command = match self.parser.parse(line) {
+            Some(command) => command,
+            None if is_blank(line) => return Ok(Vec::new()),
+            None => return Err(format!("Invalid command: {}", line.trim())),
+        };
+        match command {
+            GCommand::G20 => self.parser.set_distance_unit(DistanceUnit::Inch),
+            GCommand::G21 => self.parser.set_distance_unit(DistanceUnit::Millimeter),
+            GCommand::M149 { u } => self.parser.set_temperature_unit(u),
+            command if is_planner_command(&command) => {
+                let mut lines = Vec::new();
+                let mut report = |args: core::fmt::Arguments| lines.push(format!("{}", args));
+                // the errors are in the feedback already
+                let _ = block_on(dispatch(&mut self.planner, command, &mut report));
+                for line in lines {
+                    self.report(PLANNER_LABEL, line);
+                }
+            }
+            // the angle of the servo is reported without s
+            GCommand::M280 { p, s } => match (self.servo.as_mut(), p, s) {
+                (Some(servo), 0, Some(angle)) => servo.set_angle(angle),
+                (Some(servo), 0, None) => {
+                    let msg = match servo.get_angle() {
+                        Some(angle) => format!("Servo {} angle: {:.1}", p, angle),
+                        None => format!("Servo {} detached", p),
+                    };
+                    self.report(PLANNER_LABEL, msg);
+                }
+                _ => self.report(PLANNER_LABEL, format!("Servo {} not found", p)),
+            },
+            GCommand::M104 { s } => self.hotend.set_temperature(s, &mut self.pwm),
+            GCommand::M140 { s } => self.heatbed.set_temperature(s, &mut self.pwm),
+            GCommand::M109 { s } => self.wait_for_temperature(HeaterId::Hotend, s),
+            GCommand::M190 { s } => self.wait_for_temperature(HeaterId::Heatbed, s),
+            GCommand::M105 => {
+                let msg = format!("Temperature: {}", self.hotend.get_temperature());
+                self.report(HOTEND_LABEL, msg);
+                let msg = format!("Temperature: {}", self.heatbed.get_temperature());
+                self.report(HEATBED_LABEL, msg);
+            }
+            GCommand::M155 { s } => {
+                self.temperature_report = Some(s);
+                self.report_counter = Duration::ZERO;
+            }
+            // fans, SD-card and drivers aren't simulated
+            _ => {}
+        }
+        self.update_heaters();
+        self.record_position();
+        Ok(core::mem::take(&mut self.feedback))
+    }
+
+    fn record_position(&mut self) {
+        let time = clock::now();
+        let positions = [
+            (Axis::X, self.planner.get_x_position()),
+            (Axis::Y, self.planner.get_y_position()),
+            (Axis::Z, self.planner.get_z_position()),
+            (Axis::E, self.planner.get_e_position()),
+        ];
+        let mut trace = self.trace.borrow_mut();
+        for (axis, position) in positions {
+            trace.push(TraceEvent {
+                time,
+                kind: TraceKind::Position,
+                axis,
+                position,
+            });
+        }
+    }
+
+    // run the iterations of the heater loops the firmware would have run until now
+    fn update_heaters(&mut self) {
+        while self.last_update + HEATER_DT <= clock::now() {
+            self.last_update += HEATER_DT;
+            self.update_heaters_once();
+        }
+    }
+
+    fn update_heaters_once(&mut self) {
+        let hotend = self.hotend.update(HEATER_DT, &mut self.pwm, &mut self.adc);
+        let heatbed = self.heatbed.update(HEATER_DT, &mut self.pwm, &mut self.adc);
+        // the iterations may run late, when catching up after a move
+        let time = self.last_update;
+        // an overheating heater turns off both of them, as the firmware does
+        let mut overheating = false;
+        if hotend > self.hotend.limit.1 {
+            self.report_at(
+                time,
+                HOTEND_LABEL,
+                format!("Hotend overheating: {}C", hotend.as_celsius()),
+            );
+            overheating = true;
+        }
+        if heatbed > self.heatbed.limit.1 {
+            self.report_at(
+                time,
+                HEATBED_LABEL,
+                format!("Heatbed overheating: {}C", heatbed.as_celsius()),
+            );
+            overheating = true;
+        }
+        if overheating {
+            self.hotend.disable(&mut self.pwm);
+            self.heatbed.disable(&mut self.pwm);
+        }
+        if let Some(period) = self.temperature_report {
+            self.report_counter += HEATER_DT;
+            if self.report_counter >= period {
+                self.report_counter = Duration::ZERO;
+                let msg = format!("Temperature: {:.2}°C", hotend.as_celsius());
+                self.report_at(time, HOTEND_LABEL, msg);
+                let msg = format!("Temperature: {:.2}°C", heatbed.as_celsius());
+                self.report_at(time, HEATBED_LABEL, msg);
+            }
+        }
+    }
+
+    fn wait_for_temperature(&mut self, id: HeaterId, target: Temperature) {
+        let start = clock::now();
+        loop {
+            let (temperature, label) = match id {
+                HeaterId::Hotend => (self.hotend.get_temperature(), HOTEND_LABEL),
+                HeaterId::Heatbed => (self.heatbed.get_temperature(), HEATBED_LABEL),
+            };
+            if temperature >= target {
+                return;
+            }
+            if clock::now() - start >= HEATING_TIMEOUT {
+                let msg = format!("Timeout waiting for temperature: {}", target);
+                self.report(label, msg);
+                return;
+            }
+            clock::advance_to(self.last_update + HEATER_DT);
+            self.update_heaters();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    const CONFIG: &str = r#"
+[motion]
+feedrate = 3000.0
+positioning = "absolute"
+e_positioning = "relative"
+feedrate_multiplier = 1
+flow_multiplier = 1
+filament_diameter = 0.0
+
+[motion.limits]
+print_acceleration = 1000.0
+retract_acceleration = 1000.0
+travel_acceleration = 1500.0
+
+[motion.limits.max_feedrate]
+x = 18000.0
+y = 18000.0
+z = 600.0
+e = 3000.0
+
+[motion.limits.max_acceleration]
+x = 3000.0
+y = 3000.0
+z = 100.0
+e = 10000.0
+
+[motion.limits.jerk]
+x = 600.0
+y = 600.0
+z = 18.0
+e = 300.0
+
+[motion.retraction]
+feedrate = 1800.0
+length = 2.0
+z_lift = 0.0
+
+[motion.recover]
+feedrate = 1800.0
+length = 2.0
+
+[motion.software_endstops]
+enabled = true
+clip = false
+
+[motion.arcs]
+tolerance = 0.01
+min_segment_length = 0.1
+max_segment_length = 1.0
+min_segment_duration = 5.0
+
+[motion.leveling]
+points.x = 3
+points.y = 3
+min.x = 10.0
+min.y = -10.0
+max.x = 40.0
+max.y = 10.0
+fade_height = 10.0
+
+[motion.probe]
+offset.x = 0.0
+offset.y = 0.0
+offset.z = 0.0
+clearance = 5.0
+max_depth = 10.0
+feedrate = 300.0
+travel_feedrate = 3000.0
+samples = 2
+retract = 1.0
+
+[motion.alignment]
+points = [{ x = 10.0, y = 0.0 }, { x = 40.0, y = 0.0 }]
+iterations = 3
+accuracy = 0.02
+
+[motion.backlash]
+x = 0.0
+y = 0.0
+z = 0.0
+correction = 1.0
+smoothing = 0.0
+
+[motion.skew]
+xy = 0.0
+xz = 0.0
+yz = 0.0
+
+[motion.input_shaping.x]
+shaper = "zv"
+frequency = 0.0
+damping = 0.1
+
+[motion.input_shaping.y]
+shaper = "zv"
+frequency = 0.0
+damping = 0.1
+
+[motion.linear_advance]
+k = 0.0
+max_feedrate = 3000.0
+
+[motion.homing]
+order = "xyz"
+z_lift = 0.0
+required = true
+
+[motion.homing.x]
+direction = "max"
+fast_speed = 3000.0
+slow_speed = 600.0
+bump = 2.0
+offset = 0.0
+sensorless = false
+min_travel = 0.0
+backoff = 5.0
+
+[motion.homing.y]
+direction = "min"
+fast_speed = 3000.0
+slow_speed = 600.0
+bump = 0.0
+offset = 0.0
+sensorless = false
+min_travel = 0.0
+backoff = 5.0
+
+[motion.homing.z]
+direction = "min"
+fast_speed = 600.0
+slow_speed = 300.0
+bump = 0.0
+offset = 0.0
+sensorless = false
+min_travel = 0.0
+backoff = 1.0
+
+[steppers.x]
+stepping_mode = "quarter"
+distance_per_step = 0.2
+steps_per_revolution = 200
+bounds.min = 0
+bounds.max = 50
+positive_direction = "counterclockwise"
+
+[steppers.y]
+stepping_mode = "half"
+distance_per_step = 0.2
+steps_per_revolution = 200
+bounds.min = -20
+bounds.max = 20
+positive_direction = "clockwise"
+
+[steppers.z]
+stepping_mode = "full"
+distance_per_step = 0.05
+steps_per_revolution = 200
+bounds.min = 0
+bounds.max = 100
+positive_direction = "clockwise"
+
+[steppers.e]
+stepping_mode = "quarter"
+distance_per_step = 0.19
+steps_per_revolution = 200
+bounds.min = -1000
+bounds.max = 1000
+positive_direction = "clockwise"
+
+[hotend.heater]
+pwm.channel = 1
+max_temperature_limit = 250
+min_temperature_limit = 180
+
+[hotend.heater.pid]
+k_p = 1.7
+k_i = 0.01
+k_d = 0
+
+[hotend.thermistor]
+r_series = 10000
+r0 = 100000
+b = 3676.85
+samples = 5
+
+[heatbed.heater]
+pwm.channel = 2
+max_temperature_limit = 100
+min_temperature_limit = 30
+
+[heatbed.heater.pid]
+k_p = 5000
+k_i = 4
+k_d = 0
+
+[heatbed.thermistor]
+r_series = 10000
+r0 = 100000
+b = 3676.85
+samples = 5
+
+[preflight]
+enabled = true
+min_extrusion_temperature = 170.0
+
+[servo]
+min_pulse = 1000
+max_pulse = 2000
+max_angle = 180
+"#;
+
+    fn printer() -> Printer {
+        Printer::new(&Config::parse(CONFIG).unwrap()).unwrap()
+    }
+
+    fn run(printer: &mut Printer, gcode: &str) -> Vec<String> {
+        gcode
+            .lines()
+            .flat_map(|line| printer.execute(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_printer_home_and_move() {
+        let mut printer = printer();
+        let feedback = run(&mut printer, "G1 X10\nG28 X Y\nG1 X20 Y5.3 E1.0 F1200\n");
+        assert_eq!(feedback.len(), 1);
+        assert!(feedback[0].ends_with("[PLANNER] Axis X not homed"));
+        // the physical axes are where the firmware thinks they are
+        let planner = printer.get_planner();
+        for (axis, position) in [
+            (Axis::X, planner.get_x_position()),
+            (Axis::Y, planner.get_y_position()),
+            (Axis::E, planner.get_e_position()),
+        ] {
+            assert_abs_diff_eq!(
+                printer.get_axis_position(axis).as_millimeters(),
+                position.as_millimeters(),
+                epsilon = 0.000001
+            );
+        }
+        assert_abs_diff_eq!(
+            planner.get_x_position().as_millimeters(),
+            20.0,
+            epsilon = 0.05
+        );
+        assert_abs_diff_eq!(
+            planner.get_y_position().as_millimeters(),
+            5.3,
+            epsilon = 0.05
+        );
+        // the endstops are hit on the bounds: x homes at 50 and y at -20
+        assert_eq!(printer.get_axis_pulses(Axis::X), 400);
+        assert!(printer.now() > Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_printer_workspaces() {
+        let mut printer = printer();
+        let program = "G28 X\nG1 X40 F600\nG92 X0\nG1 X-30\nG53 G0 X45\nM114\n";
+        let feedback = run(&mut printer, program);
+        assert_eq!(feedback.len(), 2);
+        assert!(feedback[0].contains("[PLANNER] Head position: "));
+        assert!(feedback[1].contains("[PLANNER] Machine position: "));
+        // the head is at 45 for the steppers and at 5 for the G-code
+        let planner = printer.get_planner();
+        assert_abs_diff_eq!(planner.get_x_position().as_millimeters(), 5.0, epsilon = 0.05);
+        let machine = planner.get_machine_position().0.as_millimeters();
+        assert_abs_diff_eq!(machine, 45.0, epsilon = 0.05);
+        assert_abs_diff_eq!(
+            printer.get_axis_position(Axis::X).as_millimeters(),
+            machine,
+            epsilon = 0.000001
+        );
+        run(&mut printer, "G92.1\n");
+        let x = printer.get_planner().get_x_position().as_millimeters();
+        assert_abs_diff_eq!(x, 45.0, epsilon = 0.05);
+    }
+
+    #[test]
+    fn test_printer_trace() {
+        let mut printer = printer();
+        run(&mut printer, "G28 X\nG1 X40 F600\n");
+        let trace = printer.take_trace();
+        let steps: Vec<&TraceEvent> = trace
+            .iter()
+            .filter(|e| e.kind == TraceKind::Step && e.axis == Axis::X)
+            .collect();
+        assert!(steps.windows(2).all(|w| w[0].time <= w[1].time));
+        let last = trace.last().unwrap();
+        assert_eq!(last.kind, TraceKind::Position);
+        assert_eq!(last.axis, Axis::E);
+        assert_eq!(trace[trace.len() - 4].axis, Axis::X);
+        assert_abs_diff_eq!(
+            trace[trace.len() - 4].position.as_millimeters(),
+            40.0,
+            epsilon = 0.05
+        );
+        assert!(printer.take_trace().is_empty());
+    }
+
+    #[test]
+    fn test_printer_errors() {
+        let mut printer = printer();
+        assert!(printer.execute("G28 X Y").unwrap().is_empty());
+        let feedback = printer.execute("G1 X60").unwrap();
+        assert_eq!(feedback.len(), 1);
+        assert!(feedback[0].ends_with("[PLANNER] Axis X out of bounds: 60.000mm"));
+        // the move is refused before X starts moving
+        assert_abs_diff_eq!(
+            printer.get_axis_position(Axis::X).as_millimeters(),
+            printer.get_planner().get_x_position().as_millimeters(),
+            epsilon = 0.000001
+        );
+        let feedback = printer.execute("M211 S0").unwrap();
+        assert_eq!(feedback.len(), 1);
+        assert!(feedback[0].contains("[PLANNER] Software endstops: Off [X:"));
+        assert!(printer.execute("; comment").unwrap().is_empty());
+        assert!(printer.execute("").unwrap().is_empty());
+        assert!(printer.execute("X10 G1").is_err());
+    }
+
+    #[test]
+    fn test_printer_leveling() {
+        let mut printer = printer();
+        let feedback = run(&mut printer, "G29 T\nG28\nG29\nG29 T\nM420 S0 Z5\n");
+        assert_eq!(feedback.len(), 6);
+        assert!(feedback[0].ends_with("[PLANNER] No bed mesh"));
+        assert!(feedback[1].ends_with("[PLANNER] Bed mesh 3x3"));
+        // the bed is flat, the probe touches it at Z 0
+        assert!(feedback[2].ends_with("[PLANNER] [Y:10.000] 0.000 0.000 0.000"));
+        assert!(feedback[4].ends_with("[PLANNER] [Y:-10.000] 0.000 0.000 0.000"));
+        assert!(feedback[5].ends_with("[PLANNER] Bed leveling: Off [fade height:5.000] [mesh:3x3]"));
+        // the probe is left at the clearance
+        assert_abs_diff_eq!(
+            printer.get_axis_position(Axis::Z).as_millimeters(),
+            5.0,
+            epsilon = 0.000001
+        );
+    }
+
+    #[test]
+    fn test_printer_probe() {
+        let mut printer = printer();
+        let feedback = run(&mut printer, "G28\nG30 X20 Y0\nM851 Z-1.5\nG30 X20 Y0\nM48 P3\n");
+        assert_eq!(feedback.len(), 4);
+        // the bed is flat at Z 0
+        assert!(feedback[0].ends_with("[PLANNER] Bed [X:20.000] [Y:0.000] [Z:0.000]"));
+        assert!(feedback[1].ends_with("[PLANNER] Probe offset: [X:0.000] [Y:0.000] [Z:-1.500]"));
+        // the probe triggers 1.5mm below the nozzle
+        assert!(feedback[2].ends_with("[PLANNER] Bed [X:20.000] [Y:0.000] [Z:-1.500]"));
+        assert!(feedback[3].ends_with(
+            "[PLANNER] Probe repeatability: [samples:3] [mean:-1.5000] [min:-1.5000] [max:-1.5000] [std dev:0.0000]"
+        ));
+        assert_abs_diff_eq!(
+            printer.get_axis_position(Axis::Z).as_millimeters(),
+            5.0,
+            epsilon = 0.000001
+        );
+    }
+
+    #[test]
+    fn test_printer_backlash() {
+        let mut printer = printer();
+        let feedback = run(&mut printer, "M425 F0.5 S2 Z0.1\nM425 F2\n");
+        assert_eq!(feedback.len(), 3);
+        assert!(feedback[0].ends_with(
+            "[PLANNER] Backlash: [correction:0.50] [smoothing:2.000] [X:0.000] [Y:0.000] [Z:0.100]"
+        ));
+        assert!(feedback[1].ends_with("[PLANNER] Move not valid"));
+        assert!(feedback[2].ends_with(
+            "[PLANNER] Backlash: [correction:0.50] [smoothing:2.000] [X:0.000] [Y:0.000] [Z:0.100]"
+        ));
+    }
+
+    #[test]
+    fn test_printer_skew() {
+        let mut printer = printer();
+        let feedback = run(&mut printer, "G28\nM852 I0.01\nG1 X20 Y10 F3000\n");
+        assert_eq!(feedback.len(), 1);
+        assert!(feedback[0].ends_with("[PLANNER] Skew: [XY:0.010000] [XZ:0.000000] [YZ:0.000000]"));
+        // the planner reports the commanded position, the axis moves by the correction
+        assert_abs_diff_eq!(
+            printer.get_planner().get_x_position().as_millimeters(),
+            20.0,
+            epsilon = 0.000001
+        );
+        assert_abs_diff_eq!(
+            printer.get_axis_position(Axis::X).as_millimeters(),
+            19.9,
+            epsilon = 0.000001
+        );
+    }
+
+    #[test]
+    fn test_printer_input_shaping() {
+        let mut printer = printer();
+        let feedback = run(&mut printer, "M593 Y T1 F45.5 D0.05\nM593 T9\n");
+        assert_eq!(feedback.len(), 3);
+        assert!(feedback[0]
+            .ends_with("[PLANNER] Input shaping: [X:ZV F0.00 D0.100] [Y:MZV F45.50 D0.050]"));
+        assert!(feedback[1].ends_with("[PLANNER] Move not valid"));
+        assert!(feedback[2]
+            .ends_with("[PLANNER] Input shaping: [X:ZV F0.00 D0.100] [Y:MZV F45.50 D0.050]"));
+    }
+
+    #[test]
+    fn test_printer_linear_advance() {
+        let mut printer = printer();
+        let feedback = run(&mut printer, "M900 K0.04\nM900 K-1\nM900\n");
+        assert_eq!(feedback.len(), 4);
+        assert!(feedback[0].ends_with("[PLANNER] Linear advance: [K:0.040]"));
+        assert!(feedback[1].ends_with("[PLANNER] Move not valid"));
+        assert!(feedback[2].ends_with("[PLANNER] Linear advance: [K:0.040]"));
+        assert!(feedback[3].ends_with("[PLANNER] Linear advance: [K:0.040]"));
+    }
+
+    #[test]
+    fn test_printer_extrusion() {
+        use std::f64::consts::PI;
+        let mut printer = printer();
+        let feedback = run(&mut printer, "M221 S90\nM200 D1.75\nM221 S-1\nM503\n");
+        assert_eq!(feedback.len(), 9);
+        assert!(feedback[0].ends_with("[PLANNER] Move not valid"));
+        assert!(feedback[1].contains("[PLANNER] Limits: "));
+        assert!(feedback[2].ends_with("[PLANNER] Extrusion: [flow:90.0%] [volumetric:1.750]"));
+        assert!(feedback[3].ends_with("[PLANNER] Linear advance: [K:0.000]"));
+        assert!(feedback[8].contains("[PLANNER] Software endstops: On"));
+        // 10mm³ are about 3.7mm of filament at 90%
+        run(&mut printer, "G1 E10\n");
+        let e = printer.planner.get_e_position().as_millimeters();
+        assert_abs_diff_eq!(e, 10.0 / (PI * 0.875 * 0.875) * 0.9, epsilon = 0.025);
+        let feedback = run(&mut printer, "M200 D0\nM221 S100\nM503\n");
+        assert!(feedback[1].ends_with("[PLANNER] Extrusion: [flow:100.0%] [volumetric:Off]"));
+    }
+
+    #[test]
+    fn test_printer_limits() {
+        let mut printer = printer();
+        let feedback = run(&mut printer, "M203 E2\nM203 X0\nM204 P500\nM503\n");
+        assert_eq!(feedback.len(), 9);
+        assert!(feedback[0].ends_with("[PLANNER] Move not valid"));
+        assert!(feedback[1].ends_with(
+            "[PLANNER] Limits: [feedrate X:300.0 Y:300.0 Z:10.0 E:2.0] \
+             [acceleration X:3000 Y:3000 Z:100 E:10000] [P:500 R:1000 T:1500] \
+             [jerk X:10.0 Y:10.0 Z:0.3 E:5.0]"
+        ));
+        // 4mm of filament at 2mm/s, whatever the feedrate asked
+        let start = printer.now();
+        run(&mut printer, "G1 E4 F6000\n");
+        let elapsed = printer.now() - start;
+        assert!(elapsed >= Duration::from_millis(1990));
+        assert!(elapsed < Duration::from_millis(2100));
+    }
+
+    #[test]
+    fn test_printer_z_alignment() {
+        let mut printer = printer();
+        let feedback = run(&mut printer, "G34\nG28\nG34\n");
+        assert_eq!(feedback.len(), 2);
+        assert!(feedback[0].ends_with("[PLANNER] Axis X not homed"));
+        // the simulated Z axis is driven by a single motor
+        assert!(feedback[1].ends_with("[PLANNER] Move not supported"));
+    }
+
+    #[test]
+    fn test_printer_servo() {
+        let mut printer = printer();
+        let feedback = run(&mut printer, "M280 P0\nM280 P0 S90\nM280 P0\nM280 P1 S10\n");
+        assert_eq!(feedback.len(), 3);
+        assert!(feedback[0].ends_with("[PLANNER] Servo 0 detached"));
+        assert!(feedback[1].ends_with("[PLANNER] Servo 0 angle: 90.0"));
+        assert!(feedback[2].ends_with("[PLANNER] Servo 1 not found"));
+        assert_eq!(printer.servo.as_ref().unwrap().pulse_width(90.0), Duration::from_micros(1500));
+    }
+
+    #[test]
+    fn test_printer_heating() {
+        let mut printer = printer();
+        run(&mut printer, "M104 S200\nM140 S60\nM190 S60\nM109 S200\n");
+        assert!(printer.get_hotend().get_temperature().as_celsius() >= 200.0);
+        assert!(printer.get_heatbed().get_temperature().as_celsius() >= 60.0);
+        assert!(printer.now() > Duration::from_secs(10));
+        let feedback = printer.execute("M105").unwrap();
+        assert_eq!(feedback.len(), 2);
+        assert!(feedback[0].contains("[HOTEND] Temperature: "));
+        assert!(feedback[1].contains("[HEATBED] Temperature: "));
+    }
+
+    #[test]
+    fn test_printer_heating_timeout() {
+        let mut printer = printer();
+        // nothing heats the hotend
+        let feedback = printer.execute("M109 S200").unwrap();
+        assert_eq!(feedback.len(), 1);
+        assert!(feedback[0].contains("[HOTEND] Timeout waiting for temperature"));
+        assert!(printer.now() >= HEATING_TIMEOUT);
+    }
+}
@@ -0,0 +1,188 @@
+use core::time::Duration;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use common::{AdcBase, PwmBase};
+use config::ThermalActuatorConfig;
+use math::measurements::Temperature;
+use sim::{block_on, AdcSource, SimAdc, SimPwm};
+use thermal_actuator::controller::ThermalActuator;
+use thermal_actuator::heater::Heater;
+use thermal_actuator::thermistor::{DmaBufType, Thermistor, ThermistorConfig};
+
+const AMBIENT_TEMPERATURE: f64 = 25.0;
+
+// first-order model of a heated block: the heater power warms it up, the losses toward
+// the ambient are proportional to the difference of temperature
+#[derive(Clone, Copy, Debug)]
+pub struct ThermalModel {
+    // °C
+    pub temperature: f64,
+    // W, at full duty cycle
+    pub power: f64,
+    // J/K
+    pub capacity: f64,
+    // W/K
+    pub loss: f64,
+}
+
+impl ThermalModel {
+    pub fn hotend() -> Self {
+        Self {
+            temperature: AMBIENT_TEMPERATURE,
+            power: 40.0,
+            capacity: 10.0,
+            loss: 0.15,
+        }
+    }
+
+    pub fn heatbed() -> Self {
+        Self {
+            temperature: AMBIENT_TEMPERATURE,
+            power: 200.0,
+            capacity: 400.0,
+            loss: 1.5,
+        }
+    }
+
+    // strength goes from 0 (off) to 1 (full power)
+    pub fn update(&mut self, dt: Duration, strength: f64) {
+        let power = self.power * strength - self.loss * (self.temperature - AMBIENT_TEMPERATURE);
+        self.temperature += power / self.capacity * dt.as_secs_f64();
+    }
+}
+
+// ADC sample of the thermistor divider at the given temperature, that is the inverse of
+// the conversion done by the thermistor
+fn thermistor_sample(temperature: f64, config: &ThermistorConfig, max_sample: u64) -> u16 {
+    let t0 = Temperature::from_celsius(25.0).as_kelvin();
+    let t = Temperature::from_celsius(temperature).as_kelvin();
+    let r0 = config.r0.as_ohms();
+    let r_series = config.r_series.as_ohms();
+    let r_ntc = r0 * (config.b.as_kelvin() * (1.0 / t - 1.0 / t0)).exp();
+    let sample = max_sample as f64 * r_ntc / (r_ntc + r_series);
+    sample.round().clamp(1.0, (max_sample - 1) as f64) as u16
+}
+
+// the firmware thermal actuator (heater, PID and thermistor) driving a thermal model
+// through the simulated PWM and ADC
+pub struct SimHeater {
+    actuator: ThermalActuator<'static, SimPwm, SimAdc>,
+    channel: usize,
+    model: Rc<RefCell<ThermalModel>>,
+    // min and max temperature
+    pub limit: (Temperature, Temperature),
+    last_temperature: Temperature,
+}
+
+impl SimHeater {
+    pub fn new(
+        config: &ThermalActuatorConfig,
+        model: ThermalModel,
+        pwm: &SimPwm,
+        adc: &mut SimAdc,
+    ) -> Result<Self, String> {
+        let channel = usize::from(config.heater.get_pwm().get_channel());
+        if pwm.channels() <= channel {
+            return Err(format!("Invalid PWM channel: {}", channel));
+        }
+        let thermistor_config = config.thermistor.config();
+        let max_sample: u64 = adc.resolution().into();
+        let model = Rc::new(RefCell::new(model));
+        let source = {
+            let model = model.clone();
+            AdcSource::function(move |_| {
+                thermistor_sample(model.borrow().temperature, &thermistor_config, max_sample)
+            })
+        };
+        let pin = adc.add_source(source);
+        // the firmware keeps the DMA buffer in a static, a leaked one has the same lifetime
+        let readings: &'static mut DmaBufType = Box::leak(Box::new([0u16; 1]));
+        let thermistor = Thermistor::new(pin, readings, thermistor_config);
+        let heater = Heater::new(channel, config.heater.pid_config());
+        Ok(Self {
+            actuator: ThermalActuator::new(heater, thermistor),
+            channel,
+            model,
+            limit: config.heater.temperature_limit(),
+            last_temperature: Temperature::from_celsius(AMBIENT_TEMPERATURE),
+        })
+    }
+
+    pub fn set_temperature(&mut self, temperature: Temperature, pwm: &mut SimPwm) {
+        self.actuator.set_temperature(temperature);
+        self.actuator.enable(pwm);
+    }
+
+    pub fn disable(&mut self, pwm: &mut SimPwm) {
+        self.actuator.disable(pwm);
+    }
+
+    // temperature read by the firmware on the last update
+    pub fn get_temperature(&self) -> Temperature {
+        self.last_temperature
+    }
+
+    pub fn get_model(&self) -> ThermalModel {
+        *self.model.borrow()
+    }
+
+    // one iteration of the heater loop of the firmware, followed by dt of physics
+    pub fn update(&mut self, dt: Duration, pwm: &mut SimPwm, adc: &mut SimAdc) -> Temperature {
+        let (temperature, _) = block_on(self.actuator.update(dt, pwm, adc));
+        self.last_temperature = temperature;
+        let strength = if pwm.is_enabled(self.channel) {
+            pwm.get_duty(self.channel) as f64 / pwm.get_max_duty() as f64
+        } else {
+            0.0
+        };
+        self.model.borrow_mut().update(dt, strength);
+        temperature
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use math::measurements::Resistance;
+
+    #[test]
+    fn test_thermistor_sample() {
+        let config = ThermistorConfig {
+            r_series: Resistance::from_ohms(10_000.0),
+            r0: Resistance::from_ohms(100_000.0),
+            b: Temperature::from_kelvin(3950.0),
+            samples: 1,
+        };
+        for temperature in [25.0, 60.0, 200.0] {
+            let sample = thermistor_sample(temperature, &config, 4096);
+            let t = math::common::compute_ntf_thermistor_temperature(
+                u64::from(sample),
+                4096,
+                Temperature::from_celsius(25.0),
+                config.b,
+                config.r0,
+                config.r_series,
+            );
+            // a sample is worth a few degrees at high temperature
+            assert_abs_diff_eq!(t.as_celsius(), temperature, epsilon = 3.0);
+        }
+    }
+
+    #[test]
+    fn test_thermal_model() {
+        let mut model = ThermalModel::hotend();
+        model.update(Duration::from_secs(1), 1.0);
+        assert_abs_diff_eq!(model.temperature, 29.0, epsilon = 0.001);
+        // the temperature settles where the losses match the power
+        for _ in 0..100_000 {
+            model.update(Duration::from_millis(100), 0.5);
+        }
+        assert_abs_diff_eq!(
+            model.temperature,
+            AMBIENT_TEMPERATURE + 20.0 / 0.15,
+            epsilon = 0.01
+        );
+    }
+}
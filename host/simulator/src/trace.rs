@@ -0,0 +1,159 @@
+use core::time::Duration;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use math::measurements::Distance;
+use math::Axis;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TraceKind {
+    // a pulse on the step pin, with the physical position of the axis after it
+    Step,
+    // the position reported by the firmware once a command is over
+    Position,
+}
+
+impl TraceKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TraceKind::Step => "step",
+            TraceKind::Position => "position",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TraceEvent {
+    pub time: Duration,
+    pub kind: TraceKind,
+    pub axis: Axis,
+    pub position: Distance,
+}
+
+// events shared by the axes of the printer, in the order they happened
+pub type Trace = Rc<RefCell<Vec<TraceEvent>>>;
+
+pub fn new_trace() -> Trace {
+    Rc::new(RefCell::new(Vec::new()))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TraceFormat {
+    Csv,
+    Json,
+}
+
+impl TryFrom<&str> for TraceFormat {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "csv" => Ok(TraceFormat::Csv),
+            "json" => Ok(TraceFormat::Json),
+            _ => Err(format!("Invalid trace format: {}", value)),
+        }
+    }
+}
+
+// writes the events as they come, so a long print doesn't have to be kept in memory
+pub struct TraceWriter<W: Write> {
+    writer: W,
+    format: TraceFormat,
+    empty: bool,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(mut writer: W, format: TraceFormat) -> io::Result<Self> {
+        match format {
+            TraceFormat::Csv => writeln!(writer, "time_us,kind,axis,position_mm")?,
+            TraceFormat::Json => write!(writer, "[")?,
+        }
+        Ok(Self {
+            writer,
+            format,
+            empty: true,
+        })
+    }
+
+    pub fn write(&mut self, event: &TraceEvent) -> io::Result<()> {
+        let time = event.time.as_micros();
+        let kind = event.kind.as_str();
+        let position = event.position.as_millimeters();
+        match self.format {
+            TraceFormat::Csv => writeln!(
+                self.writer,
+                "{},{},{},{:.4}",
+                time, kind, event.axis, position
+            )?,
+            TraceFormat::Json => {
+                let separator = if self.empty { "" } else { "," };
+                write!(
+                    self.writer,
+                    "{}\n{{\"time_us\":{},\"kind\":\"{}\",\"axis\":\"{}\",\"position_mm\":{:.4}}}",
+                    separator, time, kind, event.axis, position
+                )?
+            }
+        }
+        self.empty = false;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.format == TraceFormat::Json {
+            writeln!(self.writer, "\n]")?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events() -> [TraceEvent; 2] {
+        [
+            TraceEvent {
+                time: Duration::from_micros(1500),
+                kind: TraceKind::Step,
+                axis: Axis::X,
+                position: Distance::from_millimeters(0.04),
+            },
+            TraceEvent {
+                time: Duration::from_millis(2),
+                kind: TraceKind::Position,
+                axis: Axis::E,
+                position: Distance::from_millimeters(-1.0),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_trace_csv() {
+        let mut writer = TraceWriter::new(Vec::new(), TraceFormat::Csv).unwrap();
+        for event in events() {
+            writer.write(&event).unwrap();
+        }
+        let output = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert_eq!(
+            output,
+            "time_us,kind,axis,position_mm\n1500,step,X,0.0400\n2000,position,E,-1.0000\n"
+        );
+    }
+
+    #[test]
+    fn test_trace_json() {
+        let mut writer = TraceWriter::new(Vec::new(), TraceFormat::Json).unwrap();
+        for event in events() {
+            writer.write(&event).unwrap();
+        }
+        let output = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert_eq!(
+            output,
+            "[\n{\"time_us\":1500,\"kind\":\"step\",\"axis\":\"X\",\"position_mm\":0.0400},\n{\"time_us\":2000,\"kind\":\"position\",\"axis\":\"E\",\"position_mm\":-1.0000}\n]\n"
+        );
+        let writer = TraceWriter::new(Vec::new(), TraceFormat::Json).unwrap();
+        assert_eq!(writer.finish().unwrap(), b"[\n]\n");
+    }
+}
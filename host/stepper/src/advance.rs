@@ -0,0 +1,144 @@
+use core::fmt::Display;
+use core::time::Duration;
+
+use heapless::Vec;
+use math::measurements::Speed;
+
+use crate::ramp::{Trapezoid, MAX_RAMP_PIECES, RAMP_PIECES};
+
+// the pieces of the accelerated move the advance is added to
+pub const MAX_ADVANCED_PIECES: usize = MAX_RAMP_PIECES;
+
+// linear advance of the extruder (M900). The pressure in the nozzle lags behind the flow, so
+// while extruding at the velocity v the filament is pushed K·v ahead of where it should be.
+// The advance follows the changes of speed of a move: K·a more is pushed while it accelerates at
+// a, and as much less while it decelerates
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct LinearAdvance {
+    // in seconds, 0 disables the advance
+    pub k: f64,
+    // the extruder doesn't go faster than this to build the advance up
+    pub max_speed: Speed,
+}
+
+impl Default for LinearAdvance {
+    fn default() -> Self {
+        Self {
+            k: 0.0,
+            max_speed: Speed::from_meters_per_second(0.05),
+        }
+    }
+}
+
+impl LinearAdvance {
+    pub fn new(k: f64, max_speed: Speed) -> Self {
+        Self { k, max_speed }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.k > 0.0 && self.max_speed.as_meters_per_second() > 0.0
+    }
+
+    // the pieces (duration, velocity) of an extrusion along the trapezoid (in mm), covering the
+    // same distance in the same time. The extruder doesn't go over the maximum speed to build
+    // the advance up nor backwards to release it, what's built up is released
+    pub fn profile(&self, trapezoid: &Trapezoid) -> Vec<(Duration, f64), MAX_ADVANCED_PIECES> {
+        let pieces = trapezoid.pieces();
+        if !self.is_enabled() {
+            return pieces;
+        }
+        let max = self.max_speed.as_meters_per_second() * 1000.0;
+        let push = self.k * trapezoid.acceleration;
+        // the acceleration is the first pieces of the profile, the deceleration the last ones
+        let built = if trapezoid.accelerate.is_zero() { 0 } else { RAMP_PIECES };
+        let released = match trapezoid.decelerate.is_zero() {
+            true => pieces.len(),
+            false => pieces.len() - RAMP_PIECES,
+        };
+        let up = |v: f64| push.min(max - v).max(0.0);
+        let down = |v: f64| push.min(v).max(0.0);
+        let total = |pieces: &[(Duration, f64)], extra: &dyn Fn(f64) -> f64| -> f64 {
+            pieces.iter().map(|(d, v)| d.as_secs_f64() * extra(*v)).sum()
+        };
+        let build = total(&pieces[..built], &up);
+        let release = total(&pieces[released..], &down);
+        let advance = build.min(release);
+        let ratio = |total: f64| if total > 0.0 { advance / total } else { 0.0 };
+        let (build, release) = (ratio(build), ratio(release));
+        pieces
+            .iter()
+            .enumerate()
+            .map(|(n, (d, v))| match n {
+                n if n < built => (*d, v + build * up(*v)),
+                n if n >= released => (*d, v - release * down(*v)),
+                _ => (*d, *v),
+            })
+            .collect()
+    }
+}
+
+impl Display for LinearAdvance {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::write!(f, "Linear advance: [K:{:.3}]", self.k)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ramp::Ramp;
+    use approx::assert_abs_diff_eq;
+
+    fn distance(pieces: &[(Duration, f64)]) -> f64 {
+        pieces.iter().map(|(d, v)| d.as_secs_f64() * v).sum()
+    }
+
+    #[test]
+    fn test_advance_profile() {
+        let advance = LinearAdvance::new(0.01, Speed::from_meters_per_second(0.05));
+        // 10mm up to 20mm/s from 5mm/s at 500mm/s²: 5mm/s more while accelerating, 5mm/s less
+        // while decelerating, 0.15mm pushed ahead in 30ms
+        let trapezoid = Ramp::new(500.0, 5.0, 5.0).trapezoid(10.0, 20.0);
+        let plain = trapezoid.pieces();
+        let pieces = advance.profile(&trapezoid);
+        assert_eq!(pieces.len(), plain.len());
+        assert_abs_diff_eq!(distance(&pieces), 10.0, epsilon = 1e-9);
+        let extra = |n: usize| pieces[n].1 - plain[n].1;
+        for n in 0..RAMP_PIECES {
+            assert_abs_diff_eq!(extra(n), 0.01 * 500.0, epsilon = 1e-9);
+            assert_abs_diff_eq!(extra(RAMP_PIECES + 1 + n), -0.01 * 500.0, epsilon = 1e-9);
+        }
+        assert_eq!(pieces[RAMP_PIECES], plain[RAMP_PIECES]);
+        let built = distance(&pieces[..RAMP_PIECES]) - distance(&plain[..RAMP_PIECES]);
+        assert_abs_diff_eq!(built, 0.01 * (20.0 - 5.0), epsilon = 1e-9);
+        // the extra steps grow with the acceleration
+        for acceleration in [100.0, 250.0] {
+            let trapezoid = Ramp::new(acceleration, 5.0, 5.0).trapezoid(10.0, 20.0);
+            let pieces = advance.profile(&trapezoid);
+            let plain = trapezoid.pieces();
+            assert_abs_diff_eq!(pieces[0].1 - plain[0].1, 0.01 * acceleration, epsilon = 1e-9);
+            let last = pieces.len() - 1;
+            assert_abs_diff_eq!(
+                pieces[last].1 - plain[last].1,
+                -0.01 * acceleration,
+                epsilon = 1e-9
+            );
+        }
+
+        // down to rest the extruder would go backwards, as little is built up as can be
+        // released. Up to speed it doesn't go faster than the max speed
+        let advance = LinearAdvance::new(0.1, Speed::from_meters_per_second(0.03));
+        let trapezoid = Ramp::new(500.0, 0.0, 0.0).trapezoid(10.0, 20.0);
+        let pieces = advance.profile(&trapezoid);
+        assert_abs_diff_eq!(distance(&pieces), 10.0, epsilon = 1e-9);
+        assert!(pieces.iter().all(|(_, v)| *v >= 0.0 && *v <= 30.0 + 1e-9));
+        assert_abs_diff_eq!(pieces.last().unwrap().1, 0.0, epsilon = 1e-9);
+
+        // a move at its speed from the start to the end isn't advanced
+        let trapezoid = Ramp::default().trapezoid(10.0, 20.0);
+        assert_eq!(advance.profile(&trapezoid), trapezoid.pieces());
+        let trapezoid = Ramp::new(500.0, 5.0, 5.0).trapezoid(10.0, 20.0);
+        assert_eq!(LinearAdvance::default().profile(&trapezoid), trapezoid.pieces());
+        assert_eq!(format!("{}", advance), "Linear advance: [K:0.100]");
+    }
+}
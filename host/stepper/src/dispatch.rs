@@ -0,0 +1,213 @@
+use core::fmt::Arguments;
+
+use common::{ExtiInputPinBase, HalfDuplexSerialBase, OutputPinBase, TimerBase};
+use parser::gcode::GCommand;
+
+use crate::planner::Planner;
+use crate::probe::Probe;
+use crate::stepper::StepperError;
+
+// the commands handled by dispatch, the firmware sends them to the planner task
+pub fn is_planner_command(command: &GCommand) -> bool {
+    matches!(
+        command,
+        GCommand::G0 { .. }
+            | GCommand::G1 { .. }
+            | GCommand::G2 { .. }
+            | GCommand::G3 { .. }
+            | GCommand::G4 { .. }
+            | GCommand::G5 { .. }
+            | GCommand::G10
+            | GCommand::G10_L2 { .. }
+            | GCommand::G10_L20 { .. }
+            | GCommand::G11
+            | GCommand::G17
+            | GCommand::G18
+            | GCommand::G19
+            | GCommand::G28 { .. }
+            | GCommand::G29 { .. }
+            | GCommand::G30 { .. }
+            | GCommand::G34 { .. }
+            | GCommand::G53 { .. }
+            | GCommand::G54
+            | GCommand::G55
+            | GCommand::G56
+            | GCommand::G57
+            | GCommand::G58
+            | GCommand::G59
+            | GCommand::G90
+            | GCommand::G91
+            | GCommand::G92 { .. }
+            | GCommand::G92_1
+            | GCommand::M82
+            | GCommand::M83
+            | GCommand::M48 { .. }
+            | GCommand::M114
+            | GCommand::M122
+            | GCommand::M200 { .. }
+            | GCommand::M201 { .. }
+            | GCommand::M203 { .. }
+            | GCommand::M204 { .. }
+            | GCommand::M205 { .. }
+            | GCommand::M206 { .. }
+            | GCommand::M207 { .. }
+            | GCommand::M208 { .. }
+            | GCommand::M211 { .. }
+            | GCommand::M220 { .. }
+            | GCommand::M221 { .. }
+            | GCommand::M420 { .. }
+            | GCommand::M425 { .. }
+            | GCommand::M503
+            | GCommand::M569 { .. }
+            | GCommand::M593 { .. }
+            | GCommand::M851 { .. }
+            | GCommand::M852 { .. }
+            | GCommand::M900 { .. }
+            | GCommand::M906 { .. }
+            | GCommand::M913 { .. }
+            | GCommand::M914 { .. }
+    )
+}
+
+// execute a command of the planner, the same way on the board and in the simulator. The
+// feedback is passed to report a line at a time, errors included. The first error is returned
+// too, so that the caller can raise it. The other commands are ignored
+pub async fn dispatch<P, T, I, S, Z, F>(
+    planner: &mut Planner<P, T, I, S, Z>,
+    command: GCommand,
+    report: &mut F,
+) -> Result<(), StepperError>
+where
+    P: OutputPinBase,
+    T: TimerBase,
+    I: ExtiInputPinBase,
+    S: HalfDuplexSerialBase,
+    Z: Probe,
+    F: FnMut(Arguments),
+{
+    match command {
+        GCommand::G30 { x, y } => {
+            let point = planner.probe(x, y).await;
+            return report_result(point, report);
+        }
+        GCommand::G34 { i, t } => {
+            let alignment = planner.align_z(i, t).await;
+            return report_result(alignment, report);
+        }
+        GCommand::M48 { p, x, y } => {
+            let stats = planner.probe_repeatability(p, x, y).await;
+            return report_result(stats, report);
+        }
+        // the motion settings, a line each
+        GCommand::M503 => {
+            report(format_args!("{}", planner.get_limits()));
+            report(format_args!("{}", planner.get_extrusion()));
+            report(format_args!("{}", planner.get_advance()));
+            report(format_args!("{}", planner.get_backlash()));
+            report(format_args!("{}", planner.get_skew()));
+            report(format_args!("{}", planner.get_shaping()));
+            report(format_args!("{}", planner.get_leveling()));
+            report(format_args!("{}", planner.get_software_endstops()));
+            return Ok(());
+        }
+        GCommand::M114 => {
+            report(format_args!(
+                "Head position: [X:{}] [Y:{}] [Z:{}]",
+                planner.get_x_position(),
+                planner.get_y_position(),
+                planner.get_z_position(),
+            ));
+            let (x, y, z) = planner.get_machine_position();
+            report(format_args!("Machine position: [X:{}] [Y:{}] [Z:{}]", x, y, z));
+            return Ok(());
+        }
+        GCommand::M122 => {
+            if let Some((x, y, z, e)) = planner.get_drivers_status().await {
+                for (label, status) in [("X", x), ("Y", y), ("Z", z), ("E", e)] {
+                    match status {
+                        Some(Ok(status)) => report(format_args!("Driver {}: {}", label, status)),
+                        Some(Err(e)) => report(format_args!("Driver {}: {}", label, e)),
+                        None => {}
+                    }
+                }
+            }
+            return Ok(());
+        }
+        _ if !is_planner_command(&command) => return Ok(()),
+        _ => {}
+    }
+    let result = planner.execute(command.clone()).await.map(|_| ());
+    if let Err(e) = result {
+        report(format_args!("{}", e));
+    }
+    // the settings are reported once set
+    match command {
+        GCommand::M211 { .. } => report(format_args!("{}", planner.get_software_endstops())),
+        GCommand::M420 { .. } => report(format_args!("{}", planner.get_leveling())),
+        GCommand::M425 { .. } => report(format_args!("{}", planner.get_backlash())),
+        GCommand::M593 { .. } => report(format_args!("{}", planner.get_shaping())),
+        GCommand::M852 { .. } => report(format_args!("{}", planner.get_skew())),
+        GCommand::M900 { .. } => report(format_args!("{}", planner.get_advance())),
+        GCommand::M851 { .. } => {
+            let (x, y, z) = planner.get_probe_offset();
+            report(format_args!(
+                "Probe offset: [X:{:.3}] [Y:{:.3}] [Z:{:.3}]",
+                x.as_millimeters(),
+                y.as_millimeters(),
+                z.as_millimeters()
+            ));
+        }
+        // the mesh is printed with T instead of being probed, a row per line from the back of
+        // the bed
+        GCommand::G29 { t: true } => match planner.get_leveling().get_mesh() {
+            Some(mesh) => {
+                let (x_points, y_points) = mesh.get_points();
+                report(format_args!("Bed mesh {}x{}", x_points, y_points));
+                for j in (0..y_points).rev() {
+                    report(format_args!("{}", mesh.row(j)));
+                }
+            }
+            None => report(format_args!("No bed mesh")),
+        },
+        _ => {}
+    }
+    result
+}
+
+fn report_result<R: core::fmt::Display, F: FnMut(Arguments)>(
+    result: Result<R, StepperError>,
+    report: &mut F,
+) -> Result<(), StepperError> {
+    match result {
+        Ok(value) => {
+            report(format_args!("{}", value));
+            Ok(())
+        }
+        Err(e) => {
+            report(format_args!("{}", e));
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_planner_command() {
+        assert!(is_planner_command(&GCommand::G92_1));
+        assert!(is_planner_command(&GCommand::G10_L20 {
+            p: None,
+            x: None,
+            y: None,
+            z: None
+        }));
+        assert!(is_planner_command(&GCommand::M503));
+        assert!(is_planner_command(&GCommand::G29 { t: true }));
+        // the heaters, the SD-card and the servo aren't the planner's business
+        assert!(!is_planner_command(&GCommand::M105));
+        assert!(!is_planner_command(&GCommand::M524));
+        assert!(!is_planner_command(&GCommand::M280 { p: 0, s: None }));
+    }
+}
@@ -0,0 +1,475 @@
+use core::f64::consts::PI;
+use core::time::Duration;
+
+use common::{ExtiInputPinBase, HalfDuplexSerialBase, OutputPinBase, TimerBase};
+use math::common::abs;
+use math::measurements::{Density, Distance, Mass, Volume};
+use math::vector::Vector3D;
+use parser::gcode::GCommand;
+
+use crate::planner::Planner;
+use crate::probe::Probe;
+use crate::stepper::StepperError;
+
+// layers closer than this are the same layer
+const LAYER_TOLERANCE: f64 = 1e-6;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Layer {
+    pub z: Distance,
+    pub duration: Duration,
+    // net length of filament pushed into the extruder, retractions included
+    pub filament: Distance,
+}
+
+#[derive(Clone, Copy)]
+pub struct Estimate {
+    pub duration: Duration,
+    pub filament: Distance,
+    // min and max corners of the box holding the extruding moves
+    pub bounds: Option<(Vector3D<Distance>, Vector3D<Distance>)>,
+    pub layers: usize,
+}
+
+// print-time and filament estimation of a G-code program, made by executing it on a planner in
+// dry run. The commands that don't move the printer (temperatures, fans...) are skipped, so the
+// time spent heating up isn't taken into account.
+// A layer starts with the first extruding move at a new Z and lasts until the next layer starts,
+// so the travels and the retractions between two layers belong to the first one. The moves that
+// come before the first extrusion count only for the total
+pub struct Estimator<
+    P: OutputPinBase,
+    T: TimerBase,
+    I: ExtiInputPinBase,
+    S: HalfDuplexSerialBase,
+    Z: Probe,
+> {
+    planner: Planner<P, T, I, S, Z>,
+    duration: Duration,
+    filament: Distance,
+    bounds: Option<(Vector3D<Distance>, Vector3D<Distance>)>,
+    layer: Option<Layer>,
+    layers: usize,
+}
+
+impl<P: OutputPinBase, T: TimerBase, I: ExtiInputPinBase, S: HalfDuplexSerialBase, Z: Probe>
+    Estimator<P, T, I, S, Z>
+{
+    pub fn new(mut planner: Planner<P, T, I, S, Z>) -> Self {
+        planner.set_dry_run(true);
+        Self {
+            planner,
+            duration: Duration::ZERO,
+            filament: Distance::from_millimeters(0.0),
+            bounds: None,
+            layer: None,
+            layers: 0,
+        }
+    }
+
+    pub fn get_planner(&self) -> &Planner<P, T, I, S, Z> {
+        &self.planner
+    }
+
+    // returns the layer completed by the command, if any
+    pub async fn execute(&mut self, command: GCommand) -> Result<Option<Layer>, StepperError> {
+        let extrusion = matches!(
+            command,
+            GCommand::G1 { e: Some(_), .. }
+                | GCommand::G2 { e: Some(_), .. }
+                | GCommand::G3 { e: Some(_), .. }
+                | GCommand::G5 { e: Some(_), .. }
+        );
+        // G92 sets the position of E without pushing any filament
+        let counted = !matches!(command, GCommand::G92 { .. });
+        let start = self.get_position();
+        let start_e = self.planner.get_e_position();
+        let duration = match self.planner.execute(command).await {
+            Ok(duration) => duration.unwrap_or(Duration::ZERO),
+            // the planner doesn't handle it, nothing moves
+            Err(StepperError::NotSupported) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let end = self.get_position();
+        let filament = if counted {
+            self.planner.get_e_position() - start_e
+        } else {
+            Distance::from_millimeters(0.0)
+        };
+        let moved = start.get_x() != end.get_x() || start.get_y() != end.get_y();
+        let mut completed = None;
+        if extrusion && moved && filament.as_millimeters() > 0.0 {
+            self.add_to_bounds(start);
+            self.add_to_bounds(end);
+            let new_layer = match self.layer {
+                Some(layer) => abs((layer.z - end.get_z()).as_millimeters()) > LAYER_TOLERANCE,
+                None => true,
+            };
+            if new_layer {
+                completed = self.layer.replace(Layer {
+                    z: end.get_z(),
+                    duration: Duration::ZERO,
+                    filament: Distance::from_millimeters(0.0),
+                });
+                self.layers += 1;
+            }
+        }
+        if let Some(layer) = self.layer.as_mut() {
+            layer.duration += duration;
+            layer.filament = layer.filament + filament;
+        }
+        self.duration += duration;
+        self.filament = self.filament + filament;
+        Ok(completed)
+    }
+
+    // the layer in progress, that is the last one once the program is over
+    pub fn get_layer(&self) -> Option<Layer> {
+        self.layer
+    }
+
+    pub fn get_estimate(&self) -> Estimate {
+        Estimate {
+            duration: self.duration,
+            filament: self.filament,
+            bounds: self.bounds,
+            layers: self.layers,
+        }
+    }
+
+    fn get_position(&self) -> Vector3D<Distance> {
+        Vector3D::new(
+            self.planner.get_x_position(),
+            self.planner.get_y_position(),
+            self.planner.get_z_position(),
+        )
+    }
+
+    // arcs are bounded by their endpoints, which is short of the bulge of the arc
+    fn add_to_bounds(&mut self, point: Vector3D<Distance>) {
+        let (min, max) = self.bounds.unwrap_or((point, point));
+        let lower = |a: Distance, b: Distance| if a < b { a } else { b };
+        let upper = |a: Distance, b: Distance| if a > b { a } else { b };
+        self.bounds = Some((
+            Vector3D::new(
+                lower(min.get_x(), point.get_x()),
+                lower(min.get_y(), point.get_y()),
+                lower(min.get_z(), point.get_z()),
+            ),
+            Vector3D::new(
+                upper(max.get_x(), point.get_x()),
+                upper(max.get_y(), point.get_y()),
+                upper(max.get_z(), point.get_z()),
+            ),
+        ));
+    }
+}
+
+// mass of a length of filament of the given diameter
+pub fn filament_mass(length: Distance, diameter: Distance, density: Density) -> Mass {
+    let radius = diameter.as_meters() / 2.0;
+    let volume = Volume::from_cubic_meters(PI * radius * radius * length.as_meters());
+    volume * density
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::advance::LinearAdvance;
+    use crate::skew::Skew;
+    use crate::motion::{HomingConfig, Positioning};
+    use crate::planner::{
+        AlignmentMotionConfig, ArcMotionConfig, BacklashMotionConfig, ExtrusionMotionConfig,
+        HomingMotionConfig, LimitsMotionConfig,
+        InputShapingMotionConfig, LevelingMotionConfig,
+        MotionConfig, ProbeMotionConfig, RecoverMotionConfig, RetractionMotionConfig,
+        SoftwareEndstopsConfig,
+    };
+    use crate::probe::SwitchProbe;
+    use crate::stepper::{Attached, Stepper, StepperAttachment, StepperOptions};
+    use approx::assert_abs_diff_eq;
+    use math::measurements::{Acceleration, Length, Speed};
+    use math::Axis;
+    use sim::{block_on, clock, SimInputPin, SimOutputPin, SimSerial, SimTimer};
+
+    type EstimatorMock =
+        Estimator<SimOutputPin, SimTimer, SimInputPin, SimSerial, SwitchProbe<SimInputPin>>;
+
+    fn stepper() -> Stepper<SimOutputPin, Attached> {
+        let options = StepperOptions {
+            bounds: Some((
+                Distance::from_millimeters(-100.0),
+                Distance::from_millimeters(100.0),
+            )),
+            ..Default::default()
+        };
+        let attachment = StepperAttachment {
+            distance_per_step: Distance::from_millimeters(0.1),
+        };
+        Stepper::new_with_attachment(
+            SimOutputPin::new(),
+            SimOutputPin::new(),
+            options,
+            attachment,
+        )
+    }
+
+    fn planner() -> Planner<SimOutputPin, SimTimer, SimInputPin, SimSerial, SwitchProbe<SimInputPin>> {
+        let speed = Speed::from_meters_per_second;
+        let acceleration = Acceleration::from_meters_per_second_per_second;
+        let homing = HomingConfig {
+            backoff: Distance::from_millimeters(5.0),
+            ..Default::default()
+        };
+        let config = MotionConfig {
+            arcs: ArcMotionConfig {
+                tolerance: Length::from_millimeters(0.01),
+                min_segment_length: Length::from_millimeters(0.1),
+                max_segment_length: Length::from_millimeters(1.0),
+                min_segment_duration: Duration::from_millis(5),
+            },
+            feedrate: Speed::from_meters_per_second(0.01),
+            positioning: Positioning::Absolute,
+            e_positioning: Positioning::Relative,
+            feedrate_multiplier: 1.0,
+            extrusion: ExtrusionMotionConfig::default(),
+            limits: LimitsMotionConfig {
+                max_feedrate: (speed(1.0), speed(1.0), speed(1.0), speed(1.0)),
+                max_acceleration: (
+                    acceleration(10.0),
+                    acceleration(10.0),
+                    acceleration(10.0),
+                    acceleration(10.0),
+                ),
+                print_acceleration: acceleration(1.0),
+                retract_acceleration: acceleration(1.0),
+                travel_acceleration: acceleration(1.0),
+                jerk: (speed(0.01), speed(0.01), speed(0.01), speed(0.01)),
+            },
+            retraction: RetractionMotionConfig {
+                feedrate: Speed::from_meters_per_second(0.01),
+                length: Length::from_millimeters(1.0),
+                z_lift: Length::from_millimeters(0.0),
+            },
+            recover: RecoverMotionConfig {
+                feedrate: Speed::from_meters_per_second(0.01),
+                length: Length::from_millimeters(1.0),
+            },
+            homing: HomingMotionConfig {
+                axes: (homing, homing, homing),
+                order: [Axis::X, Axis::Y, Axis::Z],
+                z_lift: Length::from_millimeters(0.0),
+                required: true,
+                offset: (
+                    Distance::from_millimeters(0.0),
+                    Distance::from_millimeters(0.0),
+                    Distance::from_millimeters(0.0),
+                ),
+            },
+            software_endstops: SoftwareEndstopsConfig {
+                enabled: true,
+                clip: false,
+            },
+            leveling: LevelingMotionConfig {
+                points: (3, 3),
+                min: (
+                    Distance::from_millimeters(10.0),
+                    Distance::from_millimeters(10.0),
+                ),
+                max: (
+                    Distance::from_millimeters(30.0),
+                    Distance::from_millimeters(30.0),
+                ),
+                fade_height: Length::from_millimeters(0.0),
+            },
+            probe: ProbeMotionConfig {
+                offset: (
+                    Distance::from_millimeters(0.0),
+                    Distance::from_millimeters(0.0),
+                    Distance::from_millimeters(0.0),
+                ),
+                clearance: Length::from_millimeters(2.0),
+                max_depth: Length::from_millimeters(4.0),
+                feedrate: Speed::from_meters_per_second(0.005),
+                travel_feedrate: Speed::from_meters_per_second(0.05),
+                samples: 1,
+                retract: Length::from_millimeters(1.0),
+            },
+            alignment: AlignmentMotionConfig {
+                points: heapless::Vec::from_slice(&[
+                    (Distance::from_millimeters(10.0), Distance::from_millimeters(20.0)),
+                    (Distance::from_millimeters(30.0), Distance::from_millimeters(20.0)),
+                ])
+                .unwrap(),
+                iterations: 3,
+                accuracy: Length::from_millimeters(0.02),
+            },
+            backlash: BacklashMotionConfig {
+                distance: (
+                    Distance::from_millimeters(0.0),
+                    Distance::from_millimeters(0.0),
+                    Distance::from_millimeters(0.0),
+                ),
+                correction: 1.0,
+                smoothing: Length::from_millimeters(0.0),
+            },
+            skew: Skew::default(),
+            shaping: InputShapingMotionConfig::default(),
+            advance: LinearAdvance::default(),
+        };
+        Planner::new(
+            stepper(),
+            stepper(),
+            stepper(),
+            stepper(),
+            config,
+            (None, None, None, None),
+            None,
+        )
+    }
+
+    fn distance(value: f64) -> Option<Distance> {
+        Some(Distance::from_millimeters(value))
+    }
+
+    fn g1(x: Option<Distance>, z: Option<Distance>, e: Option<Distance>) -> GCommand {
+        GCommand::G1 {
+            x,
+            y: None,
+            z,
+            e,
+            f: None,
+        }
+    }
+
+    #[test]
+    fn test_dry_run_duration() {
+        let mut p = planner();
+        p.set_dry_run(true);
+        block_on(p.execute(GCommand::G28 {
+            x: true,
+            y: true,
+            z: true,
+        }))
+        .unwrap();
+        let origin = GCommand::G0 {
+            x: distance(0.0),
+            y: distance(0.0),
+            z: None,
+            f: None,
+        };
+        block_on(p.execute(origin.clone())).unwrap();
+        p.set_dry_run(false);
+        let command = GCommand::G0 {
+            x: distance(10.0),
+            y: distance(5.0),
+            z: None,
+            f: None,
+        };
+        clock::reset();
+        let duration = block_on(p.execute(command.clone())).unwrap().unwrap();
+        assert_eq!(clock::now(), duration);
+        block_on(p.execute(origin)).unwrap();
+        p.set_dry_run(true);
+        clock::reset();
+        let dry_run = block_on(p.execute(command)).unwrap().unwrap();
+        // same time, but nothing waited
+        assert_eq!(dry_run, duration);
+        assert_eq!(clock::now(), Duration::ZERO);
+        assert_abs_diff_eq!(p.get_x_position().as_millimeters(), 10.0, epsilon = 1e-9);
+        let dwell = block_on(p.execute(GCommand::G4 {
+            p: Some(Duration::from_secs(2)),
+            s: None,
+        }));
+        assert_eq!(dwell, Ok(Some(Duration::from_secs(2))));
+        assert_eq!(clock::now(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_dry_run_homing() {
+        let mut p = planner();
+        p.set_dry_run(true);
+        let duration = block_on(p.execute(GCommand::G28 {
+            x: true,
+            y: true,
+            z: false,
+        }))
+        .unwrap()
+        .unwrap();
+        // only the backoff is timed, 5mm at 50mm/s for each axis
+        assert_abs_diff_eq!(duration.as_secs_f64(), 0.2, epsilon = 0.01);
+        assert_eq!(p.is_homed(), (true, true, false));
+        assert_abs_diff_eq!(p.get_x_position().as_millimeters(), 95.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(p.get_y_position().as_millimeters(), 95.0, epsilon = 1e-9);
+    }
+
+    #[test]
+    fn test_estimator() {
+        let mut estimator: EstimatorMock = Estimator::new(planner());
+        let commands = [
+            GCommand::G28 {
+                x: true,
+                y: true,
+                z: true,
+            },
+            // temperatures aren't estimated
+            GCommand::M104 {
+                s: math::measurements::Temperature::from_celsius(200.0),
+            },
+            g1(distance(0.0), distance(0.2), None),
+            g1(distance(10.0), None, distance(1.0)),
+            // retraction and z-hop, still the first layer
+            g1(None, distance(1.0), distance(-0.5)),
+            g1(distance(0.0), None, None),
+            g1(None, distance(0.4), distance(0.5)),
+            GCommand::G92 {
+                x: None,
+                y: None,
+                z: None,
+                e: distance(0.0),
+            },
+            g1(distance(20.0), None, distance(2.0)),
+        ];
+        let mut completed = None;
+        for command in commands {
+            if let Some(layer) = block_on(estimator.execute(command)).unwrap() {
+                assert!(completed.is_none());
+                completed = Some(layer);
+            }
+        }
+        let first = completed.unwrap();
+        assert_abs_diff_eq!(first.z.as_millimeters(), 0.2, epsilon = 1e-9);
+        assert_abs_diff_eq!(first.filament.as_millimeters(), 1.0, epsilon = 1e-9);
+        let last = estimator.get_layer().unwrap();
+        assert_abs_diff_eq!(last.z.as_millimeters(), 0.4, epsilon = 1e-9);
+        assert_abs_diff_eq!(last.filament.as_millimeters(), 2.0, epsilon = 1e-9);
+        // 10mm and 20mm extruding, 1.4mm of z-hop and 10mm of travel at 10mm/s. The step
+        // durations of the steppers are a bit longer than the ones of the exact speed
+        assert_abs_diff_eq!(
+            (first.duration + last.duration).as_secs_f64(),
+            4.14,
+            epsilon = 0.2
+        );
+        let estimate = estimator.get_estimate();
+        assert_eq!(estimate.layers, 2);
+        assert_abs_diff_eq!(estimate.filament.as_millimeters(), 3.0, epsilon = 1e-9);
+        assert!(estimate.duration > first.duration + last.duration);
+        let (min, max) = estimate.bounds.unwrap();
+        assert_abs_diff_eq!(min.get_x().as_millimeters(), 0.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(max.get_x().as_millimeters(), 20.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(min.get_z().as_millimeters(), 0.2, epsilon = 1e-9);
+        assert_abs_diff_eq!(max.get_z().as_millimeters(), 0.4, epsilon = 1e-9);
+        assert_abs_diff_eq!(max.get_y().as_millimeters(), 95.0, epsilon = 1e-9);
+    }
+
+    #[test]
+    fn test_filament_mass() {
+        // 1m of 1.75mm PLA weighs about 3g
+        let mass = filament_mass(
+            Distance::from_meters(1.0),
+            Distance::from_millimeters(1.75),
+            Density::from_kilograms_per_cubic_meter(1240.0),
+        );
+        assert_abs_diff_eq!(mass.as_grams(), 2.98, epsilon = 0.01);
+    }
+}
@@ -0,0 +1,374 @@
+use core::fmt::Display;
+
+use math::common::abs;
+use math::measurements::{Distance, Length};
+
+use crate::stepper::StepperError;
+
+// largest number of points of a mesh along X and Y
+pub const MAX_MESH_POINTS: usize = 9;
+
+// height of the bed probed on a grid of points evenly spread from min to max, in machine
+// coordinates
+#[derive(Clone, Copy)]
+pub struct Mesh {
+    points: (usize, usize),
+    min: (Distance, Distance),
+    max: (Distance, Distance),
+    // heights in mm, by row along Y
+    z: [[f64; MAX_MESH_POINTS]; MAX_MESH_POINTS],
+}
+
+impl Mesh {
+    pub fn new(
+        points: (usize, usize),
+        min: (Distance, Distance),
+        max: (Distance, Distance),
+    ) -> Result<Self, StepperError> {
+        let range = 2..=MAX_MESH_POINTS;
+        if !range.contains(&points.0) || !range.contains(&points.1) {
+            return Err(StepperError::MoveNotValid);
+        }
+        if max.0 <= min.0 || max.1 <= min.1 {
+            return Err(StepperError::MoveNotValid);
+        }
+        Ok(Self {
+            points,
+            min,
+            max,
+            z: [[0.0; MAX_MESH_POINTS]; MAX_MESH_POINTS],
+        })
+    }
+
+    pub fn get_points(&self) -> (usize, usize) {
+        self.points
+    }
+
+    fn spacing(&self) -> (f64, f64) {
+        (
+            (self.max.0 - self.min.0).as_millimeters() / (self.points.0 - 1) as f64,
+            (self.max.1 - self.min.1).as_millimeters() / (self.points.1 - 1) as f64,
+        )
+    }
+
+    // position of the point in column i and row j
+    pub fn point(&self, i: usize, j: usize) -> (Distance, Distance) {
+        let (dx, dy) = self.spacing();
+        (
+            self.min.0 + Distance::from_millimeters(dx * i as f64),
+            self.min.1 + Distance::from_millimeters(dy * j as f64),
+        )
+    }
+
+    pub fn get(&self, i: usize, j: usize) -> Distance {
+        Distance::from_millimeters(self.z[j][i])
+    }
+
+    pub fn set(&mut self, i: usize, j: usize, z: Distance) {
+        self.z[j][i] = z.as_millimeters();
+    }
+
+    // cell holding the coordinate and the position within it from 0 to 1. Out of the mesh the
+    // closest cell is taken, clamped to its edge
+    fn cell(value: f64, min: f64, spacing: f64, points: usize) -> (usize, f64) {
+        let position = ((value - min) / spacing).clamp(0.0, (points - 1) as f64);
+        let index = (position as usize).min(points - 2);
+        (index, position - index as f64)
+    }
+
+    // height of the bed at (x, y), interpolated between the four points of the cell. Out of the
+    // mesh the height of its closest edge is taken
+    pub fn z_at(&self, x: Distance, y: Distance) -> Distance {
+        let (dx, dy) = self.spacing();
+        let (i, u) = Self::cell(
+            x.as_millimeters(),
+            self.min.0.as_millimeters(),
+            dx,
+            self.points.0,
+        );
+        let (j, v) = Self::cell(
+            y.as_millimeters(),
+            self.min.1.as_millimeters(),
+            dy,
+            self.points.1,
+        );
+        let bottom = self.z[j][i] * (1.0 - u) + self.z[j][i + 1] * u;
+        let top = self.z[j + 1][i] * (1.0 - u) + self.z[j + 1][i + 1] * u;
+        Distance::from_millimeters(bottom * (1.0 - v) + top * v)
+    }
+
+    // fraction of the segment from start to end, past from, at which it next crosses a line
+    // between two cells. 1 if it doesn't cross any before the end
+    pub fn next_crossing(
+        &self,
+        start: (Distance, Distance),
+        end: (Distance, Distance),
+        from: f64,
+    ) -> f64 {
+        let (dx, dy) = self.spacing();
+        let mut next: f64 = 1.0;
+        for (start, end, min, spacing, points) in [
+            (start.0, end.0, self.min.0, dx, self.points.0),
+            (start.1, end.1, self.min.1, dy, self.points.1),
+        ] {
+            let (start, end) = (start.as_millimeters(), end.as_millimeters());
+            if start == end {
+                continue;
+            }
+            for line in 1..(points - 1) {
+                let position = min.as_millimeters() + spacing * line as f64;
+                let t = (position - start) / (end - start);
+                // the lines closer than a micron to the current point are already crossed
+                if t > from && t < next && abs((t - from) * (end - start)) > 1e-3 {
+                    next = t;
+                }
+            }
+        }
+        next
+    }
+
+    // heights of the points of row j, from the lowest X to the highest
+    pub fn row(&self, j: usize) -> MeshRow<'_> {
+        MeshRow { mesh: self, row: j }
+    }
+}
+
+pub struct MeshRow<'a> {
+    mesh: &'a Mesh,
+    row: usize,
+}
+
+impl Display for MeshRow<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let (_, y) = self.mesh.point(0, self.row);
+        core::write!(f, "[Y:{:.3}]", y.as_millimeters())?;
+        for z in &self.mesh.z[self.row][..self.mesh.points.0] {
+            core::write!(f, " {:.3}", z)?;
+        }
+        Ok(())
+    }
+}
+
+// the rows are printed from the highest Y to the lowest, as the bed is seen from above
+impl Display for Mesh {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::write!(f, "Bed mesh {}x{}", self.points.0, self.points.1)?;
+        for j in (0..self.points.1).rev() {
+            core::write!(f, "\n{}", self.row(j))?;
+        }
+        Ok(())
+    }
+}
+
+// mesh bed leveling: the height of the bed is added to the Z of the moves while it's enabled
+// and a mesh has been probed
+#[derive(Clone, Copy)]
+pub struct Leveling {
+    mesh: Option<Mesh>,
+    enabled: bool,
+    // the compensation fades out linearly from Z 0 to this height, 0 applies it at every height
+    fade_height: Length,
+}
+
+impl Leveling {
+    pub fn new(fade_height: Length) -> Self {
+        Self {
+            mesh: None,
+            enabled: false,
+            fade_height,
+        }
+    }
+
+    pub fn get_mesh(&self) -> Option<&Mesh> {
+        self.mesh.as_ref()
+    }
+
+    // a new mesh enables the leveling
+    pub fn set_mesh(&mut self, mesh: Mesh) {
+        self.mesh = Some(mesh);
+        self.enabled = true;
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_fade_height(&mut self, fade_height: Length) {
+        self.fade_height = fade_height;
+    }
+
+    pub fn get_fade_height(&self) -> Length {
+        self.fade_height
+    }
+
+    fn active_mesh(&self) -> Option<&Mesh> {
+        self.mesh.as_ref().filter(|_| self.enabled)
+    }
+
+    fn fade(&self, z: Distance) -> f64 {
+        let height = self.fade_height.as_millimeters();
+        if height <= 0.0 {
+            return 1.0;
+        }
+        (1.0 - z.as_millimeters() / height).clamp(0.0, 1.0)
+    }
+
+    // what to add to the Z of a point of a move
+    pub fn z_offset(&self, x: Distance, y: Distance, z: Distance) -> Distance {
+        match self.active_mesh() {
+            Some(mesh) => mesh.z_at(x, y) * self.fade(z),
+            None => Distance::from_millimeters(0.0),
+        }
+    }
+
+    // see Mesh::next_crossing, the moves are split only while the leveling is active
+    pub fn next_split(
+        &self,
+        start: (Distance, Distance),
+        end: (Distance, Distance),
+        from: f64,
+    ) -> f64 {
+        match self.active_mesh() {
+            Some(mesh) => mesh.next_crossing(start, end, from),
+            None => 1.0,
+        }
+    }
+}
+
+impl Display for Leveling {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let state = if self.enabled { "On" } else { "Off" };
+        core::write!(f, "Bed leveling: {}", state)?;
+        let height = self.fade_height.as_millimeters();
+        if height > 0.0 {
+            core::write!(f, " [fade height:{:.3}]", height)?;
+        }
+        match &self.mesh {
+            Some(mesh) => core::write!(f, " [mesh:{}x{}]", mesh.points.0, mesh.points.1),
+            None => core::write!(f, " [mesh:-]"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_abs_diff_eq;
+    use math::measurements::{Distance, Length};
+
+    use super::{Leveling, Mesh};
+
+    fn mm(value: f64) -> Distance {
+        Distance::from_millimeters(value)
+    }
+
+    // 3x3 points from (0, 0) to (20, 10), the height of each point is x / 10 + y / 5
+    fn mesh() -> Mesh {
+        let mut mesh = Mesh::new((3, 3), (mm(0.0), mm(0.0)), (mm(20.0), mm(10.0))).unwrap();
+        for j in 0..3 {
+            for i in 0..3 {
+                let (x, y) = mesh.point(i, j);
+                mesh.set(i, j, (x / 10.0) + (y / 5.0));
+            }
+        }
+        mesh
+    }
+
+    #[test]
+    fn test_mesh_invalid() {
+        let (min, max) = ((mm(0.0), mm(0.0)), (mm(10.0), mm(10.0)));
+        assert!(Mesh::new((1, 3), min, max).is_err());
+        assert!(Mesh::new((3, 10), min, max).is_err());
+        assert!(Mesh::new((3, 3), max, min).is_err());
+        assert!(Mesh::new((2, 9), min, max).is_ok());
+    }
+
+    #[test]
+    fn test_mesh_bilinear() {
+        let mesh = mesh();
+        assert_eq!(mesh.point(2, 1), (mm(20.0), mm(5.0)));
+        // a plane is interpolated exactly
+        for (x, y) in [(0.0, 0.0), (3.0, 7.5), (10.0, 5.0), (17.2, 2.4), (20.0, 10.0)] {
+            let z = mesh.z_at(mm(x), mm(y)).as_millimeters();
+            assert_abs_diff_eq!(z, x / 10.0 + y / 5.0, epsilon = 1e-9);
+        }
+        // out of the mesh the edge is taken
+        assert_abs_diff_eq!(mesh.z_at(mm(-5.0), mm(15.0)).as_millimeters(), 2.0, epsilon = 1e-9);
+
+        // a single raised point, halfway between it and its neighbours the height is halved
+        let mut mesh = Mesh::new((3, 3), (mm(0.0), mm(0.0)), (mm(20.0), mm(10.0))).unwrap();
+        mesh.set(1, 1, mm(0.4));
+        assert_abs_diff_eq!(mesh.z_at(mm(5.0), mm(5.0)).as_millimeters(), 0.2, epsilon = 1e-9);
+        assert_abs_diff_eq!(mesh.z_at(mm(5.0), mm(2.5)).as_millimeters(), 0.1, epsilon = 1e-9);
+    }
+
+    #[test]
+    fn test_mesh_crossings() {
+        let mesh = mesh();
+        let crossings = |start: (f64, f64), end: (f64, f64)| {
+            let mut t = 0.0;
+            let mut splits = Vec::new();
+            while t < 1.0 {
+                t = mesh.next_crossing((mm(start.0), mm(start.1)), (mm(end.0), mm(end.1)), t);
+                splits.push(t);
+            }
+            splits
+        };
+        // the inner lines are at X10 and Y5
+        assert_eq!(crossings((0.0, 0.0), (20.0, 10.0)), vec![0.5, 1.0]);
+        assert_eq!(crossings((0.0, 2.0), (20.0, 8.0)), vec![0.5, 1.0]);
+        assert_eq!(crossings((5.0, 0.0), (15.0, 10.0)), vec![0.5, 1.0]);
+        assert_eq!(crossings((0.0, 4.0), (16.0, 8.0)), vec![0.25, 0.625, 1.0]);
+        assert_eq!(crossings((12.0, 1.0), (18.0, 4.0)), vec![1.0]);
+        // starting on a line doesn't split there
+        assert_eq!(crossings((10.0, 1.0), (18.0, 1.0)), vec![1.0]);
+        assert_eq!(crossings((3.0, 3.0), (3.0, 3.0)), vec![1.0]);
+    }
+
+    #[test]
+    fn test_leveling_fade() {
+        let mut leveling = Leveling::new(Length::from_millimeters(10.0));
+        let offset = |l: &Leveling, z: f64| l.z_offset(mm(20.0), mm(10.0), mm(z)).as_millimeters();
+        // no mesh yet
+        assert_eq!(offset(&leveling, 0.0), 0.0);
+        leveling.set_mesh(mesh());
+        assert!(leveling.is_enabled());
+        assert_abs_diff_eq!(offset(&leveling, 0.0), 4.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(offset(&leveling, 2.5), 3.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(offset(&leveling, 10.0), 0.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(offset(&leveling, 25.0), 0.0, epsilon = 1e-9);
+        // a zero fade height keeps the compensation at every height
+        leveling.set_fade_height(Length::from_millimeters(0.0));
+        assert_abs_diff_eq!(offset(&leveling, 25.0), 4.0, epsilon = 1e-9);
+        leveling.set_enabled(false);
+        assert_eq!(offset(&leveling, 0.0), 0.0);
+        assert_eq!(
+            leveling.next_split((mm(0.0), mm(0.0)), (mm(20.0), mm(10.0)), 0.0),
+            1.0
+        );
+    }
+
+    #[test]
+    fn test_mesh_display() {
+        let mut mesh = Mesh::new((2, 2), (mm(0.0), mm(0.0)), (mm(10.0), mm(10.0))).unwrap();
+        mesh.set(1, 0, mm(0.05));
+        mesh.set(0, 1, mm(-0.1));
+        assert_eq!(
+            std::format!("{}", mesh),
+            "Bed mesh 2x2\n[Y:10.000] -0.100 0.000\n[Y:0.000] 0.000 0.050"
+        );
+        let mut leveling = Leveling::new(Length::from_millimeters(5.0));
+        assert_eq!(
+            std::format!("{}", leveling),
+            "Bed leveling: Off [fade height:5.000] [mesh:-]"
+        );
+        leveling.set_mesh(mesh);
+        assert_eq!(
+            std::format!("{}", leveling),
+            "Bed leveling: On [fade height:5.000] [mesh:2x2]"
+        );
+    }
+}
@@ -1,5 +1,17 @@
 #![cfg_attr(not(test), no_std)]
 
+pub mod advance;
+pub mod dispatch;
+pub mod estimator;
+pub mod leveling;
 pub mod motion;
 pub mod planner;
+pub mod preflight;
+pub mod probe;
+pub mod ramp;
+pub mod resonance;
+pub mod shaper;
+pub mod skew;
 pub mod stepper;
+pub mod tmc;
+pub mod workspace;
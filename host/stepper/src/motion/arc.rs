@@ -0,0 +1,499 @@
+use core::f64::consts::PI;
+
+use math::angle::atan2;
+use math::common::{abs, precise_sqrt, round, RotationDirection};
+use math::measurements::Distance;
+use math::vector::Vector2D;
+use math::Axis;
+
+use crate::stepper::StepperError;
+
+// the end of an arc given by its center offset is rarely exactly on the circle, because of the
+// rounding of the coordinates. The arc is refused when it misses the circle by more than both
+const RADIUS_TOLERANCE_MM: f64 = 0.01;
+const RADIUS_TOLERANCE_RATIO: f64 = 0.001;
+
+// an end closer than this to the start makes a full circle
+const POINT_TOLERANCE_MM: f64 = 0.001;
+
+// plane where the arcs are drawn, selected by G17, G18 and G19
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Plane {
+    XY,
+    ZX,
+    YZ,
+}
+
+impl Plane {
+    // the two axes of the plane, ordered so that the arcs turn as seen from the positive side of
+    // the third axis, which moves linearly along a helical arc
+    pub fn axes(&self) -> [Axis; 3] {
+        match self {
+            Plane::XY => [Axis::X, Axis::Y, Axis::Z],
+            Plane::ZX => [Axis::Z, Axis::X, Axis::Y],
+            Plane::YZ => [Axis::Y, Axis::Z, Axis::X],
+        }
+    }
+}
+
+// micromath is accurate to about 1e-3, which is a tenth of millimeter on a 100mm radius. The arcs
+// use these instead, computed on f64
+
+// sine and cosine from their Taylor series, around the closest multiple of PI/2
+pub fn precise_sin_cos(angle: f64) -> (f64, f64) {
+    let quarter = round(angle / (PI / 2.0));
+    let x = angle - quarter * PI / 2.0;
+    let x2 = x * x;
+    let (mut sin, mut cos) = (0.0, 0.0);
+    let (mut sin_term, mut cos_term) = (x, 1.0);
+    for n in 1..=10 {
+        sin += sin_term;
+        cos += cos_term;
+        sin_term *= -x2 / ((2 * n) * (2 * n + 1)) as f64;
+        cos_term *= -x2 / ((2 * n - 1) * (2 * n)) as f64;
+    }
+    match (quarter as i64).rem_euclid(4) {
+        0 => (sin, cos),
+        1 => (cos, -sin),
+        2 => (-sin, -cos),
+        _ => (-cos, sin),
+    }
+}
+
+// the angle of micromath corrected by the angle left between its direction and (x, y)
+pub fn precise_atan2(y: f64, x: f64) -> f64 {
+    if x == 0.0 && y == 0.0 {
+        return 0.0;
+    }
+    let mut angle = atan2(y, x).as_radians();
+    for _ in 0..2 {
+        let (sin, cos) = precise_sin_cos(angle);
+        angle += (y * cos - x * sin) / (x * cos + y * sin);
+    }
+    angle
+}
+
+// counterclockwise angle from a to b, in [0, 2PI)
+fn ccw_angle(a: f64, b: f64) -> f64 {
+    let angle = (b - a) % (2.0 * PI);
+    if angle < 0.0 {
+        angle + 2.0 * PI
+    } else {
+        angle
+    }
+}
+
+fn to_mm(v: Vector2D<Distance>) -> (f64, f64) {
+    (v.get_x().as_millimeters(), v.get_y().as_millimeters())
+}
+
+fn from_mm(x: f64, y: f64) -> Vector2D<Distance> {
+    Vector2D::new(Distance::from_millimeters(x), Distance::from_millimeters(y))
+}
+
+// arc on the plane, from start to end around center
+#[derive(Clone, Copy)]
+pub struct Arc {
+    start: Vector2D<Distance>,
+    center: Vector2D<Distance>,
+    end: Vector2D<Distance>,
+    radius: Distance,
+    direction: RotationDirection,
+    // angle covered from start to end, full turns included
+    sweep: f64,
+}
+
+impl Arc {
+    fn new(
+        start: Vector2D<Distance>,
+        center: Vector2D<Distance>,
+        end: Vector2D<Distance>,
+        direction: RotationDirection,
+        turns: u8,
+    ) -> Self {
+        let (sx, sy) = to_mm(start);
+        let (ex, ey) = to_mm(end);
+        let (cx, cy) = to_mm(center);
+        let radius = precise_sqrt((sx - cx) * (sx - cx) + (sy - cy) * (sy - cy));
+        let a0 = precise_atan2(sy - cy, sx - cx);
+        let a1 = precise_atan2(ey - cy, ex - cx);
+        let mut sweep = match direction {
+            RotationDirection::CounterClockwise => ccw_angle(a0, a1),
+            RotationDirection::Clockwise => ccw_angle(a1, a0),
+        };
+        let chord = precise_sqrt((ex - sx) * (ex - sx) + (ey - sy) * (ey - sy));
+        if chord < POINT_TOLERANCE_MM {
+            sweep = 2.0 * PI;
+        }
+        sweep += 2.0 * PI * turns as f64;
+        Self {
+            start,
+            center,
+            end,
+            radius: Distance::from_millimeters(radius),
+            direction,
+            sweep,
+        }
+    }
+
+    // the center is given as an offset from the start (I, J, K). The same start and end make a
+    // full circle
+    pub fn from_offset(
+        start: Vector2D<Distance>,
+        end: Vector2D<Distance>,
+        offset: Vector2D<Distance>,
+        direction: RotationDirection,
+        turns: u8,
+    ) -> Result<Self, StepperError> {
+        let center = start + offset;
+        let (ox, oy) = to_mm(offset);
+        let radius = precise_sqrt(ox * ox + oy * oy);
+        if radius == 0.0 {
+            return Err(StepperError::MoveNotValid);
+        }
+        let (ex, ey) = to_mm(end - center);
+        let error = abs(precise_sqrt(ex * ex + ey * ey) - radius);
+        if error > RADIUS_TOLERANCE_MM && error > radius * RADIUS_TOLERANCE_RATIO {
+            return Err(StepperError::MoveNotValid);
+        }
+        Ok(Self::new(start, center, end, direction, turns))
+    }
+
+    // the center is solved from the radius (R). A positive radius takes the shorter arc between
+    // start and end, a negative one the longer
+    pub fn from_radius(
+        start: Vector2D<Distance>,
+        end: Vector2D<Distance>,
+        radius: Distance,
+        direction: RotationDirection,
+        turns: u8,
+    ) -> Result<Self, StepperError> {
+        let (sx, sy) = to_mm(start);
+        let (dx, dy) = to_mm(end - start);
+        let d = precise_sqrt(dx * dx + dy * dy);
+        let r = abs(radius.as_millimeters());
+        if d < POINT_TOLERANCE_MM || r == 0.0 {
+            return Err(StepperError::MoveNotValid);
+        }
+        // distance of the center from the middle of the chord. A radius a bit shorter than half
+        // the chord is rounding, the center is then the middle itself
+        let h2 = r * r - d * d / 4.0;
+        let h = if h2 >= 0.0 {
+            precise_sqrt(h2)
+        } else if d / 2.0 - r <= RADIUS_TOLERANCE_MM.max(r * RADIUS_TOLERANCE_RATIO) {
+            0.0
+        } else {
+            return Err(StepperError::MoveNotValid);
+        };
+        // the shorter clockwise arc has its center on the right of the chord
+        let mut side = match direction {
+            RotationDirection::Clockwise => -1.0,
+            RotationDirection::CounterClockwise => 1.0,
+        };
+        if radius.as_millimeters() < 0.0 {
+            side = -side;
+        }
+        let center = from_mm(
+            sx + dx / 2.0 - side * h * dy / d,
+            sy + dy / 2.0 + side * h * dx / d,
+        );
+        Ok(Self::new(start, center, end, direction, turns))
+    }
+
+    pub fn get_start(&self) -> Vector2D<Distance> {
+        self.start
+    }
+
+    pub fn get_center(&self) -> Vector2D<Distance> {
+        self.center
+    }
+
+    pub fn get_end(&self) -> Vector2D<Distance> {
+        self.end
+    }
+
+    pub fn get_radius(&self) -> Distance {
+        self.radius
+    }
+
+    pub fn get_direction(&self) -> RotationDirection {
+        self.direction
+    }
+
+    pub fn get_sweep(&self) -> f64 {
+        self.sweep
+    }
+
+    pub fn length(&self) -> Distance {
+        self.radius * self.sweep
+    }
+
+    // point reached after turning by angle from the start
+    pub fn point_at(&self, angle: f64) -> Vector2D<Distance> {
+        let angle = match self.direction {
+            RotationDirection::CounterClockwise => angle,
+            RotationDirection::Clockwise => -angle,
+        };
+        let (sin, cos) = precise_sin_cos(angle);
+        let (cx, cy) = to_mm(self.center);
+        let (dx, dy) = to_mm(self.start - self.center);
+        from_mm(cx + dx * cos - dy * sin, cy + dx * sin + dy * cos)
+    }
+
+    // number of chords the arc is split into. The chords are as long as they can be while staying
+    // within tolerance from the arc, then kept between min_length and max_length. When the two
+    // clash, min_length wins: shorter chords couldn't be moved anyway
+    pub fn segments(&self, tolerance: Distance, min_length: Distance, max_length: Distance) -> u64 {
+        let radius = self.radius.as_millimeters();
+        let tolerance = tolerance.as_millimeters();
+        let mut length = if tolerance < radius {
+            // chord whose middle is tolerance away from the arc
+            2.0 * precise_sqrt(tolerance * (2.0 * radius - tolerance))
+        } else {
+            max_length.as_millimeters()
+        };
+        length = length
+            .min(max_length.as_millimeters())
+            .max(min_length.as_millimeters());
+        let ratio = self.length().as_millimeters() / length;
+        let segments = ratio as u64;
+        if (segments as f64) < ratio {
+            segments + 1
+        } else {
+            segments.max(1)
+        }
+    }
+
+    // smallest box holding the arc, as its min and max corners
+    pub fn bounding_box(&self) -> (Vector2D<Distance>, Vector2D<Distance>) {
+        let (sx, sy) = to_mm(self.start);
+        let (ex, ey) = to_mm(self.end);
+        let (cx, cy) = to_mm(self.center);
+        let radius = self.radius.as_millimeters();
+        let a0 = precise_atan2(sy - cy, sx - cx);
+        let (mut min_x, mut max_x) = (sx.min(ex), sx.max(ex));
+        let (mut min_y, mut max_y) = (sy.min(ey), sy.max(ey));
+        // the arc reaches the farthest point of the circle along an axis if it sweeps over its angle
+        for (angle, (dx, dy)) in [
+            (0.0, (radius, 0.0)),
+            (PI / 2.0, (0.0, radius)),
+            (PI, (-radius, 0.0)),
+            (3.0 * PI / 2.0, (0.0, -radius)),
+        ] {
+            let swept = match self.direction {
+                RotationDirection::CounterClockwise => ccw_angle(a0, angle),
+                RotationDirection::Clockwise => ccw_angle(angle, a0),
+            };
+            if swept <= self.sweep {
+                min_x = min_x.min(cx + dx);
+                max_x = max_x.max(cx + dx);
+                min_y = min_y.min(cy + dy);
+                max_y = max_y.max(cy + dy);
+            }
+        }
+        (from_mm(min_x, min_y), from_mm(max_x, max_y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_abs_diff_eq;
+
+    use super::*;
+
+    fn point(x: f64, y: f64) -> Vector2D<Distance> {
+        from_mm(x, y)
+    }
+
+    fn assert_point(v: Vector2D<Distance>, x: f64, y: f64) {
+        assert_abs_diff_eq!(v.get_x().as_millimeters(), x, epsilon = 1e-9);
+        assert_abs_diff_eq!(v.get_y().as_millimeters(), y, epsilon = 1e-9);
+    }
+
+    #[test]
+    fn test_precise_trigonometry() {
+        let mut angle = -10.0;
+        while angle < 10.0 {
+            let (sin, cos) = precise_sin_cos(angle);
+            assert_abs_diff_eq!(sin, angle.sin(), epsilon = 1e-12);
+            assert_abs_diff_eq!(cos, angle.cos(), epsilon = 1e-12);
+            let (y, x) = (3.0 * sin, 3.0 * cos);
+            assert_abs_diff_eq!(precise_atan2(y, x), y.atan2(x), epsilon = 1e-12);
+            angle += 0.37;
+        }
+    }
+
+    #[test]
+    fn test_arc_from_offset_quarter() {
+        let start = point(10.0, 0.0);
+        let end = point(0.0, 10.0);
+        let offset = point(-10.0, 0.0);
+
+        let arc =
+            Arc::from_offset(start, end, offset, RotationDirection::CounterClockwise, 0).unwrap();
+        assert_point(arc.get_center(), 0.0, 0.0);
+        assert_abs_diff_eq!(arc.get_radius().as_millimeters(), 10.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(arc.get_sweep(), PI / 2.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(arc.length().as_millimeters(), 5.0 * PI, epsilon = 1e-9);
+        let half = 10.0 / 2.0_f64.sqrt();
+        assert_point(arc.point_at(PI / 4.0), half, half);
+        assert_point(arc.point_at(arc.get_sweep()), 0.0, 10.0);
+
+        let arc = Arc::from_offset(start, end, offset, RotationDirection::Clockwise, 0).unwrap();
+        assert_abs_diff_eq!(arc.get_sweep(), 3.0 * PI / 2.0, epsilon = 1e-9);
+        assert_point(arc.point_at(PI / 4.0), half, -half);
+        let (min, max) = arc.bounding_box();
+        assert_point(min, -10.0, -10.0);
+        assert_point(max, 10.0, 10.0);
+    }
+
+    #[test]
+    fn test_arc_from_offset_full_circle() {
+        let start = point(3.0, 4.0);
+        let offset = point(5.0, 0.0);
+
+        let arc = Arc::from_offset(start, start, offset, RotationDirection::Clockwise, 0).unwrap();
+        assert_abs_diff_eq!(arc.get_sweep(), 2.0 * PI, epsilon = 1e-9);
+        let (min, max) = arc.bounding_box();
+        assert_point(min, 3.0, -1.0);
+        assert_point(max, 13.0, 9.0);
+
+        // helical moves add full turns
+        let arc = Arc::from_offset(start, start, offset, RotationDirection::Clockwise, 2).unwrap();
+        assert_abs_diff_eq!(arc.get_sweep(), 6.0 * PI, epsilon = 1e-9);
+        assert_abs_diff_eq!(arc.length().as_millimeters(), 30.0 * PI, epsilon = 1e-9);
+    }
+
+    #[test]
+    fn test_arc_from_offset_tolerance() {
+        let start = point(10.0, 0.0);
+        let offset = point(-10.0, 0.0);
+        let direction = RotationDirection::CounterClockwise;
+
+        // a rounded endpoint is accepted
+        assert!(Arc::from_offset(start, point(0.0, 10.005), offset, direction, 0).is_ok());
+        // an endpoint off the circle is not
+        assert!(matches!(
+            Arc::from_offset(start, point(0.0, 10.5), offset, direction, 0),
+            Err(StepperError::MoveNotValid)
+        ));
+        assert!(matches!(
+            Arc::from_offset(start, point(0.0, 10.0), point(0.0, 0.0), direction, 0),
+            Err(StepperError::MoveNotValid)
+        ));
+    }
+
+    #[test]
+    fn test_arc_from_radius() {
+        let start = point(0.0, 0.0);
+        let end = point(10.0, 0.0);
+        let h = 75.0_f64.sqrt();
+        let radius = Distance::from_millimeters(10.0);
+        let negative = Distance::from_millimeters(-10.0);
+
+        // the chord is a radius long, the shorter arc sweeps PI/3
+        let arc = Arc::from_radius(start, end, radius, RotationDirection::Clockwise, 0).unwrap();
+        assert_point(arc.get_center(), 5.0, -h);
+        assert_abs_diff_eq!(arc.get_sweep(), PI / 3.0, epsilon = 1e-9);
+        let (min, max) = arc.bounding_box();
+        assert_point(min, 0.0, 0.0);
+        assert_point(max, 10.0, 10.0 - h);
+
+        let arc =
+            Arc::from_radius(start, end, radius, RotationDirection::CounterClockwise, 0).unwrap();
+        assert_point(arc.get_center(), 5.0, h);
+        assert_abs_diff_eq!(arc.get_sweep(), PI / 3.0, epsilon = 1e-9);
+
+        // a negative radius takes the longer arc
+        let arc = Arc::from_radius(start, end, negative, RotationDirection::Clockwise, 0).unwrap();
+        assert_point(arc.get_center(), 5.0, h);
+        assert_abs_diff_eq!(arc.get_sweep(), 5.0 * PI / 3.0, epsilon = 1e-9);
+        let (min, max) = arc.bounding_box();
+        assert_point(min, -5.0, 0.0);
+        assert_point(max, 15.0, h + 10.0);
+
+        let arc =
+            Arc::from_radius(start, end, negative, RotationDirection::CounterClockwise, 0).unwrap();
+        assert_point(arc.get_center(), 5.0, -h);
+        assert_abs_diff_eq!(arc.get_sweep(), 5.0 * PI / 3.0, epsilon = 1e-9);
+    }
+
+    #[test]
+    fn test_arc_from_radius_off_origin() {
+        // half a circle, the center is the middle of the chord wherever the start is
+        let start = point(30.0, 40.0);
+        let end = point(30.0, 60.0);
+        let arc = Arc::from_radius(
+            start,
+            end,
+            Distance::from_millimeters(10.0),
+            RotationDirection::Clockwise,
+            0,
+        )
+        .unwrap();
+        assert_point(arc.get_center(), 30.0, 50.0);
+        assert_abs_diff_eq!(arc.get_sweep(), PI, epsilon = 1e-9);
+        // clockwise from the bottom of the circle passes on the left
+        assert_point(arc.point_at(PI / 2.0), 20.0, 50.0);
+    }
+
+    #[test]
+    fn test_arc_from_radius_invalid() {
+        let start = point(0.0, 0.0);
+        let direction = RotationDirection::Clockwise;
+        // the radius is shorter than half the chord
+        assert!(matches!(
+            Arc::from_radius(
+                start,
+                point(10.0, 0.0),
+                Distance::from_millimeters(4.0),
+                direction,
+                0
+            ),
+            Err(StepperError::MoveNotValid)
+        ));
+        // the circle through the same start and end is not defined
+        assert!(matches!(
+            Arc::from_radius(start, start, Distance::from_millimeters(4.0), direction, 0),
+            Err(StepperError::MoveNotValid)
+        ));
+    }
+
+    #[test]
+    fn test_arc_segments() {
+        let mm = Distance::from_millimeters;
+        let quarter = |radius: f64| {
+            Arc::from_offset(
+                point(radius, 0.0),
+                point(0.0, radius),
+                point(-radius, 0.0),
+                RotationDirection::CounterClockwise,
+                0,
+            )
+            .unwrap()
+        };
+
+        // chords of 2 * sqrt(0.01 * (2 * 10 - 0.01)) = 0.894mm on a 15.708mm arc
+        let arc = quarter(10.0);
+        assert_eq!(arc.segments(mm(0.01), mm(0.1), mm(2.0)), 18);
+        // a small arc gets more chords than a fixed length would give it, a large one fewer
+        let small = quarter(1.0);
+        assert_eq!(small.segments(mm(0.01), mm(0.1), mm(2.0)), 6);
+        let large = quarter(1000.0);
+        assert_eq!(large.segments(mm(0.01), mm(0.1), mm(2.0)), 786);
+        // the chord length is kept within its bounds
+        assert_eq!(arc.segments(mm(0.01), mm(0.1), mm(0.5)), 32);
+        assert_eq!(arc.segments(mm(0.01), mm(2.0), mm(4.0)), 8);
+        assert_eq!(small.segments(mm(5.0), mm(0.1), mm(2.0)), 1);
+        // an arc shorter than the shortest chord is a single chord
+        let tiny = quarter(0.05);
+        assert_eq!(tiny.segments(mm(0.01), mm(0.1), mm(2.0)), 1);
+    }
+
+    #[test]
+    fn test_plane_axes() {
+        assert_eq!(Plane::XY.axes(), [Axis::X, Axis::Y, Axis::Z]);
+        assert_eq!(Plane::ZX.axes(), [Axis::Z, Axis::X, Axis::Y]);
+        assert_eq!(Plane::YZ.axes(), [Axis::Y, Axis::Z, Axis::X]);
+    }
+}
@@ -1,18 +1,30 @@
-use crate::motion::auto_home;
+use crate::advance::LinearAdvance;
+use crate::leveling::{Leveling, Mesh};
+use crate::probe::{Probe, ProbePoint, ProbeStats};
+use crate::ramp::Ramp;
+use crate::shaper::{InputShaper, ShaperType};
+use crate::skew::Skew;
+use crate::motion::{auto_home_motors, dry_run_home, sensorless_home, HomingConfig};
+use crate::tmc::{DriverStatus, TmcDrivers, TmcError};
+use crate::workspace::Workspaces;
 
+use super::motion::arc::{Arc, Plane};
 use super::motion::{
-    arc_move_3d_e_offset_from_center, arc_move_3d_e_radius, linear_move_3d, linear_move_3d_e,
-    linear_move_to, no_move, retract, Positioning,
+    arc_move_3d_e, bezier_move_3d_e, linear_move_3d, linear_move_3d_e, linear_move_to,
+    linear_move_to_ramped, retract, Positioning,
 };
-use super::stepper::{Attached, Stepper, StepperError};
+use super::stepper::{Attached, Backlash, Stepper, StepperError, MAX_MOTORS};
+use core::fmt::Display;
 use core::marker::PhantomData;
 use core::time::Duration;
-use math::common::RotationDirection;
-use math::measurements::{Distance, Length, Speed};
+use math::bezier::CubicBezier;
+use math::common::{abs, precise_sqrt, RotationDirection};
+use math::measurements::{Acceleration, Distance, Length, Speed};
 use math::vector::{Vector2D, Vector3D};
+use math::Axis;
 use parser::gcode::GCommand;
 
-use common::{ExtiInputPinBase, OutputPinBase, TimerBase};
+use common::{ExtiInputPinBase, HalfDuplexSerialBase, OutputPinBase, TimerBase};
 
 #[derive(Clone, Copy)]
 pub struct RecoverMotionConfig {
@@ -27,17 +39,291 @@ pub struct RetractionMotionConfig {
     pub z_lift: Length,
 }
 
+// arcs are moved as a sequence of chords
+#[derive(Clone, Copy)]
+pub struct ArcMotionConfig {
+    // largest distance between a chord and the arc
+    pub tolerance: Length,
+    pub min_segment_length: Length,
+    pub max_segment_length: Length,
+    // shortest time a chord may take, the chords get longer at high feedrates
+    pub min_segment_duration: Duration,
+}
+
+// mesh bed leveling, the mesh is probed by G29 on a grid of points
+#[derive(Clone, Copy)]
+pub struct LevelingMotionConfig {
+    // number of points along X and Y
+    pub points: (usize, usize),
+    // corners of the probed area, in machine coordinates
+    pub min: (Distance, Distance),
+    pub max: (Distance, Distance),
+    // the compensation fades out up to this height, 0 disables the fading
+    pub fade_height: Length,
+}
+
+// the probe touches the bed for G29, G30 and M48
+#[derive(Clone, Copy)]
+pub struct ProbeMotionConfig {
+    // position of the probe from the nozzle (M851). Z is negative when the probe triggers below
+    // the nozzle: the height of the bed is the Z of the nozzle plus the offset
+    pub offset: (Distance, Distance, Distance),
+    // Z at which the probe moves from a point to the next one
+    pub clearance: Length,
+    // how far below the clearance the probe looks for the bed before giving up
+    pub max_depth: Length,
+    pub feedrate: Speed,
+    pub travel_feedrate: Speed,
+    // the bed is touched this many times at each point, its height is the average
+    pub samples: usize,
+    // lift between two touches
+    pub retract: Length,
+}
+
+// G34 aligns the motors of Z, the bed is probed near the lead screw of each one
+#[derive(Clone)]
+pub struct AlignmentMotionConfig {
+    // position of the probe near the lead screws, in machine coordinates. One for each motor
+    pub points: heapless::Vec<(Distance, Distance), MAX_MOTORS>,
+    // most corrections made by G34
+    pub iterations: usize,
+    // the motors are aligned once the heights of the bed differ by less than this
+    pub accuracy: Length,
+}
+
+// slack of X, Y and Z, taken up after each reversal (M425)
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct BacklashMotionConfig {
+    pub distance: (Distance, Distance, Distance),
+    // fraction of the backlash taken up, from 0 to 1
+    pub correction: f64,
+    // travel over which the correction is spread, 0 takes it up at once
+    pub smoothing: Length,
+}
+
+impl Display for BacklashMotionConfig {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::write!(
+            f,
+            "Backlash: [correction:{:.2}] [smoothing:{:.3}] [X:{:.3}] [Y:{:.3}] [Z:{:.3}]",
+            self.correction,
+            self.smoothing.as_millimeters(),
+            self.distance.0.as_millimeters(),
+            self.distance.1.as_millimeters(),
+            self.distance.2.as_millimeters()
+        )
+    }
+}
+
+// resonance compensation of X and Y (M593)
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct InputShapingMotionConfig {
+    pub x: InputShaper,
+    pub y: InputShaper,
+}
+
+impl Display for InputShapingMotionConfig {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::write!(f, "Input shaping: [X:{}] [Y:{}]", self.x, self.y)
+    }
+}
+
+// flow multiplier (M221) and volumetric extrusion (M200). The E of the moves and the lengths of
+// the firmware retraction are scaled by them before reaching the extruder
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ExtrusionMotionConfig {
+    pub flow_multiplier: f64,
+    // E is a volume in mm³ of this filament, a length without it
+    pub filament_diameter: Option<Distance>,
+}
+
+impl Default for ExtrusionMotionConfig {
+    fn default() -> Self {
+        Self {
+            flow_multiplier: 1.0,
+            filament_diameter: None,
+        }
+    }
+}
+
+impl ExtrusionMotionConfig {
+    // length of filament pushed for a unit of E
+    pub fn factor(&self) -> f64 {
+        match self.filament_diameter {
+            Some(diameter) => {
+                let radius = diameter.as_millimeters() / 2.0;
+                self.flow_multiplier / (core::f64::consts::PI * radius * radius)
+            }
+            None => self.flow_multiplier,
+        }
+    }
+}
+
+impl Display for ExtrusionMotionConfig {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::write!(f, "Extrusion: [flow:{:.1}%]", self.flow_multiplier * 100.0)?;
+        match self.filament_diameter {
+            Some(diameter) => core::write!(f, " [volumetric:{:.3}]", diameter.as_millimeters()),
+            None => core::write!(f, " [volumetric:Off]"),
+        }
+    }
+}
+
+// limits of X, Y, Z and E. The feedrate of every move is lowered until no axis goes faster than
+// its max feedrate (M203). A move speeds up at the acceleration of its kind (M204) and no axis
+// accelerates over its max acceleration (M201). It starts and ends at the speed that changes no
+// axis by more than its jerk (M205), see limit_ramp
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct LimitsMotionConfig {
+    pub max_feedrate: (Speed, Speed, Speed, Speed),
+    pub max_acceleration: (Acceleration, Acceleration, Acceleration, Acceleration),
+    // acceleration of the extruding moves, of the retractions and of the travels
+    pub print_acceleration: Acceleration,
+    pub retract_acceleration: Acceleration,
+    pub travel_acceleration: Acceleration,
+    // largest change of speed of an axis taken without slowing down
+    pub jerk: (Speed, Speed, Speed, Speed),
+}
+
+impl Display for LimitsMotionConfig {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mm_s = |s: Speed| s.as_meters_per_second() * 1000.0;
+        let mm_s2 = |a: Acceleration| a.as_meters_per_second_per_second() * 1000.0;
+        let (feedrate, acceleration, jerk) = (self.max_feedrate, self.max_acceleration, self.jerk);
+        core::write!(
+            f,
+            "Limits: [feedrate X:{:.1} Y:{:.1} Z:{:.1} E:{:.1}]",
+            mm_s(feedrate.0),
+            mm_s(feedrate.1),
+            mm_s(feedrate.2),
+            mm_s(feedrate.3)
+        )?;
+        core::write!(
+            f,
+            " [acceleration X:{:.0} Y:{:.0} Z:{:.0} E:{:.0}]",
+            mm_s2(acceleration.0),
+            mm_s2(acceleration.1),
+            mm_s2(acceleration.2),
+            mm_s2(acceleration.3)
+        )?;
+        core::write!(
+            f,
+            " [P:{:.0} R:{:.0} T:{:.0}]",
+            mm_s2(self.print_acceleration),
+            mm_s2(self.retract_acceleration),
+            mm_s2(self.travel_acceleration)
+        )?;
+        core::write!(
+            f,
+            " [jerk X:{:.1} Y:{:.1} Z:{:.1} E:{:.1}]",
+            mm_s(jerk.0),
+            mm_s(jerk.1),
+            mm_s(jerk.2),
+            mm_s(jerk.3)
+        )
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct HomingMotionConfig {
+    pub axes: (HomingConfig, HomingConfig, HomingConfig),
+    // order in which the axes are homed by G28
+    pub order: [Axis; 3],
+    // lift Z before homing X and Y, 0 disables it
+    pub z_lift: Length,
+    // refuse moves on axes that haven't been homed yet
+    pub required: bool,
+    // home offsets (M206), the position of an axis seen from G-code is its machine position plus the offset
+    pub offset: (Distance, Distance, Distance),
+}
+
+#[derive(Clone, Copy)]
+pub struct SoftwareEndstopsConfig {
+    // check the destination of every move against the bounds of the steppers before moving
+    pub enabled: bool,
+    // clip the linear moves to the bounds instead of refusing them. Arcs are always refused,
+    // clipping them would change their shape
+    pub clip: bool,
+}
+
+// state of the software endstops, as reported by M211
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct SoftwareEndstops {
+    pub enabled: bool,
+    // bounds of X, Y and Z in G-code coordinates, none if the stepper has no bounds
+    pub bounds: [Option<(Distance, Distance)>; 3],
+}
+
+// outcome of G34
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ZAlignment {
+    // corrections made
+    pub iterations: usize,
+    // difference between the highest and the lowest height of the bed, measured last
+    pub deviation: Distance,
+}
+
+impl Display for ZAlignment {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::write!(
+            f,
+            "Z alignment: [iterations:{}] [deviation:{:.4}]",
+            self.iterations,
+            self.deviation.as_millimeters()
+        )
+    }
+}
+
+impl Display for SoftwareEndstops {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let state = if self.enabled { "On" } else { "Off" };
+        core::write!(f, "Software endstops: {}", state)?;
+        for (axis, bounds) in [Axis::X, Axis::Y, Axis::Z].into_iter().zip(self.bounds) {
+            match bounds {
+                Some((min, max)) => core::write!(
+                    f,
+                    " [{}:{:.3}..{:.3}]",
+                    axis,
+                    min.as_millimeters(),
+                    max.as_millimeters()
+                )?,
+                None => core::write!(f, " [{}:-]", axis)?,
+            }
+        }
+        Ok(())
+    }
+}
+
 pub struct MotionConfig {
-    pub arc_unit_length: Length,
+    pub arcs: ArcMotionConfig,
     pub feedrate: Speed,
     pub positioning: Positioning,
     pub e_positioning: Positioning,
     pub feedrate_multiplier: f64,
+    pub extrusion: ExtrusionMotionConfig,
+    pub limits: LimitsMotionConfig,
     pub retraction: RetractionMotionConfig,
     pub recover: RecoverMotionConfig,
+    pub homing: HomingMotionConfig,
+    pub software_endstops: SoftwareEndstopsConfig,
+    pub leveling: LevelingMotionConfig,
+    pub probe: ProbeMotionConfig,
+    pub alignment: AlignmentMotionConfig,
+    pub backlash: BacklashMotionConfig,
+    // squareness of the frame (M852), corrected on the moves of G0, G1, G2, G3 and G5
+    pub skew: Skew,
+    pub shaping: InputShapingMotionConfig,
+    // pressure advance of the extruder (M900)
+    pub advance: LinearAdvance,
 }
 
-pub struct Planner<P: OutputPinBase, T: TimerBase, I: ExtiInputPinBase> {
+pub struct Planner<
+    P: OutputPinBase,
+    T: TimerBase,
+    I: ExtiInputPinBase,
+    S: HalfDuplexSerialBase,
+    Z: Probe,
+> {
     x_stepper: Stepper<P, Attached>,
     y_stepper: Stepper<P, Attached>,
     z_stepper: Stepper<P, Attached>,
@@ -45,9 +331,33 @@ pub struct Planner<P: OutputPinBase, T: TimerBase, I: ExtiInputPinBase> {
     config: MotionConfig,
     _timer: PhantomData<T>,
     endstops: (Option<I>, Option<I>, Option<I>, Option<I>),
+    // endstops of the other motors of X, Y and Z, see add_motor
+    motor_endstops: [heapless::Vec<Option<I>, { MAX_MOTORS - 1 }>; 3],
+    drivers: Option<TmcDrivers<S>>,
+    homed: (bool, bool, bool),
+    // position requested by the last moves, in machine coordinates. Moves are computed from it
+    // rather than from the position of the steppers, so the part of a move that can't be covered
+    // by a whole step is carried over to the next one instead of being lost
+    commanded: (Distance, Distance, Distance, Distance),
+    // position of E seen from G-code. The extruder moves by its changes scaled by the
+    // extrusion, see e_target
+    extruded: Distance,
+    // compute the moves without moving, see set_dry_run
+    dry_run: bool,
+    // plane of the arcs
+    plane: Plane,
+    // touches the bed for G29, G30 and M48, see set_probe
+    probe: Option<Z>,
+    leveling: Leveling,
+    // offsets of the coordinate systems, see offset
+    workspaces: Workspaces,
+    // shift of X, Y and Z set by G92, on top of the coordinate system in use
+    g92_offset: [Distance; 3],
 }
 
-impl<P: OutputPinBase, T: TimerBase, I: ExtiInputPinBase> Planner<P, T, I> {
+impl<P: OutputPinBase, T: TimerBase, I: ExtiInputPinBase, S: HalfDuplexSerialBase, Z: Probe>
+    Planner<P, T, I, S, Z>
+{
     pub fn new(
         x_stepper: Stepper<P, Attached>,
         y_stepper: Stepper<P, Attached>,
@@ -55,8 +365,22 @@ impl<P: OutputPinBase, T: TimerBase, I: ExtiInputPinBase> Planner<P, T, I> {
         e_stepper: Stepper<P, Attached>,
         config: MotionConfig,
         endstops: (Option<I>, Option<I>, Option<I>, Option<I>),
+        drivers: Option<TmcDrivers<S>>,
     ) -> Self {
-        Planner {
+        let mut x_stepper = x_stepper;
+        let mut y_stepper = y_stepper;
+        let mut z_stepper = z_stepper;
+        for stepper in [&mut x_stepper, &mut y_stepper, &mut z_stepper] {
+            stepper.set_bounds_check(config.software_endstops.enabled);
+        }
+        let commanded = (
+            x_stepper.get_position(),
+            y_stepper.get_position(),
+            z_stepper.get_position(),
+            e_stepper.get_position(),
+        );
+        let leveling = Leveling::new(config.leveling.fade_height);
+        let mut planner = Planner {
             x_stepper,
             y_stepper,
             z_stepper,
@@ -64,19 +388,202 @@ impl<P: OutputPinBase, T: TimerBase, I: ExtiInputPinBase> Planner<P, T, I> {
             _timer: PhantomData,
             config,
             endstops,
+            motor_endstops: [heapless::Vec::new(), heapless::Vec::new(), heapless::Vec::new()],
+            drivers,
+            homed: (false, false, false),
+            commanded,
+            extruded: commanded.3,
+            dry_run: false,
+            plane: Plane::XY,
+            probe: None,
+            leveling,
+            workspaces: Workspaces::default(),
+            g92_offset: [Distance::from_millimeters(0.0); 3],
+        };
+        planner.apply_backlash();
+        planner.apply_shaping();
+        planner.e_stepper.set_advance(planner.config.advance);
+        planner
+    }
+
+    // drive X, Y or Z with one more motor. With an endstop of its own, the motor stops on it
+    // while homing, which squares the axis
+    pub fn add_motor(
+        &mut self,
+        axis: Axis,
+        step: P,
+        dir: P,
+        endstop: Option<I>,
+    ) -> Result<(), StepperError> {
+        let stepper = match axis {
+            Axis::X => &mut self.x_stepper,
+            Axis::Y => &mut self.y_stepper,
+            Axis::Z => &mut self.z_stepper,
+            Axis::E => return Err(StepperError::NotSupported),
+        };
+        stepper.add_motor(step, dir)?;
+        self.motor_endstops[axis_index(axis)]
+            .push(endstop)
+            .map_err(|_| StepperError::NotSupported)
+    }
+
+    // G29, G30 and M48 need a probe
+    pub fn set_probe(&mut self, probe: Option<Z>) {
+        self.probe = probe;
+    }
+
+    // e.g. to send the commands of M280 to a BLTouch
+    pub fn get_probe_mut(&mut self) -> Option<&mut Z> {
+        self.probe.as_mut()
+    }
+
+    pub fn get_probe_offset(&self) -> (Distance, Distance, Distance) {
+        self.config.probe.offset
+    }
+
+    pub fn get_leveling(&self) -> &Leveling {
+        &self.leveling
+    }
+
+    pub fn get_skew(&self) -> Skew {
+        self.config.skew
+    }
+
+    pub fn get_backlash(&self) -> BacklashMotionConfig {
+        self.config.backlash
+    }
+
+    fn apply_backlash(&mut self) {
+        let config = self.config.backlash;
+        let steppers = [&mut self.x_stepper, &mut self.y_stepper, &mut self.z_stepper];
+        for (stepper, distance) in steppers
+            .into_iter()
+            .zip([config.distance.0, config.distance.1, config.distance.2])
+        {
+            stepper.set_backlash(Backlash {
+                distance,
+                correction: config.correction,
+                smoothing: config.smoothing,
+            });
+        }
+    }
+
+    pub fn get_shaping(&self) -> InputShapingMotionConfig {
+        self.config.shaping
+    }
+
+    fn apply_shaping(&mut self) {
+        self.x_stepper.set_shaper(self.config.shaping.x);
+        self.y_stepper.set_shaper(self.config.shaping.y);
+    }
+
+    pub fn get_advance(&self) -> LinearAdvance {
+        self.config.advance
+    }
+
+    pub fn get_extrusion(&self) -> ExtrusionMotionConfig {
+        self.config.extrusion
+    }
+
+    pub fn get_limits(&self) -> LimitsMotionConfig {
+        self.config.limits
+    }
+
+    // in dry run the commands are executed without pulsing the steppers nor waiting, they
+    // return the time they would take. Homing puts the axes where they would be once homed
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+        self.x_stepper.set_dry_run(dry_run);
+        self.y_stepper.set_dry_run(dry_run);
+        self.z_stepper.set_dry_run(dry_run);
+        self.e_stepper.set_dry_run(dry_run);
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    // write the configuration of the stepper drivers, if any
+    pub async fn init_drivers(&mut self) -> Result<(), StepperError> {
+        if let Some(drivers) = self.drivers.as_mut() {
+            drivers.init().await?;
         }
+        Ok(())
+    }
+
+    pub async fn get_drivers_status(
+        &mut self,
+    ) -> Option<(
+        Option<Result<DriverStatus, TmcError>>,
+        Option<Result<DriverStatus, TmcError>>,
+        Option<Result<DriverStatus, TmcError>>,
+        Option<Result<DriverStatus, TmcError>>,
+    )> {
+        let drivers = self.drivers.as_mut()?;
+        Some(drivers.get_status().await)
     }
 
+    // the skew correction isn't part of the position
     pub fn get_x_position(&self) -> Distance {
-        self.x_stepper.get_position()
+        self.get_machine_position().0 + self.offset().0
     }
 
     pub fn get_y_position(&self) -> Distance {
-        self.y_stepper.get_position()
+        self.get_machine_position().1 + self.offset().1
     }
 
+    // the height of the bed added by the leveling isn't part of the position
     pub fn get_z_position(&self) -> Distance {
-        self.z_stepper.get_position()
+        self.get_machine_position().2 + self.offset().2
+    }
+
+    // offsets from machine to G-code coordinates: the home offsets and the ones of the
+    // coordinate system in use on top of them
+    fn offset(&self) -> (Distance, Distance, Distance) {
+        let home = self.config.homing.offset;
+        let [x, y, z] = self.workspaces.offset();
+        let [gx, gy, gz] = self.g92_offset;
+        (home.0 + x + gx, home.1 + y + gy, home.2 + z + gz)
+    }
+
+    pub fn get_workspaces(&self) -> Workspaces {
+        self.workspaces
+    }
+
+    // position of the steppers without the skew correction and the compensation of the bed
+    // leveling, in machine coordinates
+    pub fn get_machine_position(&self) -> (Distance, Distance, Distance) {
+        let (x, y, z) = self.config.skew.revert(
+            self.x_stepper.get_position(),
+            self.y_stepper.get_position(),
+            self.z_stepper.get_position(),
+        );
+        (x, y, z - self.leveling.z_offset(x, y, z))
+    }
+
+    pub fn is_homed(&self) -> (bool, bool, bool) {
+        self.homed
+    }
+
+    // the steppers of X, Y and Z don't check their bounds either while the software endstops are
+    // disabled
+    pub fn set_software_endstops(&mut self, enabled: bool) {
+        self.config.software_endstops.enabled = enabled;
+        self.x_stepper.set_bounds_check(enabled);
+        self.y_stepper.set_bounds_check(enabled);
+        self.z_stepper.set_bounds_check(enabled);
+    }
+
+    pub fn get_software_endstops(&self) -> SoftwareEndstops {
+        let offset = self.offset();
+        let mut bounds = self.machine_bounds();
+        for (b, offset) in bounds.iter_mut().zip([offset.0, offset.1, offset.2]) {
+            *b = b.map(|(min, max)| (min + offset, max + offset));
+        }
+        SoftwareEndstops {
+            enabled: self.config.software_endstops.enabled,
+            bounds,
+        }
     }
 
     pub fn get_e_position(&self) -> Distance {
@@ -101,9 +608,11 @@ impl<P: OutputPinBase, T: TimerBase, I: ExtiInputPinBase> Planner<P, T, I> {
                 f,
                 i,
                 j,
+                k,
                 r,
+                p,
             } => {
-                let duration = self.g2(x, y, z, e, f, i, j, r).await?;
+                let duration = self.g2(x, y, z, e, f, i, j, k, r, p).await?;
                 Ok(Some(duration))
             }
             GCommand::G3 {
@@ -114,14 +623,29 @@ impl<P: OutputPinBase, T: TimerBase, I: ExtiInputPinBase> Planner<P, T, I> {
                 f,
                 i,
                 j,
+                k,
                 r,
+                p,
             } => {
-                let duration = self.g3(x, y, z, e, f, i, j, r).await?;
+                let duration = self.g3(x, y, z, e, f, i, j, k, r, p).await?;
                 Ok(Some(duration))
             }
             GCommand::G4 { p, s } => {
-                self.g4(p, s).await;
-                Ok(None)
+                let duration = self.g4(p, s).await;
+                Ok(Some(duration))
+            }
+            GCommand::G5 {
+                x,
+                y,
+                e,
+                f,
+                i,
+                j,
+                p,
+                q,
+            } => {
+                let duration = self.g5(x, y, e, f, (i, j), (p, q)).await?;
+                Ok(Some(duration))
             }
             GCommand::G90 => {
                 self.g90();
@@ -135,16 +659,88 @@ impl<P: OutputPinBase, T: TimerBase, I: ExtiInputPinBase> Planner<P, T, I> {
                 self.g92(x, y, z, e);
                 Ok(None)
             }
+            GCommand::G92_1 => {
+                self.g92_offset = [Distance::from_millimeters(0.0); 3];
+                Ok(None)
+            }
+            GCommand::G53 { x, y, z, f } => {
+                let duration = self.g53(x, y, z, f).await?;
+                Ok(Some(duration))
+            }
+            GCommand::G54 => {
+                self.workspaces.select(0);
+                Ok(None)
+            }
+            GCommand::G55 => {
+                self.workspaces.select(1);
+                Ok(None)
+            }
+            GCommand::G56 => {
+                self.workspaces.select(2);
+                Ok(None)
+            }
+            GCommand::G57 => {
+                self.workspaces.select(3);
+                Ok(None)
+            }
+            GCommand::G58 => {
+                self.workspaces.select(4);
+                Ok(None)
+            }
+            GCommand::G59 => {
+                self.workspaces.select(5);
+                Ok(None)
+            }
             GCommand::G10 => {
                 self.g10().await?;
                 Ok(None)
             }
+            GCommand::G10_L2 { p, x, y, z } => {
+                self.g10_l2(p, [x, y, z], false)?;
+                Ok(None)
+            }
+            GCommand::G10_L20 { p, x, y, z } => {
+                self.g10_l2(p, [x, y, z], true)?;
+                Ok(None)
+            }
             GCommand::G11 => {
                 self.g11().await?;
                 Ok(None)
             }
+            GCommand::G17 => {
+                self.plane = Plane::XY;
+                Ok(None)
+            }
+            GCommand::G18 => {
+                self.plane = Plane::ZX;
+                Ok(None)
+            }
+            GCommand::G19 => {
+                self.plane = Plane::YZ;
+                Ok(None)
+            }
             GCommand::G28 { x, y, z } => {
-                let duration = self.g28((x, y, z)).await?;
+                let result = self.g28((x, y, z)).await;
+                self.sync_commanded();
+                Ok(Some(result?))
+            }
+            // T only prints the mesh
+            GCommand::G29 { t: true } => Ok(None),
+            GCommand::G29 { t: false } => {
+                let duration = self.g29().await?;
+                Ok(Some(duration))
+            }
+            GCommand::G30 { x, y } => {
+                let samples = self.config.probe.samples;
+                let (_, _, duration) = self.probe_series(x, y, 1, samples).await?;
+                Ok(Some(duration))
+            }
+            GCommand::M48 { p, x, y } => {
+                let (_, _, duration) = self.probe_series(x, y, m48_count(p)?, 1).await?;
+                Ok(Some(duration))
+            }
+            GCommand::G34 { i, t } => {
+                let (_, duration) = self.g34(i, t).await?;
                 Ok(Some(duration))
             }
             GCommand::M82 => {
@@ -155,6 +751,10 @@ impl<P: OutputPinBase, T: TimerBase, I: ExtiInputPinBase> Planner<P, T, I> {
                 self.m83();
                 Ok(None)
             }
+            GCommand::M206 { x, y, z } => {
+                self.m206(x, y, z);
+                Ok(None)
+            }
             GCommand::M207 { f, s, z } => {
                 self.m207(f, s, z);
                 Ok(None)
@@ -163,22 +763,94 @@ impl<P: OutputPinBase, T: TimerBase, I: ExtiInputPinBase> Planner<P, T, I> {
                 self.m208(f, s);
                 Ok(None)
             }
+            GCommand::M211 { s } => {
+                if let Some(enabled) = s {
+                    self.set_software_endstops(enabled);
+                }
+                Ok(None)
+            }
             GCommand::M220 { s } => {
                 self.config.feedrate_multiplier = s;
                 Ok(None)
             }
+            GCommand::M221 { s } => {
+                self.m221(s)?;
+                Ok(None)
+            }
+            GCommand::M200 { d } => {
+                self.m200(d)?;
+                Ok(None)
+            }
+            GCommand::M201 { x, y, z, e } => {
+                self.m201(x, y, z, e)?;
+                Ok(None)
+            }
+            GCommand::M203 { x, y, z, e } => {
+                self.m203(x, y, z, e)?;
+                Ok(None)
+            }
+            GCommand::M204 { p, r, t } => {
+                self.m204(p, r, t)?;
+                Ok(None)
+            }
+            GCommand::M205 { x, y, z, e } => {
+                self.m205(x, y, z, e)?;
+                Ok(None)
+            }
+            GCommand::M420 { s, z } => {
+                self.m420(s, z);
+                Ok(None)
+            }
+            GCommand::M425 { f, s, x, y, z } => {
+                self.m425(f, s, x, y, z)?;
+                Ok(None)
+            }
+            GCommand::M593 { d, f, t, x, y } => {
+                self.m593(d, f, t, x, y)?;
+                Ok(None)
+            }
+            GCommand::M900 { k } => {
+                self.m900(k)?;
+                Ok(None)
+            }
+            GCommand::M851 { x, y, z } => {
+                self.m851(x, y, z);
+                Ok(None)
+            }
+            GCommand::M852 { i, j, k } => {
+                self.m852(i, j, k);
+                Ok(None)
+            }
+            GCommand::M569 { .. }
+            | GCommand::M906 { .. }
+            | GCommand::M913 { .. }
+            | GCommand::M914 { .. } => {
+                let drivers = self.drivers.as_mut().ok_or(StepperError::NotSupported)?;
+                drivers.execute(command).await?;
+                Ok(None)
+            }
             _ => Err(StepperError::NotSupported),
         }
     }
 
-    async fn g4(&mut self, p: Option<core::time::Duration>, s: Option<core::time::Duration>) {
+    async fn g4(
+        &mut self,
+        p: Option<core::time::Duration>,
+        s: Option<core::time::Duration>,
+    ) -> core::time::Duration {
         let d = match (p, s) {
             (None, None) => None,
             (None, Some(_)) | (Some(_), Some(_)) => s,
             (Some(_), None) => p,
         };
-        if let Some(duration) = d {
-            T::after(duration).await
+        match d {
+            Some(duration) => {
+                if !self.dry_run {
+                    T::after(duration).await
+                }
+                duration
+            }
+            None => Duration::ZERO,
         }
     }
 
@@ -186,22 +858,61 @@ impl<P: OutputPinBase, T: TimerBase, I: ExtiInputPinBase> Planner<P, T, I> {
         self.config.positioning = Positioning::Absolute;
     }
 
+    // G10 L2 puts the origin of the coordinate system P at the values in machine coordinates,
+    // G10 L20 sets its offsets so that the position of the head in it becomes the values
+    fn g10_l2(
+        &mut self,
+        p: Option<u8>,
+        values: [Option<Distance>; 3],
+        current: bool,
+    ) -> Result<(), StepperError> {
+        let index = self.workspaces.index(p).ok_or(StepperError::MoveNotValid)?;
+        let mut offset = self.workspaces.get_offset(index).ok_or(StepperError::MoveNotValid)?;
+        let home = self.config.homing.offset;
+        let home = [home.0, home.1, home.2];
+        let commanded = [self.commanded.0, self.commanded.1, self.commanded.2];
+        for (i, value) in values.into_iter().enumerate() {
+            if let Some(value) = value {
+                offset[i] = match current {
+                    true => value - commanded[i] - home[i] - self.g92_offset[i],
+                    false => Distance::from_millimeters(-value.as_millimeters()),
+                };
+            }
+        }
+        self.workspaces.set_offset(index, offset);
+        Ok(())
+    }
+
     fn g91(&mut self) {
         self.config.positioning = Positioning::Relative;
     }
 
+    // the head stays where it is, the G92 shift is changed so that its position becomes the one
+    // given. It stays through the changes of coordinate system. E has no offsets, its position
+    // is set
     fn g92(&mut self, x: Option<Distance>, y: Option<Distance>, z: Option<Distance>, e: Option<Distance>) {
-        if let Some(x) = x{
-            self.x_stepper.set_position(x);
+        let home = self.config.homing.offset;
+        let home = [home.0, home.1, home.2];
+        let workspace = self.workspaces.offset();
+        let commanded = [self.commanded.0, self.commanded.1, self.commanded.2];
+        for (i, value) in [x, y, z].into_iter().enumerate() {
+            if let Some(value) = value {
+                self.g92_offset[i] = value - commanded[i] - home[i] - workspace[i];
+            }
         }
-        if let Some(y) = y{
-            self.y_stepper.set_position(y);
+        if let Some(e) = e {
+            self.e_stepper.set_position(e);
+            self.extruded = e;
+            self.commanded.3 = e;
         }
-        if let Some(z) = z{
-            self.z_stepper.set_position(z);
+    }
+
+    fn m420(&mut self, s: Option<bool>, z: Option<Distance>) {
+        if let Some(enabled) = s {
+            self.leveling.set_enabled(enabled);
         }
-        if let Some(e) = e{
-            self.e_stepper.set_position(e);
+        if let Some(height) = z {
+            self.leveling.set_fade_height(height);
         }
     }
 
@@ -213,6 +924,127 @@ impl<P: OutputPinBase, T: TimerBase, I: ExtiInputPinBase> Planner<P, T, I> {
         self.config.e_positioning = Positioning::Relative;
     }
 
+    // nothing changes if one of the values isn't valid
+    fn m425(
+        &mut self,
+        f: Option<f64>,
+        s: Option<Distance>,
+        x: Option<Distance>,
+        y: Option<Distance>,
+        z: Option<Distance>,
+    ) -> Result<(), StepperError> {
+        let zero = Distance::from_millimeters(0.0);
+        if f.is_some_and(|f| !(0.0..=1.0).contains(&f))
+            || [s, x, y, z].into_iter().flatten().any(|d| d < zero)
+        {
+            return Err(StepperError::MoveNotValid);
+        }
+        let backlash = &mut self.config.backlash;
+        if let Some(f) = f {
+            backlash.correction = f;
+        }
+        if let Some(s) = s {
+            backlash.smoothing = s;
+        }
+        if let Some(x) = x {
+            backlash.distance.0 = x;
+        }
+        if let Some(y) = y {
+            backlash.distance.1 = y;
+        }
+        if let Some(z) = z {
+            backlash.distance.2 = z;
+        }
+        self.apply_backlash();
+        Ok(())
+    }
+
+    // nothing changes if one of the values isn't valid
+    fn m593(
+        &mut self,
+        d: Option<f64>,
+        f: Option<f64>,
+        t: Option<u8>,
+        x: bool,
+        y: bool,
+    ) -> Result<(), StepperError> {
+        let shaper_type = match t {
+            Some(t) => Some(ShaperType::try_from(t).map_err(|_| StepperError::MoveNotValid)?),
+            None => None,
+        };
+        if d.is_some_and(|d| !(0.0..1.0).contains(&d)) || f.is_some_and(|f| f < 0.0) {
+            return Err(StepperError::MoveNotValid);
+        }
+        let shaping = &mut self.config.shaping;
+        for (shaper, selected) in [(&mut shaping.x, x), (&mut shaping.y, y)] {
+            if !selected {
+                continue;
+            }
+            if let Some(t) = shaper_type {
+                shaper.shaper_type = t;
+            }
+            if let Some(f) = f {
+                shaper.frequency = f;
+            }
+            if let Some(d) = d {
+                shaper.damping = d;
+            }
+        }
+        self.apply_shaping();
+        Ok(())
+    }
+
+    fn m900(&mut self, k: Option<f64>) -> Result<(), StepperError> {
+        if let Some(k) = k {
+            if k < 0.0 {
+                return Err(StepperError::MoveNotValid);
+            }
+            self.config.advance.k = k;
+            self.e_stepper.set_advance(self.config.advance);
+        }
+        Ok(())
+    }
+
+    fn m851(&mut self, x: Option<Distance>, y: Option<Distance>, z: Option<Distance>) {
+        let offset = &mut self.config.probe.offset;
+        if let Some(x) = x {
+            offset.0 = x;
+        }
+        if let Some(y) = y {
+            offset.1 = y;
+        }
+        if let Some(z) = z {
+            offset.2 = z;
+        }
+    }
+
+    // the position of the steppers is kept, the commanded one follows the new skew
+    fn m852(&mut self, i: Option<f64>, j: Option<f64>, k: Option<f64>) {
+        let skew = &mut self.config.skew;
+        if let Some(xy) = i {
+            skew.xy = xy;
+        }
+        if let Some(xz) = j {
+            skew.xz = xz;
+        }
+        if let Some(yz) = k {
+            skew.yz = yz;
+        }
+        self.sync_commanded();
+    }
+
+    fn m206(&mut self, x: Option<Distance>, y: Option<Distance>, z: Option<Distance>) {
+        if let Some(x) = x {
+            self.config.homing.offset.0 = x;
+        }
+        if let Some(y) = y {
+            self.config.homing.offset.1 = y;
+        }
+        if let Some(z) = z {
+            self.config.homing.offset.2 = z;
+        }
+    }
+
     // firmware retraction settings
     fn m207(&mut self, f: Speed, s: Distance, z: Distance) {
         self.config.retraction.feedrate = f;
@@ -225,50 +1057,227 @@ impl<P: OutputPinBase, T: TimerBase, I: ExtiInputPinBase> Planner<P, T, I> {
         self.config.recover.length = s + self.config.retraction.length;
     }
 
-    async fn g0(
-        &mut self,
-        x: Option<Distance>,
-        y: Option<Distance>,
-        z: Option<Distance>,
-        f: Option<Speed>,
-    ) -> Result<core::time::Duration, StepperError> {
-        if let Some(feedrate) = f {
-            self.config.feedrate = feedrate;
+    fn m221(&mut self, s: f64) -> Result<(), StepperError> {
+        if s <= 0.0 {
+            return Err(StepperError::MoveNotValid);
         }
-        let feedrate = self.config.feedrate * self.config.feedrate_multiplier;
-        let x = match x {
-            Some(v) => v,
-            None => no_move(&self.x_stepper, self.config.positioning),
-        };
+        self.config.extrusion.flow_multiplier = s;
+        Ok(())
+    }
 
-        let y = match y {
-            Some(v) => v,
-            None => no_move(&self.y_stepper, self.config.positioning),
-        };
+    // a diameter of 0 goes back to lengths, without it nothing changes
+    fn m200(&mut self, d: Option<Distance>) -> Result<(), StepperError> {
+        match d {
+            Some(d) if d.as_millimeters() < 0.0 => return Err(StepperError::MoveNotValid),
+            Some(d) if d.as_millimeters() == 0.0 => self.config.extrusion.filament_diameter = None,
+            Some(d) => self.config.extrusion.filament_diameter = Some(d),
+            None => (),
+        }
+        Ok(())
+    }
+
+    // nothing changes if one of the accelerations isn't positive
+    fn m201(
+        &mut self,
+        x: Option<Acceleration>,
+        y: Option<Acceleration>,
+        z: Option<Acceleration>,
+        e: Option<Acceleration>,
+    ) -> Result<(), StepperError> {
+        if [x, y, z, e].into_iter().flatten().any(|a| a.as_meters_per_second_per_second() <= 0.0) {
+            return Err(StepperError::MoveNotValid);
+        }
+        let max = &mut self.config.limits.max_acceleration;
+        *max = (
+            x.unwrap_or(max.0),
+            y.unwrap_or(max.1),
+            z.unwrap_or(max.2),
+            e.unwrap_or(max.3),
+        );
+        Ok(())
+    }
+
+    // nothing changes if one of the feedrates isn't positive
+    fn m203(
+        &mut self,
+        x: Option<Speed>,
+        y: Option<Speed>,
+        z: Option<Speed>,
+        e: Option<Speed>,
+    ) -> Result<(), StepperError> {
+        if [x, y, z, e].into_iter().flatten().any(|s| s.as_meters_per_second() <= 0.0) {
+            return Err(StepperError::MoveNotValid);
+        }
+        let max = &mut self.config.limits.max_feedrate;
+        *max = (
+            x.unwrap_or(max.0),
+            y.unwrap_or(max.1),
+            z.unwrap_or(max.2),
+            e.unwrap_or(max.3),
+        );
+        Ok(())
+    }
 
-        let z = match z {
-            Some(v) => v,
-            None => no_move(&self.z_stepper, self.config.positioning),
+    fn m204(
+        &mut self,
+        p: Option<Acceleration>,
+        r: Option<Acceleration>,
+        t: Option<Acceleration>,
+    ) -> Result<(), StepperError> {
+        if [p, r, t].into_iter().flatten().any(|a| a.as_meters_per_second_per_second() <= 0.0) {
+            return Err(StepperError::MoveNotValid);
+        }
+        let limits = &mut self.config.limits;
+        limits.print_acceleration = p.unwrap_or(limits.print_acceleration);
+        limits.retract_acceleration = r.unwrap_or(limits.retract_acceleration);
+        limits.travel_acceleration = t.unwrap_or(limits.travel_acceleration);
+        Ok(())
+    }
+
+    // a jerk of 0 stops the axis at every change of speed
+    fn m205(
+        &mut self,
+        x: Option<Speed>,
+        y: Option<Speed>,
+        z: Option<Speed>,
+        e: Option<Speed>,
+    ) -> Result<(), StepperError> {
+        if [x, y, z, e].into_iter().flatten().any(|s| s.as_meters_per_second() < 0.0) {
+            return Err(StepperError::MoveNotValid);
+        }
+        let jerk = &mut self.config.limits.jerk;
+        *jerk = (
+            x.unwrap_or(jerk.0),
+            y.unwrap_or(jerk.1),
+            z.unwrap_or(jerk.2),
+            e.unwrap_or(jerk.3),
+        );
+        Ok(())
+    }
+
+    // highest feedrate at which no axis goes faster than its max feedrate, for a move that covers
+    // the distances along X, Y, Z and E while the feedrate covers the length
+    fn limit_feedrate(&self, feedrate: Speed, distances: [Distance; 4], length: Distance) -> Speed {
+        let length = abs(length.as_millimeters());
+        if length == 0.0 {
+            return feedrate;
+        }
+        let max = self.config.limits.max_feedrate;
+        let mut limited = feedrate.as_meters_per_second();
+        for (distance, max) in distances.into_iter().zip([max.0, max.1, max.2, max.3]) {
+            let ratio = abs(distance.as_millimeters()) / length;
+            if limited * ratio > max.as_meters_per_second() {
+                limited = max.as_meters_per_second() / ratio;
+            }
+        }
+        Speed::from_meters_per_second(limited)
+    }
+
+    // acceleration along a move covering the distances along X, Y, Z and E, and the speed it
+    // starts and stops at. The moves of E alone are retractions (M204 R), the others print when
+    // they extrude (P) and travel otherwise (T). The acceleration is lowered until no axis
+    // accelerates beyond its max acceleration (M201), and the moves start and stop at the speed
+    // at which no axis changes its speed by more than its jerk (M205)
+    fn limit_ramp(&self, feedrate: Speed, distances: [Distance; 4], length: Distance) -> Ramp {
+        let length = abs(length.as_millimeters());
+        if length == 0.0 {
+            return Ramp::default();
+        }
+        let limits = self.config.limits;
+        let moving = |d: &Distance| abs(d.as_millimeters()) >= 1e-6;
+        let acceleration = if !distances[..3].iter().any(moving) {
+            limits.retract_acceleration
+        } else if moving(&distances[3]) {
+            limits.print_acceleration
+        } else {
+            limits.travel_acceleration
         };
+        let mm_s = |s: Speed| s.as_meters_per_second() * 1000.0;
+        let mm_s2 = |a: Acceleration| a.as_meters_per_second_per_second() * 1000.0;
+        let (max, jerk) = (limits.max_acceleration, limits.jerk);
+        let mut acceleration = mm_s2(acceleration);
+        let mut start = mm_s(feedrate);
+        for ((distance, max), jerk) in distances
+            .into_iter()
+            .zip([max.0, max.1, max.2, max.3])
+            .zip([jerk.0, jerk.1, jerk.2, jerk.3])
+        {
+            let ratio = abs(distance.as_millimeters()) / length;
+            if ratio * acceleration > mm_s2(max) {
+                acceleration = mm_s2(max) / ratio;
+            }
+            if ratio * start > mm_s(jerk) {
+                start = mm_s(jerk) / ratio;
+            }
+        }
+        Ramp::new(acceleration, start, start)
+    }
 
-        let dst = Vector3D::new(x, y, z);
+    // destination of E seen from G-code and the one of the extruder
+    fn e_target(&self, e: Option<Distance>) -> (Distance, Distance) {
+        let extruded = target(
+            e,
+            self.extruded,
+            Distance::from_millimeters(0.0),
+            self.config.e_positioning,
+        );
+        let factor = self.config.extrusion.factor();
+        (extruded, self.commanded.3 + (extruded - self.extruded) * factor)
+    }
 
-        linear_move_3d::<P, T, I>(
-            (
-                &mut self.x_stepper,
-                &mut self.y_stepper,
-                &mut self.z_stepper,
-            ),
-            dst,
-            feedrate,
-            self.config.positioning,
-            (
-                &mut self.endstops.0,
-                &mut self.endstops.1,
-                &mut self.endstops.2,
-            ),
-        )
-        .await
+    async fn g0(
+        &mut self,
+        x: Option<Distance>,
+        y: Option<Distance>,
+        z: Option<Distance>,
+        f: Option<Speed>,
+    ) -> Result<core::time::Duration, StepperError> {
+        self.check_homed((x.is_some(), y.is_some(), z.is_some()))?;
+        if let Some(feedrate) = f {
+            self.config.feedrate = feedrate;
+        }
+        let feedrate = self.config.feedrate * self.config.feedrate_multiplier;
+        let offset = self.offset();
+        let positioning = self.config.positioning;
+        let x = target(x, self.commanded.0, offset.0, positioning);
+        let y = target(y, self.commanded.1, offset.1, positioning);
+        let z = target(z, self.commanded.2, offset.2, positioning);
+        let (x, y, z) = self.check_destination((x, y, z))?;
+
+        let result = self.leveled_move((x, y, z, None), feedrate).await;
+        match result {
+            Ok(_) => (self.commanded.0, self.commanded.1, self.commanded.2) = (x, y, z),
+            Err(_) => self.sync_commanded(),
+        }
+        result
+    }
+
+    // G53: linear move to a destination in machine coordinates, always absolute
+    async fn g53(
+        &mut self,
+        x: Option<Distance>,
+        y: Option<Distance>,
+        z: Option<Distance>,
+        f: Option<Speed>,
+    ) -> Result<core::time::Duration, StepperError> {
+        self.check_homed((x.is_some(), y.is_some(), z.is_some()))?;
+        if let Some(feedrate) = f {
+            self.config.feedrate = feedrate;
+        }
+        let feedrate = self.config.feedrate * self.config.feedrate_multiplier;
+        let destination = (
+            x.unwrap_or(self.commanded.0),
+            y.unwrap_or(self.commanded.1),
+            z.unwrap_or(self.commanded.2),
+        );
+        let (x, y, z) = self.check_destination(destination)?;
+
+        let result = self.leveled_move((x, y, z, None), feedrate).await;
+        match result {
+            Ok(_) => (self.commanded.0, self.commanded.1, self.commanded.2) = (x, y, z),
+            Err(_) => self.sync_commanded(),
+        }
+        result
     }
 
     async fn g1(
@@ -279,44 +1288,200 @@ impl<P: OutputPinBase, T: TimerBase, I: ExtiInputPinBase> Planner<P, T, I> {
         e: Option<Distance>,
         f: Option<Speed>,
     ) -> Result<core::time::Duration, StepperError> {
+        self.check_homed((x.is_some(), y.is_some(), z.is_some()))?;
         if let Some(feedrate) = f {
             self.config.feedrate = feedrate;
         }
         let feedrate = self.config.feedrate * self.config.feedrate_multiplier;
-        let x = match x {
-            Some(v) => v,
-            None => no_move(&self.x_stepper, self.config.positioning),
-        };
+        let offset = self.offset();
+        let positioning = self.config.positioning;
+        let x = target(x, self.commanded.0, offset.0, positioning);
+        let y = target(y, self.commanded.1, offset.1, positioning);
+        let z = target(z, self.commanded.2, offset.2, positioning);
+        let (extruded, e) = self.e_target(e);
+        let (x, y, z) = self.check_destination((x, y, z))?;
 
-        let y = match y {
-            Some(v) => v,
-            None => no_move(&self.y_stepper, self.config.positioning),
-        };
+        let result = self.leveled_move((x, y, z, Some(e)), feedrate).await;
+        match result {
+            Ok(_) => (self.commanded, self.extruded) = ((x, y, z, e), extruded),
+            Err(_) => self.sync_commanded(),
+        }
+        result
+    }
 
-        let z = match z {
-            Some(v) => v,
-            None => no_move(&self.z_stepper, self.config.positioning),
-        };
+    // linear move from the commanded position to the destination in machine coordinates, E
+    // doesn't move without its destination. While the bed leveling is active the move is split
+    // where it crosses the lines of the mesh, the height of the bed is added to the Z of each piece.
+    // The skew is corrected last
+    async fn leveled_move(
+        &mut self,
+        destination: (Distance, Distance, Distance, Option<Distance>),
+        feedrate: Speed,
+    ) -> Result<core::time::Duration, StepperError> {
+        self.leveled_move_ramped(destination, feedrate, true).await
+    }
 
-        let e = match e {
-            Some(v) => v,
-            None => no_move(&self.e_stepper, self.config.positioning),
+    // the move accelerated along the ramp of the limits, or at the feedrate from the start to
+    // the end
+    async fn leveled_move_ramped(
+        &mut self,
+        destination: (Distance, Distance, Distance, Option<Distance>),
+        feedrate: Speed,
+        accelerated: bool,
+    ) -> Result<core::time::Duration, StepperError> {
+        let start = self.commanded;
+        let (x, y, z, e) = destination;
+        // E alone moves at the feedrate
+        let distances = [x - start.0, y - start.1, z - start.2, e.unwrap_or(start.3) - start.3];
+        let squared: f64 = distances[..3]
+            .iter()
+            .map(|d| d.as_millimeters() * d.as_millimeters())
+            .sum();
+        // as the steppers do, a move shorter than this doesn't move the head
+        let length = match precise_sqrt(squared) {
+            l if l >= 1e-6 => Distance::from_millimeters(l),
+            _ => distances[3],
+        };
+        let feedrate = self.limit_feedrate(feedrate, distances, length);
+        // the pieces follow the ramp of the whole move
+        let ramp = match accelerated {
+            true => self.limit_ramp(feedrate, distances, length),
+            false => Ramp::default(),
         };
+        let (length, mm_s) = (
+            abs(length.as_millimeters()),
+            feedrate.as_meters_per_second() * 1000.0,
+        );
+        let mut duration = Duration::ZERO;
+        let mut t = 0.0;
+        while t < 1.0 {
+            let from = t;
+            t = self.leveling.next_split((start.0, start.1), (x, y), t);
+            let piece = ramp.split(length, mm_s, from * length, t.min(1.0) * length);
+            let at = |a: Distance, b: Distance| if t < 1.0 { a + (b - a) * t } else { b };
+            let (x, y, z) = (at(start.0, x), at(start.1, y), at(start.2, z));
+            let dst = to_steppers(Vector3D::new(x, y, z), &self.leveling, &self.config.skew);
+            duration += match e {
+                Some(e) => {
+                    linear_move_3d_e::<P, T, I>(
+                        (
+                            &mut self.x_stepper,
+                            &mut self.y_stepper,
+                            &mut self.z_stepper,
+                            &mut self.e_stepper,
+                        ),
+                        dst,
+                        feedrate,
+                        piece,
+                        at(start.3, e),
+                        Positioning::Absolute,
+                        Positioning::Absolute,
+                        (
+                            &mut self.endstops.0,
+                            &mut self.endstops.1,
+                            &mut self.endstops.2,
+                            &mut self.endstops.3,
+                        ),
+                    )
+                    .await?
+                }
+                None => {
+                    linear_move_3d::<P, T, I>(
+                        (
+                            &mut self.x_stepper,
+                            &mut self.y_stepper,
+                            &mut self.z_stepper,
+                        ),
+                        dst,
+                        feedrate,
+                        piece,
+                        Positioning::Absolute,
+                        (
+                            &mut self.endstops.0,
+                            &mut self.endstops.1,
+                            &mut self.endstops.2,
+                        ),
+                    )
+                    .await?
+                }
+            };
+        }
+        Ok(duration)
+    }
 
-        let dst = Vector3D::new(x, y, z);
+    // cubic Bézier curve on the XY plane, the destination follows the positioning as G1 does.
+    // The offsets of the control points default to 0
+    async fn g5(
+        &mut self,
+        x: Option<Distance>,
+        y: Option<Distance>,
+        e: Option<Distance>,
+        f: Option<Speed>,
+        (i, j): (Option<Distance>, Option<Distance>),
+        (p, q): (Option<Distance>, Option<Distance>),
+    ) -> Result<core::time::Duration, StepperError> {
+        self.check_homed((true, true, false))?;
+        if let Some(feedrate) = f {
+            self.config.feedrate = feedrate;
+        }
+        let feedrate = self.config.feedrate * self.config.feedrate_multiplier;
+        let offset = self.offset();
+        let positioning = self.config.positioning;
+        let x = target(x, self.commanded.0, offset.0, positioning);
+        let y = target(y, self.commanded.1, offset.1, positioning);
+        let z = self.commanded.2;
+        let (extruded, e) = self.e_target(e);
+
+        let zero = Distance::from_millimeters(0.0);
+        let (start_x, start_y, _) = self.get_machine_position();
+        let start = Vector2D::new(start_x, start_y);
+        let end = Vector2D::new(x, y);
+        let curve = CubicBezier::new(
+            start,
+            start + Vector2D::new(i.unwrap_or(zero), j.unwrap_or(zero)),
+            end + Vector2D::new(p.unwrap_or(zero), q.unwrap_or(zero)),
+            end,
+        );
+        let tolerance = self.config.arcs.tolerance;
+        let (mut low, mut high) = ([x, y, z], [x, y, z]);
+        let (mut length, mut previous) = (0.0, start);
+        for (_, point) in curve.flatten(tolerance) {
+            for (n, v) in [point.get_x(), point.get_y()].into_iter().enumerate() {
+                if v < low[n] {
+                    low[n] = v;
+                }
+                if v > high[n] {
+                    high[n] = v;
+                }
+            }
+            let (dx, dy) = (
+                (point.get_x() - previous.get_x()).as_millimeters(),
+                (point.get_y() - previous.get_y()).as_millimeters(),
+            );
+            length += precise_sqrt(dx * dx + dy * dy);
+            previous = point;
+        }
+        self.check_arc_bounds(low, high)?;
+        // X and Y can each go as fast as the head along the curve
+        let length = Distance::from_millimeters(length);
+        let distances = [length, length, zero, e - self.commanded.3];
+        let feedrate = self.limit_feedrate(feedrate, distances, length);
+        let ramp = self.limit_ramp(feedrate, distances, length);
 
-        linear_move_3d_e::<P, T, I>(
+        let result = bezier_move_3d_e::<P, T, I>(
             (
                 &mut self.x_stepper,
                 &mut self.y_stepper,
                 &mut self.z_stepper,
                 &mut self.e_stepper,
             ),
-            dst,
+            &curve,
+            z,
             feedrate,
+            ramp,
             e,
-            self.config.positioning,
-            self.config.e_positioning,
+            tolerance,
+            |point| to_steppers(point, &self.leveling, &self.config.skew),
             (
                 &mut self.endstops.0,
                 &mut self.endstops.1,
@@ -324,22 +1489,27 @@ impl<P: OutputPinBase, T: TimerBase, I: ExtiInputPinBase> Planner<P, T, I> {
                 &mut self.endstops.3,
             ),
         )
-        .await
+        .await;
+        self.arc_done(&result, (x, y, z, e), extruded);
+        result
     }
 
     /**
-     * clockwise arc move
-     * IJ form:
-     * - i or j is required. Omitting both will throw an error
-     * - x and y can be omitted to do a complete circle
+     * arc move on the plane selected by G17, G18 or G19, the third axis moves linearly along
+     * with it (helical arc). i, j and k are the offsets of the center along x, y and z
+     * offset form:
+     * - at least one offset along the plane is required. Omitting both will throw an error
+     * - the destination can be omitted to do a complete circle
+     * - a destination off the circle by more than the tolerance will throw an error
      *
      * R form:
-     * - x or y is required. Omitting both will throw an error
-     * - x or y must differ from the current xy position
+     * - the destination on the plane is required. Omitting it will throw an error
+     * - it must differ from the current position
+     * - a negative r takes the arc longer than half a circle
      *
-     * mixing i or j with r will throw an error
-     *  
+     * p adds full circles. Mixing the offsets with r will throw an error
      */
+    #[allow(clippy::too_many_arguments)]
     async fn g2_3(
         &mut self,
         x: Option<Distance>,
@@ -349,122 +1519,174 @@ impl<P: OutputPinBase, T: TimerBase, I: ExtiInputPinBase> Planner<P, T, I> {
         f: Option<Speed>,
         i: Option<Distance>,
         j: Option<Distance>,
+        k: Option<Distance>,
         r: Option<Distance>,
+        p: Option<u8>,
         d: RotationDirection,
     ) -> Result<core::time::Duration, StepperError> {
-        match (i, j, r) {
+        let [a, b, c] = self.plane.axes().map(axis_index);
+        let destination = [x, y, z];
+        let center_offset = [i, j, k];
+        match (center_offset[a], center_offset[b], r) {
             (Some(_), Some(_), Some(_))
             | (None, None, None)
             | (Some(_), None, Some(_))
             | (None, Some(_), Some(_)) => return Err(StepperError::MoveNotValid),
             _ => (),
         }
+        let mut homed = [true; 3];
+        homed[c] = destination[c].is_some();
+        self.check_homed((homed[0], homed[1], homed[2]))?;
 
         if let Some(feedrate) = f {
             self.config.feedrate = feedrate;
         }
         let feedrate = self.config.feedrate * self.config.feedrate_multiplier;
+        let offset = self.offset();
 
-        let z = match z {
-            Some(v) => v,
-            None => no_move(&self.z_stepper, Positioning::Absolute),
-        };
+        let commanded = [self.commanded.0, self.commanded.1, self.commanded.2];
+        let offset = [offset.0, offset.1, offset.2];
+        let mut end = commanded;
+        for n in 0..3 {
+            end[n] = target(destination[n], commanded[n], offset[n], Positioning::Absolute);
+        }
+        let (extruded, e) = self.e_target(e);
 
-        let e = match e {
-            Some(v) => v,
-            None => no_move(&self.z_stepper, Positioning::Relative),
+        let position = self.get_machine_position();
+        let position = [position.0, position.1, position.2];
+        let start = Vector2D::new(position[a], position[b]);
+        let arc_end = Vector2D::new(end[a], end[b]);
+        let turns = p.unwrap_or(0);
+        let arc = match r {
+            Some(r) => {
+                if destination[a].is_none() && destination[b].is_none() {
+                    return Err(StepperError::MoveNotValid);
+                }
+                Arc::from_radius(start, arc_end, r, d, turns)?
+            }
+            None => {
+                let zero = Distance::from_millimeters(0.0);
+                let center_offset = Vector2D::new(
+                    center_offset[a].unwrap_or(zero),
+                    center_offset[b].unwrap_or(zero),
+                );
+                Arc::from_offset(start, arc_end, center_offset, d, turns)?
+            }
         };
 
-        if i.is_some() || j.is_some() {
-            let x = match x {
-                Some(v) => v,
-                None => no_move(&self.x_stepper, Positioning::Absolute),
-            };
+        let (min, max) = arc.bounding_box();
+        let (mut low, mut high) = (end, end);
+        (low[a], high[a]) = (min.get_x(), max.get_x());
+        (low[b], high[b]) = (min.get_y(), max.get_y());
+        self.check_arc_bounds(low, high)?;
+        // the axes of the plane can each go as fast as the head along the arc
+        let arc_length = arc.length();
+        let linear = end[c] - position[c];
+        let length = precise_sqrt(
+            arc_length.as_millimeters() * arc_length.as_millimeters()
+                + linear.as_millimeters() * linear.as_millimeters(),
+        );
+        let mut distances = [arc_length, arc_length, arc_length, e - self.commanded.3];
+        distances[c] = linear;
+        let length = Distance::from_millimeters(length);
+        let feedrate = self.limit_feedrate(feedrate, distances, length);
+        let ramp = self.limit_ramp(feedrate, distances, length);
 
-            let y = match y {
-                Some(v) => v,
-                None => no_move(&self.y_stepper, Positioning::Absolute),
-            };
-
-            let dst = Vector3D::new(x, y, z);
-
-            let i = match i {
-                Some(v) => v,
-                None => Distance::from_millimeters(0f64),
-            };
-
-            let j = match j {
-                Some(v) => v,
-                None => Distance::from_millimeters(0f64),
-            };
-
-            let offset_from_center = Vector2D::new(i, j);
-            return arc_move_3d_e_offset_from_center::<P, T, I>(
+        // the steppers and endstops are ordered as the axes of the plane
+        let (steppers, endstops) = match self.plane {
+            Plane::XY => (
                 (
                     &mut self.x_stepper,
                     &mut self.y_stepper,
                     &mut self.z_stepper,
                     &mut self.e_stepper,
                 ),
-                dst,
-                offset_from_center,
-                feedrate,
-                d,
-                e,
-                self.config.arc_unit_length,
                 (
                     &mut self.endstops.0,
                     &mut self.endstops.1,
                     &mut self.endstops.2,
                     &mut self.endstops.3,
                 ),
-            )
-            .await;
-        }
-
-        if let Some(r) = r {
-            if x.is_none() && y.is_none() {
-                return Err(StepperError::MoveNotValid);
-            }
-
-            let x = match x {
-                Some(v) => v,
-                None => no_move(&self.x_stepper, Positioning::Absolute),
-            };
-
-            let y = match y {
-                Some(v) => v,
-                None => no_move(&self.y_stepper, Positioning::Absolute),
-            };
-
-            let dst = Vector3D::new(x, y, z);
-
-            return arc_move_3d_e_radius::<P, T, I>(
+            ),
+            Plane::ZX => (
                 (
+                    &mut self.z_stepper,
                     &mut self.x_stepper,
                     &mut self.y_stepper,
-                    &mut self.z_stepper,
                     &mut self.e_stepper,
                 ),
-                dst,
-                r,
-                feedrate,
-                d,
-                e,
-                self.config.arc_unit_length,
                 (
+                    &mut self.endstops.2,
                     &mut self.endstops.0,
+                    &mut self.endstops.1,
+                    &mut self.endstops.3,
+                ),
+            ),
+            Plane::YZ => (
+                (
+                    &mut self.y_stepper,
+                    &mut self.z_stepper,
+                    &mut self.x_stepper,
+                    &mut self.e_stepper,
+                ),
+                (
                     &mut self.endstops.1,
                     &mut self.endstops.2,
+                    &mut self.endstops.0,
                     &mut self.endstops.3,
                 ),
-            )
-            .await;
+            ),
+        };
+        // only the arcs on the XY plane are leveled. The points of the arc are ordered as the
+        // axes of the plane
+        let leveling = match self.plane {
+            Plane::XY => self.leveling,
+            _ => Leveling::new(Length::from_millimeters(0.0)),
+        };
+        let skew = self.config.skew;
+        let transform = |point: Vector3D<Distance>| {
+            let mut xyz = [Distance::from_millimeters(0.0); 3];
+            (xyz[a], xyz[b], xyz[c]) = (point.get_x(), point.get_y(), point.get_z());
+            let point = to_steppers(Vector3D::new(xyz[0], xyz[1], xyz[2]), &leveling, &skew);
+            let xyz = [point.get_x(), point.get_y(), point.get_z()];
+            Vector3D::new(xyz[a], xyz[b], xyz[c])
+        };
+        let result = arc_move_3d_e::<P, T, I>(
+            steppers,
+            &arc,
+            (position[c], end[c]),
+            feedrate,
+            ramp,
+            e,
+            &self.config.arcs,
+            transform,
+            endstops,
+        )
+        .await;
+        self.arc_done(&result, (end[0], end[1], end[2], e), extruded);
+        result
+    }
+
+    fn arc_done(
+        &mut self,
+        result: &Result<core::time::Duration, StepperError>,
+        destination: (Distance, Distance, Distance, Distance),
+        extruded: Distance,
+    ) {
+        match result {
+            Ok(_) => (self.commanded, self.extruded) = (destination, extruded),
+            Err(_) => self.sync_commanded(),
         }
+    }
 
-        Err(StepperError::MoveNotValid)
+    // forget the commanded position and start again from the one of the steppers,
+    // used when the steppers moved by something else than a planned move
+    fn sync_commanded(&mut self) {
+        let (x, y, z) = self.get_machine_position();
+        self.commanded = (x, y, z, self.e_stepper.get_position());
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn g2(
         &mut self,
         x: Option<Distance>,
@@ -474,12 +1696,15 @@ impl<P: OutputPinBase, T: TimerBase, I: ExtiInputPinBase> Planner<P, T, I> {
         f: Option<Speed>,
         i: Option<Distance>,
         j: Option<Distance>,
+        k: Option<Distance>,
         r: Option<Distance>,
+        p: Option<u8>,
     ) -> Result<core::time::Duration, StepperError> {
-        self.g2_3(x, y, z, e, f, i, j, r, RotationDirection::Clockwise)
+        self.g2_3(x, y, z, e, f, i, j, k, r, p, RotationDirection::Clockwise)
             .await
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn g3(
         &mut self,
         x: Option<Distance>,
@@ -489,34 +1714,163 @@ impl<P: OutputPinBase, T: TimerBase, I: ExtiInputPinBase> Planner<P, T, I> {
         f: Option<Speed>,
         i: Option<Distance>,
         j: Option<Distance>,
+        k: Option<Distance>,
         r: Option<Distance>,
+        p: Option<u8>,
     ) -> Result<core::time::Duration, StepperError> {
-        self.g2_3(x, y, z, e, f, i, j, r, RotationDirection::CounterClockwise)
+        self.g2_3(x, y, z, e, f, i, j, k, r, p, RotationDirection::CounterClockwise)
             .await
     }
 
     // retract
     async fn g10(&mut self) -> Result<core::time::Duration, StepperError> {
-        retract::<P, T, I>(
+        let z = self.z_stepper.get_position();
+        let (_, _, z_lifted) = self.check_destination((
+            self.x_stepper.get_position(),
+            self.y_stepper.get_position(),
+            z + self.config.retraction.z_lift,
+        ))?;
+        let length = self.config.retraction.length;
+        let e_length = length * self.config.extrusion.factor();
+        // Z is lifted while E retracts
+        let zero = Distance::from_millimeters(0.0);
+        let distances = [zero, zero, z_lifted - z, e_length];
+        let feedrate = self.limit_feedrate(self.config.retraction.feedrate, distances, e_length);
+        let ramp = self.limit_ramp(feedrate, distances, e_length);
+        let result = retract::<P, T, I>(
             (&mut self.z_stepper, &mut self.e_stepper),
-            self.config.retraction.feedrate,
-            self.config.retraction.length,
-            self.config.retraction.z_lift,
+            feedrate,
+            e_length,
+            z_lifted - z,
+            ramp,
             (&mut self.endstops.2, &mut self.endstops.3),
         )
-        .await
+        .await;
+        self.sync_commanded();
+        if result.is_ok() {
+            self.extruded = self.extruded - length;
+        }
+        result
     }
 
     // recover
     async fn g11(&mut self) -> Result<core::time::Duration, StepperError> {
-        let e_destination = self.e_stepper.get_position() + self.config.recover.length;
-        linear_move_to::<P, T, I>(
+        let length = self.config.recover.length;
+        let e_length = length * self.config.extrusion.factor();
+        let zero = Distance::from_millimeters(0.0);
+        let distances = [zero, zero, zero, e_length];
+        let feedrate = self.limit_feedrate(self.config.recover.feedrate, distances, e_length);
+        let ramp = self.limit_ramp(feedrate, distances, e_length);
+        let e_destination = self.e_stepper.get_position() + e_length;
+        let result = linear_move_to_ramped::<P, T, I>(
             &mut self.e_stepper,
             e_destination,
-            self.config.recover.feedrate,
+            feedrate,
+            ramp,
             &mut self.endstops.3,
         )
-        .await
+        .await;
+        self.sync_commanded();
+        if result.is_ok() {
+            self.extruded = self.extruded + length;
+        }
+        result
+    }
+
+    fn machine_bounds(&self) -> [Option<(Distance, Distance)>; 3] {
+        [
+            self.x_stepper.get_options().bounds,
+            self.y_stepper.get_options().bounds,
+            self.z_stepper.get_options().bounds,
+        ]
+    }
+
+    // destination of a linear move once checked against the software endstops, before any
+    // stepper moves. It's clipped to the bounds or refused, according to the configuration
+    fn check_destination(
+        &self,
+        destination: (Distance, Distance, Distance),
+    ) -> Result<(Distance, Distance, Distance), StepperError> {
+        let endstops = self.config.software_endstops;
+        if !endstops.enabled {
+            return Ok(destination);
+        }
+        let offset = self.offset();
+        let mut destination = [destination.0, destination.1, destination.2];
+        for (((value, bounds), offset), axis) in destination
+            .iter_mut()
+            .zip(self.machine_bounds())
+            .zip([offset.0, offset.1, offset.2])
+            .zip([Axis::X, Axis::Y, Axis::Z])
+        {
+            if let Some((min, max)) = bounds {
+                if *value >= min && *value <= max {
+                    continue;
+                }
+                if !endstops.clip {
+                    return Err(StepperError::OutOfBounds(axis, *value + offset));
+                }
+                *value = if *value < min { min } else { max };
+            }
+        }
+        Ok((destination[0], destination[1], destination[2]))
+    }
+
+    // the box holding an arc or a curve must lie within the software endstops, they are never
+    // clipped
+    fn check_arc_bounds(
+        &self,
+        low: [Distance; 3],
+        high: [Distance; 3],
+    ) -> Result<(), StepperError> {
+        if !self.config.software_endstops.enabled {
+            return Ok(());
+        }
+        let offset = self.offset();
+        for ((((low, high), bounds), offset), axis) in low
+            .into_iter()
+            .zip(high)
+            .zip(self.machine_bounds())
+            .zip([offset.0, offset.1, offset.2])
+            .zip([Axis::X, Axis::Y, Axis::Z])
+        {
+            if let Some((lower, upper)) = bounds {
+                if low < lower {
+                    return Err(StepperError::OutOfBounds(axis, low + offset));
+                }
+                if high > upper {
+                    return Err(StepperError::OutOfBounds(axis, high + offset));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_homed(&self, axes: (bool, bool, bool)) -> Result<(), StepperError> {
+        if !self.config.homing.required {
+            return Ok(());
+        }
+        for (moved, homed, axis) in [
+            (axes.0, self.homed.0, Axis::X),
+            (axes.1, self.homed.1, Axis::Y),
+            (axes.2, self.homed.2, Axis::Z),
+        ] {
+            if moved && !homed {
+                return Err(StepperError::NotHomed(axis));
+            }
+        }
+        Ok(())
+    }
+
+    // raise the nozzle so that it doesn't drag on the bed while X and Y are homed
+    async fn homing_z_lift(&mut self) -> Result<core::time::Duration, StepperError> {
+        let lift = self.config.homing.z_lift;
+        if lift.as_millimeters() <= 0.0 {
+            return Ok(Duration::ZERO);
+        }
+        self.z_stepper
+            .set_speed_from_attachment(self.config.homing.axes.2.fast_speed);
+        self.z_stepper.move_for_distance::<T>(lift).await
     }
 
     // auto home
@@ -525,18 +1879,1992 @@ impl<P: OutputPinBase, T: TimerBase, I: ExtiInputPinBase> Planner<P, T, I> {
         enabled: (bool, bool, bool),
     ) -> Result<core::time::Duration, StepperError> {
         let mut duration = Duration::ZERO;
-        if enabled.0 {
-            let e = self.endstops.0.as_ref().ok_or(StepperError::MoveNotValid)?;
-            duration += auto_home::<_, _, T>(&mut self.x_stepper, e).await?;
+        let mut lifted = false;
+        for axis in self.config.homing.order {
+            let enabled = match axis {
+                Axis::X => enabled.0,
+                Axis::Y => enabled.1,
+                Axis::Z => enabled.2,
+                Axis::E => false,
+            };
+            if !enabled {
+                continue;
+            }
+            if axis != Axis::Z && !lifted {
+                duration += self.homing_z_lift().await?;
+                lifted = true;
+            }
+            match axis {
+                Axis::X => {
+                    self.homed.0 = false;
+                    duration += home_axis::<P, T, I, S>(
+                        &mut self.x_stepper,
+                        &self.endstops.0,
+                        &self.motor_endstops[0],
+                        &self.config.homing.axes.0,
+                        &mut self.drivers,
+                        Axis::X,
+                        self.dry_run,
+                    )
+                    .await?;
+                    self.homed.0 = true;
+                }
+                Axis::Y => {
+                    self.homed.1 = false;
+                    duration += home_axis::<P, T, I, S>(
+                        &mut self.y_stepper,
+                        &self.endstops.1,
+                        &self.motor_endstops[1],
+                        &self.config.homing.axes.1,
+                        &mut self.drivers,
+                        Axis::Y,
+                        self.dry_run,
+                    )
+                    .await?;
+                    self.homed.1 = true;
+                }
+                Axis::Z => {
+                    self.homed.2 = false;
+                    duration += home_axis::<P, T, I, S>(
+                        &mut self.z_stepper,
+                        &self.endstops.2,
+                        &self.motor_endstops[2],
+                        &self.config.homing.axes.2,
+                        &mut self.drivers,
+                        Axis::Z,
+                        self.dry_run,
+                    )
+                    .await?;
+                    self.homed.2 = true;
+                }
+                Axis::E => (),
+            }
         }
-        if enabled.1 {
-            let e = self.endstops.1.as_ref().ok_or(StepperError::MoveNotValid)?;
-            duration += auto_home::<_, _, T>(&mut self.y_stepper, e).await?;
+        Ok(duration)
+    }
+
+    // probe the bed on the grid of the configuration and level the next moves with the mesh. In
+    // dry run the bed is taken as flat at Z 0 and the mesh is left as it is
+    async fn g29(&mut self) -> Result<core::time::Duration, StepperError> {
+        self.check_homed((true, true, true))?;
+        let config = self.config.leveling;
+        let mut mesh = Mesh::new(config.points, config.min, config.max)?;
+        // the probing moves aren't leveled
+        let enabled = self.leveling.is_enabled();
+        self.leveling.set_enabled(false);
+        let result = self.probe_mesh(&mut mesh).await;
+        self.leveling.set_enabled(enabled);
+        if result.is_ok() && !self.dry_run {
+            self.leveling.set_mesh(mesh);
         }
-        if enabled.2 {
-            let e = self.endstops.2.as_ref().ok_or(StepperError::MoveNotValid)?;
-            duration += auto_home::<_, _, T>(&mut self.z_stepper, e).await?;
+        self.sync_commanded();
+        result
+    }
+
+    async fn probe_mesh(&mut self, mesh: &mut Mesh) -> Result<core::time::Duration, StepperError> {
+        self.deploy_probe().await?;
+        let (columns, rows) = mesh.get_points();
+        let samples = self.config.probe.samples;
+        let mut duration = Duration::ZERO;
+        for j in 0..rows {
+            for n in 0..columns {
+                // back and forth along the rows
+                let i = if j % 2 == 0 { n } else { columns - 1 - n };
+                let (x, y) = mesh.point(i, j);
+                let (z, d) = self.probe_point(x, y, samples).await?;
+                duration += d;
+                mesh.set(i, j, z);
+            }
         }
+        duration += self.probe_clearance().await?;
+        self.stow_probe().await?;
         Ok(duration)
     }
+
+    // G30 and M48: probe the bed count times at a point in G-code coordinates, the current
+    // position of the probe without it. Each time the bed is touched samples times. The probe
+    // is left at the clearance
+    async fn probe_series(
+        &mut self,
+        x: Option<Distance>,
+        y: Option<Distance>,
+        count: usize,
+        samples: usize,
+    ) -> Result<((Distance, Distance), ProbeStats, core::time::Duration), StepperError> {
+        self.check_homed((true, true, true))?;
+        let offset = self.config.probe.offset;
+        let x = match x {
+            Some(x) => x - self.offset().0,
+            None => self.commanded.0 + offset.0,
+        };
+        let y = match y {
+            Some(y) => y - self.offset().1,
+            None => self.commanded.1 + offset.1,
+        };
+        // the probing moves aren't leveled
+        let enabled = self.leveling.is_enabled();
+        self.leveling.set_enabled(false);
+        let result = self.probe_repeatedly(x, y, count, samples).await;
+        self.leveling.set_enabled(enabled);
+        self.sync_commanded();
+        let (stats, duration) = result?;
+        let point = (x + self.offset().0, y + self.offset().1);
+        Ok((point, stats, duration))
+    }
+
+    async fn probe_repeatedly(
+        &mut self,
+        x: Distance,
+        y: Distance,
+        count: usize,
+        samples: usize,
+    ) -> Result<(ProbeStats, core::time::Duration), StepperError> {
+        self.deploy_probe().await?;
+        let mut stats = ProbeStats::default();
+        let mut duration = Duration::ZERO;
+        for _ in 0..count {
+            let (z, d) = self.probe_point(x, y, samples).await?;
+            stats.add(z);
+            duration += d;
+        }
+        duration += self.probe_clearance().await?;
+        self.stow_probe().await?;
+        Ok((stats, duration))
+    }
+
+    // G30: probe the bed once and return the point it's touched at, in G-code coordinates
+    pub async fn probe(
+        &mut self,
+        x: Option<Distance>,
+        y: Option<Distance>,
+    ) -> Result<ProbePoint, StepperError> {
+        let samples = self.config.probe.samples;
+        let ((x, y), stats, _) = self.probe_series(x, y, 1, samples).await?;
+        Ok(ProbePoint {
+            x,
+            y,
+            z: stats.get_mean() + self.offset().2,
+        })
+    }
+
+    // M48: probe the bed p times, 10 by default, touching it once each time
+    pub async fn probe_repeatability(
+        &mut self,
+        p: Option<u8>,
+        x: Option<Distance>,
+        y: Option<Distance>,
+    ) -> Result<ProbeStats, StepperError> {
+        let (_, stats, _) = self.probe_series(x, y, m48_count(p)?, 1).await?;
+        Ok(stats)
+    }
+
+    // G34: align the motors of Z, the bed is left at the height measured by the lowest one
+    pub async fn align_z(
+        &mut self,
+        i: Option<u8>,
+        t: Option<Distance>,
+    ) -> Result<ZAlignment, StepperError> {
+        let (alignment, _) = self.g34(i, t).await?;
+        Ok(alignment)
+    }
+
+    // move X or Y by the distance from the commanded position at the given speed, without
+    // touching the feedrate and the positioning of the G-code. Used to excite the frame when
+    // measuring its resonances
+    pub async fn move_axis(
+        &mut self,
+        axis: Axis,
+        distance: Distance,
+        speed: Speed,
+    ) -> Result<core::time::Duration, StepperError> {
+        let (mut x, mut y, z, e) = self.commanded;
+        match axis {
+            Axis::X => x = x + distance,
+            Axis::Y => y = y + distance,
+            _ => return Err(StepperError::NotSupported),
+        }
+        self.check_homed((axis == Axis::X, axis == Axis::Y, false))?;
+        let (x, y, z) = self.check_destination((x, y, z))?;
+        // the excitation sets its own speeds, a chord at a time
+        let result = self.leveled_move_ramped((x, y, z, None), speed, false).await;
+        match result {
+            Ok(_) => self.commanded = (x, y, z, e),
+            Err(_) => self.sync_commanded(),
+        }
+        result
+    }
+
+    async fn g34(
+        &mut self,
+        i: Option<u8>,
+        t: Option<Distance>,
+    ) -> Result<(ZAlignment, core::time::Duration), StepperError> {
+        self.check_homed((true, true, true))?;
+        // a point to probe for each motor
+        let motors = self.z_stepper.get_motors();
+        if motors < 2 || self.config.alignment.points.len() != motors {
+            return Err(StepperError::NotSupported);
+        }
+        let iterations = match i {
+            Some(0) => return Err(StepperError::MoveNotValid),
+            Some(i) => i as usize,
+            None => self.config.alignment.iterations,
+        };
+        let accuracy = t.unwrap_or(self.config.alignment.accuracy);
+        // the probing moves aren't leveled
+        let enabled = self.leveling.is_enabled();
+        self.leveling.set_enabled(false);
+        let result = self.align_motors(iterations, accuracy).await;
+        self.leveling.set_enabled(enabled);
+        self.sync_commanded();
+        result
+    }
+
+    // probe the bed near each lead screw and raise the motors over the lower points, until the
+    // heights are within the accuracy. The alignment fails when they get further apart
+    async fn align_motors(
+        &mut self,
+        iterations: usize,
+        accuracy: Distance,
+    ) -> Result<(ZAlignment, core::time::Duration), StepperError> {
+        self.deploy_probe().await?;
+        let motors = self.z_stepper.get_motors();
+        let samples = self.config.probe.samples;
+        let mut duration = Duration::ZERO;
+        let mut previous: Option<Distance> = None;
+        let mut iteration = 0;
+        loop {
+            let mut heights = [Distance::from_millimeters(0.0); MAX_MOTORS];
+            for (motor, height) in heights.iter_mut().enumerate().take(motors) {
+                let (x, y) = self.config.alignment.points[motor];
+                let (z, d) = self.probe_point(x, y, samples).await?;
+                *height = z;
+                duration += d;
+            }
+            let heights = &heights[..motors];
+            let lowest = heights.iter().copied().fold(heights[0], |a, b| if b < a { b } else { a });
+            let highest = heights.iter().copied().fold(heights[0], |a, b| if b > a { b } else { a });
+            let deviation = highest - lowest;
+            let alignment = ZAlignment {
+                iterations: iteration,
+                deviation,
+            };
+            if deviation < accuracy {
+                duration += self.probe_clearance().await?;
+                self.stow_probe().await?;
+                return Ok((alignment, duration));
+            }
+            if iteration == iterations || previous.is_some_and(|p| deviation > p) {
+                self.probe_clearance().await?;
+                self.stow_probe().await?;
+                return Err(StepperError::AlignmentFailed);
+            }
+            // the bed looks higher under the motors that are lower
+            self.z_stepper
+                .set_speed_from_attachment(self.config.probe.feedrate);
+            for (motor, height) in heights.iter().enumerate() {
+                let correction = *height - lowest;
+                if correction.as_millimeters() > 0.0 {
+                    duration += self
+                        .z_stepper
+                        .move_motor_for_distance::<T>(motor, correction)
+                        .await?;
+                }
+            }
+            previous = Some(deviation);
+            iteration += 1;
+        }
+    }
+
+    async fn deploy_probe(&mut self) -> Result<(), StepperError> {
+        if self.dry_run {
+            return Ok(());
+        }
+        match self.probe.as_mut() {
+            Some(probe) => probe.deploy().await,
+            None => Err(StepperError::NotSupported),
+        }
+    }
+
+    async fn stow_probe(&mut self) -> Result<(), StepperError> {
+        match self.probe.as_mut() {
+            Some(probe) if !self.dry_run => probe.stow().await,
+            _ => Ok(()),
+        }
+    }
+
+    // bring the probe over a point of the bed, in machine coordinates, and return the height of
+    // the bed there. The probe goes down from the clearance until it touches the bed, then it's
+    // lifted by the retract length and lowered again for the next samples
+    async fn probe_point(
+        &mut self,
+        x: Distance,
+        y: Distance,
+        samples: usize,
+    ) -> Result<(Distance, core::time::Duration), StepperError> {
+        let config = self.config.probe;
+        // the probing moves go straight to the steppers, they are kept within the software
+        // endstops like the others
+        let (x, y, z) =
+            self.check_destination((x - config.offset.0, y - config.offset.1, config.clearance))?;
+        let mut duration = linear_move_3d::<P, T, I>(
+            (
+                &mut self.x_stepper,
+                &mut self.y_stepper,
+                &mut self.z_stepper,
+            ),
+            Vector3D::new(x, y, z),
+            config.travel_feedrate,
+            Ramp::default(),
+            Positioning::Absolute,
+            (
+                &mut self.endstops.0,
+                &mut self.endstops.1,
+                &mut self.endstops.2,
+            ),
+        )
+        .await?;
+        let samples = samples.max(1);
+        let mut sum = Distance::from_millimeters(0.0);
+        for n in 0..samples {
+            if n > 0 {
+                let z = (self.z_stepper.get_position() + config.retract).as_millimeters();
+                let z = Distance::from_millimeters(z.min(config.clearance.as_millimeters()));
+                let z = self.check_z(z)?;
+                duration += linear_move_to::<P, T, I>(
+                    &mut self.z_stepper,
+                    z,
+                    config.travel_feedrate,
+                    &mut None,
+                )
+                .await?;
+            }
+            let (z, d) = self.probe_down().await?;
+            sum = sum + z + config.offset.2;
+            duration += d;
+        }
+        Ok((sum / samples as f64, duration))
+    }
+
+    // lower the probe until it touches the bed and return the Z of the nozzle at that moment
+    async fn probe_down(&mut self) -> Result<(Distance, core::time::Duration), StepperError> {
+        let config = self.config.probe;
+        let bottom = self.probe_bottom(config.clearance - config.max_depth);
+        let start = self.z_stepper.get_position();
+        if self.dry_run {
+            // the probe touches the bed at Z 0
+            let z = Distance::from_millimeters(
+                bottom.as_millimeters().max(-config.offset.2.as_millimeters()),
+            );
+            let duration =
+                linear_move_to::<P, T, I>(&mut self.z_stepper, z, config.feedrate, &mut None)
+                    .await?;
+            return Ok((z, duration));
+        }
+        match linear_move_to::<P, T, Z>(
+            &mut self.z_stepper,
+            bottom,
+            config.feedrate,
+            &mut self.probe,
+        )
+        .await
+        {
+            Ok(_) => return Err(StepperError::ProbeNotTriggered),
+            Err(StepperError::EndstopHit) => (),
+            Err(e) => return Err(e),
+        }
+        let z = self.z_stepper.get_position();
+        // the move was interrupted, its duration is the one of the distance covered
+        let travel = (start - z).as_millimeters() / 1000.0;
+        let duration = Duration::from_secs_f64(travel / config.feedrate.as_meters_per_second());
+        Ok((z, duration))
+    }
+
+    // the Z of a probing move over the current X and Y, within the software endstops
+    fn check_z(&self, z: Distance) -> Result<Distance, StepperError> {
+        let x = self.x_stepper.get_position();
+        let y = self.y_stepper.get_position();
+        let (_, _, z) = self.check_destination((x, y, z))?;
+        Ok(z)
+    }
+
+    // the probe looks for the bed down to the bottom of Z at most, within the software endstops.
+    // It isn't refused there, the bed is usually found before
+    fn probe_bottom(&self, bottom: Distance) -> Distance {
+        match self.z_stepper.get_options().bounds {
+            Some((min, _)) if self.config.software_endstops.enabled && bottom < min => min,
+            _ => bottom,
+        }
+    }
+
+    // lift the probe where it is up to the clearance
+    async fn probe_clearance(&mut self) -> Result<core::time::Duration, StepperError> {
+        let config = self.config.probe;
+        let clearance = self.check_z(config.clearance)?;
+        linear_move_to::<P, T, I>(
+            &mut self.z_stepper,
+            clearance,
+            config.travel_feedrate,
+            &mut None,
+        )
+        .await
+    }
+}
+
+// where the steppers go for a point in machine coordinates: the height of the bed is added to Z,
+// then the skew is corrected
+fn to_steppers(point: Vector3D<Distance>, leveling: &Leveling, skew: &Skew) -> Vector3D<Distance> {
+    let (x, y, z) = (point.get_x(), point.get_y(), point.get_z());
+    let (x, y, z) = skew.apply(x, y, z + leveling.z_offset(x, y, z));
+    Vector3D::new(x, y, z)
+}
+
+// number of probes of M48
+fn m48_count(p: Option<u8>) -> Result<usize, StepperError> {
+    match p.unwrap_or(10) {
+        0 => Err(StepperError::MoveNotValid),
+        p => Ok(p as usize),
+    }
+}
+
+fn axis_index(axis: Axis) -> usize {
+    match axis {
+        Axis::X => 0,
+        Axis::Y => 1,
+        Axis::Z => 2,
+        Axis::E => 3,
+    }
+}
+
+// absolute destination of an axis in machine coordinates. G-code coordinates are shifted by the
+// home offsets, the steppers work with machine coordinates
+fn target(
+    value: Option<Distance>,
+    commanded: Distance,
+    offset: Distance,
+    positioning: Positioning,
+) -> Distance {
+    match (value, positioning) {
+        (None, _) => commanded,
+        (Some(v), Positioning::Absolute) => v - offset,
+        (Some(v), Positioning::Relative) => commanded + v,
+    }
+}
+
+async fn home_axis<P: OutputPinBase, T: TimerBase, I: ExtiInputPinBase, S: HalfDuplexSerialBase>(
+    stepper: &mut Stepper<P, Attached>,
+    endstop: &Option<I>,
+    motor_endstops: &[Option<I>],
+    config: &HomingConfig,
+    drivers: &mut Option<TmcDrivers<S>>,
+    axis: Axis,
+    dry_run: bool,
+) -> Result<Duration, StepperError> {
+    if dry_run {
+        return dry_run_home::<_, T>(stepper, config).await;
+    }
+    let endstop = endstop.as_ref().ok_or(StepperError::MoveNotValid)?;
+    if config.sensorless {
+        // with sensorless homing the endstop is the DIAG output of the driver
+        let (driver, serial) = drivers
+            .as_mut()
+            .and_then(|d| d.get_mut(axis))
+            .ok_or(StepperError::MoveNotValid)?;
+        sensorless_home::<_, _, T, _>(stepper, endstop, driver, serial, config).await
+    } else {
+        auto_home_motors::<_, _, T>(stepper, endstop, motor_endstops, config).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use crate::probe::SwitchProbe;
+    use crate::stepper::{StepperAttachment, StepperOptions, SteppingMode, MICROSTEPS_PER_STEP};
+    use math::common::abs;
+    use proptest::prelude::*;
+    use sim::{block_on, Recorder, SimAxis, SimBed, SimInputPin, SimOutputPin, SimSerial, SimTimer};
+
+    type PlannerMock =
+        Planner<SimOutputPin, SimTimer, SimInputPin, SimSerial, SwitchProbe<SimInputPin>>;
+
+    fn stepper(mode: SteppingMode, distance_per_step: f64) -> Stepper<SimOutputPin, Attached> {
+        let options = StepperOptions {
+            stepping_mode: mode,
+            ..Default::default()
+        };
+        let attachment = StepperAttachment {
+            distance_per_step: Distance::from_millimeters(distance_per_step),
+        };
+        Stepper::new_with_attachment(
+            SimOutputPin::new(),
+            SimOutputPin::new(),
+            options,
+            attachment,
+        )
+    }
+
+    fn planner() -> PlannerMock {
+        let speed = Speed::from_meters_per_second;
+        let acceleration = Acceleration::from_meters_per_second_per_second;
+        let config = MotionConfig {
+            arcs: ArcMotionConfig {
+                tolerance: Length::from_millimeters(0.01),
+                min_segment_length: Length::from_millimeters(0.1),
+                max_segment_length: Length::from_millimeters(1.0),
+                min_segment_duration: Duration::from_millis(5),
+            },
+            feedrate: Speed::from_meters_per_second(0.05),
+            positioning: Positioning::Absolute,
+            e_positioning: Positioning::Relative,
+            feedrate_multiplier: 1.0,
+            extrusion: ExtrusionMotionConfig::default(),
+            limits: LimitsMotionConfig {
+                max_feedrate: (speed(1.0), speed(1.0), speed(1.0), speed(1.0)),
+                max_acceleration: (
+                    acceleration(10.0),
+                    acceleration(10.0),
+                    acceleration(10.0),
+                    acceleration(10.0),
+                ),
+                print_acceleration: acceleration(1.0),
+                retract_acceleration: acceleration(1.0),
+                travel_acceleration: acceleration(1.0),
+                jerk: (speed(0.01), speed(0.01), speed(0.01), speed(0.01)),
+            },
+            retraction: RetractionMotionConfig {
+                feedrate: Speed::from_meters_per_second(0.05),
+                length: Length::from_millimeters(1.0),
+                z_lift: Length::from_millimeters(0.0),
+            },
+            recover: RecoverMotionConfig {
+                feedrate: Speed::from_meters_per_second(0.05),
+                length: Length::from_millimeters(1.0),
+            },
+            homing: HomingMotionConfig {
+                axes: (
+                    HomingConfig::default(),
+                    HomingConfig::default(),
+                    HomingConfig::default(),
+                ),
+                order: [Axis::X, Axis::Y, Axis::Z],
+                z_lift: Length::from_millimeters(0.0),
+                required: false,
+                offset: (
+                    Distance::from_millimeters(0.0),
+                    Distance::from_millimeters(0.0),
+                    Distance::from_millimeters(0.0),
+                ),
+            },
+            software_endstops: SoftwareEndstopsConfig {
+                enabled: true,
+                clip: false,
+            },
+            leveling: LevelingMotionConfig {
+                points: (3, 3),
+                min: (
+                    Distance::from_millimeters(10.0),
+                    Distance::from_millimeters(10.0),
+                ),
+                max: (
+                    Distance::from_millimeters(30.0),
+                    Distance::from_millimeters(30.0),
+                ),
+                fade_height: Length::from_millimeters(0.0),
+            },
+            probe: ProbeMotionConfig {
+                offset: (
+                    Distance::from_millimeters(0.0),
+                    Distance::from_millimeters(0.0),
+                    Distance::from_millimeters(0.0),
+                ),
+                clearance: Length::from_millimeters(2.0),
+                max_depth: Length::from_millimeters(4.0),
+                feedrate: Speed::from_meters_per_second(0.005),
+                travel_feedrate: Speed::from_meters_per_second(0.05),
+                samples: 1,
+                retract: Length::from_millimeters(1.0),
+            },
+            alignment: AlignmentMotionConfig {
+                points: heapless::Vec::from_slice(&[
+                    (Distance::from_millimeters(10.0), Distance::from_millimeters(20.0)),
+                    (Distance::from_millimeters(30.0), Distance::from_millimeters(20.0)),
+                ])
+                .unwrap(),
+                iterations: 3,
+                accuracy: Length::from_millimeters(0.02),
+            },
+            backlash: BacklashMotionConfig {
+                distance: (
+                    Distance::from_millimeters(0.0),
+                    Distance::from_millimeters(0.0),
+                    Distance::from_millimeters(0.0),
+                ),
+                correction: 1.0,
+                smoothing: Length::from_millimeters(0.0),
+            },
+            skew: Skew::default(),
+            shaping: InputShapingMotionConfig::default(),
+            advance: LinearAdvance::default(),
+        };
+        Planner::new(
+            stepper(SteppingMode::FullStep, 0.2),
+            stepper(SteppingMode::EighthStep, 0.2),
+            stepper(SteppingMode::SixteenthStep, 0.04),
+            stepper(SteppingMode::QuarterStep, 0.1),
+            config,
+            (None, None, None, None),
+            None,
+        )
+    }
+
+    fn distance(value: f64) -> Option<Distance> {
+        Some(Distance::from_millimeters(value))
+    }
+
+    // bounds of X, Y and Z from 0mm to 100mm
+    fn bounded_planner(clip: bool) -> PlannerMock {
+        let mut p = planner();
+        for stepper in [&mut p.x_stepper, &mut p.y_stepper, &mut p.z_stepper] {
+            stepper.set_options(StepperOptions {
+                bounds: Some((
+                    Distance::from_millimeters(0.0),
+                    Distance::from_millimeters(100.0),
+                )),
+                ..stepper.get_options()
+            });
+        }
+        p.config.software_endstops.clip = clip;
+        p
+    }
+
+    fn g1(x: Option<Distance>, y: Option<Distance>) -> GCommand {
+        GCommand::G1 {
+            x,
+            y,
+            z: None,
+            e: None,
+            f: None,
+        }
+    }
+
+    #[test]
+    fn test_planner_software_endstops_reject() {
+        block_on(async {
+            let mut p = bounded_planner(false);
+            p.execute(g1(distance(10.0), distance(10.0))).await.unwrap();
+            // nothing moves, not even the axes within the bounds
+            let res = p.execute(g1(distance(150.0), distance(20.0))).await;
+            assert_eq!(
+                res,
+                Err(StepperError::OutOfBounds(
+                    Axis::X,
+                    Distance::from_millimeters(150.0)
+                ))
+            );
+            assert!(abs(p.get_x_position().as_millimeters() - 10.0) < 1e-9);
+            assert!(abs(p.get_y_position().as_millimeters() - 10.0) < 1e-9);
+            // the coordinate is reported in G-code coordinates
+            p.execute(GCommand::M206 {
+                x: None,
+                y: distance(-5.0),
+                z: None,
+            })
+            .await
+            .unwrap();
+            let res = p.execute(g1(None, distance(-6.0))).await;
+            assert_eq!(
+                res,
+                Err(StepperError::OutOfBounds(
+                    Axis::Y,
+                    Distance::from_millimeters(-6.0)
+                ))
+            );
+            assert!(p.execute(g1(None, distance(-5.0))).await.is_ok());
+        });
+    }
+
+    #[test]
+    fn test_planner_stepper_error() {
+        block_on(async {
+            // the planner doesn't check the bounds of E, its stepper does
+            let mut p = planner();
+            p.e_stepper.set_options(StepperOptions {
+                bounds: Some((Distance::from_millimeters(0.0), Distance::from_millimeters(5.0))),
+                ..p.e_stepper.get_options()
+            });
+            let g1_e = |x: f64, e: f64| GCommand::G1 {
+                x: distance(x),
+                y: None,
+                z: None,
+                e: distance(e),
+                f: None,
+            };
+            p.execute(g1_e(10.0, 4.0)).await.unwrap();
+            // the error of the stepper comes back as it is through the coordinated move
+            assert_eq!(p.execute(g1_e(20.0, 4.0)).await, Err(StepperError::MoveOutOfBounds));
+        });
+    }
+
+    #[test]
+    fn test_planner_software_endstops_clip() {
+        block_on(async {
+            let mut p = bounded_planner(true);
+            p.execute(g1(distance(150.0), distance(20.0))).await.unwrap();
+            assert!(abs(p.get_x_position().as_millimeters() - 100.0) < 1e-9);
+            assert!(abs(p.get_y_position().as_millimeters() - 20.0) < 1e-9);
+        });
+    }
+
+    #[test]
+    fn test_planner_m211() {
+        block_on(async {
+            let mut p = bounded_planner(false);
+            p.execute(GCommand::M211 { s: Some(false) }).await.unwrap();
+            assert!(!p.get_software_endstops().enabled);
+            p.execute(g1(distance(110.0), None)).await.unwrap();
+            assert!(abs(p.get_x_position().as_millimeters() - 110.0) < 1e-9);
+            p.execute(GCommand::M211 { s: None }).await.unwrap();
+            assert!(!p.get_software_endstops().enabled);
+            p.execute(GCommand::M211 { s: Some(true) }).await.unwrap();
+            assert!(p.execute(g1(distance(120.0), None)).await.is_err());
+            p.execute(GCommand::M206 {
+                x: distance(5.0),
+                y: None,
+                z: None,
+            })
+            .await
+            .unwrap();
+            assert_eq!(
+                format!("{}", p.get_software_endstops()),
+                "Software endstops: On [X:5.000..105.000] [Y:0.000..100.000] [Z:0.000..100.000]"
+            );
+        });
+    }
+
+    #[test]
+    fn test_planner_software_endstops_arc() {
+        block_on(async {
+            let mut p = bounded_planner(true);
+            p.execute(g1(distance(10.0), distance(5.0))).await.unwrap();
+            // counterclockwise the half circle around (20, 5) goes down to Y -5
+            let res = p
+                .execute(GCommand::G3 {
+                    x: distance(30.0),
+                    y: distance(5.0),
+                    z: None,
+                    e: None,
+                    f: None,
+                    i: distance(10.0),
+                    j: distance(0.0),
+                    k: None,
+                    r: None,
+                    p: None,
+                })
+                .await;
+            assert!(matches!(
+                res,
+                Err(StepperError::OutOfBounds(Axis::Y, y)) if abs(y.as_millimeters() + 5.0) < 1e-6
+            ));
+            assert!(abs(p.get_x_position().as_millimeters() - 10.0) < 1e-9);
+        });
+    }
+
+    #[test]
+    fn test_planner_workspaces() {
+        block_on(async {
+            let mut p = bounded_planner(false);
+            let assert_x = |p: &PlannerMock, logical: f64, machine: f64| {
+                let (l, m) = (p.get_x_position(), p.get_machine_position().0);
+                assert_abs_diff_eq!(l.as_millimeters(), logical, epsilon = 1e-9);
+                assert_abs_diff_eq!(m.as_millimeters(), machine, epsilon = 1e-9);
+            };
+            let g92_x = |x: f64| GCommand::G92 {
+                x: distance(x),
+                y: None,
+                z: None,
+                e: None,
+            };
+            p.execute(g1(distance(10.0), distance(10.0))).await.unwrap();
+            // the steppers aren't moved by G92, the bounds stay where they are
+            p.execute(g92_x(0.0)).await.unwrap();
+            assert_x(&p, 0.0, 10.0);
+            assert_abs_diff_eq!(p.x_stepper.get_position().as_millimeters(), 10.0, epsilon = 1e-9);
+            p.execute(g1(distance(-5.0), None)).await.unwrap();
+            assert_x(&p, -5.0, 5.0);
+            assert_eq!(
+                p.execute(g1(distance(-15.0), None)).await,
+                Err(StepperError::OutOfBounds(
+                    Axis::X,
+                    Distance::from_millimeters(-15.0)
+                ))
+            );
+            let (min, max) = p.get_software_endstops().bounds[0].unwrap();
+            assert_abs_diff_eq!(min.as_millimeters(), -10.0, epsilon = 1e-9);
+            assert_abs_diff_eq!(max.as_millimeters(), 90.0, epsilon = 1e-9);
+
+            // the shift of G92 stays when the coordinate system changes
+            p.execute(GCommand::G55).await.unwrap();
+            assert_eq!(p.get_workspaces().get_active(), 1);
+            assert_x(&p, -5.0, 5.0);
+            // G10 L2 puts the origin of G55 at -20mm, G10 L20 the head at 0mm in G54
+            let g10 = |l20: bool, p: Option<u8>, x: f64| match l20 {
+                false => GCommand::G10_L2 {
+                    p,
+                    x: distance(x),
+                    y: None,
+                    z: None,
+                },
+                true => GCommand::G10_L20 {
+                    p,
+                    x: distance(x),
+                    y: None,
+                    z: None,
+                },
+            };
+            p.execute(g10(false, Some(0), -20.0)).await.unwrap();
+            assert_x(&p, 15.0, 5.0);
+            p.execute(g10(true, Some(1), 0.0)).await.unwrap();
+            assert_x(&p, 15.0, 5.0);
+            assert_eq!(
+                p.execute(g10(false, Some(7), 0.0)).await,
+                Err(StepperError::MoveNotValid)
+            );
+            p.execute(GCommand::G54).await.unwrap();
+            assert_x(&p, 0.0, 5.0);
+
+            // G53 ignores the offsets, the home offsets too
+            p.execute(GCommand::M206 {
+                x: distance(2.0),
+                y: None,
+                z: None,
+            })
+            .await
+            .unwrap();
+            assert_x(&p, 2.0, 5.0);
+            let g53 = GCommand::G53 {
+                x: distance(20.0),
+                y: None,
+                z: None,
+                f: None,
+            };
+            p.execute(g53).await.unwrap();
+            assert_x(&p, 17.0, 20.0);
+            assert_abs_diff_eq!(p.get_y_position().as_millimeters(), 10.0, epsilon = 1e-9);
+
+            // G92.1 clears the shift of G92 only, the coordinate systems keep their offsets
+            p.execute(GCommand::G92_1).await.unwrap();
+            assert_x(&p, 27.0, 20.0);
+            p.execute(GCommand::G55).await.unwrap();
+            assert_x(&p, 42.0, 20.0);
+        });
+    }
+
+    fn g2(
+        x: Option<Distance>,
+        y: Option<Distance>,
+        z: Option<Distance>,
+        (i, j, k): (Option<Distance>, Option<Distance>, Option<Distance>),
+        r: Option<Distance>,
+    ) -> GCommand {
+        GCommand::G2 {
+            x,
+            y,
+            z,
+            e: None,
+            f: None,
+            i,
+            j,
+            k,
+            r,
+            p: None,
+        }
+    }
+
+    #[test]
+    fn test_planner_skew() {
+        block_on(async {
+            let mut p = planner();
+            p.execute(GCommand::M852 {
+                i: Some(0.01),
+                j: Some(0.02),
+                k: None,
+            })
+            .await
+            .unwrap();
+            assert_eq!(
+                format!("{}", p.get_skew()),
+                "Skew: [XY:0.010000] [XZ:0.020000] [YZ:0.000000]"
+            );
+            p.execute(g1_z(10.0, 100.0, 10.0)).await.unwrap();
+            // X leans by 1mm over 100mm of Y and by 0.2mm over 10mm of Z
+            assert_abs_diff_eq!(p.x_stepper.get_position().as_millimeters(), 8.8, epsilon = 1e-9);
+            assert_abs_diff_eq!(p.get_x_position().as_millimeters(), 10.0, epsilon = 1e-9);
+            assert_abs_diff_eq!(p.get_y_position().as_millimeters(), 100.0, epsilon = 1e-9);
+            // half a circle around (20, 100) ends on the skewed line too
+            let none = (None, None, None);
+            p.execute(g2(distance(30.0), distance(100.0), None, none, distance(10.0)))
+                .await
+                .unwrap();
+            assert_abs_diff_eq!(p.x_stepper.get_position().as_millimeters(), 28.8, epsilon = 1e-9);
+            assert_abs_diff_eq!(p.get_x_position().as_millimeters(), 30.0, epsilon = 1e-9);
+            // the steppers stay where they are, the position follows the new skew
+            p.execute(GCommand::M852 {
+                i: Some(0.0),
+                j: Some(0.0),
+                k: None,
+            })
+            .await
+            .unwrap();
+            assert_abs_diff_eq!(p.get_x_position().as_millimeters(), 28.8, epsilon = 1e-9);
+            p.execute(g1_z(30.0, 100.0, 10.0)).await.unwrap();
+            assert_abs_diff_eq!(p.x_stepper.get_position().as_millimeters(), 30.0, epsilon = 1e-9);
+        });
+    }
+
+    #[test]
+    fn test_planner_arc_radius() {
+        block_on(async {
+            let mut p = bounded_planner(false);
+            p.execute(g1(distance(10.0), distance(50.0))).await.unwrap();
+            let none = (None, None, None);
+            // half a circle around (20, 50)
+            p.execute(g2(distance(30.0), distance(50.0), None, none, distance(10.0)))
+                .await
+                .unwrap();
+            assert!(abs(p.get_x_position().as_millimeters() - 30.0) <= 0.1 + 1e-9);
+            assert!(abs(p.get_y_position().as_millimeters() - 50.0) <= 0.0125 + 1e-9);
+            // the longer arc around (35, 58.66)
+            p.execute(g2(distance(40.0), distance(50.0), None, none, distance(-10.0)))
+                .await
+                .unwrap();
+            assert!(abs(p.get_x_position().as_millimeters() - 40.0) <= 0.1 + 1e-9);
+            assert!(abs(p.get_y_position().as_millimeters() - 50.0) <= 0.0125 + 1e-9);
+            // the radius is shorter than half the chord
+            assert!(matches!(
+                p.execute(g2(distance(80.0), None, None, none, distance(10.0))).await,
+                Err(StepperError::MoveNotValid)
+            ));
+            // the box holding the arc is checked, not its end. The longer arc around
+            // (15, -3.66) goes down to Y -13.66
+            p.execute(g1(distance(20.0), distance(5.0))).await.unwrap();
+            let res = p
+                .execute(g2(distance(10.0), distance(5.0), None, none, distance(-10.0)))
+                .await;
+            let bottom = 5.0 - 75.0_f64.sqrt() - 10.0;
+            assert!(matches!(
+                res,
+                Err(StepperError::OutOfBounds(Axis::Y, y)) if abs(y.as_millimeters() - bottom) < 1e-6
+            ));
+        });
+    }
+
+    #[test]
+    fn test_planner_arc_plane() {
+        block_on(async {
+            let mut p = planner();
+            p.execute(GCommand::G18).await.unwrap();
+            // J is not an offset on the ZX plane
+            let j = (None, distance(10.0), None);
+            assert!(matches!(
+                p.execute(g2(distance(0.0), None, distance(20.0), j, None)).await,
+                Err(StepperError::MoveNotValid)
+            ));
+            // half a circle from Z 0 to Z 20 around Z 10, Y moves along with it
+            p.execute(g2(
+                distance(0.0),
+                distance(5.0),
+                distance(20.0),
+                (None, None, distance(10.0)),
+                None,
+            ))
+            .await
+            .unwrap();
+            assert!(abs(p.get_z_position().as_millimeters() - 20.0) <= 0.00125 + 1e-9);
+            assert!(abs(p.get_x_position().as_millimeters()) <= 0.1 + 1e-9);
+            assert!(abs(p.get_y_position().as_millimeters() - 5.0) <= 0.0125 + 1e-9);
+            p.execute(GCommand::G17).await.unwrap();
+            // K is not an offset on the XY plane
+            let k = (None, None, distance(10.0));
+            assert!(matches!(
+                p.execute(g2(None, None, None, k, None)).await,
+                Err(StepperError::MoveNotValid)
+            ));
+        });
+    }
+
+    fn g5(
+        x: Option<Distance>,
+        y: Option<Distance>,
+        e: Option<Distance>,
+        (i, j, p, q): (f64, f64, f64, f64),
+    ) -> GCommand {
+        GCommand::G5 {
+            x,
+            y,
+            e,
+            f: None,
+            i: distance(i),
+            j: distance(j),
+            p: distance(p),
+            q: distance(q),
+        }
+    }
+
+    #[test]
+    fn test_planner_bezier() {
+        block_on(async {
+            let mut p = bounded_planner(false);
+            p.execute(g1(distance(10.0), distance(5.0))).await.unwrap();
+            // the curve bulges up to Y 12.5 and ends where it started on Y
+            p.execute(g5(distance(30.0), distance(5.0), distance(2.0), (0.0, 10.0, 0.0, 10.0)))
+                .await
+                .unwrap();
+            assert!(abs(p.get_x_position().as_millimeters() - 30.0) <= 0.1 + 1e-9);
+            assert!(abs(p.get_y_position().as_millimeters() - 5.0) <= 0.0125 + 1e-9);
+            assert!(abs(p.get_e_position().as_millimeters() - 2.0) <= 0.0125 + 1e-9);
+            // bulging down it goes below Y 0, the curve is checked rather than its end
+            let res = p
+                .execute(g5(distance(50.0), distance(5.0), None, (0.0, -10.0, 0.0, -10.0)))
+                .await;
+            assert!(matches!(
+                res,
+                Err(StepperError::OutOfBounds(Axis::Y, y))
+                    if y.as_millimeters() < 0.0 && y.as_millimeters() > -2.5 - 1e-9
+            ));
+            assert!(abs(p.get_x_position().as_millimeters() - 30.0) <= 0.1 + 1e-9);
+        });
+    }
+
+    // planner moving the nozzle over a simulated bed, touched by its probe. The recorder gets
+    // the pins of X, Y and Z
+    fn leveled_planner(height: impl Fn(f64, f64) -> f64 + 'static) -> (PlannerMock, SimBed, Recorder) {
+        let recorder = Recorder::new();
+        let mut p = planner();
+        let mut axes = Vec::new();
+        for (stepper, name, mode, distance_per_step) in [
+            (&mut p.x_stepper, "x", SteppingMode::FullStep, 0.2),
+            (&mut p.y_stepper, "y", SteppingMode::EighthStep, 0.2),
+            (&mut p.z_stepper, "z", SteppingMode::SixteenthStep, 0.04),
+        ] {
+            let (step, dir) = (format!("{}_step", name), format!("{}_dir", name));
+            *stepper = Stepper::new_with_attachment(
+                recorder.output_pin(&step),
+                recorder.output_pin(&dir),
+                StepperOptions {
+                    stepping_mode: mode,
+                    ..Default::default()
+                },
+                StepperAttachment {
+                    distance_per_step: Distance::from_millimeters(distance_per_step),
+                },
+            );
+            axes.push(SimAxis::new(&step, &dir, stepper.get_resolution().as_millimeters()));
+        }
+        let axes: [SimAxis; 3] = axes.try_into().ok().unwrap();
+        let bed = SimBed::new(recorder.clone(), axes, height);
+        p.set_probe(Some(SwitchProbe::new(bed.probe())));
+        (p, bed, recorder)
+    }
+
+    fn g29() -> GCommand {
+        GCommand::G29 { t: false }
+    }
+
+    fn g1_z(x: f64, y: f64, z: f64) -> GCommand {
+        GCommand::G1 {
+            x: distance(x),
+            y: distance(y),
+            z: distance(z),
+            e: None,
+            f: None,
+        }
+    }
+
+    #[test]
+    fn test_planner_leveling() {
+        block_on(async {
+            // tilted and twisted, the mesh interpolates it exactly
+            let height = |x: f64, y: f64| 0.01 * x - 0.02 * y + 0.0002 * x * y;
+            let (mut p, bed, _) = leveled_planner(height);
+            // the probe stops on the first step that touches the bed
+            let resolution = p.z_stepper.get_resolution().as_millimeters() + 1e-9;
+            p.execute(g29()).await.unwrap();
+            let mesh = *p.get_leveling().get_mesh().unwrap();
+            assert_eq!(mesh.get_points(), (3, 3));
+            for j in 0..3 {
+                for i in 0..3 {
+                    let (x, y) = mesh.point(i, j);
+                    let expected = height(x.as_millimeters(), y.as_millimeters());
+                    let z = mesh.get(i, j).as_millimeters();
+                    assert!(z <= expected && z > expected - resolution);
+                }
+            }
+            assert!(p.get_leveling().is_enabled());
+            // the probe is left at the clearance
+            assert_abs_diff_eq!(bed.position()[2], 2.0, epsilon = resolution);
+
+            p.execute(g1_z(25.0, 15.0, 0.5)).await.unwrap();
+            let [x, y, z] = bed.position();
+            assert_abs_diff_eq!(z - height(x, y), 0.5, epsilon = 2.0 * resolution);
+            // the position doesn't show the compensation
+            assert_abs_diff_eq!(p.get_z_position().as_millimeters(), 0.5, epsilon = resolution);
+
+            // over the fade height the bed isn't followed anymore
+            p.execute(GCommand::M420 {
+                s: None,
+                z: distance(1.0),
+            })
+            .await
+            .unwrap();
+            p.execute(g1_z(30.0, 30.0, 1.5)).await.unwrap();
+            assert_abs_diff_eq!(bed.position()[2], 1.5, epsilon = resolution);
+            assert_abs_diff_eq!(p.get_z_position().as_millimeters(), 1.5, epsilon = resolution);
+            // halfway it's followed by half
+            p.execute(g1_z(30.0, 30.0, 0.5)).await.unwrap();
+            assert_abs_diff_eq!(
+                bed.position()[2],
+                0.5 + height(30.0, 30.0) / 2.0,
+                epsilon = resolution
+            );
+
+            p.execute(GCommand::M420 {
+                s: Some(false),
+                z: None,
+            })
+            .await
+            .unwrap();
+            p.execute(g1_z(10.0, 10.0, 0.5)).await.unwrap();
+            assert_abs_diff_eq!(bed.position()[2], 0.5, epsilon = resolution);
+        });
+    }
+
+    #[test]
+    fn test_planner_leveling_split() {
+        block_on(async {
+            // a valley along X 20, the mesh gets 0.1 on its sides and 0 in the middle
+            let height = |x: f64, _: f64| 0.001 * (x - 20.0) * (x - 20.0);
+            let (mut p, bed, recorder) = leveled_planner(height);
+            p.execute(g29()).await.unwrap();
+            p.execute(g1_z(10.0, 15.0, 0.5)).await.unwrap();
+            let start = bed.position()[2];
+            // the ends are as high, Z moves only because the move is split on X 20
+            let steps = recorder.rising_edges("z_step").len();
+            p.execute(g1_z(30.0, 15.0, 0.5)).await.unwrap();
+            assert!(recorder.rising_edges("z_step").len() > steps);
+            assert_abs_diff_eq!(bed.position()[2], start, epsilon = 1e-9);
+            assert_abs_diff_eq!(p.get_z_position().as_millimeters(), 0.5, epsilon = 0.0025 + 1e-9);
+        });
+    }
+
+    // times Z went up after going down, from the given event on
+    fn z_lifts(recorder: &Recorder, from: usize) -> usize {
+        let levels: Vec<bool> = recorder.events()[from..]
+            .iter()
+            .filter(|e| e.pin == "z_dir")
+            .map(|e| e.high)
+            .collect();
+        levels.windows(2).filter(|w| !w[0] && w[1]).count()
+    }
+
+    #[test]
+    fn test_planner_probe_software_endstops() {
+        block_on(async {
+            // the bed is lower than Z can go
+            let (mut p, _, recorder) = leveled_planner(|_, _| -3.0);
+            for (stepper, min) in [(&mut p.x_stepper, 0.0), (&mut p.z_stepper, -1.0)] {
+                stepper.set_options(StepperOptions {
+                    bounds: Some((
+                        Distance::from_millimeters(min),
+                        Distance::from_millimeters(50.0),
+                    )),
+                    ..stepper.get_options()
+                });
+            }
+            assert_eq!(
+                p.probe(distance(60.0), distance(20.0)).await,
+                Err(StepperError::OutOfBounds(Axis::X, Distance::from_millimeters(60.0)))
+            );
+            assert!(recorder.rising_edges("x_step").is_empty());
+            // the probe looks for the bed down to the bottom of Z
+            assert_eq!(
+                p.probe(distance(20.0), distance(20.0)).await,
+                Err(StepperError::ProbeNotTriggered)
+            );
+            assert_abs_diff_eq!(p.get_z_position().as_millimeters(), -1.0, epsilon = 1e-9);
+        });
+    }
+
+    #[test]
+    fn test_planner_probe() {
+        block_on(async {
+            let height = |x: f64, y: f64| 0.01 * x + 0.02 * y;
+            let (mut p, bed, recorder) = leveled_planner(height);
+            let resolution = p.z_stepper.get_resolution().as_millimeters() + 1e-9;
+            let point = p.probe(distance(20.0), distance(20.0)).await.unwrap();
+            assert_eq!((point.x, point.y), (Distance::from_millimeters(20.0), Distance::from_millimeters(20.0)));
+            let z = point.z.as_millimeters();
+            assert!(z < 0.6 + 1e-9 && z > 0.6 - resolution);
+            // the probe is left at the clearance
+            assert_abs_diff_eq!(bed.position()[2], 2.0, epsilon = resolution);
+            assert_abs_diff_eq!(p.get_z_position().as_millimeters(), 2.0, epsilon = resolution);
+
+            // the probe is 5mm on the left of the nozzle and triggers 1mm below it
+            p.execute(GCommand::M851 {
+                x: distance(-5.0),
+                y: None,
+                z: distance(-1.0),
+            })
+            .await
+            .unwrap();
+            assert_eq!(p.get_probe_offset(), (Distance::from_millimeters(-5.0), Distance::from_millimeters(0.0), Distance::from_millimeters(-1.0)));
+            p.config.probe.samples = 3;
+            let from = recorder.events().len();
+            let point = p.probe(distance(20.0), distance(20.0)).await.unwrap();
+            assert_abs_diff_eq!(bed.position()[0], 25.0, epsilon = 1e-9);
+            assert_abs_diff_eq!(point.z.as_millimeters(), height(25.0, 20.0) - 1.0, epsilon = resolution);
+            // lifted between the samples and at the end
+            assert_eq!(z_lifts(&recorder, from), 3);
+
+            // without X and Y the probe stays where it is
+            let point = p.probe(None, None).await.unwrap();
+            assert_eq!((point.x, point.y), (Distance::from_millimeters(20.0), Distance::from_millimeters(20.0)));
+
+            p.config.probe.samples = 1;
+            let stats = p.probe_repeatability(Some(4), None, None).await.unwrap();
+            assert_eq!(stats.get_samples(), 4);
+            // the bed doesn't move, the probe repeats within a step
+            assert!((stats.get_max() - stats.get_min()).as_millimeters() < resolution);
+            assert!(stats.get_std_dev().as_millimeters() < resolution);
+            assert_eq!(
+                p.probe_repeatability(Some(0), None, None).await,
+                Err(StepperError::MoveNotValid)
+            );
+            // through execute the duration of the probing is returned
+            let duration = p.execute(GCommand::G30 { x: None, y: None }).await.unwrap();
+            assert!(duration.unwrap() > Duration::ZERO);
+        });
+    }
+
+    #[test]
+    fn test_planner_leveling_errors() {
+        block_on(async {
+            // no probe
+            let mut p = planner();
+            assert_eq!(p.execute(g29()).await, Err(StepperError::NotSupported));
+            // the bed is out of reach
+            let (mut p, _, _) = leveled_planner(|_, _| -10.0);
+            assert_eq!(p.execute(g29()).await, Err(StepperError::ProbeNotTriggered));
+            assert!(p.get_leveling().get_mesh().is_none());
+            assert!(!p.get_leveling().is_enabled());
+            // T only prints the mesh
+            assert_eq!(p.execute(GCommand::G29 { t: true }).await, Ok(None));
+        });
+    }
+
+    fn m425(f: Option<f64>, x: Option<Distance>) -> GCommand {
+        GCommand::M425 {
+            f,
+            s: None,
+            x,
+            y: None,
+            z: None,
+        }
+    }
+
+    #[test]
+    fn test_planner_backlash() {
+        block_on(async {
+            let (mut p, _, recorder) = leveled_planner(|_, _| 0.0);
+            p.execute(m425(Some(1.0), distance(0.4))).await.unwrap();
+            assert_eq!(
+                format!("{}", p.get_backlash()),
+                "Backlash: [correction:1.00] [smoothing:0.000] [X:0.400] [Y:0.000] [Z:0.000]"
+            );
+            p.execute(g1_z(10.0, 0.0, 0.0)).await.unwrap();
+            let edges = recorder.rising_edges("x_step").len();
+            p.execute(g1_z(20.0, 0.0, 0.0)).await.unwrap();
+            assert_eq!(recorder.rising_edges("x_step").len(), edges + 50);
+            // the reversal takes up 0.4mm, two steps of X
+            p.execute(g1_z(10.0, 0.0, 0.0)).await.unwrap();
+            assert_eq!(recorder.rising_edges("x_step").len(), edges + 102);
+            assert_eq!(p.get_x_position(), Distance::from_millimeters(10.0));
+            // the values are checked before changing any of them
+            assert_eq!(
+                p.execute(m425(Some(1.5), distance(0.2))).await,
+                Err(StepperError::MoveNotValid)
+            );
+            assert_eq!(
+                p.execute(m425(Some(0.5), distance(-0.2))).await,
+                Err(StepperError::MoveNotValid)
+            );
+            assert_eq!(p.get_backlash().correction, 1.0);
+            p.execute(m425(Some(0.5), None)).await.unwrap();
+            p.execute(g1_z(20.0, 0.0, 0.0)).await.unwrap();
+            assert_eq!(recorder.rising_edges("x_step").len(), edges + 153);
+        });
+    }
+
+    #[test]
+    fn test_planner_linear_advance() {
+        block_on(async {
+            let recorder = Recorder::new();
+            let mut p = planner();
+            p.e_stepper = Stepper::new_with_attachment(
+                recorder.output_pin("e_step"),
+                recorder.output_pin("e_dir"),
+                StepperOptions {
+                    stepping_mode: SteppingMode::QuarterStep,
+                    ..Default::default()
+                },
+                StepperAttachment {
+                    distance_per_step: Distance::from_millimeters(0.1),
+                },
+            );
+            let steps = |from: Duration, to: Duration| {
+                recorder
+                    .rising_edges("e_step")
+                    .iter()
+                    .filter(|t| **t >= from && **t < to)
+                    .count() as f64
+            };
+            // E is relative. 10mm of X up to 80mm/s from 10mm/s at 1000mm/s², 70ms to get up to
+            // speed and as long to slow down. Along with it E speeds up by 35mm/s: K·35mm/s is
+            // pushed ahead while it accelerates and released while it decelerates. The move
+            // takes as long as without the advance
+            let g1 = |x: f64, e: f64| GCommand::G1 {
+                x: distance(x),
+                y: None,
+                z: None,
+                e: distance(e),
+                f: Some(Speed::from_meters_per_second(0.08)),
+            };
+            let ramp = Duration::from_millis(70);
+            let mut x = 0.0;
+            let mut moves = Vec::new();
+            for k in [0.0, 0.01] {
+                p.execute(GCommand::M900 { k: Some(k) }).await.unwrap();
+                x += 10.0;
+                let start = SimTimer::now();
+                let e = p.get_e_position().as_millimeters() + 5.0;
+                let duration = p.execute(g1(x, 5.0)).await.unwrap().unwrap();
+                assert_abs_diff_eq!(p.get_e_position().as_millimeters(), e, epsilon = 1e-9);
+                let end = SimTimer::now();
+                moves.push((steps(start, start + ramp), steps(end - ramp, end), duration));
+            }
+            assert!(moves[0].2.abs_diff(moves[1].2) < Duration::from_micros(1));
+            assert_eq!(format!("{}", p.get_advance()), "Linear advance: [K:0.010]");
+            let extra = 0.01 * 35.0 / 0.025;
+            assert!(abs(moves[1].0 - moves[0].0 - extra) <= 1.5);
+            assert!(abs(moves[0].1 - moves[1].1 - extra) <= 1.5);
+
+            // a retraction isn't advanced, its steps are the same as without the advance
+            let mut retractions = Vec::new();
+            for k in [0.01, 0.0] {
+                p.execute(GCommand::M900 { k: Some(k) }).await.unwrap();
+                recorder.clear();
+                let start = SimTimer::now();
+                p.execute(g1(x, -1.0)).await.unwrap();
+                let edges: Vec<Duration> =
+                    recorder.rising_edges("e_step").iter().map(|t| *t - start).collect();
+                assert_eq!(edges.len(), 40);
+                retractions.push(edges);
+            }
+            assert_eq!(retractions[0], retractions[1]);
+
+            assert_eq!(
+                p.execute(GCommand::M900 { k: Some(-0.1) }).await,
+                Err(StepperError::MoveNotValid)
+            );
+            assert_eq!(p.get_advance().k, 0.0);
+        });
+    }
+
+    #[test]
+    fn test_planner_limits() {
+        block_on(async {
+            let mm_s = |v: f64| Some(Speed::from_meters_per_second(v / 1000.0));
+            let mut p = planner();
+            p.execute(GCommand::M203 {
+                x: mm_s(10.0),
+                y: None,
+                z: mm_s(5.0),
+                e: mm_s(20.0),
+            })
+            .await
+            .unwrap();
+            // the same moves at the feedrate that takes the fastest axis to its limit
+            let mut reference = planner();
+            let g1_f = |[x, y, z, e]: [Option<f64>; 4], f: f64| GCommand::G1 {
+                x: x.and_then(distance),
+                y: y.and_then(distance),
+                z: z.and_then(distance),
+                e: e.and_then(distance),
+                f: mm_s(f),
+            };
+            let g2_f = |f: f64| GCommand::G2 {
+                x: distance(20.0),
+                y: distance(10.0),
+                z: None,
+                e: None,
+                f: mm_s(f),
+                i: None,
+                j: None,
+                k: None,
+                r: distance(5.0),
+                p: None,
+            };
+            let (z, xy, e, back) = (
+                [None, None, Some(10.0), None],
+                [Some(10.0), Some(10.0), None, None],
+                [None, None, None, Some(2.0)],
+                [Some(0.0), Some(0.0), None, None],
+            );
+            let moves = [
+                // Z alone
+                (g1_f(z, 50.0), g1_f(z, 5.0)),
+                // X covers 0.71mm for each mm of the move
+                (g1_f(xy, 50.0), g1_f(xy, 10.0 * 2.0_f64.sqrt())),
+                // E alone
+                (g1_f(e, 50.0), g1_f(e, 20.0)),
+                // X goes as fast as the head along the arc
+                (g2_f(50.0), g2_f(10.0)),
+                // below the limits
+                (g1_f(back, 5.0), g1_f(back, 5.0)),
+            ];
+            for (limited, expected) in moves {
+                let duration = p.execute(limited).await.unwrap().unwrap();
+                let expected = reference.execute(expected).await.unwrap().unwrap();
+                assert!(duration.abs_diff(expected) < Duration::from_millis(1));
+            }
+            assert_abs_diff_eq!(p.get_z_position().as_millimeters(), 10.0, epsilon = 1e-9);
+
+            // nothing changes if one of the values isn't valid
+            assert_eq!(
+                p.execute(GCommand::M203 {
+                    x: mm_s(0.0),
+                    y: mm_s(100.0),
+                    z: None,
+                    e: None,
+                })
+                .await,
+                Err(StepperError::MoveNotValid)
+            );
+            let acceleration =
+                |a: f64| Some(Acceleration::from_meters_per_second_per_second(a / 1000.0));
+            p.execute(GCommand::M201 {
+                x: acceleration(3000.0),
+                y: acceleration(3000.0),
+                z: acceleration(100.0),
+                e: acceleration(5000.0),
+            })
+            .await
+            .unwrap();
+            p.execute(GCommand::M204 {
+                p: acceleration(1000.0),
+                r: None,
+                t: acceleration(1500.0),
+            })
+            .await
+            .unwrap();
+            p.execute(GCommand::M205 {
+                x: mm_s(8.0),
+                y: None,
+                z: mm_s(0.4),
+                e: None,
+            })
+            .await
+            .unwrap();
+            assert_eq!(
+                p.execute(GCommand::M204 {
+                    p: None,
+                    r: acceleration(-1.0),
+                    t: None,
+                })
+                .await,
+                Err(StepperError::MoveNotValid)
+            );
+            assert_eq!(
+                format!("{}", p.get_limits()),
+                "Limits: [feedrate X:10.0 Y:1000.0 Z:5.0 E:20.0] \
+                 [acceleration X:3000 Y:3000 Z:100 E:5000] [P:1000 R:1000 T:1500] \
+                 [jerk X:8.0 Y:10.0 Z:0.4 E:10.0]"
+            );
+        });
+    }
+
+    #[test]
+    fn test_planner_acceleration() {
+        block_on(async {
+            let mm_s = |v: f64| Some(Speed::from_meters_per_second(v / 1000.0));
+            let acceleration =
+                |a: f64| Some(Acceleration::from_meters_per_second_per_second(a / 1000.0));
+            let mut p = planner();
+            p.execute(GCommand::M204 {
+                p: acceleration(500.0),
+                r: acceleration(2000.0),
+                t: acceleration(2000.0),
+            })
+            .await
+            .unwrap();
+            // the moves start at their speed, they aren't accelerated
+            let mut reference = planner();
+            reference
+                .execute(GCommand::M205 {
+                    x: mm_s(1000.0),
+                    y: mm_s(1000.0),
+                    z: mm_s(1000.0),
+                    e: mm_s(1000.0),
+                })
+                .await
+                .unwrap();
+            let g1 = |[x, z, e]: [Option<f64>; 3], f: f64| GCommand::G1 {
+                x: x.and_then(distance),
+                y: None,
+                z: z.and_then(distance),
+                e: e.and_then(distance),
+                f: mm_s(f),
+            };
+            // getting from the entry speed to the speed and back takes (v - e)² / (a·v) longer
+            async fn assert_ramp(
+                planners: (&mut PlannerMock, &mut PlannerMock),
+                command: GCommand,
+                a: f64,
+                entry: f64,
+                speed: f64,
+            ) {
+                let duration = planners.0.execute(command.clone()).await.unwrap().unwrap();
+                let expected = planners.1.execute(command).await.unwrap().unwrap();
+                let ramp = (speed - entry) * (speed - entry) / (a * speed);
+                assert_abs_diff_eq!((duration - expected).as_secs_f64(), ramp, epsilon = 0.001);
+            }
+            // a travel, a print move and a retraction, at T, P and R
+            let travel = g1([Some(100.0), None, None], 100.0);
+            assert_ramp((&mut p, &mut reference), travel, 2000.0, 10.0, 100.0).await;
+            let print = g1([Some(0.0), None, Some(1.0)], 100.0);
+            assert_ramp((&mut p, &mut reference), print, 500.0, 10.0, 100.0).await;
+            let retract = g1([None, None, Some(-10.0)], 100.0);
+            assert_ramp((&mut p, &mut reference), retract, 2000.0, 10.0, 100.0).await;
+
+            // Z can't accelerate over 50mm/s², nor start over 10mm/s: along the move it's the
+            // ratio of the length covered by Z higher
+            for planner in [&mut p, &mut reference] {
+                planner
+                    .execute(GCommand::M201 {
+                        x: None,
+                        y: None,
+                        z: acceleration(50.0),
+                        e: None,
+                    })
+                    .await
+                    .unwrap();
+            }
+            let ratio = precise_sqrt(200.0) / 10.0;
+            let xz = g1([Some(10.0), Some(10.0), None], 20.0);
+            assert_ramp((&mut p, &mut reference), xz, 50.0 * ratio, 10.0 * ratio, 20.0).await;
+
+            // the move starts and ends at the jerk of X
+            p.execute(GCommand::M205 {
+                x: mm_s(50.0),
+                y: None,
+                z: None,
+                e: None,
+            })
+            .await
+            .unwrap();
+            let travel = g1([Some(110.0), None, None], 100.0);
+            assert_ramp((&mut p, &mut reference), travel, 2000.0, 50.0, 100.0).await;
+        });
+    }
+
+    #[test]
+    fn test_planner_extrusion() {
+        block_on(async {
+            let mut p = planner();
+            let g1_e = |x: f64, e: f64| GCommand::G1 {
+                x: distance(x),
+                y: None,
+                z: None,
+                e: distance(e),
+                f: None,
+            };
+            // E is relative
+            p.execute(g1_e(10.0, 2.0)).await.unwrap();
+            assert_abs_diff_eq!(p.get_e_position().as_millimeters(), 2.0, epsilon = 1e-9);
+            p.execute(GCommand::M221 { s: 1.5 }).await.unwrap();
+            p.execute(g1_e(20.0, 2.0)).await.unwrap();
+            assert_abs_diff_eq!(p.get_e_position().as_millimeters(), 5.0, epsilon = 1e-9);
+            assert_eq!(
+                p.execute(GCommand::M221 { s: 0.0 }).await,
+                Err(StepperError::MoveNotValid)
+            );
+
+            // 1mm² of cross-section, E is a volume
+            let diameter = 2.0 / core::f64::consts::PI.sqrt();
+            p.execute(GCommand::M200 { d: distance(diameter) }).await.unwrap();
+            assert_eq!(
+                format!("{}", p.get_extrusion()),
+                "Extrusion: [flow:150.0%] [volumetric:1.128]"
+            );
+            p.execute(g1_e(30.0, 2.0)).await.unwrap();
+            assert_abs_diff_eq!(p.get_e_position().as_millimeters(), 8.0, epsilon = 1e-9);
+            p.execute(GCommand::G2 {
+                x: distance(40.0),
+                y: None,
+                z: None,
+                e: distance(2.0),
+                f: None,
+                i: None,
+                j: None,
+                k: None,
+                r: distance(5.0),
+                p: None,
+            })
+            .await
+            .unwrap();
+            assert_abs_diff_eq!(p.get_e_position().as_millimeters(), 11.0, epsilon = 1e-9);
+            // the firmware retraction too
+            p.execute(GCommand::G10).await.unwrap();
+            assert_abs_diff_eq!(p.get_e_position().as_millimeters(), 9.5, epsilon = 1e-9);
+            p.execute(GCommand::G11).await.unwrap();
+            assert_abs_diff_eq!(p.get_e_position().as_millimeters(), 11.0, epsilon = 1e-9);
+
+            // E is at 8 seen from G-code, the retraction is recovered
+            p.execute(GCommand::M82).await.unwrap();
+            p.execute(g1_e(50.0, 10.0)).await.unwrap();
+            assert_abs_diff_eq!(p.get_e_position().as_millimeters(), 14.0, epsilon = 1e-9);
+            p.execute(GCommand::G92 {
+                x: None,
+                y: None,
+                z: None,
+                e: distance(0.0),
+            })
+            .await
+            .unwrap();
+            p.execute(g1_e(60.0, 1.0)).await.unwrap();
+            assert_abs_diff_eq!(p.get_e_position().as_millimeters(), 1.5, epsilon = 1e-9);
+
+            p.execute(GCommand::M200 { d: distance(0.0) }).await.unwrap();
+            assert_eq!(
+                format!("{}", p.get_extrusion()),
+                "Extrusion: [flow:150.0%] [volumetric:Off]"
+            );
+            p.execute(g1_e(70.0, 2.0)).await.unwrap();
+            assert_abs_diff_eq!(p.get_e_position().as_millimeters(), 3.0, epsilon = 1e-9);
+            assert_eq!(
+                p.execute(GCommand::M200 { d: distance(-1.0) }).await,
+                Err(StepperError::MoveNotValid)
+            );
+        });
+    }
+
+    #[test]
+    fn test_planner_input_shaping() {
+        block_on(async {
+            let (mut p, _, _) = leveled_planner(|_, _| 0.0);
+            let unshaped = p.execute(g1_z(10.0, 10.0, 0.0)).await.unwrap().unwrap();
+            p.execute(GCommand::M593 {
+                d: Some(0.0),
+                f: Some(20.0),
+                t: Some(0),
+                x: true,
+                y: false,
+            })
+            .await
+            .unwrap();
+            assert_eq!(
+                format!("{}", p.get_shaping()),
+                "Input shaping: [X:ZV F20.00 D0.000] [Y:ZV F0.00 D0.100]"
+            );
+            // X ends half a period of the resonance later
+            let shaped = p.execute(g1_z(20.0, 20.0, 0.0)).await.unwrap().unwrap();
+            let delay = unshaped + Duration::from_millis(25);
+            assert!(shaped.abs_diff(delay) < Duration::from_micros(1));
+            assert_eq!(p.get_x_position(), Distance::from_millimeters(20.0));
+            assert_eq!(p.get_y_position(), Distance::from_millimeters(20.0));
+            // the values are checked before changing any of them
+            for (d, t) in [(Some(1.0), None), (None, Some(5))] {
+                assert_eq!(
+                    p.execute(GCommand::M593 {
+                        d,
+                        f: Some(30.0),
+                        t,
+                        x: true,
+                        y: true,
+                    })
+                    .await,
+                    Err(StepperError::MoveNotValid)
+                );
+            }
+            assert_eq!(p.get_shaping().x.frequency, 20.0);
+            assert!(!p.get_shaping().y.is_enabled());
+        });
+    }
+
+    // Z driven by two motors, the second one is d higher than the tracked position. The lead
+    // screws are under the points probed by G34, at X10 and X30, the bed is flat at Z 0
+    fn aligned_planner(d: f64) -> (PlannerMock, SimBed, SimBed, Recorder) {
+        let (mut p, bed, recorder) = leveled_planner(|_, _| 0.0);
+        p.add_motor(
+            Axis::Z,
+            recorder.output_pin("z2_step"),
+            recorder.output_pin("z2_dir"),
+            None,
+        )
+        .unwrap();
+        let axes = [
+            SimAxis::new("x_step", "x_dir", p.x_stepper.get_resolution().as_millimeters()),
+            SimAxis::new("y_step", "y_dir", p.y_stepper.get_resolution().as_millimeters()),
+            SimAxis::new("z2_step", "z2_dir", p.z_stepper.get_resolution().as_millimeters()),
+        ];
+        let second = SimBed::new(recorder.clone(), axes, |_, _| 0.0);
+        let (b1, b2) = (bed.clone(), second.clone());
+        let probe = SimInputPin::from_fn(move || {
+            let [x, _, z1] = b1.position();
+            let z2 = b2.position()[2] + d;
+            z1 + (z2 - z1) * (x - 10.0) / 20.0 <= 0.0
+        });
+        p.set_probe(Some(SwitchProbe::new(probe)));
+        (p, bed, second, recorder)
+    }
+
+    #[test]
+    fn test_planner_z_alignment() {
+        block_on(async {
+            let (mut p, first, second, _) = aligned_planner(0.4);
+            let resolution = p.z_stepper.get_resolution().as_millimeters() + 1e-9;
+            let alignment = p.align_z(None, None).await.unwrap();
+            // the first motor is raised by 0.4mm at once
+            assert_eq!(alignment.iterations, 1);
+            assert!(alignment.deviation.as_millimeters() < 0.02);
+            assert_abs_diff_eq!(
+                p.z_stepper.get_motor_offset(0).as_millimeters(),
+                0.4,
+                epsilon = resolution
+            );
+            assert_abs_diff_eq!(
+                first.position()[2],
+                second.position()[2] + 0.4,
+                epsilon = resolution
+            );
+            // the probe is left at the clearance
+            assert_abs_diff_eq!(p.get_z_position().as_millimeters(), 2.0, epsilon = resolution);
+            // once aligned nothing moves
+            let alignment = p.align_z(None, None).await.unwrap();
+            assert_eq!(alignment.iterations, 0);
+            let duration = p.execute(GCommand::G34 { i: None, t: None }).await.unwrap();
+            assert!(duration.unwrap() > Duration::ZERO);
+        });
+    }
+
+    #[test]
+    fn test_planner_z_alignment_errors() {
+        block_on(async {
+            // a single motor
+            let (mut p, _, _) = leveled_planner(|_, _| 0.0);
+            assert_eq!(p.align_z(None, None).await, Err(StepperError::NotSupported));
+            let (mut p, _, _, _) = aligned_planner(0.4);
+            assert_eq!(p.align_z(Some(0), None).await, Err(StepperError::MoveNotValid));
+            // the heights can't get closer than the accuracy
+            assert_eq!(
+                p.align_z(Some(1), distance(0.0)).await,
+                Err(StepperError::AlignmentFailed)
+            );
+            // a point for each motor
+            p.config.alignment.points.pop();
+            assert_eq!(p.align_z(None, None).await, Err(StepperError::NotSupported));
+        });
+    }
+
+    #[test]
+    fn test_planner_z_motors_homing() {
+        block_on(async {
+            let (mut p, first, second, recorder) = aligned_planner(0.4);
+            p.z_stepper.set_options(StepperOptions {
+                bounds: Some((
+                    Distance::from_millimeters(-10.0),
+                    Distance::from_millimeters(10.0),
+                )),
+                ..p.z_stepper.get_options()
+            });
+            // the endstops are 1mm over the first motor
+            let b1 = first.clone();
+            p.endstops.2 = Some(SimInputPin::from_fn(move || b1.position()[2] >= 1.0));
+            let b2 = second.clone();
+            p.motor_endstops[2][0] = Some(SimInputPin::from_fn(move || b2.position()[2] + 0.4 >= 1.0));
+            p.execute(GCommand::G28 {
+                x: false,
+                y: false,
+                z: true,
+            })
+            .await
+            .unwrap();
+            let resolution = p.z_stepper.get_resolution().as_millimeters() + 1e-9;
+            // each motor stopped on its own endstop
+            assert_abs_diff_eq!(first.position()[2], 1.0, epsilon = resolution);
+            assert_abs_diff_eq!(second.position()[2] + 0.4, 1.0, epsilon = resolution);
+            assert!(recorder.rising_edges("z_step").len() > recorder.rising_edges("z2_step").len());
+            assert_eq!(p.get_z_position(), Distance::from_millimeters(10.0));
+            assert_eq!(
+                p.add_motor(Axis::E, SimOutputPin::new(), SimOutputPin::new(), None),
+                Err(StepperError::NotSupported)
+            );
+        });
+    }
+
+    // the test planner with its steppers at 1/16, a step is a microstep
+    fn microstepping_planner() -> PlannerMock {
+        let mut p = planner();
+        for stepper in [&mut p.x_stepper, &mut p.y_stepper, &mut p.z_stepper, &mut p.e_stepper] {
+            stepper.set_options(StepperOptions {
+                stepping_mode: SteppingMode::SixteenthStep,
+                ..stepper.get_options()
+            });
+        }
+        p
+    }
+
+    // half a microstep of a stepper moving the distance per full step
+    fn half_microstep(distance_per_step: f64) -> f64 {
+        distance_per_step / MICROSTEPS_PER_STEP as f64 / 2.0
+    }
+
+    #[test]
+    fn test_planner_carries_remainder() {
+        block_on(async {
+            let mut p = microstepping_planner();
+            p.execute(GCommand::G91).await.unwrap();
+            // each move is 9.6 microsteps on X, the stepper must not get stuck on the same
+            // microstep nor drift away
+            for n in 1..=10 {
+                let res = p
+                    .execute(GCommand::G1 {
+                        x: distance(0.12),
+                        y: None,
+                        z: None,
+                        e: None,
+                        f: None,
+                    })
+                    .await;
+                assert!(res.is_ok());
+                let error = abs(p.get_x_position().as_millimeters() - 0.12 * n as f64);
+                assert!(error <= half_microstep(0.2) + 1e-9);
+            }
+        });
+    }
+
+    #[test]
+    fn test_planner_resonance() {
+        use crate::resonance::{measure_resonance, ResonanceConfig, ResonanceError};
+        use accelerometer::adxl345::{Adxl345, Adxl345Config, Adxl345Error};
+        use core::f64::consts::PI;
+
+        let (mut p, _, recorder) = leveled_planner(|_, _| 0.0);
+        block_on(p.execute(g1_z(50.0, 50.0, 10.0))).unwrap();
+        // the toolhead is a mass on a spring at 40Hz, carried by Y
+        let (omega, damping) = (2.0 * PI * 40.0, 0.1);
+        let events = recorder.clone();
+        let (mut cursor, mut forward, mut base) = (0, false, 0.0);
+        let mut pending: std::collections::VecDeque<sim::PinEvent> = Default::default();
+        let mut toolhead: Option<(f64, f64, Duration)> = None;
+        let mut spi = sim::adxl345(move |t| {
+            let new = events.events_from(cursor);
+            cursor += new.len();
+            pending.extend(new.into_iter().filter(|e| e.pin.starts_with("y_")));
+            let (mut y, mut v, mut last) = toolhead.unwrap_or((0.0, 0.0, t));
+            let h = Duration::from_micros(10);
+            while last < t {
+                last = (last + h).min(t);
+                while pending.front().is_some_and(|e| e.time <= last) {
+                    let e = pending.pop_front().unwrap();
+                    if e.pin == "y_dir" {
+                        forward = e.high;
+                    } else if e.high {
+                        base += if forward { 0.025 } else { -0.025 };
+                    }
+                }
+                let a = omega * omega * (base - y) - 2.0 * damping * omega * v;
+                v += a * h.as_secs_f64();
+                y += v * h.as_secs_f64();
+            }
+            toolhead = Some((y, v, last));
+            let a = omega * omega * (base - y) - 2.0 * damping * omega * v;
+            [0.0, a / 1000.0, 9.80665]
+        });
+        let mut adxl = Adxl345::new(Adxl345Config::default());
+        let config = ResonanceConfig {
+            min_frequency: 20.0,
+            max_frequency: 60.0,
+            frequency_step: 2.0,
+            acceleration: 20000.0,
+            hold: Duration::from_millis(250),
+            segments: 8,
+        };
+        let resonance = block_on(measure_resonance(&mut p, &mut adxl, &mut spi, Axis::Y, &config)).unwrap();
+        assert_eq!(resonance.psd.len(), 21);
+        assert_abs_diff_eq!(resonance.frequency, 40.0, epsilon = 1.0);
+        assert_abs_diff_eq!(resonance.damping, 0.1, epsilon = 0.03);
+        assert_abs_diff_eq!(resonance.shaper.frequency, resonance.frequency, epsilon = 1e-9);
+        // the axis is back where it started, the accelerometer in standby
+        assert_abs_diff_eq!(p.get_y_position().as_millimeters(), 50.0, epsilon = 1e-6);
+        assert_eq!(spi.written().last(), Some(&vec![0x2D, 0x00]));
+
+        assert_eq!(
+            block_on(measure_resonance(&mut p, &mut adxl, &mut spi, Axis::Z, &config)).err(),
+            Some(ResonanceError::Stepper(StepperError::NotSupported))
+        );
+        // without the accelerometer nothing moves
+        let y = p.get_y_position();
+        let mut spi = sim::SimSpi::new();
+        spi.queue_response(&[0xFF, 0xFF]);
+        assert_eq!(
+            block_on(measure_resonance(&mut p, &mut adxl, &mut spi, Axis::Y, &config)).err(),
+            Some(ResonanceError::Accelerometer(Adxl345Error::InvalidDevice))
+        );
+        assert_eq!(p.get_y_position(), y);
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        // every axis ends within half a microstep from the target, whatever the moves before
+        #[test]
+        fn prop_planner_position_within_half_microstep(
+            moves in proptest::collection::vec(
+                (any::<bool>(), -20.0f64..20.0, -20.0f64..20.0, -5.0f64..5.0, -5.0f64..5.0),
+                1..30,
+            )
+        ) {
+            let mut p = microstepping_planner();
+            let mut expected = (0.0, 0.0, 0.0, 0.0);
+            for (relative, x, y, z, e) in moves {
+                let positioning = if relative { GCommand::G91 } else { GCommand::G90 };
+                block_on(p.execute(positioning)).unwrap();
+                let res = block_on(p.execute(GCommand::G1 {
+                    x: distance(x),
+                    y: distance(y),
+                    z: distance(z),
+                    e: distance(e),
+                    f: None,
+                }));
+                prop_assert!(res.is_ok());
+                expected = if relative {
+                    (expected.0 + x, expected.1 + y, expected.2 + z, expected.3 + e)
+                } else {
+                    (x, y, z, expected.3 + e)
+                };
+                // the distances per step of the test planner
+                for (actual, expected, distance_per_step) in [
+                    (p.get_x_position(), expected.0, 0.2),
+                    (p.get_y_position(), expected.1, 0.2),
+                    (p.get_z_position(), expected.2, 0.04),
+                    (p.get_e_position(), expected.3, 0.1),
+                ] {
+                    let error = abs(actual.as_millimeters() - expected);
+                    prop_assert!(error <= half_microstep(distance_per_step) + 1e-9);
+                }
+            }
+        }
+    }
 }
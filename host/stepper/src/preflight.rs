@@ -0,0 +1,1193 @@
+use core::fmt::Display;
+
+use math::bezier::CubicBezier;
+use math::common::{abs, precise_sqrt, RotationDirection};
+use math::measurements::{Distance, Speed, Temperature};
+use math::vector::Vector2D;
+use math::{Axis, DistanceUnit};
+use parser::gcode::{is_blank, GCodeParser, GCommand};
+
+use crate::motion::arc::{Arc, Plane};
+use crate::motion::{HomingConfig, HomingDirection, Positioning};
+use crate::planner::{ExtrusionMotionConfig, LevelingMotionConfig, MotionConfig, ProbeMotionConfig};
+use crate::stepper::MAX_MOTORS;
+use crate::workspace::Workspaces;
+
+// the feedrates are checked against the max feedrates of the axes of the motion config (M203)
+#[derive(Clone, Copy)]
+pub struct PreflightLimits {
+    // extruding while the target of the hotend is below this is a cold extrusion
+    pub min_extrusion_temperature: Temperature,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PreflightError {
+    InvalidCommand,
+    NotSupported,
+    NotHomed(Axis),
+    // G-code position the axis would reach
+    OutOfBounds(Axis, Distance),
+    FeedrateTooHigh(Speed),
+    // target of the hotend when the extrusion happens
+    ColdExtrusion(Temperature),
+    MoveNotValid,
+}
+
+impl Display for PreflightError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match &self {
+            PreflightError::InvalidCommand => core::write!(f, "Invalid command"),
+            PreflightError::NotSupported => core::write!(f, "Command not supported"),
+            PreflightError::NotHomed(axis) => core::write!(f, "Axis {} not homed", axis),
+            PreflightError::OutOfBounds(axis, position) => core::write!(
+                f,
+                "Axis {} out of bounds: {:.3}mm",
+                axis,
+                position.as_millimeters()
+            ),
+            PreflightError::FeedrateTooHigh(speed) => core::write!(
+                f,
+                "Feedrate too high: {:.0}mm/min",
+                speed.as_meters_per_second() * 1000.0 * 60.0
+            ),
+            PreflightError::ColdExtrusion(target) => {
+                core::write!(
+                    f,
+                    "Cold extrusion: hotend set to {:.0}°C",
+                    target.as_celsius()
+                )
+            }
+            PreflightError::MoveNotValid => core::write!(f, "Move not valid"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct PreflightIssue {
+    // starting from 1
+    pub line: usize,
+    pub error: PreflightError,
+}
+
+impl Display for PreflightIssue {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::write!(f, "line {}: {}", self.line, self.error)
+    }
+}
+
+// the state of the printer the commands of a program depend on
+#[derive(Clone, Copy)]
+struct PreflightState {
+    positioning: Positioning,
+    e_positioning: Positioning,
+    feedrate: Speed,
+    feedrate_multiplier: f64,
+    // max feedrates of X, Y, Z and E
+    max_feedrate: [Speed; 4],
+    extrusion: ExtrusionMotionConfig,
+    // home offsets of x, y and z
+    offset: [Distance; 3],
+    // offsets of the coordinate systems, on top of the home offsets
+    workspaces: Workspaces,
+    // shift set by G92, on top of the coordinate system in use
+    g92_offset: [Distance; 3],
+    // position of x, y and z in machine coordinates
+    position: [Distance; 3],
+    e: Distance,
+    homed: [bool; 3],
+    software_endstops: bool,
+    hotend: Temperature,
+    retraction_length: Distance,
+    retraction_z_lift: Distance,
+    recover_length: Distance,
+    plane: Plane,
+    // position of the probe from the nozzle on x and y
+    probe_offset: [Distance; 2],
+}
+
+// checks a G-code program line by line against the configuration of the printer, before it's
+// printed. The positioning, the units, the home offsets and the temperature of the hotend are
+// followed the same way the firmware would, starting from a printer that has just been powered
+// on. A command that fails a check is still applied, so that a single mistake doesn't make the
+// rest of the program fail.
+pub struct Preflight {
+    limits: PreflightLimits,
+    // bounds of x, y and z in machine coordinates
+    bounds: [(Distance, Distance); 3],
+    // position of x, y and z once homed
+    home: [Distance; 3],
+    homing_required: bool,
+    // curves are checked along their chords, as the planner moves them
+    tolerance: Distance,
+    // area probed by G29
+    leveling: LevelingMotionConfig,
+    probe: ProbeMotionConfig,
+    // points probed by G34
+    alignment: heapless::Vec<(Distance, Distance), MAX_MOTORS>,
+    initial: PreflightState,
+    state: PreflightState,
+    parser: GCodeParser,
+    line: usize,
+}
+
+// position where the homing leaves the axis, that is the bound on the homing side moved back
+// by the backoff
+fn home_position(bounds: (Distance, Distance), config: &HomingConfig) -> Distance {
+    match config.direction {
+        HomingDirection::Min => bounds.0 + config.backoff,
+        HomingDirection::Max => bounds.1 - config.backoff,
+    }
+}
+
+impl Preflight {
+    pub fn new(
+        limits: PreflightLimits,
+        motion: &MotionConfig,
+        bounds: [(Distance, Distance); 3],
+    ) -> Self {
+        let homing = &motion.homing;
+        let zero = Distance::from_millimeters(0.0);
+        let max_feedrate = motion.limits.max_feedrate;
+        let initial = PreflightState {
+            positioning: motion.positioning,
+            e_positioning: motion.e_positioning,
+            feedrate: motion.feedrate,
+            feedrate_multiplier: motion.feedrate_multiplier,
+            max_feedrate: [max_feedrate.0, max_feedrate.1, max_feedrate.2, max_feedrate.3],
+            extrusion: motion.extrusion,
+            offset: [homing.offset.0, homing.offset.1, homing.offset.2],
+            workspaces: Workspaces::default(),
+            g92_offset: [zero; 3],
+            position: [zero; 3],
+            e: zero,
+            homed: [false; 3],
+            software_endstops: motion.software_endstops.enabled,
+            hotend: Temperature::from_celsius(0.0),
+            retraction_length: motion.retraction.length,
+            retraction_z_lift: motion.retraction.z_lift,
+            recover_length: motion.recover.length,
+            plane: Plane::XY,
+            probe_offset: [motion.probe.offset.0, motion.probe.offset.1],
+        };
+        Self {
+            limits,
+            bounds,
+            home: [
+                home_position(bounds[0], &homing.axes.0),
+                home_position(bounds[1], &homing.axes.1),
+                home_position(bounds[2], &homing.axes.2),
+            ],
+            homing_required: homing.required,
+            tolerance: motion.arcs.tolerance,
+            leveling: motion.leveling,
+            probe: motion.probe,
+            alignment: motion.alignment.points.clone(),
+            initial,
+            state: initial,
+            parser: GCodeParser::new(),
+            line: 0,
+        }
+    }
+
+    // get ready to check another program
+    pub fn reset(&mut self) {
+        self.state = self.initial;
+        self.parser = GCodeParser::new();
+        self.line = 0;
+    }
+
+    // number of lines checked so far
+    pub fn get_line(&self) -> usize {
+        self.line
+    }
+
+    // check the next line of the program
+    pub fn check(&mut self, line: &str) -> Result<(), PreflightIssue> {
+        self.line += 1;
+        self.check_line(line).map_err(|error| PreflightIssue {
+            line: self.line,
+            error,
+        })
+    }
+
+    fn check_line(&mut self, line: &str) -> Result<(), PreflightError> {
+        let command = match self.parser.parse(line) {
+            Some(command) => command,
+            None if is_blank(line) => return Ok(()),
+            None => return Err(PreflightError::InvalidCommand),
+        };
+        match command {
+            GCommand::G20 => self.parser.set_distance_unit(DistanceUnit::Inch),
+            GCommand::G21 => self.parser.set_distance_unit(DistanceUnit::Millimeter),
+            GCommand::M149 { u } => self.parser.set_temperature_unit(u),
+            GCommand::G0 { x, y, z, f } => return self.linear_move([x, y, z], None, f),
+            GCommand::G1 { x, y, z, e, f } => return self.linear_move([x, y, z], e, f),
+            GCommand::G53 { x, y, z, f } => return self.machine_move([x, y, z], f),
+            GCommand::G2 {
+                x,
+                y,
+                z,
+                e,
+                f,
+                i,
+                j,
+                k,
+                r,
+                p,
+            } => {
+                return self.arc_move(
+                    [x, y, z],
+                    e,
+                    f,
+                    ([i, j, k], r, p),
+                    RotationDirection::Clockwise,
+                )
+            }
+            GCommand::G3 {
+                x,
+                y,
+                z,
+                e,
+                f,
+                i,
+                j,
+                k,
+                r,
+                p,
+            } => {
+                return self.arc_move(
+                    [x, y, z],
+                    e,
+                    f,
+                    ([i, j, k], r, p),
+                    RotationDirection::CounterClockwise,
+                )
+            }
+            GCommand::G5 {
+                x,
+                y,
+                e,
+                f,
+                i,
+                j,
+                p,
+                q,
+            } => return self.curve_move([x, y], e, f, [i, j, p, q]),
+            GCommand::G17 => self.state.plane = Plane::XY,
+            GCommand::G18 => self.state.plane = Plane::ZX,
+            GCommand::G19 => self.state.plane = Plane::YZ,
+            GCommand::G10 => {
+                self.state.e = self.state.e - self.state.retraction_length;
+                self.state.position[2] = self.state.position[2] + self.state.retraction_z_lift;
+                return self.check_bounds();
+            }
+            GCommand::G11 => {
+                self.state.e = self.state.e + self.state.recover_length;
+                return self.check_extrusion(self.state.recover_length);
+            }
+            GCommand::G28 { x, y, z } => {
+                for (i, enabled) in [x, y, z].into_iter().enumerate() {
+                    if enabled {
+                        self.state.position[i] = self.home[i];
+                        self.state.homed[i] = true;
+                    }
+                }
+            }
+            GCommand::G29 { t: false } => return self.probe_mesh(),
+            GCommand::G30 { x, y } | GCommand::M48 { x, y, .. } => return self.probe_point(x, y),
+            GCommand::G34 { .. } => return self.align_z(),
+            GCommand::G90 => self.state.positioning = Positioning::Absolute,
+            GCommand::G91 => self.state.positioning = Positioning::Relative,
+            // the head stays where it is, as in the planner
+            GCommand::G92 { x, y, z, e } => {
+                let workspace = self.state.workspaces.offset();
+                for (i, value) in [x, y, z].into_iter().enumerate() {
+                    if let Some(v) = value {
+                        self.state.g92_offset[i] =
+                            v - self.state.position[i] - self.state.offset[i] - workspace[i];
+                    }
+                }
+                if let Some(e) = e {
+                    self.state.e = e;
+                }
+            }
+            GCommand::G92_1 => self.state.g92_offset = [Distance::from_millimeters(0.0); 3],
+            GCommand::G10_L2 { p, x, y, z } => return self.set_workspace(p, [x, y, z], false),
+            GCommand::G10_L20 { p, x, y, z } => return self.set_workspace(p, [x, y, z], true),
+            GCommand::G54 => self.state.workspaces.select(0),
+            GCommand::G55 => self.state.workspaces.select(1),
+            GCommand::G56 => self.state.workspaces.select(2),
+            GCommand::G57 => self.state.workspaces.select(3),
+            GCommand::G58 => self.state.workspaces.select(4),
+            GCommand::G59 => self.state.workspaces.select(5),
+            GCommand::M82 => self.state.e_positioning = Positioning::Absolute,
+            GCommand::M83 => self.state.e_positioning = Positioning::Relative,
+            GCommand::M104 { s } | GCommand::M109 { s } => self.state.hotend = s,
+            GCommand::M206 { x, y, z } => {
+                for (i, value) in [x, y, z].into_iter().enumerate() {
+                    if let Some(v) = value {
+                        self.state.offset[i] = v;
+                    }
+                }
+            }
+            GCommand::M207 { s, z, .. } => {
+                self.state.retraction_length = s;
+                self.state.retraction_z_lift = z;
+            }
+            GCommand::M208 { s, .. } => {
+                self.state.recover_length = s + self.state.retraction_length;
+            }
+            GCommand::M211 { s } => {
+                if let Some(enabled) = s {
+                    self.state.software_endstops = enabled;
+                }
+            }
+            GCommand::M220 { s } => self.state.feedrate_multiplier = s,
+            // the planner refuses the invalid values and keeps the previous ones
+            GCommand::M221 { s } => {
+                if s <= 0.0 {
+                    return Err(PreflightError::MoveNotValid);
+                }
+                self.state.extrusion.flow_multiplier = s;
+            }
+            GCommand::M203 { x, y, z, e } => {
+                let values = [x, y, z, e];
+                if values.into_iter().flatten().any(|s| s.as_meters_per_second() <= 0.0) {
+                    return Err(PreflightError::MoveNotValid);
+                }
+                for (i, value) in values.into_iter().enumerate() {
+                    if let Some(v) = value {
+                        self.state.max_feedrate[i] = v;
+                    }
+                }
+            }
+            GCommand::M200 { d } => match d {
+                Some(d) if d.as_millimeters() < 0.0 => return Err(PreflightError::MoveNotValid),
+                Some(d) if d.as_millimeters() == 0.0 => self.state.extrusion.filament_diameter = None,
+                Some(d) => self.state.extrusion.filament_diameter = Some(d),
+                None => (),
+            },
+            GCommand::M851 { x, y, .. } => {
+                for (i, value) in [x, y].into_iter().enumerate() {
+                    if let Some(v) = value {
+                        self.state.probe_offset[i] = v;
+                    }
+                }
+            }
+            // the other commands handled by the firmware
+            GCommand::G4 { .. }
+            | GCommand::M20
+            | GCommand::M21
+            | GCommand::M22
+            | GCommand::M23 { .. }
+            | GCommand::M24 { .. }
+            | GCommand::M25
+            | GCommand::M31
+            | GCommand::M105
+            | GCommand::M106 { .. }
+            | GCommand::M107
+            | GCommand::M114
+            | GCommand::M122
+            | GCommand::M140 { .. }
+            | GCommand::M155 { .. }
+            | GCommand::M190 { .. }
+            | GCommand::G29 { t: true }
+            | GCommand::M420 { .. }
+            | GCommand::M425 { .. }
+            | GCommand::M201 { .. }
+            | GCommand::M204 { .. }
+            | GCommand::M205 { .. }
+            | GCommand::M503
+            | GCommand::M524
+            | GCommand::M593 { .. }
+            | GCommand::M900 { .. }
+            | GCommand::M852 { .. }
+            | GCommand::M569 { .. }
+            | GCommand::M906 { .. }
+            | GCommand::M913 { .. }
+            | GCommand::M914 { .. } => (),
+            _ => return Err(PreflightError::NotSupported),
+        }
+        Ok(())
+    }
+
+    fn check_homed(&self, axes: [bool; 3]) -> Result<(), PreflightError> {
+        if !self.homing_required {
+            return Ok(());
+        }
+        for (i, axis) in [Axis::X, Axis::Y, Axis::Z].into_iter().enumerate() {
+            if axes[i] && !self.state.homed[i] {
+                return Err(PreflightError::NotHomed(axis));
+            }
+        }
+        Ok(())
+    }
+
+    // position of the nozzle that brings the probe over a point of the bed, at the clearance
+    fn probe_position(&self, x: Distance, y: Distance) -> [Distance; 3] {
+        let offset = self.state.probe_offset;
+        [x - offset[0], y - offset[1], self.probe.clearance]
+    }
+
+    // the probe goes over the corners of the mesh at the clearance, the rows are probed back and
+    // forth and it's left on the last point
+    fn probe_mesh(&mut self) -> Result<(), PreflightError> {
+        self.check_homed([true; 3])?;
+        let mesh = self.leveling;
+        let result = self
+            .check_point(self.probe_position(mesh.min.0, mesh.min.1))
+            .and_then(|_| self.check_point(self.probe_position(mesh.max.0, mesh.max.1)));
+        let x = if mesh.points.1 % 2 == 1 {
+            mesh.max.0
+        } else {
+            mesh.min.0
+        };
+        self.state.position = self.probe_position(x, mesh.max.1);
+        result
+    }
+
+    // G30 and M48 probe a point in G-code coordinates, where the probe is without it
+    fn probe_point(&mut self, x: Option<Distance>, y: Option<Distance>) -> Result<(), PreflightError> {
+        self.check_homed([true; 3])?;
+        let (offset, probe_offset) = (self.offset(), self.state.probe_offset);
+        let x = x.map_or(self.state.position[0] + probe_offset[0], |x| x - offset[0]);
+        let y = y.map_or(self.state.position[1] + probe_offset[1], |y| y - offset[1]);
+        self.state.position = self.probe_position(x, y);
+        self.check_bounds()
+    }
+
+    // G34 probes near each lead screw, the probe is left on the last point
+    fn align_z(&mut self) -> Result<(), PreflightError> {
+        self.check_homed([true; 3])?;
+        if self.alignment.is_empty() {
+            return Err(PreflightError::NotSupported);
+        }
+        let mut result = Ok(());
+        for (x, y) in self.alignment.clone() {
+            let point = self.probe_position(x, y);
+            result = result.and(self.check_point(point));
+            self.state.position = point;
+        }
+        result
+    }
+
+    fn check_bounds(&self) -> Result<(), PreflightError> {
+        self.check_point(self.state.position)
+    }
+
+    fn check_point(&self, point: [Distance; 3]) -> Result<(), PreflightError> {
+        if !self.state.software_endstops {
+            return Ok(());
+        }
+        for (i, axis) in [Axis::X, Axis::Y, Axis::Z].into_iter().enumerate() {
+            let (min, max) = self.bounds[i];
+            if point[i] < min || point[i] > max {
+                return Err(PreflightError::OutOfBounds(
+                    axis,
+                    point[i] + self.offset()[i],
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // shares is the largest part of the feedrate each of X, Y, Z and E takes along the move, no
+    // axis may go faster than its max feedrate
+    fn check_feedrate(&self, shares: [f64; 4]) -> Result<(), PreflightError> {
+        let feedrate = self.state.feedrate * self.state.feedrate_multiplier;
+        for (share, max) in shares.into_iter().zip(self.state.max_feedrate) {
+            let axis_feedrate = feedrate * share;
+            if axis_feedrate > max {
+                return Err(PreflightError::FeedrateTooHigh(axis_feedrate));
+            }
+        }
+        Ok(())
+    }
+
+    // share of the feedrate taken by E, when the head covers length. The extruder covers e scaled
+    // by the extrusion, alone it goes at the feedrate
+    fn e_share(&self, e: Distance, length: f64) -> f64 {
+        let e = abs(e.as_millimeters()) * self.state.extrusion.factor();
+        if length > 0.0 {
+            e / length
+        } else if e > 0.0 {
+            1.0
+        } else {
+            0.0
+        }
+    }
+
+    fn check_extrusion(&self, e: Distance) -> Result<(), PreflightError> {
+        if e.as_millimeters() > 0.0 && self.state.hotend < self.limits.min_extrusion_temperature {
+            return Err(PreflightError::ColdExtrusion(self.state.hotend));
+        }
+        Ok(())
+    }
+
+    // offsets from machine to G-code coordinates, as in the planner
+    fn offset(&self) -> [Distance; 3] {
+        let workspace = self.state.workspaces.offset();
+        [0, 1, 2].map(|i| self.state.offset[i] + workspace[i] + self.state.g92_offset[i])
+    }
+
+    // the offsets of a coordinate system set by G10 L2 and L20, as in the planner
+    fn set_workspace(
+        &mut self,
+        p: Option<u8>,
+        values: [Option<Distance>; 3],
+        current: bool,
+    ) -> Result<(), PreflightError> {
+        let workspaces = &mut self.state.workspaces;
+        let index = workspaces.index(p).ok_or(PreflightError::MoveNotValid)?;
+        let mut offset = workspaces.get_offset(index).ok_or(PreflightError::MoveNotValid)?;
+        for (i, value) in values.into_iter().enumerate() {
+            if let Some(v) = value {
+                offset[i] = match current {
+                    true => {
+                        v - self.state.position[i] - self.state.offset[i] - self.state.g92_offset[i]
+                    }
+                    false => Distance::from_millimeters(-v.as_millimeters()),
+                };
+            }
+        }
+        self.state.workspaces.set_offset(index, offset);
+        Ok(())
+    }
+
+    // absolute destination of an axis in machine coordinates, as the planner computes it
+    fn target(&self, value: Option<Distance>, i: usize, positioning: Positioning) -> Distance {
+        match (value, positioning) {
+            (None, _) => self.state.position[i],
+            (Some(v), Positioning::Absolute) => v - self.offset()[i],
+            (Some(v), Positioning::Relative) => self.state.position[i] + v,
+        }
+    }
+
+    fn e_target(&self, value: Option<Distance>) -> Distance {
+        match (value, self.state.e_positioning) {
+            (None, _) => self.state.e,
+            (Some(v), Positioning::Absolute) => v,
+            (Some(v), Positioning::Relative) => self.state.e + v,
+        }
+    }
+
+    fn linear_move(
+        &mut self,
+        destination: [Option<Distance>; 3],
+        e: Option<Distance>,
+        f: Option<Speed>,
+    ) -> Result<(), PreflightError> {
+        self.check_homed(destination.map(|v| v.is_some()))?;
+        let positioning = self.state.positioning;
+        let target = [
+            self.target(destination[0], 0, positioning),
+            self.target(destination[1], 1, positioning),
+            self.target(destination[2], 2, positioning),
+        ];
+        self.move_to(target, e, f)
+    }
+
+    // G53, the destination is in machine coordinates
+    fn machine_move(
+        &mut self,
+        destination: [Option<Distance>; 3],
+        f: Option<Speed>,
+    ) -> Result<(), PreflightError> {
+        self.check_homed(destination.map(|v| v.is_some()))?;
+        let target = [0, 1, 2].map(|i| destination[i].unwrap_or(self.state.position[i]));
+        self.move_to(target, None, f)
+    }
+
+    fn move_to(
+        &mut self,
+        target: [Distance; 3],
+        e: Option<Distance>,
+        f: Option<Speed>,
+    ) -> Result<(), PreflightError> {
+        if let Some(f) = f {
+            self.state.feedrate = f;
+        }
+        let start = self.state.position;
+        let e_target = self.e_target(e);
+        let e = e_target - self.state.e;
+        self.state.position = target;
+        self.state.e = e_target;
+
+        let d = [0, 1, 2].map(|i| (target[i] - start[i]).as_millimeters());
+        let length = precise_sqrt(d[0] * d[0] + d[1] * d[1] + d[2] * d[2]);
+        let mut shares = [0.0, 0.0, 0.0, self.e_share(e, length)];
+        if length > 0.0 {
+            for i in 0..3 {
+                shares[i] = abs(d[i]) / length;
+            }
+        }
+        self.check_bounds()?;
+        self.check_feedrate(shares)?;
+        self.check_extrusion(e)
+    }
+
+    fn arc_move(
+        &mut self,
+        destination: [Option<Distance>; 3],
+        e: Option<Distance>,
+        f: Option<Speed>,
+        (center_offset, r, turns): ([Option<Distance>; 3], Option<Distance>, Option<u8>),
+        direction: RotationDirection,
+    ) -> Result<(), PreflightError> {
+        let [a, b, c] = self.state.plane.axes().map(|axis| match axis {
+            Axis::X => 0,
+            Axis::Y => 1,
+            _ => 2,
+        });
+        match (center_offset[a], center_offset[b], r) {
+            (Some(_), Some(_), Some(_))
+            | (None, None, None)
+            | (Some(_), None, Some(_))
+            | (None, Some(_), Some(_)) => return Err(PreflightError::MoveNotValid),
+            _ => (),
+        }
+        if r.is_some() && destination[a].is_none() && destination[b].is_none() {
+            return Err(PreflightError::MoveNotValid);
+        }
+        let mut homed = [true; 3];
+        homed[c] = destination[c].is_some();
+        self.check_homed(homed)?;
+        if let Some(f) = f {
+            self.state.feedrate = f;
+        }
+        // the planner takes the arc coordinates as absolute
+        let start = self.state.position;
+        let target = [
+            self.target(destination[0], 0, Positioning::Absolute),
+            self.target(destination[1], 1, Positioning::Absolute),
+            self.target(destination[2], 2, Positioning::Absolute),
+        ];
+        let e_target = self.e_target(e);
+        let e = e_target - self.state.e;
+        self.state.position = target;
+        self.state.e = e_target;
+
+        let (arc_start, arc_end) = (
+            Vector2D::new(start[a], start[b]),
+            Vector2D::new(target[a], target[b]),
+        );
+        let turns = turns.unwrap_or(0);
+        let arc = match r {
+            Some(r) => Arc::from_radius(arc_start, arc_end, r, direction, turns),
+            None => {
+                let zero = Distance::from_millimeters(0.0);
+                let offset = Vector2D::new(
+                    center_offset[a].unwrap_or(zero),
+                    center_offset[b].unwrap_or(zero),
+                );
+                Arc::from_offset(arc_start, arc_end, offset, direction, turns)
+            }
+        }
+        .map_err(|_| PreflightError::MoveNotValid)?;
+        let (min, max) = arc.bounding_box();
+        let (mut low, mut high) = (target, target);
+        (low[a], high[a]) = (min.get_x(), max.get_x());
+        (low[b], high[b]) = (min.get_y(), max.get_y());
+        self.check_point(low)?;
+        self.check_point(high)?;
+
+        // along the chords within the tolerance, the linear axis moves at the same pace
+        let length = arc.length().as_millimeters();
+        let linear = (target[c] - start[c]).as_millimeters();
+        let total = precise_sqrt(length * length + linear * linear);
+        let mut shares = [0.0, 0.0, 0.0, self.e_share(e, total)];
+        if total > 0.0 {
+            let chords = arc.segments(self.tolerance, Distance::from_millimeters(0.0), arc.length());
+            let sweep = arc.get_sweep();
+            let mut previous = arc_start;
+            for n in 1..=chords {
+                let point = arc.point_at(sweep * n as f64 / chords as f64);
+                let da = (point.get_x() - previous.get_x()).as_millimeters();
+                let db = (point.get_y() - previous.get_y()).as_millimeters();
+                let dc = linear / chords as f64;
+                let chord = precise_sqrt(da * da + db * db + dc * dc);
+                if chord > 0.0 {
+                    shares[a] = shares[a].max(abs(da) / chord);
+                    shares[b] = shares[b].max(abs(db) / chord);
+                    shares[c] = shares[c].max(abs(dc) / chord);
+                }
+                previous = point;
+            }
+        }
+        self.check_bounds()?;
+        self.check_feedrate(shares)?;
+        self.check_extrusion(e)
+    }
+
+    fn curve_move(
+        &mut self,
+        destination: [Option<Distance>; 2],
+        e: Option<Distance>,
+        f: Option<Speed>,
+        offsets: [Option<Distance>; 4],
+    ) -> Result<(), PreflightError> {
+        self.check_homed([true, true, false])?;
+        if let Some(f) = f {
+            self.state.feedrate = f;
+        }
+        let positioning = self.state.positioning;
+        let start = self.state.position;
+        let mut target = start;
+        target[0] = self.target(destination[0], 0, positioning);
+        target[1] = self.target(destination[1], 1, positioning);
+        let e_target = self.e_target(e);
+        let e = e_target - self.state.e;
+        self.state.position = target;
+        self.state.e = e_target;
+
+        let zero = Distance::from_millimeters(0.0);
+        let [i, j, p, q] = offsets.map(|v| v.unwrap_or(zero));
+        let (curve_start, curve_end) = (
+            Vector2D::new(start[0], start[1]),
+            Vector2D::new(target[0], target[1]),
+        );
+        let curve = CubicBezier::new(
+            curve_start,
+            curve_start + Vector2D::new(i, j),
+            curve_end + Vector2D::new(p, q),
+            curve_end,
+        );
+        let mut length = 0.0;
+        let mut shares: [f64; 4] = [0.0; 4];
+        let mut previous = curve_start;
+        for (_, point) in curve.flatten(self.tolerance) {
+            self.check_point([point.get_x(), point.get_y(), target[2]])?;
+            let (dx, dy) = (
+                (point.get_x() - previous.get_x()).as_millimeters(),
+                (point.get_y() - previous.get_y()).as_millimeters(),
+            );
+            let chord = precise_sqrt(dx * dx + dy * dy);
+            if chord > 0.0 {
+                shares[0] = shares[0].max(abs(dx) / chord);
+                shares[1] = shares[1].max(abs(dy) / chord);
+            }
+            length += chord;
+            previous = point;
+        }
+        shares[3] = self.e_share(e, length);
+        self.check_feedrate(shares)?;
+        self.check_extrusion(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::advance::LinearAdvance;
+    use crate::skew::Skew;
+    use crate::planner::{
+        AlignmentMotionConfig, ArcMotionConfig, BacklashMotionConfig, HomingMotionConfig,
+        InputShapingMotionConfig, LimitsMotionConfig, RecoverMotionConfig,
+        RetractionMotionConfig, SoftwareEndstopsConfig,
+    };
+    use core::time::Duration;
+    use math::measurements::{Acceleration, Length};
+
+    fn mm(value: f64) -> Distance {
+        Distance::from_millimeters(value)
+    }
+
+    fn preflight(required: bool) -> Preflight {
+        let speed = Speed::from_meters_per_second;
+        let acceleration = Acceleration::from_meters_per_second_per_second;
+        let homing = HomingConfig {
+            direction: HomingDirection::Min,
+            backoff: mm(2.0),
+            ..Default::default()
+        };
+        let motion = MotionConfig {
+            arcs: ArcMotionConfig {
+                tolerance: Length::from_millimeters(0.01),
+                min_segment_length: Length::from_millimeters(0.1),
+                max_segment_length: Length::from_millimeters(1.0),
+                min_segment_duration: Duration::from_millis(5),
+            },
+            feedrate: Speed::from_meters_per_second(0.05),
+            positioning: Positioning::Absolute,
+            e_positioning: Positioning::Absolute,
+            feedrate_multiplier: 1.0,
+            extrusion: ExtrusionMotionConfig::default(),
+            limits: LimitsMotionConfig {
+                // 6000mm/min for X, Y and Z and 3000mm/min for E
+                max_feedrate: (speed(0.1), speed(0.1), speed(0.1), speed(0.05)),
+                max_acceleration: (
+                    acceleration(10.0),
+                    acceleration(10.0),
+                    acceleration(10.0),
+                    acceleration(10.0),
+                ),
+                print_acceleration: acceleration(1.0),
+                retract_acceleration: acceleration(1.0),
+                travel_acceleration: acceleration(1.0),
+                jerk: (speed(0.01), speed(0.01), speed(0.01), speed(0.01)),
+            },
+            retraction: RetractionMotionConfig {
+                feedrate: Speed::from_meters_per_second(0.05),
+                length: Length::from_millimeters(1.0),
+                z_lift: Length::from_millimeters(0.5),
+            },
+            recover: RecoverMotionConfig {
+                feedrate: Speed::from_meters_per_second(0.05),
+                length: Length::from_millimeters(1.0),
+            },
+            homing: HomingMotionConfig {
+                axes: (homing, homing, homing),
+                order: [Axis::X, Axis::Y, Axis::Z],
+                z_lift: Length::from_millimeters(0.0),
+                required,
+                offset: (mm(0.0), mm(0.0), mm(0.0)),
+            },
+            software_endstops: SoftwareEndstopsConfig {
+                enabled: true,
+                clip: false,
+            },
+            leveling: LevelingMotionConfig {
+                points: (3, 3),
+                min: (mm(10.0), mm(10.0)),
+                max: (mm(190.0), mm(190.0)),
+                fade_height: Length::from_millimeters(0.0),
+            },
+            probe: ProbeMotionConfig {
+                offset: (mm(0.0), mm(0.0), mm(0.0)),
+                clearance: Length::from_millimeters(5.0),
+                max_depth: Length::from_millimeters(10.0),
+                feedrate: Speed::from_meters_per_second(0.005),
+                travel_feedrate: Speed::from_meters_per_second(0.05),
+                samples: 1,
+                retract: Length::from_millimeters(1.0),
+            },
+            alignment: AlignmentMotionConfig {
+                points: heapless::Vec::from_slice(&[
+                    (Distance::from_millimeters(10.0), Distance::from_millimeters(20.0)),
+                    (Distance::from_millimeters(30.0), Distance::from_millimeters(20.0)),
+                ])
+                .unwrap(),
+                iterations: 3,
+                accuracy: Length::from_millimeters(0.02),
+            },
+            backlash: BacklashMotionConfig {
+                distance: (
+                    Distance::from_millimeters(0.0),
+                    Distance::from_millimeters(0.0),
+                    Distance::from_millimeters(0.0),
+                ),
+                correction: 1.0,
+                smoothing: Length::from_millimeters(0.0),
+            },
+            skew: Skew::default(),
+            shaping: InputShapingMotionConfig::default(),
+            advance: LinearAdvance::default(),
+        };
+        let limits = PreflightLimits {
+            min_extrusion_temperature: Temperature::from_celsius(170.0),
+        };
+        Preflight::new(
+            limits,
+            &motion,
+            [
+                (mm(0.0), mm(200.0)),
+                (mm(0.0), mm(200.0)),
+                (mm(0.0), mm(100.0)),
+            ],
+        )
+    }
+
+    fn assert_out_of_bounds(error: Option<PreflightError>, axis: Axis, position: f64) {
+        match error {
+            Some(PreflightError::OutOfBounds(a, p)) => {
+                assert_eq!(a, axis);
+                assert!(abs(p.as_millimeters() - position) < 1e-9);
+            }
+            _ => panic!("{:?} is not out of bounds", error),
+        }
+    }
+
+    fn check_program(preflight: &mut Preflight, program: &str) -> [Option<PreflightError>; 16] {
+        let mut errors = [None; 16];
+        for line in program.lines() {
+            if let Err(issue) = preflight.check(line) {
+                errors[issue.line - 1] = Some(issue.error);
+            }
+        }
+        errors
+    }
+
+    #[test]
+    fn test_preflight_valid_program() {
+        let mut p = preflight(true);
+        let program = "G28 ; home\nM104 S200\nG1 Z0.2 F1200\nG1 X10 Y10 E1\n(comment)\n\nG91\nG1 X10 E1 F3000\nG10\nG11\nG4 S1\nM107";
+        let errors = check_program(&mut p, program);
+        assert_eq!(errors, [None; 16]);
+        assert_eq!(p.get_line(), 12);
+    }
+
+    #[test]
+    fn test_preflight_bounds() {
+        let mut p = preflight(false);
+        let program =
+            "G1 X-1\nG1 X10\nG91\nG1 Y150\nG1 Y60\nG90\nG92 X0\nG1 X-15\nG1 X0 Y0\nG20\nG1 Z5";
+        let errors = check_program(&mut p, program);
+        assert_out_of_bounds(errors[0], Axis::X, -1.0);
+        assert_eq!(errors[1], None);
+        assert_eq!(errors[3], None);
+        // relative moves add up
+        assert_out_of_bounds(errors[4], Axis::Y, 210.0);
+        // G92 doesn't move the bounds, X-15 is 5mm below them
+        assert_out_of_bounds(errors[7], Axis::X, -15.0);
+        // a move that fails is still applied
+        assert_eq!(errors[8], None);
+        // 5 inches
+        assert_out_of_bounds(errors[10], Axis::Z, 127.0);
+    }
+
+    #[test]
+    fn test_preflight_software_endstops() {
+        let mut p = preflight(false);
+        let errors = check_program(&mut p, "M211 S0\nG1 X-5\nM211\nG1 X-6\nM211 S1\nG1 X-7");
+        assert_eq!(errors[1], None);
+        assert_eq!(errors[3], None);
+        assert_out_of_bounds(errors[5], Axis::X, -7.0);
+    }
+
+    #[test]
+    fn test_preflight_workspaces() {
+        let mut p = preflight(false);
+        let program = "G1 X10\nG92 X0\nG1 X-10\nG55\nG1 X-11\nG10 L2 P2 X5\nG1 X-11\nG54\n\
+                       G53 X-1\nG92.1\nG1 X-1\nG1 X0\nG10 L20 P1 X5\nG1 X4\nG10 L2 P7 X0";
+        let errors = check_program(&mut p, program);
+        assert_eq!(errors[2], None);
+        // the shift of G92 stays in G55, until its origin is moved 5mm above
+        assert_out_of_bounds(errors[4], Axis::X, -11.0);
+        assert_eq!(errors[6], None);
+        assert_out_of_bounds(errors[8], Axis::X, -11.0);
+        assert_out_of_bounds(errors[10], Axis::X, -1.0);
+        // X at 0mm is at 5mm in G54
+        assert_out_of_bounds(errors[13], Axis::X, 4.0);
+        assert_eq!(errors[14], Some(PreflightError::MoveNotValid));
+        assert_eq!(errors.iter().flatten().count(), 5);
+    }
+
+    #[test]
+    fn test_preflight_home_offsets() {
+        let mut p = preflight(false);
+        let program = "M206 X-10\nG1 X-5\nG1 X-15";
+        let errors = check_program(&mut p, program);
+        assert_eq!(errors[1], None);
+        assert_out_of_bounds(errors[2], Axis::X, -15.0);
+    }
+
+    #[test]
+    fn test_preflight_arc_bounds() {
+        let mut p = preflight(false);
+        // half circle from (10, 5) to (30, 5) around (20, 5), counterclockwise it goes below
+        // Y 0, clockwise it doesn't
+        let program =
+            "G1 X10 Y5\nG3 X30 Y5 I10 J0\nG1 X10 Y5\nG2 X30 Y5 I10 J0\nG1 X10 Y5\nG2 X10 Y5 I0 J11";
+        let errors = check_program(&mut p, program);
+        assert_out_of_bounds(errors[1], Axis::Y, -5.0);
+        assert_eq!(errors[3], None);
+        // a full circle
+        assert_out_of_bounds(errors[5], Axis::X, -1.0);
+    }
+
+    #[test]
+    fn test_preflight_arc_radius_and_planes() {
+        let mut p = preflight(false);
+        // the longer arc from (20, 5) to (10, 5) goes around (15, -3.66)
+        let program = "G1 X20 Y5\nG2 X10 Y5 R-10\nG1 X20 Y50 Z5\nG18\nG2 K-10\nG17\nG2 X31 Y50 I5";
+        let errors = check_program(&mut p, program);
+        assert_out_of_bounds(errors[1], Axis::Y, 5.0 - 75.0_f64.sqrt() - 10.0);
+        assert_eq!(errors[2], None);
+        // a full circle on the ZX plane around Z -5
+        assert_out_of_bounds(errors[4], Axis::Z, -15.0);
+        // the end is off the circle
+        assert_eq!(errors[6], Some(PreflightError::MoveNotValid));
+    }
+
+    #[test]
+    fn test_preflight_bezier() {
+        let mut p = preflight(false);
+        // bulging up the curve stays within the bounds, bulging down it doesn't
+        let program = "G1 X10 Y5\nG5 X30 Y5 I0 J10 P0 Q10\nG5 X50 Y5 I0 J-10 P0 Q-10\nG5 X60 E1";
+        let errors = check_program(&mut p, program);
+        assert_eq!(errors[1], None);
+        assert!(matches!(
+            errors[2],
+            Some(PreflightError::OutOfBounds(Axis::Y, y)) if y.as_millimeters() < 0.0
+        ));
+        assert!(matches!(errors[3], Some(PreflightError::ColdExtrusion(_))));
+    }
+
+    #[test]
+    fn test_preflight_homing_required() {
+        let mut p = preflight(true);
+        let errors = check_program(&mut p, "G1 X10\nG28 X\nG1 X10\nG1 Y10");
+        assert_eq!(errors[0], Some(PreflightError::NotHomed(Axis::X)));
+        assert_eq!(errors[2], None);
+        assert_eq!(errors[3], Some(PreflightError::NotHomed(Axis::Y)));
+    }
+
+    #[test]
+    fn test_preflight_leveling() {
+        let mut p = preflight(true);
+        let errors = check_program(&mut p, "G29\nG28\nG29\nG29 T\nM420 S1 Z10\nG91\nG1 X15");
+        assert_eq!(errors[0], Some(PreflightError::NotHomed(Axis::X)));
+        assert_eq!(errors[2..5], [None; 3]);
+        // the probing ends on the last point of the mesh, at X190 Y190 after 3 rows
+        assert_out_of_bounds(errors[6], Axis::X, 205.0);
+    }
+
+    #[test]
+    fn test_preflight_probe() {
+        let mut p = preflight(true);
+        let program = "G30\nG28\nM851 X-20 Z-1\nG30 X10 Y10\nG30 X190 Y10\nM48 P5\nG29";
+        let errors = check_program(&mut p, program);
+        assert_eq!(errors[0], Some(PreflightError::NotHomed(Axis::X)));
+        // the nozzle goes 20mm on the right of the point
+        assert_eq!(errors[3], None);
+        assert_out_of_bounds(errors[4], Axis::X, 210.0);
+        // M48 probes where the probe is
+        assert_out_of_bounds(errors[5], Axis::X, 210.0);
+        assert_out_of_bounds(errors[6], Axis::X, 210.0);
+    }
+
+    #[test]
+    fn test_preflight_alignment() {
+        let mut p = preflight(true);
+        let errors = check_program(&mut p, "G34\nG28\nG34 I2\nM851 X20\nG34");
+        assert_eq!(errors[0], Some(PreflightError::NotHomed(Axis::X)));
+        assert_eq!(errors[2], None);
+        // the nozzle goes 20mm on the left of the first point
+        assert_out_of_bounds(errors[4], Axis::X, -10.0);
+        p.alignment.clear();
+        p.reset();
+        let errors = check_program(&mut p, "G28\nG34");
+        assert_eq!(errors[1], Some(PreflightError::NotSupported));
+    }
+
+    #[test]
+    fn test_preflight_feedrate() {
+        let mut p = preflight(false);
+        let program = "M104 S200\nG1 X10 F7000\nG1 X20 F6000\nM220 S2\nG1 X30\nM220 S1\nG1 E5 F4000\nG1 X40 E20 F3000\nG1 X50 E25";
+        let errors = check_program(&mut p, program);
+        assert!(matches!(
+            errors[1],
+            Some(PreflightError::FeedrateTooHigh(_))
+        ));
+        assert_eq!(errors[2], None);
+        // the feedrate multiplier counts
+        assert!(matches!(
+            errors[4],
+            Some(PreflightError::FeedrateTooHigh(_))
+        ));
+        // the extruder alone
+        assert!(matches!(
+            errors[6],
+            Some(PreflightError::FeedrateTooHigh(_))
+        ));
+        // 15mm of filament over 10mm at 3000mm/min
+        assert!(matches!(
+            errors[7],
+            Some(PreflightError::FeedrateTooHigh(_))
+        ));
+        assert_eq!(errors[8], None);
+    }
+
+    #[test]
+    fn test_preflight_feedrate_limits() {
+        let mut p = preflight(false);
+        let program = "G1 X10 Y10 F8000\nG1 Z10 F7000\nM203 Z50\nG1 Z20 F4000\nG1 Z30 F3000\nM203 X0\nG2 X30 Y10 I10 J0 F6500\nG3 X10 Y10 I-10 J0 F5000";
+        let errors = check_program(&mut p, program);
+        // about 5660mm/min along X and Y each
+        assert_eq!(errors[0], None);
+        assert!(matches!(
+            errors[1],
+            Some(PreflightError::FeedrateTooHigh(_))
+        ));
+        // 3000mm/min along Z from M203
+        assert!(matches!(
+            errors[3],
+            Some(PreflightError::FeedrateTooHigh(_))
+        ));
+        assert_eq!(errors[4], None);
+        assert_eq!(errors[5], Some(PreflightError::MoveNotValid));
+        // the top of the arc goes along X alone
+        assert!(matches!(
+            errors[6],
+            Some(PreflightError::FeedrateTooHigh(_))
+        ));
+        assert_eq!(errors[7], None);
+    }
+
+    #[test]
+    fn test_preflight_extrusion() {
+        let mut p = preflight(false);
+        let program = "M104 S200\nG1 X10 E10 F3000\nM221 S150\nG1 X20 E20\nM221 S0\nM200 D1.75\nG1 X30 E30\nM200 D0\nG1 X40 E40\nM200 D-1";
+        let errors = check_program(&mut p, program);
+        assert_eq!(errors[1], None);
+        // 15mm of filament over 10mm at 3000mm/min
+        assert!(matches!(
+            errors[3],
+            Some(PreflightError::FeedrateTooHigh(_))
+        ));
+        assert_eq!(errors[4], Some(PreflightError::MoveNotValid));
+        // 10mm³ are about 6.2mm of filament once multiplied by the flow
+        assert_eq!(errors[6], None);
+        assert!(matches!(
+            errors[8],
+            Some(PreflightError::FeedrateTooHigh(_))
+        ));
+        assert_eq!(errors[9], Some(PreflightError::MoveNotValid));
+    }
+
+    #[test]
+    fn test_preflight_cold_extrusion() {
+        let mut p = preflight(false);
+        let program =
+            "G1 X10 E1\nG1 X20 E0.5\nM104 S200\nG1 X30 E1\nM104 S0\nG11\nM83\nM109 S210\nG1 X40 E1";
+        let errors = check_program(&mut p, program);
+        assert_eq!(
+            errors[0],
+            Some(PreflightError::ColdExtrusion(Temperature::from_celsius(
+                0.0
+            )))
+        );
+        // retracting is fine
+        assert_eq!(errors[1], None);
+        assert_eq!(errors[3], None);
+        assert!(matches!(errors[5], Some(PreflightError::ColdExtrusion(_))));
+        assert_eq!(errors[8], None);
+    }
+
+    #[test]
+    fn test_preflight_commands() {
+        let mut p = preflight(false);
+        let errors = check_program(&mut p, "hello\nM154 S1\nM105\nG2 X10 I1 R1");
+        assert_eq!(errors[0], Some(PreflightError::InvalidCommand));
+        assert_eq!(errors[1], Some(PreflightError::NotSupported));
+        assert_eq!(errors[2], None);
+        assert_eq!(errors[3], Some(PreflightError::MoveNotValid));
+        p.reset();
+        assert_eq!(p.get_line(), 0);
+        assert_eq!(
+            p.check("G1 X-1"),
+            Err(PreflightIssue {
+                line: 1,
+                error: PreflightError::OutOfBounds(Axis::X, mm(-1.0))
+            })
+        );
+        let issue = PreflightIssue {
+            line: 3,
+            error: PreflightError::OutOfBounds(Axis::X, mm(-1.0)),
+        };
+        assert_eq!(
+            format!("{}", issue),
+            "line 3: Axis X out of bounds: -1.000mm"
+        );
+    }
+}
@@ -0,0 +1,542 @@
+use core::fmt::Display;
+use core::future::Future;
+use core::marker::PhantomData;
+use core::time::Duration;
+
+use common::{ExtiInputPinBase, ServoBase, TimerBase};
+use math::common::{abs, precise_sqrt};
+use math::measurements::Distance;
+
+use crate::stepper::StepperError;
+
+// input going high when the probe touches the bed. The probe is deployed before probing and
+// stowed once done, the probes that are always in place do nothing
+pub trait Probe: ExtiInputPinBase {
+    fn deploy(&mut self) -> impl Future<Output = Result<(), StepperError>>;
+    fn stow(&mut self) -> impl Future<Output = Result<(), StepperError>>;
+}
+
+// switch closed by the bed, e.g. a microswitch next to the nozzle
+pub struct SwitchProbe<I: ExtiInputPinBase> {
+    pin: I,
+}
+
+impl<I: ExtiInputPinBase> SwitchProbe<I> {
+    pub fn new(pin: I) -> Self {
+        Self { pin }
+    }
+}
+
+impl<I: ExtiInputPinBase> ExtiInputPinBase for SwitchProbe<I> {
+    fn is_high(&self) -> bool {
+        self.pin.is_high()
+    }
+
+    fn wait_for_high(&mut self) -> impl Future<Output = ()> {
+        self.pin.wait_for_high()
+    }
+
+    fn wait_for_low(&mut self) -> impl Future<Output = ()> {
+        self.pin.wait_for_low()
+    }
+}
+
+impl<I: ExtiInputPinBase> Probe for SwitchProbe<I> {
+    async fn deploy(&mut self) -> Result<(), StepperError> {
+        Ok(())
+    }
+
+    async fn stow(&mut self) -> Result<(), StepperError> {
+        Ok(())
+    }
+}
+
+// inductive sensor, it senses the bed a few mm above it, which the Z offset of the probe takes
+// into account. NPN sensors pull their output low when they sense the bed
+pub struct InductiveProbe<I: ExtiInputPinBase> {
+    pin: I,
+    active_low: bool,
+}
+
+impl<I: ExtiInputPinBase> InductiveProbe<I> {
+    pub fn new(pin: I, active_low: bool) -> Self {
+        Self { pin, active_low }
+    }
+}
+
+impl<I: ExtiInputPinBase> ExtiInputPinBase for InductiveProbe<I> {
+    fn is_high(&self) -> bool {
+        self.pin.is_high() != self.active_low
+    }
+
+    async fn wait_for_high(&mut self) {
+        if self.active_low {
+            self.pin.wait_for_low().await
+        } else {
+            self.pin.wait_for_high().await
+        }
+    }
+
+    async fn wait_for_low(&mut self) {
+        if self.active_low {
+            self.pin.wait_for_high().await
+        } else {
+            self.pin.wait_for_low().await
+        }
+    }
+}
+
+impl<I: ExtiInputPinBase> Probe for InductiveProbe<I> {
+    async fn deploy(&mut self) -> Result<(), StepperError> {
+        Ok(())
+    }
+
+    async fn stow(&mut self) -> Result<(), StepperError> {
+        Ok(())
+    }
+}
+
+// commands of a BLTouch, each one is an angle of its servo signal
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlTouchCommand {
+    Deploy,
+    // the output stays high until the pin is stowed, instead of a short pulse
+    SwMode,
+    Stow,
+    SelfTest,
+    // the output is stored in the BLTouch, a reset takes it back to open drain
+    FiveVoltMode,
+    OpenDrainMode,
+    AlarmRelease,
+}
+
+impl BlTouchCommand {
+    pub fn angle(&self) -> f64 {
+        match self {
+            BlTouchCommand::Deploy => 10.0,
+            BlTouchCommand::SwMode => 60.0,
+            BlTouchCommand::Stow => 90.0,
+            BlTouchCommand::SelfTest => 120.0,
+            BlTouchCommand::FiveVoltMode => 140.0,
+            BlTouchCommand::OpenDrainMode => 150.0,
+            BlTouchCommand::AlarmRelease => 160.0,
+        }
+    }
+
+    // the command of an angle given to the servo of the BLTouch, as M280 P0 S<angle>
+    pub fn from_angle(angle: f64) -> Option<Self> {
+        [
+            BlTouchCommand::Deploy,
+            BlTouchCommand::SwMode,
+            BlTouchCommand::Stow,
+            BlTouchCommand::SelfTest,
+            BlTouchCommand::FiveVoltMode,
+            BlTouchCommand::OpenDrainMode,
+            BlTouchCommand::AlarmRelease,
+        ]
+        .into_iter()
+        .find(|command| abs(command.angle() - angle) < 0.5)
+    }
+}
+
+// BLTouch: its pin is pushed out and pulled back by a servo signal, the bed pushes it up while
+// probing. The output is a short pulse when it triggers
+pub struct BlTouch<I: ExtiInputPinBase, S: ServoBase, T: TimerBase> {
+    pin: I,
+    servo: S,
+    // time the pin takes to move
+    delay: Duration,
+    _timer: PhantomData<T>,
+}
+
+impl<I: ExtiInputPinBase, S: ServoBase, T: TimerBase> BlTouch<I, S, T> {
+    pub fn new(pin: I, servo: S, delay: Duration) -> Self {
+        Self {
+            pin,
+            servo,
+            delay,
+            _timer: PhantomData,
+        }
+    }
+
+    pub async fn command(&mut self, command: BlTouchCommand) {
+        self.servo.set_angle(command.angle());
+        T::after(self.delay).await;
+    }
+
+    // the pin goes up and down 10 times, then it stays stowed
+    pub async fn self_test(&mut self) {
+        self.command(BlTouchCommand::SelfTest).await;
+        self.command(BlTouchCommand::Stow).await;
+    }
+
+    // the output blinks while the BLTouch is in alarm, releasing it leaves the pin stowed
+    pub async fn alarm_release(&mut self) {
+        self.command(BlTouchCommand::AlarmRelease).await;
+        self.command(BlTouchCommand::Stow).await;
+    }
+
+    // 5V output for boards without a pull-up on the probe pin, open drain otherwise
+    pub async fn set_5v_mode(&mut self, enabled: bool) {
+        let command = if enabled {
+            BlTouchCommand::FiveVoltMode
+        } else {
+            BlTouchCommand::OpenDrainMode
+        };
+        self.command(command).await;
+        self.command(BlTouchCommand::Stow).await;
+    }
+
+    // a command given through M280, the self-test, the alarm release and the change of mode
+    // leave the pin stowed
+    pub async fn execute(&mut self, command: BlTouchCommand) {
+        match command {
+            BlTouchCommand::SelfTest => self.self_test().await,
+            BlTouchCommand::AlarmRelease => self.alarm_release().await,
+            BlTouchCommand::FiveVoltMode => self.set_5v_mode(true).await,
+            BlTouchCommand::OpenDrainMode => self.set_5v_mode(false).await,
+            command => self.command(command).await,
+        }
+    }
+}
+
+impl<I: ExtiInputPinBase, S: ServoBase, T: TimerBase> ExtiInputPinBase for BlTouch<I, S, T> {
+    fn is_high(&self) -> bool {
+        self.pin.is_high()
+    }
+
+    fn wait_for_high(&mut self) -> impl Future<Output = ()> {
+        self.pin.wait_for_high()
+    }
+
+    fn wait_for_low(&mut self) -> impl Future<Output = ()> {
+        self.pin.wait_for_low()
+    }
+}
+
+impl<I: ExtiInputPinBase, S: ServoBase, T: TimerBase> Probe for BlTouch<I, S, T> {
+    // the output stays high while the BLTouch is in alarm, e.g. when the pin got stuck. The alarm
+    // is released once before giving up
+    async fn deploy(&mut self) -> Result<(), StepperError> {
+        self.command(BlTouchCommand::Deploy).await;
+        if !self.pin.is_high() {
+            return Ok(());
+        }
+        self.alarm_release().await;
+        self.command(BlTouchCommand::Deploy).await;
+        if self.pin.is_high() {
+            return Err(StepperError::ProbeAlarm);
+        }
+        Ok(())
+    }
+
+    async fn stow(&mut self) -> Result<(), StepperError> {
+        self.command(BlTouchCommand::Stow).await;
+        Ok(())
+    }
+}
+
+// kind of probe the printer is built with, chosen in its config
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProbeType {
+    Switch,
+    Inductive,
+    BlTouch,
+}
+
+impl From<&str> for ProbeType {
+    fn from(value: &str) -> Self {
+        match value {
+            "switch" => ProbeType::Switch,
+            "inductive" => ProbeType::Inductive,
+            "bltouch" => ProbeType::BlTouch,
+            _ => panic!("Invalid probe type"),
+        }
+    }
+}
+
+// any of the probes, for the printers that choose theirs at startup
+pub enum AnyProbe<I: ExtiInputPinBase, S: ServoBase, T: TimerBase> {
+    Switch(SwitchProbe<I>),
+    Inductive(InductiveProbe<I>),
+    BlTouch(BlTouch<I, S, T>),
+}
+
+impl<I: ExtiInputPinBase, S: ServoBase, T: TimerBase> AnyProbe<I, S, T> {
+    pub fn get_type(&self) -> ProbeType {
+        match self {
+            AnyProbe::Switch(_) => ProbeType::Switch,
+            AnyProbe::Inductive(_) => ProbeType::Inductive,
+            AnyProbe::BlTouch(_) => ProbeType::BlTouch,
+        }
+    }
+
+    pub fn as_bltouch(&mut self) -> Option<&mut BlTouch<I, S, T>> {
+        match self {
+            AnyProbe::BlTouch(probe) => Some(probe),
+            _ => None,
+        }
+    }
+}
+
+impl<I: ExtiInputPinBase, S: ServoBase, T: TimerBase> ExtiInputPinBase for AnyProbe<I, S, T> {
+    fn is_high(&self) -> bool {
+        match self {
+            AnyProbe::Switch(probe) => probe.is_high(),
+            AnyProbe::Inductive(probe) => probe.is_high(),
+            AnyProbe::BlTouch(probe) => probe.is_high(),
+        }
+    }
+
+    async fn wait_for_high(&mut self) {
+        match self {
+            AnyProbe::Switch(probe) => probe.wait_for_high().await,
+            AnyProbe::Inductive(probe) => probe.wait_for_high().await,
+            AnyProbe::BlTouch(probe) => probe.wait_for_high().await,
+        }
+    }
+
+    async fn wait_for_low(&mut self) {
+        match self {
+            AnyProbe::Switch(probe) => probe.wait_for_low().await,
+            AnyProbe::Inductive(probe) => probe.wait_for_low().await,
+            AnyProbe::BlTouch(probe) => probe.wait_for_low().await,
+        }
+    }
+}
+
+impl<I: ExtiInputPinBase, S: ServoBase, T: TimerBase> Probe for AnyProbe<I, S, T> {
+    async fn deploy(&mut self) -> Result<(), StepperError> {
+        match self {
+            AnyProbe::Switch(probe) => probe.deploy().await,
+            AnyProbe::Inductive(probe) => probe.deploy().await,
+            AnyProbe::BlTouch(probe) => probe.deploy().await,
+        }
+    }
+
+    async fn stow(&mut self) -> Result<(), StepperError> {
+        match self {
+            AnyProbe::Switch(probe) => probe.stow().await,
+            AnyProbe::Inductive(probe) => probe.stow().await,
+            AnyProbe::BlTouch(probe) => probe.stow().await,
+        }
+    }
+}
+
+// statistics of the heights measured by a series of probes, see M48
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProbeStats {
+    samples: usize,
+    mean: f64,
+    // sum of the squared differences from the mean
+    m2: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Default for ProbeStats {
+    fn default() -> Self {
+        Self {
+            samples: 0,
+            mean: 0.0,
+            m2: 0.0,
+            min: f64::MAX,
+            max: f64::MIN,
+        }
+    }
+}
+
+impl ProbeStats {
+    pub fn add(&mut self, z: Distance) {
+        let z = z.as_millimeters();
+        self.samples += 1;
+        let delta = z - self.mean;
+        self.mean += delta / self.samples as f64;
+        self.m2 += delta * (z - self.mean);
+        self.min = self.min.min(z);
+        self.max = self.max.max(z);
+    }
+
+    pub fn get_samples(&self) -> usize {
+        self.samples
+    }
+
+    pub fn get_mean(&self) -> Distance {
+        Distance::from_millimeters(self.mean)
+    }
+
+    pub fn get_min(&self) -> Distance {
+        Distance::from_millimeters(self.min)
+    }
+
+    pub fn get_max(&self) -> Distance {
+        Distance::from_millimeters(self.max)
+    }
+
+    pub fn get_std_dev(&self) -> Distance {
+        if self.samples == 0 {
+            return Distance::from_millimeters(0.0);
+        }
+        Distance::from_millimeters(precise_sqrt(self.m2 / self.samples as f64))
+    }
+}
+
+impl Display for ProbeStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::write!(
+            f,
+            "Probe repeatability: [samples:{}] [mean:{:.4}] [min:{:.4}] [max:{:.4}] [std dev:{:.4}]",
+            self.samples,
+            self.mean,
+            self.min,
+            self.max,
+            self.get_std_dev().as_millimeters()
+        )
+    }
+}
+
+// height of the bed measured at a point, in G-code coordinates
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProbePoint {
+    pub x: Distance,
+    pub y: Distance,
+    pub z: Distance,
+}
+
+impl Display for ProbePoint {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::write!(
+            f,
+            "Bed [X:{:.3}] [Y:{:.3}] [Z:{:.3}]",
+            self.x.as_millimeters(),
+            self.y.as_millimeters(),
+            self.z.as_millimeters()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use sim::{block_on, SimInputPin, SimTimer};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct ServoMock {
+        angles: Rc<RefCell<Vec<f64>>>,
+    }
+
+    impl ServoBase for ServoMock {
+        fn set_angle(&mut self, angle: f64) {
+            self.angles.borrow_mut().push(angle);
+        }
+    }
+
+    #[test]
+    fn test_inductive_probe_active_low() {
+        let pin = SimInputPin::new(true);
+        let mut probe = InductiveProbe::new(pin.clone(), true);
+        assert!(!probe.is_high());
+        pin.set_low();
+        assert!(probe.is_high());
+        block_on(probe.wait_for_high());
+        let probe = InductiveProbe::new(pin.clone(), false);
+        assert!(!probe.is_high());
+    }
+
+    #[test]
+    fn test_bltouch() {
+        block_on(async {
+            let pin = SimInputPin::new(false);
+            let servo = ServoMock::default();
+            let mut probe: BlTouch<_, _, SimTimer> =
+                BlTouch::new(pin.clone(), servo.clone(), Duration::from_millis(500));
+            let start = SimTimer::now();
+            probe.deploy().await.unwrap();
+            probe.stow().await.unwrap();
+            assert_eq!(*servo.angles.borrow(), [10.0, 90.0]);
+            // the pin takes its time to move
+            assert_eq!(SimTimer::now() - start, Duration::from_millis(1000));
+            // in alarm the output stays high, the alarm gets released before deploying again
+            servo.angles.borrow_mut().clear();
+            pin.set_high();
+            assert_eq!(probe.deploy().await, Err(StepperError::ProbeAlarm));
+            assert_eq!(*servo.angles.borrow(), [10.0, 160.0, 90.0, 10.0]);
+        });
+    }
+
+    #[test]
+    fn test_bltouch_commands() {
+        block_on(async {
+            let servo = ServoMock::default();
+            let mut probe: BlTouch<_, _, SimTimer> = BlTouch::new(
+                SimInputPin::new(false),
+                servo.clone(),
+                Duration::from_millis(500),
+            );
+            probe.self_test().await;
+            probe.set_5v_mode(true).await;
+            probe.set_5v_mode(false).await;
+            probe.alarm_release().await;
+            probe.command(BlTouchCommand::SwMode).await;
+            assert_eq!(
+                *servo.angles.borrow(),
+                [120.0, 90.0, 140.0, 90.0, 150.0, 90.0, 160.0, 90.0, 60.0]
+            );
+
+            // the angles of M280 P0, the same commands
+            servo.angles.borrow_mut().clear();
+            for angle in [120.0, 140.0, 150.0, 160.0, 60.0, 10.0] {
+                probe.execute(BlTouchCommand::from_angle(angle).unwrap()).await;
+            }
+            assert_eq!(
+                *servo.angles.borrow(),
+                [120.0, 90.0, 140.0, 90.0, 150.0, 90.0, 160.0, 90.0, 60.0, 10.0]
+            );
+            assert_eq!(BlTouchCommand::from_angle(90.2), Some(BlTouchCommand::Stow));
+            assert_eq!(BlTouchCommand::from_angle(45.0), None);
+        });
+    }
+
+    #[test]
+    fn test_any_probe() {
+        block_on(async {
+            let pin = SimInputPin::new(false);
+            let servo = ServoMock::default();
+            let probe: AnyProbe<_, ServoMock, SimTimer> =
+                AnyProbe::Inductive(InductiveProbe::new(pin.clone(), true));
+            assert_eq!(probe.get_type(), ProbeType::Inductive);
+            assert!(probe.is_high());
+            let mut probe: AnyProbe<_, _, SimTimer> =
+                AnyProbe::BlTouch(BlTouch::new(pin, servo.clone(), Duration::from_millis(500)));
+            assert_eq!(probe.get_type(), ProbeType::BlTouch);
+            probe.deploy().await.unwrap();
+            probe.stow().await.unwrap();
+            assert_eq!(*servo.angles.borrow(), [10.0, 90.0]);
+            assert_eq!(ProbeType::from("bltouch"), ProbeType::BlTouch);
+        });
+    }
+
+    #[test]
+    fn test_probe_stats() {
+        let mut stats = ProbeStats::default();
+        for z in [0.1, 0.2, 0.3, 0.4] {
+            stats.add(Distance::from_millimeters(z));
+        }
+        assert_eq!(stats.get_samples(), 4);
+        assert_abs_diff_eq!(stats.get_mean().as_millimeters(), 0.25, epsilon = 1e-9);
+        assert_abs_diff_eq!(stats.get_min().as_millimeters(), 0.1, epsilon = 1e-9);
+        assert_abs_diff_eq!(stats.get_max().as_millimeters(), 0.4, epsilon = 1e-9);
+        assert_abs_diff_eq!(
+            stats.get_std_dev().as_millimeters(),
+            0.0125f64.sqrt(),
+            epsilon = 1e-9
+        );
+        assert_eq!(
+            format!("{}", stats),
+            "Probe repeatability: [samples:4] [mean:0.2500] [min:0.1000] [max:0.4000] [std dev:0.1118]"
+        );
+    }
+}
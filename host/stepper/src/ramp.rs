@@ -0,0 +1,227 @@
+use core::time::Duration;
+
+use heapless::Vec;
+use math::common::precise_sqrt;
+
+// pieces each change of speed is made of. A piece moves at the mean speed of its part of the
+// change, so that it covers the same distance
+pub const RAMP_PIECES: usize = 8;
+// the acceleration, the cruise and the deceleration
+pub const MAX_RAMP_PIECES: usize = 2 * RAMP_PIECES + 1;
+
+// acceleration of a move along its length (M201, M204) and the speeds it enters and leaves at
+// (M205). The move accelerates from the entry speed up to its speed, then decelerates down to
+// the exit speed. An acceleration of 0 keeps the move at its speed from the start to the end
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Ramp {
+    // in mm/s²
+    pub acceleration: f64,
+    // in mm/s
+    pub entry: f64,
+    pub exit: f64,
+}
+
+impl Ramp {
+    pub fn new(acceleration: f64, entry: f64, exit: f64) -> Self {
+        Self {
+            acceleration,
+            entry,
+            exit,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.acceleration > 0.0
+    }
+
+    // the ramp of an axis covering the ratio of the length of the move
+    pub fn scaled(&self, ratio: f64) -> Self {
+        Self::new(self.acceleration * ratio, self.entry * ratio, self.exit * ratio)
+    }
+
+    // the same move slowed down by the factor, it lasts as much longer
+    pub fn stretched(&self, factor: f64) -> Self {
+        Self::new(
+            self.acceleration * factor * factor,
+            self.entry * factor,
+            self.exit * factor,
+        )
+    }
+
+    // speed at the distance from the start of a move of the length at the speed
+    pub fn speed_at(&self, length: f64, speed: f64, at: f64) -> f64 {
+        if !self.is_enabled() {
+            return speed;
+        }
+        let at = at.clamp(0.0, length);
+        let accelerated = precise_sqrt(self.entry * self.entry + 2.0 * self.acceleration * at);
+        let decelerated = precise_sqrt(
+            self.exit * self.exit + 2.0 * self.acceleration * (length - at),
+        );
+        speed.min(accelerated).min(decelerated)
+    }
+
+    // the ramp of the part of the move between the distances from its start, e.g. a piece of a
+    // leveled move or a chord of an arc
+    pub fn split(&self, length: f64, speed: f64, from: f64, to: f64) -> Self {
+        Self::new(
+            self.acceleration,
+            self.speed_at(length, speed, from),
+            self.speed_at(length, speed, to),
+        )
+    }
+
+    // profile of a move of the length (mm) at the speed (mm/s). An entry or an exit speed that
+    // can't be reached over the length is lowered
+    pub fn trapezoid(&self, length: f64, speed: f64) -> Trapezoid {
+        if !self.is_enabled() || length <= 0.0 || speed <= 0.0 {
+            let cruise = if speed > 0.0 { length.max(0.0) / speed } else { 0.0 };
+            return Trapezoid {
+                entry: speed,
+                peak: speed,
+                exit: speed,
+                acceleration: 0.0,
+                accelerate: Duration::ZERO,
+                cruise: Duration::from_secs_f64(cruise),
+                decelerate: Duration::ZERO,
+            };
+        }
+        let a = self.acceleration;
+        // the speed of a stepper is rounded to the duration of its step, a speed this close to
+        // it is the same
+        let close = |v: f64| if speed - v < speed * 1e-3 { speed } else { v.max(0.0) };
+        let mut entry = close(self.entry);
+        let mut exit = close(self.exit);
+        entry = entry.min(precise_sqrt(exit * exit + 2.0 * a * length));
+        exit = exit.min(precise_sqrt(entry * entry + 2.0 * a * length));
+        let peak = speed.min(precise_sqrt(a * length + (entry * entry + exit * exit) / 2.0));
+        let accelerating = (peak * peak - entry * entry) / (2.0 * a);
+        let decelerating = (peak * peak - exit * exit) / (2.0 * a);
+        let cruise = ((length - accelerating - decelerating) / peak).max(0.0);
+        Trapezoid {
+            entry,
+            peak,
+            exit,
+            acceleration: a,
+            accelerate: Duration::from_secs_f64((peak - entry) / a),
+            cruise: Duration::from_secs_f64(cruise),
+            decelerate: Duration::from_secs_f64((peak - exit) / a),
+        }
+    }
+}
+
+// speeds along a move over time: from the entry speed up to the peak, a cruise at the peak and
+// down to the exit speed
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Trapezoid {
+    pub entry: f64,
+    pub peak: f64,
+    pub exit: f64,
+    pub acceleration: f64,
+    pub accelerate: Duration,
+    pub cruise: Duration,
+    pub decelerate: Duration,
+}
+
+impl Trapezoid {
+    pub fn duration(&self) -> Duration {
+        self.accelerate + self.cruise + self.decelerate
+    }
+
+    // the same profile in other units of length, e.g. microsteps instead of millimeters
+    pub fn scaled(&self, factor: f64) -> Self {
+        Self {
+            entry: self.entry * factor,
+            peak: self.peak * factor,
+            exit: self.exit * factor,
+            acceleration: self.acceleration * factor,
+            ..*self
+        }
+    }
+
+    // the profile as pieces of (duration, speed), the acceleration and the deceleration as
+    // RAMP_PIECES pieces each
+    pub fn pieces(&self) -> Vec<(Duration, f64), MAX_RAMP_PIECES> {
+        let mut pieces = Vec::new();
+        let ramp = |pieces: &mut Vec<_, MAX_RAMP_PIECES>, duration: Duration, from, to| {
+            if duration.is_zero() {
+                return;
+            }
+            let piece = duration / RAMP_PIECES as u32;
+            for n in 0..RAMP_PIECES {
+                let speed = from + (to - from) * (n as f64 + 0.5) / RAMP_PIECES as f64;
+                pieces.push((piece, speed)).unwrap_or(());
+            }
+        };
+        ramp(&mut pieces, self.accelerate, self.entry, self.peak);
+        if !self.cruise.is_zero() {
+            pieces.push((self.cruise, self.peak)).unwrap_or(());
+        }
+        ramp(&mut pieces, self.decelerate, self.peak, self.exit);
+        pieces
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    fn distance(pieces: &[(Duration, f64)]) -> f64 {
+        pieces.iter().map(|(d, v)| d.as_secs_f64() * v).sum()
+    }
+
+    #[test]
+    fn test_ramp_trapezoid() {
+        // 100mm at 100mm/s from 10mm/s: 90ms and 4.95mm to get up to speed at 1000mm/s²
+        let ramp = Ramp::new(1000.0, 10.0, 10.0);
+        let trapezoid = ramp.trapezoid(100.0, 100.0);
+        assert_abs_diff_eq!(trapezoid.peak, 100.0);
+        assert_abs_diff_eq!(trapezoid.accelerate.as_secs_f64(), 0.09, epsilon = 1e-9);
+        assert_abs_diff_eq!(trapezoid.decelerate.as_secs_f64(), 0.09, epsilon = 1e-9);
+        assert_abs_diff_eq!(trapezoid.cruise.as_secs_f64(), 0.901, epsilon = 1e-9);
+        let pieces = trapezoid.pieces();
+        assert_eq!(pieces.len(), MAX_RAMP_PIECES);
+        assert_abs_diff_eq!(distance(&pieces), 100.0, epsilon = 1e-6);
+        // the speed rises by the same amount at each piece
+        for w in pieces[..RAMP_PIECES].windows(2) {
+            assert_abs_diff_eq!(w[1].1 - w[0].1, 90.0 / RAMP_PIECES as f64, epsilon = 1e-6);
+        }
+
+        // too short to get up to speed: 10mm from rest peak at 100mm/s
+        let trapezoid = Ramp::new(1000.0, 0.0, 0.0).trapezoid(10.0, 200.0);
+        assert_abs_diff_eq!(trapezoid.peak, 100.0, epsilon = 1e-6);
+        assert_eq!(trapezoid.cruise, Duration::ZERO);
+        assert_abs_diff_eq!(trapezoid.duration().as_secs_f64(), 0.2, epsilon = 1e-6);
+        assert_abs_diff_eq!(distance(&trapezoid.pieces()), 10.0, epsilon = 1e-6);
+
+        // an exit speed out of reach is lowered
+        let trapezoid = Ramp::new(1000.0, 0.0, 100.0).trapezoid(2.0, 100.0);
+        assert_abs_diff_eq!(trapezoid.exit, 63.245, epsilon = 1e-3);
+        assert_abs_diff_eq!(distance(&trapezoid.pieces()), 2.0, epsilon = 1e-6);
+
+        // without acceleration the move is at its speed
+        let trapezoid = Ramp::default().trapezoid(10.0, 20.0);
+        assert_eq!(trapezoid.pieces().as_slice(), &[(Duration::from_millis(500), 20.0)]);
+    }
+
+    #[test]
+    fn test_ramp_split() {
+        let ramp = Ramp::new(1000.0, 0.0, 0.0);
+        // the pieces of a move follow the trapezoid of the whole move
+        let whole = ramp.trapezoid(10.0, 200.0).duration().as_secs_f64();
+        let parts: f64 = [(0.0, 2.5), (2.5, 5.0), (5.0, 10.0)]
+            .iter()
+            .map(|(from, to)| {
+                let split = ramp.split(10.0, 200.0, *from, *to);
+                split.trapezoid(to - from, 200.0).duration().as_secs_f64()
+            })
+            .sum();
+        assert_abs_diff_eq!(parts, whole, epsilon = 1e-6);
+        assert_abs_diff_eq!(ramp.speed_at(10.0, 200.0, 5.0), 100.0, epsilon = 1e-6);
+
+        // an axis at half the speed of the move, slowed down to last twice as long
+        let trapezoid = ramp.scaled(0.5).stretched(0.5).trapezoid(5.0, 50.0);
+        assert_abs_diff_eq!(trapezoid.duration().as_secs_f64(), 0.4, epsilon = 1e-6);
+    }
+}
@@ -0,0 +1,519 @@
+use core::cell::Cell;
+use core::f64::consts::PI;
+use core::fmt::Display;
+use core::time::Duration;
+
+use accelerometer::adxl345::{Adxl345, Adxl345Error, FIFO_SIZE};
+use common::{ExtiInputPinBase, HalfDuplexSerialBase, OutputPinBase, SpiBase, TimerBase};
+use futures::join;
+use heapless::Vec;
+use math::angle::{cos, sin, Angle};
+use math::common::{abs, precise_sqrt};
+use math::measurements::{Distance, Speed};
+use math::Axis;
+
+use crate::planner::Planner;
+use crate::probe::Probe;
+use crate::shaper::{InputShaper, ShaperType};
+use crate::stepper::StepperError;
+
+// frequencies a sweep can be made of
+pub const MAX_BINS: usize = 256;
+
+// the first shaper leaving less vibration than this is recommended, the shorter ones smooth
+// the print less
+const VIBRATION_THRESHOLD: f64 = 0.05;
+
+// from the shortest to the longest
+const SHAPERS: [ShaperType; 5] = [
+    ShaperType::Zv,
+    ShaperType::Mzv,
+    ShaperType::Zvd,
+    ShaperType::Ei,
+    ShaperType::TwoHumpEi,
+];
+
+// the axis is excited by a sine at each frequency from the minimum to the maximum, in Hz
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ResonanceConfig {
+    pub min_frequency: f64,
+    pub max_frequency: f64,
+    pub frequency_step: f64,
+    // peak acceleration of the sine in mm/s², the same at each frequency so that the spectrum
+    // is flat away from the resonances. The amplitude shrinks with the square of the frequency
+    pub acceleration: f64,
+    // time spent at each frequency, rounded up to whole cycles
+    pub hold: Duration,
+    // chords each cycle of the sine is made of
+    pub segments: usize,
+}
+
+impl Default for ResonanceConfig {
+    fn default() -> Self {
+        Self {
+            min_frequency: 10.0,
+            max_frequency: 100.0,
+            frequency_step: 1.0,
+            acceleration: 5000.0,
+            hold: Duration::from_millis(500),
+            segments: 16,
+        }
+    }
+}
+
+impl ResonanceConfig {
+    fn frequencies(&self) -> impl Iterator<Item = f64> + '_ {
+        (0..self.bins()).map(|n| self.min_frequency + n as f64 * self.frequency_step)
+    }
+
+    fn bins(&self) -> usize {
+        ((self.max_frequency - self.min_frequency) / self.frequency_step) as usize + 1
+    }
+
+    fn is_valid(&self) -> bool {
+        self.min_frequency > 0.0
+            && self.frequency_step > 0.0
+            && self.max_frequency >= self.min_frequency
+            && self.bins() <= MAX_BINS
+            && self.acceleration > 0.0
+            && self.segments >= 4
+    }
+}
+
+// response of the axis at a frequency of the sweep. The samples taken while the axis is excited
+// at the frequency are correlated with a cosine and a sine of it, which rejects the rest
+#[derive(Debug, Clone, Copy, Default)]
+struct Bin {
+    frequency: f64,
+    c: f64,
+    s: f64,
+    samples: usize,
+}
+
+impl Bin {
+    fn add(&mut self, time: f64, value: f64) {
+        // the phase is reduced before losing precision to f32
+        let phase = Angle::from_radians((2.0 * PI * self.frequency * time) % (2.0 * PI));
+        self.c += value * cos(phase);
+        self.s += value * sin(phase);
+        self.samples += 1;
+    }
+
+    // mean square of the response at the frequency
+    fn power(&self) -> f64 {
+        if self.samples == 0 {
+            return 0.0;
+        }
+        let amplitude = 2.0 * precise_sqrt(self.c * self.c + self.s * self.s) / self.samples as f64;
+        amplitude * amplitude / 2.0
+    }
+}
+
+// power spectral density of the vibration of the toolhead along an axis, in (mm/s²)²/Hz
+pub struct Spectrum {
+    bins: Vec<Bin, MAX_BINS>,
+    step: f64,
+}
+
+impl Spectrum {
+    pub fn new(config: &ResonanceConfig) -> Result<Self, StepperError> {
+        if !config.is_valid() {
+            return Err(StepperError::MoveNotValid);
+        }
+        let mut bins = Vec::new();
+        for frequency in config.frequencies() {
+            bins.push(Bin {
+                frequency,
+                ..Default::default()
+            })
+            .map_err(|_| StepperError::MoveNotValid)?;
+        }
+        Ok(Self {
+            bins,
+            step: config.frequency_step,
+        })
+    }
+
+    // a sample of the acceleration in mm/s², taken at the time in seconds while the axis was
+    // excited at the frequency of the bin
+    pub fn add(&mut self, bin: usize, time: f64, value: f64) {
+        if let Some(bin) = self.bins.get_mut(bin) {
+            bin.add(time, value);
+        }
+    }
+
+    // frequency and density of each bin
+    pub fn psd(&self) -> Vec<(f64, f64), MAX_BINS> {
+        self.bins
+            .iter()
+            .map(|bin| (bin.frequency, bin.power() / self.step))
+            .collect()
+    }
+
+    // frequency of the highest peak, refined between the bins by the parabola through the
+    // bins around it
+    pub fn peak(&self) -> Option<f64> {
+        let psd = self.psd();
+        let (n, (frequency, density)) = psd
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.1.total_cmp(&b.1))?;
+        if *density <= 0.0 {
+            return None;
+        }
+        if n == 0 || n == psd.len() - 1 {
+            return Some(*frequency);
+        }
+        let (before, after) = (psd[n - 1].1, psd[n + 1].1);
+        let curvature = before - 2.0 * density + after;
+        if curvature >= 0.0 {
+            return Some(*frequency);
+        }
+        Some(frequency + self.step * 0.5 * (before - after) / curvature)
+    }
+
+    // damping ratio of the highest peak from the bandwidth where the density is over half of
+    // the peak. None if the band goes past the sweep
+    pub fn damping(&self) -> Option<f64> {
+        let psd = self.psd();
+        let (n, (_, density)) = psd
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.1.total_cmp(&b.1))?;
+        let half = density / 2.0;
+        // the frequency where the density crosses half of the peak between two bins
+        let crossing =
+            |a: &(f64, f64), b: &(f64, f64)| a.0 + (b.0 - a.0) * (half - a.1) / (b.1 - a.1);
+        let low = (0..n)
+            .rev()
+            .find(|i| psd[*i].1 <= half)
+            .map(|i| crossing(&psd[i], &psd[i + 1]))?;
+        let high = (n + 1..psd.len())
+            .find(|i| psd[*i].1 <= half)
+            .map(|i| crossing(&psd[i - 1], &psd[i]))?;
+        Some((high - low) / (2.0 * self.peak()?))
+    }
+
+    // vibration the shaper leaves over the whole spectrum, relative to the unshaped one
+    pub fn vibration(&self, shaper: &InputShaper, damping: f64) -> f64 {
+        let psd = self.psd();
+        let total: f64 = psd.iter().map(|(_, d)| d).sum();
+        if total <= 0.0 {
+            return 0.0;
+        }
+        let shaped: f64 = psd
+            .iter()
+            .map(|(f, d)| {
+                let v = shaper.vibration(*f, damping);
+                d * v * v
+            })
+            .sum();
+        shaped / total
+    }
+
+    // the shortest shaper at the peak that leaves little enough vibration, the one leaving the
+    // least otherwise
+    pub fn recommend(&self) -> Option<InputShaper> {
+        let frequency = self.peak()?;
+        let damping = self.damping()?;
+        let mut best: Option<(InputShaper, f64)> = None;
+        for shaper_type in SHAPERS {
+            let shaper = InputShaper::new(shaper_type, frequency, damping);
+            let vibration = self.vibration(&shaper, damping);
+            if vibration <= VIBRATION_THRESHOLD {
+                return Some(shaper);
+            }
+            if best.map(|(_, v)| vibration < v).unwrap_or(true) {
+                best = Some((shaper, vibration));
+            }
+        }
+        best.map(|(shaper, _)| shaper)
+    }
+}
+
+// the test fails because of the motion or because of the accelerometer
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ResonanceError {
+    Stepper(StepperError),
+    Accelerometer(Adxl345Error),
+}
+
+impl Display for ResonanceError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match &self {
+            ResonanceError::Stepper(e) => core::write!(f, "{}", e),
+            ResonanceError::Accelerometer(e) => core::write!(f, "Accelerometer error: {}", e),
+        }
+    }
+}
+
+impl From<StepperError> for ResonanceError {
+    fn from(value: StepperError) -> Self {
+        ResonanceError::Stepper(value)
+    }
+}
+
+impl From<Adxl345Error> for ResonanceError {
+    fn from(value: Adxl345Error) -> Self {
+        ResonanceError::Accelerometer(value)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Resonance {
+    pub axis: Axis,
+    pub psd: Vec<(f64, f64), MAX_BINS>,
+    pub frequency: f64,
+    pub damping: f64,
+    pub shaper: InputShaper,
+}
+
+impl Display for Resonance {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::write!(
+            f,
+            "Resonance {}: [frequency:{:.2}Hz] [damping:{:.3}] [shaper:{}]",
+            self.axis,
+            self.frequency,
+            self.damping,
+            self.shaper
+        )
+    }
+}
+
+// sweep X or Y through the frequencies of the configuration while the accelerometer, mounted
+// on the toolhead, samples its vibration. The axis oscillates around the commanded position,
+// which has to leave room for the amplitude at the lowest frequency. The input shaping should
+// be disabled while measuring
+pub async fn measure_resonance<
+    P: OutputPinBase,
+    T: TimerBase,
+    I: ExtiInputPinBase,
+    S: HalfDuplexSerialBase,
+    Z: Probe,
+    B: SpiBase,
+>(
+    planner: &mut Planner<P, T, I, S, Z>,
+    accelerometer: &mut Adxl345,
+    spi: &mut B,
+    axis: Axis,
+    config: &ResonanceConfig,
+) -> Result<Resonance, ResonanceError> {
+    if axis != Axis::X && axis != Axis::Y {
+        return Err(StepperError::NotSupported.into());
+    }
+    let mut spectrum = Spectrum::new(config)?;
+    accelerometer.init(spi).await?;
+    let rate = accelerometer.get_config().rate.frequency();
+    // the FIFO is read when it's about half full
+    let poll = Duration::from_secs_f64(FIFO_SIZE as f64 / 2.0 / rate);
+    let origin = T::now();
+    // the bin being excited and since when
+    let current: Cell<Option<(usize, Duration)>> = Cell::new(None);
+    let done = Cell::new(false);
+
+    let excitation = async {
+        let result = excite(planner, axis, config, &current).await;
+        done.set(true);
+        result
+    };
+    let sampling = async {
+        let mut samples: u64 = 0;
+        while !done.get() {
+            T::after(poll).await;
+            for _ in 0..accelerometer.get_fifo_entries(spi).await? {
+                let (x, y, _) = accelerometer.read_acceleration(spi).await?;
+                let time = origin + Duration::from_secs_f64(samples as f64 / rate);
+                samples += 1;
+                let value = match axis {
+                    Axis::X => x,
+                    _ => y,
+                };
+                // the samples taken before the bin started belong to the previous one
+                if let Some((bin, start)) = current.get() {
+                    if time >= start {
+                        spectrum.add(
+                            bin,
+                            (time - origin).as_secs_f64(),
+                            value.as_meters_per_second_per_second() * 1000.0,
+                        );
+                    }
+                }
+            }
+        }
+        Ok::<(), ResonanceError>(())
+    };
+    let (excited, sampled) = join!(excitation, sampling);
+    accelerometer.stop(spi).await?;
+    excited?;
+    sampled?;
+
+    let frequency = spectrum.peak().ok_or(StepperError::MoveNotValid)?;
+    let shaper = spectrum.recommend().ok_or(StepperError::MoveNotValid)?;
+    Ok(Resonance {
+        axis,
+        psd: spectrum.psd(),
+        frequency,
+        damping: shaper.damping,
+        shaper,
+    })
+}
+
+// the sine is made of chords at constant speed. Each chord ends at its deadline, also when the
+// steppers have nothing to do because it's shorter than a step, so that the frequency doesn't
+// drift
+async fn excite<
+    P: OutputPinBase,
+    T: TimerBase,
+    I: ExtiInputPinBase,
+    S: HalfDuplexSerialBase,
+    Z: Probe,
+>(
+    planner: &mut Planner<P, T, I, S, Z>,
+    axis: Axis,
+    config: &ResonanceConfig,
+    current: &Cell<Option<(usize, Duration)>>,
+) -> Result<(), StepperError> {
+    for (bin, frequency) in config.frequencies().enumerate() {
+        let amplitude = config.acceleration / (2.0 * PI * frequency * 2.0 * PI * frequency);
+        let cycles = config.hold.as_secs_f64() * frequency;
+        let cycles = if cycles > (cycles as u64) as f64 {
+            cycles as u64 + 1
+        } else {
+            cycles as u64
+        };
+        let segment = Duration::from_secs_f64(1.0 / (frequency * config.segments as f64));
+        let mut deadline = T::now();
+        current.set(Some((bin, deadline)));
+        let mut position = 0.0;
+        for n in 1..=cycles as usize * config.segments {
+            let angle = 2.0 * PI * (n % config.segments) as f64 / config.segments as f64;
+            let next = amplitude * sin(Angle::from_radians(angle));
+            let distance = next - position;
+            deadline += segment;
+            // each move starts a little late, the chord is sped up to end on time
+            let time = deadline.saturating_sub(T::now()).max(segment / 2);
+            let speed = abs(distance) / time.as_secs_f64();
+            if speed > 0.0 {
+                planner
+                    .move_axis(
+                        axis,
+                        Distance::from_millimeters(distance),
+                        Speed::from_meters_per_second(speed / 1000.0),
+                    )
+                    .await?;
+            }
+            position = next;
+            T::at(deadline).await;
+        }
+        // the last chord ends where the sine started
+        if position != 0.0 {
+            planner
+                .move_axis(
+                    axis,
+                    Distance::from_millimeters(-position),
+                    Speed::from_meters_per_second(abs(position) / segment.as_secs_f64() / 1000.0),
+                )
+                .await?;
+        }
+    }
+    current.set(None);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    // acceleration of a mass on a spring with the frequency and damping ratio, whose base is
+    // moved by a sine of unit acceleration
+    fn response(resonance: f64, damping: f64, frequency: f64) -> f64 {
+        let r = frequency / resonance;
+        1.0 / ((1.0 - r * r).powi(2) + (2.0 * damping * r).powi(2)).sqrt()
+    }
+
+    fn config() -> ResonanceConfig {
+        ResonanceConfig {
+            min_frequency: 10.0,
+            max_frequency: 100.0,
+            frequency_step: 1.0,
+            ..Default::default()
+        }
+    }
+
+    // a second of samples at 3200Hz for each frequency
+    fn sweep(amplitude: impl Fn(f64) -> f64) -> Spectrum {
+        let config = config();
+        let mut spectrum = Spectrum::new(&config).unwrap();
+        let mut time = 0.0;
+        for (bin, frequency) in config.frequencies().enumerate() {
+            for _ in 0..3200 {
+                let value = amplitude(frequency) * (2.0 * PI * frequency * time + 0.3).sin();
+                spectrum.add(bin, time, value);
+                time += 1.0 / 3200.0;
+            }
+        }
+        spectrum
+    }
+
+    #[test]
+    fn test_resonance_psd() {
+        // a flat response: the mean square of the sine spread over a bin
+        let psd = sweep(|_| 1000.0).psd();
+        assert_eq!(psd.len(), 91);
+        assert_eq!(psd[0].0, 10.0);
+        assert_eq!(psd[90].0, 100.0);
+        for (_, density) in psd.iter() {
+            assert_abs_diff_eq!(*density, 500000.0, epsilon = 5000.0);
+        }
+
+        let empty = Spectrum::new(&config()).unwrap();
+        assert_eq!(empty.peak(), None);
+        assert_eq!(empty.recommend(), None);
+
+        for invalid in [
+            ResonanceConfig {
+                min_frequency: 0.0,
+                ..config()
+            },
+            ResonanceConfig {
+                max_frequency: 5.0,
+                ..config()
+            },
+            ResonanceConfig {
+                frequency_step: 0.1,
+                ..config()
+            },
+            ResonanceConfig {
+                segments: 2,
+                ..config()
+            },
+        ] {
+            assert!(Spectrum::new(&invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn test_resonance_peak() {
+        let spectrum = sweep(|f| 1000.0 * response(42.3, 0.1, f));
+        assert_abs_diff_eq!(spectrum.peak().unwrap(), 42.3, epsilon = 0.5);
+        assert_abs_diff_eq!(spectrum.damping().unwrap(), 0.1, epsilon = 0.01);
+        let shaper = spectrum.recommend().unwrap();
+        assert_abs_diff_eq!(shaper.frequency, 42.3, epsilon = 0.5);
+        assert!(
+            spectrum.vibration(&shaper, shaper.damping)
+                < spectrum.vibration(&InputShaper::default(), 0.1)
+        );
+
+        // a sharp resonance is cancelled by the shortest shaper
+        let spectrum = sweep(|f| 1000.0 * response(60.0, 0.02, f));
+        assert_abs_diff_eq!(spectrum.peak().unwrap(), 60.0, epsilon = 0.5);
+        assert_eq!(spectrum.recommend().unwrap().shaper_type, ShaperType::Zv);
+
+        // the band of a resonance past the sweep can't be measured
+        let spectrum = sweep(|f| 1000.0 * response(120.0, 0.1, f));
+        assert_eq!(spectrum.peak(), Some(100.0));
+        assert_eq!(spectrum.damping(), None);
+    }
+}
@@ -0,0 +1,402 @@
+use core::f64::consts::PI;
+use core::fmt::Display;
+use core::time::Duration;
+
+use heapless::Vec;
+use math::angle::{cos, sin, Angle};
+use math::common::{exp, precise_sqrt};
+
+use crate::ramp::MAX_RAMP_PIECES;
+
+// impulses of the longest shaper, the two-hump EI
+pub const MAX_IMPULSES: usize = 4;
+// pieces of a velocity profile that can be shaped at once, an accelerated move
+pub const MAX_PIECES: usize = MAX_RAMP_PIECES;
+// each bound of the pieces is repeated at the time of each impulse
+pub const MAX_SHAPED_PIECES: usize = (MAX_PIECES + 1) * MAX_IMPULSES;
+
+// vibration the EI shapers allow at their frequency, in exchange for a wider band
+const VIBRATION_TOLERANCE: f64 = 0.05;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ShaperType {
+    Zv,
+    Mzv,
+    Zvd,
+    Ei,
+    TwoHumpEi,
+}
+
+impl From<&str> for ShaperType {
+    fn from(value: &str) -> Self {
+        match value {
+            "zv" => ShaperType::Zv,
+            "mzv" => ShaperType::Mzv,
+            "zvd" => ShaperType::Zvd,
+            "ei" => ShaperType::Ei,
+            "2hump_ei" => ShaperType::TwoHumpEi,
+            _ => panic!("Invalid shaper type"),
+        }
+    }
+}
+
+// index used by M593
+impl TryFrom<u8> for ShaperType {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ShaperType::Zv),
+            1 => Ok(ShaperType::Mzv),
+            2 => Ok(ShaperType::Zvd),
+            3 => Ok(ShaperType::Ei),
+            4 => Ok(ShaperType::TwoHumpEi),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for ShaperType {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ShaperType::Zv => core::write!(f, "ZV"),
+            ShaperType::Mzv => core::write!(f, "MZV"),
+            ShaperType::Zvd => core::write!(f, "ZVD"),
+            ShaperType::Ei => core::write!(f, "EI"),
+            ShaperType::TwoHumpEi => core::write!(f, "2HUMP_EI"),
+        }
+    }
+}
+
+// the motion of an axis is convolved with a train of impulses that cancel the ringing of the
+// frame at its resonance frequency. The shaped motion lasts as much as the last impulse longer
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct InputShaper {
+    pub shaper_type: ShaperType,
+    // in Hz, 0 disables the shaping
+    pub frequency: f64,
+    // damping ratio of the resonance, from 0 to 1 (excluded)
+    pub damping: f64,
+}
+
+impl Default for InputShaper {
+    fn default() -> Self {
+        Self {
+            shaper_type: ShaperType::Zv,
+            frequency: 0.0,
+            damping: 0.1,
+        }
+    }
+}
+
+impl InputShaper {
+    pub fn new(shaper_type: ShaperType, frequency: f64, damping: f64) -> Self {
+        Self {
+            shaper_type,
+            frequency,
+            damping,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.frequency > 0.0 && (0.0..1.0).contains(&self.damping)
+    }
+
+    // amplitude and time of each impulse, the amplitudes add up to 1. A disabled shaper is a
+    // single impulse at 0
+    pub fn impulses(&self) -> Vec<(f64, Duration), MAX_IMPULSES> {
+        let mut impulses = Vec::new();
+        if !self.is_enabled() {
+            impulses.push((1.0, Duration::ZERO)).unwrap_or(());
+            return impulses;
+        }
+        let df = precise_sqrt(1.0 - self.damping * self.damping);
+        // period of the damped oscillation
+        let td = 1.0 / (self.frequency * df);
+        let k = exp(-self.damping * PI / df);
+        let raw: Vec<(f64, f64), MAX_IMPULSES> = match self.shaper_type {
+            ShaperType::Zv => [(1.0, 0.0), (k, 0.5 * td)].into_iter().collect(),
+            ShaperType::Mzv => {
+                let k = exp(-0.75 * self.damping * PI / df);
+                let a1 = 1.0 - 1.0 / precise_sqrt(2.0);
+                let a2 = (precise_sqrt(2.0) - 1.0) * k;
+                [(a1, 0.0), (a2, 0.375 * td), (a1 * k * k, 0.75 * td)]
+                    .into_iter()
+                    .collect()
+            }
+            ShaperType::Zvd => [(1.0, 0.0), (2.0 * k, 0.5 * td), (k * k, td)]
+                .into_iter()
+                .collect(),
+            ShaperType::Ei => {
+                let a1 = 0.25 * (1.0 + VIBRATION_TOLERANCE);
+                let a2 = 0.5 * (1.0 - VIBRATION_TOLERANCE) * k;
+                [(a1, 0.0), (a2, 0.5 * td), (a1 * k * k, td)]
+                    .into_iter()
+                    .collect()
+            }
+            ShaperType::TwoHumpEi => {
+                let v2 = VIBRATION_TOLERANCE * VIBRATION_TOLERANCE;
+                let x = cbrt(v2 * (precise_sqrt(1.0 - v2) + 1.0));
+                let a1 = (3.0 * x * x + 2.0 * x + 3.0 * v2) / (16.0 * x);
+                let a2 = (0.5 - a1) * k;
+                [
+                    (a1, 0.0),
+                    (a2, 0.5 * td),
+                    (a2 * k, td),
+                    (a1 * k * k * k, 1.5 * td),
+                ]
+                .into_iter()
+                .collect()
+            }
+        };
+        let sum: f64 = raw.iter().map(|(a, _)| a).sum();
+        for (a, t) in raw {
+            impulses
+                .push((a / sum, Duration::from_secs_f64(t)))
+                .unwrap_or(());
+        }
+        impulses
+    }
+
+    // time of the last impulse, the shaped motion ends this late
+    pub fn duration(&self) -> Duration {
+        self.impulses()
+            .last()
+            .map(|(_, t)| *t)
+            .unwrap_or(Duration::ZERO)
+    }
+
+    // position of the shaped motion at t. The motion is given by its position over time and
+    // stays where it starts before 0
+    pub fn position(&self, motion: impl Fn(Duration) -> f64, t: Duration) -> f64 {
+        self.impulses()
+            .iter()
+            .map(|(a, at)| a * motion(t.saturating_sub(*at)))
+            .sum()
+    }
+
+    // the profile is a sequence of pieces at constant velocity, as (duration, velocity). The
+    // shaped profile is too, its pieces are bound by the bounds of the profile delayed by each
+    // impulse. An accelerated profile can be given as short pieces. None if the profile has
+    // too many pieces
+    pub fn shape(
+        &self,
+        profile: &[(Duration, f64)],
+    ) -> Option<Vec<(Duration, f64), MAX_SHAPED_PIECES>> {
+        if profile.len() > MAX_PIECES {
+            return None;
+        }
+        let impulses = self.impulses();
+        let mut bounds: Vec<f64, { MAX_PIECES + 1 }> = Vec::new();
+        let mut time = 0.0;
+        bounds.push(time).ok()?;
+        for (duration, _) in profile {
+            time += duration.as_secs_f64();
+            bounds.push(time).ok()?;
+        }
+        let velocity = |t: f64| {
+            profile
+                .iter()
+                .zip(bounds.windows(2))
+                .find(|(_, b)| t >= b[0] && t < b[1])
+                .map(|((_, v), _)| *v)
+                .unwrap_or(0.0)
+        };
+
+        let mut breakpoints: Vec<f64, MAX_SHAPED_PIECES> = Vec::new();
+        for bound in bounds.iter() {
+            for (_, t) in impulses.iter() {
+                breakpoints.push(bound + t.as_secs_f64()).ok()?;
+            }
+        }
+        breakpoints.sort_unstable_by(|a, b| a.total_cmp(b));
+
+        let mut shaped = Vec::new();
+        for b in breakpoints.windows(2) {
+            // pieces shorter than the resolution of a duration are dropped
+            if b[1] - b[0] < 1e-9 {
+                continue;
+            }
+            let middle = (b[0] + b[1]) / 2.0;
+            let v = impulses
+                .iter()
+                .map(|(a, t)| a * velocity(middle - t.as_secs_f64()))
+                .sum();
+            shaped
+                .push((Duration::from_secs_f64(b[1] - b[0]), v))
+                .ok()?;
+        }
+        Some(shaped)
+    }
+
+    // vibration left by the impulses on an oscillator at the frequency, relative to the one of
+    // a single impulse
+    pub fn vibration(&self, frequency: f64, damping: f64) -> f64 {
+        let omega = 2.0 * PI * frequency;
+        let omega_d = omega * precise_sqrt(1.0 - damping * damping);
+        let (mut c, mut s) = (0.0, 0.0);
+        for (a, t) in self.impulses().iter() {
+            let t = t.as_secs_f64();
+            // the phase is reduced before losing precision to f32
+            let phase = Angle::from_radians((omega_d * t) % (2.0 * PI));
+            c += a * exp(damping * omega * t) * cos(phase);
+            s += a * exp(damping * omega * t) * sin(phase);
+        }
+        let last = self.duration().as_secs_f64();
+        exp(-damping * omega * last) * precise_sqrt(c * c + s * s)
+    }
+}
+
+impl Display for InputShaper {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::write!(
+            f,
+            "{} F{:.2} D{:.3}",
+            self.shaper_type,
+            self.frequency,
+            self.damping
+        )
+    }
+}
+
+// Newton's method, the value is positive
+fn cbrt(value: f64) -> f64 {
+    let mut root = 1.0;
+    for _ in 0..64 {
+        root -= (root * root * root - value) / (3.0 * root * root);
+    }
+    root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    const TYPES: [ShaperType; 5] = [
+        ShaperType::Zv,
+        ShaperType::Mzv,
+        ShaperType::Zvd,
+        ShaperType::Ei,
+        ShaperType::TwoHumpEi,
+    ];
+
+    // vibration left by the impulses on an oscillator, relative to the one of a single impulse
+    fn vibration(shaper: &InputShaper, frequency: f64, damping: f64) -> f64 {
+        let omega = 2.0 * PI * frequency;
+        let omega_d = omega * (1.0 - damping * damping).sqrt();
+        let impulses = shaper.impulses();
+        let (mut c, mut s) = (0.0, 0.0);
+        for (a, t) in impulses.iter() {
+            let t = t.as_secs_f64();
+            c += a * (damping * omega * t).exp() * (omega_d * t).cos();
+            s += a * (damping * omega * t).exp() * (omega_d * t).sin();
+        }
+        let last = shaper.duration().as_secs_f64();
+        (-damping * omega * last).exp() * (c * c + s * s).sqrt()
+    }
+
+    #[test]
+    fn test_shaper_impulses() {
+        let shaper = InputShaper::new(ShaperType::Zv, 50.0, 0.0);
+        let impulses = shaper.impulses();
+        assert_eq!(impulses.len(), 2);
+        assert_abs_diff_eq!(impulses[0].0, 0.5, epsilon = 0.000001);
+        assert_abs_diff_eq!(impulses[1].0, 0.5, epsilon = 0.000001);
+        assert_eq!(impulses[1].1, Duration::from_millis(10));
+
+        for shaper_type in TYPES {
+            let impulses = InputShaper::new(shaper_type, 40.0, 0.1).impulses();
+            let sum: f64 = impulses.iter().map(|(a, _)| a).sum();
+            assert_abs_diff_eq!(sum, 1.0, epsilon = 0.000001);
+        }
+        let impulses = InputShaper::new(ShaperType::TwoHumpEi, 40.0, 0.1).impulses();
+        assert_abs_diff_eq!(impulses[0].0, 0.245547, epsilon = 0.000001);
+        assert_abs_diff_eq!(impulses[3].0, 0.095227, epsilon = 0.000001);
+
+        let disabled = InputShaper::default();
+        assert!(!disabled.is_enabled());
+        assert_eq!(disabled.impulses().as_slice(), &[(1.0, Duration::ZERO)]);
+        assert_eq!(disabled.duration(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_shaper_vibration() {
+        for shaper_type in TYPES {
+            let shaper = InputShaper::new(shaper_type, 40.0, 0.1);
+            let tolerance = match shaper_type {
+                ShaperType::Ei => VIBRATION_TOLERANCE,
+                _ => 0.000001,
+            };
+            assert!(vibration(&shaper, 40.0, 0.1) < tolerance);
+            // off the frequency the ringing is still reduced
+            assert!(vibration(&shaper, 32.0, 0.1) < 0.35);
+            assert!(vibration(&shaper, 48.0, 0.1) < 0.35);
+            for frequency in [20.0, 40.0, 55.0] {
+                assert_abs_diff_eq!(
+                    shaper.vibration(frequency, 0.1),
+                    vibration(&shaper, frequency, 0.1),
+                    epsilon = 0.001
+                );
+            }
+        }
+        assert_abs_diff_eq!(
+            vibration(&InputShaper::default(), 40.0, 0.1),
+            1.0,
+            epsilon = 0.000001
+        );
+    }
+
+    #[test]
+    fn test_shaper_trajectory() {
+        // a step is shaped into the steps of the impulses
+        let step = |t: Duration| if t.is_zero() { 0.0 } else { 1.0 };
+        for shaper_type in TYPES {
+            let shaper = InputShaper::new(shaper_type, 40.0, 0.1);
+            let impulses = shaper.impulses();
+            let mut reached = 0.0;
+            for (a, t) in impulses.iter() {
+                reached += a;
+                let after = *t + Duration::from_micros(1);
+                assert_abs_diff_eq!(shaper.position(step, after), reached, epsilon = 0.000001);
+            }
+        }
+
+        // a ramp of 20mm at 100mm/s is shaped into the sum of the delayed ramps
+        let ramp = |t: Duration| 100.0 * t.as_secs_f64().min(0.2);
+        for shaper_type in TYPES {
+            let shaper = InputShaper::new(shaper_type, 40.0, 0.1);
+            let pieces = shaper
+                .shape(&[(Duration::from_millis(200), 100.0)])
+                .unwrap();
+            let (mut time, mut position) = (Duration::ZERO, 0.0);
+            for (duration, velocity) in pieces {
+                assert!((0.0..=100.000001).contains(&velocity));
+                time += duration;
+                position += velocity * duration.as_secs_f64();
+                assert_abs_diff_eq!(position, shaper.position(ramp, time), epsilon = 0.000001);
+                let analytic: f64 = shaper
+                    .impulses()
+                    .iter()
+                    .map(|(a, t)| a * ramp(time.saturating_sub(*t)))
+                    .sum();
+                assert_abs_diff_eq!(position, analytic, epsilon = 0.000001);
+            }
+            assert_abs_diff_eq!(position, 20.0, epsilon = 0.000001);
+            let expected = Duration::from_millis(200) + shaper.duration();
+            assert!(time.abs_diff(expected) < Duration::from_micros(1));
+        }
+
+        // the profile of an accelerated move, in pieces
+        let shaper = InputShaper::new(ShaperType::Mzv, 40.0, 0.1);
+        let profile = [
+            (Duration::from_millis(20), 50.0),
+            (Duration::from_millis(100), 100.0),
+            (Duration::from_millis(20), 50.0),
+        ];
+        let pieces = shaper.shape(&profile).unwrap();
+        let distance: f64 = pieces.iter().map(|(d, v)| v * d.as_secs_f64()).sum();
+        assert_abs_diff_eq!(distance, 12.0, epsilon = 0.000001);
+        assert_eq!(shaper.shape(&[(Duration::from_millis(1), 1.0); MAX_PIECES + 1]), None);
+    }
+}
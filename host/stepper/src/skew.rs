@@ -0,0 +1,119 @@
+use core::fmt::Display;
+
+use math::common::precise_sqrt;
+use math::measurements::Distance;
+
+// the axes of the frame aren't square. A factor is the tangent of the angle between two axes
+// minus 90°, e.g. xy is how much X drifts for each mm along Y. The commanded position is moved
+// so that the printed part comes out square
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Skew {
+    pub xy: f64,
+    pub xz: f64,
+    pub yz: f64,
+}
+
+impl Skew {
+    pub fn new(xy: f64, xz: f64, yz: f64) -> Self {
+        Self { xy, xz, yz }
+    }
+
+    // from the commanded position to the one of the steppers
+    pub fn apply(
+        &self,
+        x: Distance,
+        y: Distance,
+        z: Distance,
+    ) -> (Distance, Distance, Distance) {
+        let x = x - y * self.xy - z * (self.xz - self.xy * self.yz);
+        let y = y - z * self.yz;
+        (x, y, z)
+    }
+
+    // from the position of the steppers to the commanded one
+    pub fn revert(
+        &self,
+        x: Distance,
+        y: Distance,
+        z: Distance,
+    ) -> (Distance, Distance, Distance) {
+        let y = y + z * self.yz;
+        let x = x + y * self.xy + z * (self.xz - self.xy * self.yz);
+        (x, y, z)
+    }
+}
+
+impl Display for Skew {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::write!(
+            f,
+            "Skew: [XY:{:.6}] [XZ:{:.6}] [YZ:{:.6}]",
+            self.xy,
+            self.xz,
+            self.yz
+        )
+    }
+}
+
+// factor of a plane from a parallelogram ABCD printed on it, given its diagonals AC and BD and
+// its side AD, measured along the first axis of the plane. The side AB is worked out from the
+// diagonals, the factor is the cotangent of the angle DAB
+pub fn skew_factor(ac: Distance, bd: Distance, ad: Distance) -> Option<f64> {
+    let (ac, bd, ad) = (ac.as_millimeters(), bd.as_millimeters(), ad.as_millimeters());
+    let ab_squared = (2.0 * ac * ac + 2.0 * bd * bd - 4.0 * ad * ad) / 4.0;
+    if ad <= 0.0 || ab_squared <= 0.0 {
+        return None;
+    }
+    let ab = precise_sqrt(ab_squared);
+    let cos = (ac * ac - ab * ab - ad * ad) / (2.0 * ad * ab);
+    if cos.abs() >= 1.0 {
+        return None;
+    }
+    Some(cos / precise_sqrt(1.0 - cos * cos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    fn mm(value: f64) -> Distance {
+        Distance::from_millimeters(value)
+    }
+
+    #[test]
+    fn test_skew_roundtrip() {
+        let skew = Skew::new(0.01, -0.02, 0.005);
+        let (x, y, z) = skew.apply(mm(100.0), mm(50.0), mm(20.0));
+        // X drifts along Y and Z, Y along Z
+        assert_abs_diff_eq!(x.as_millimeters(), 100.0 - 0.5 + 0.401, epsilon = 0.000001);
+        assert_abs_diff_eq!(y.as_millimeters(), 49.9, epsilon = 0.000001);
+        assert_eq!(z, mm(20.0));
+        let (x, y, z) = skew.revert(x, y, z);
+        assert_abs_diff_eq!(x.as_millimeters(), 100.0, epsilon = 0.000001);
+        assert_abs_diff_eq!(y.as_millimeters(), 50.0, epsilon = 0.000001);
+        assert_abs_diff_eq!(z.as_millimeters(), 20.0, epsilon = 0.000001);
+        assert_eq!(Skew::default().apply(mm(1.0), mm(2.0), mm(3.0)), (mm(1.0), mm(2.0), mm(3.0)));
+    }
+
+    #[test]
+    fn test_skew_factor() {
+        // a square has no skew
+        let diagonal = 100.0 * 2f64.sqrt();
+        assert_abs_diff_eq!(
+            skew_factor(mm(diagonal), mm(diagonal), mm(100.0)).unwrap(),
+            0.0,
+            epsilon = 0.000001
+        );
+        // a square of side 100 leaning by 1mm along X: A(0,0) B(1,100) C(101,100) D(100,0)
+        let ac = (101.0f64 * 101.0 + 100.0 * 100.0).sqrt();
+        let bd = (99.0f64 * 99.0 + 100.0 * 100.0).sqrt();
+        let factor = skew_factor(mm(ac), mm(bd), mm(100.0)).unwrap();
+        assert_abs_diff_eq!(factor, 0.01, epsilon = 0.000001);
+        // B lands back over A once the skew is applied
+        let (x, _, _) = Skew::new(factor, 0.0, 0.0).apply(mm(1.0), mm(100.0), mm(0.0));
+        assert_abs_diff_eq!(x.as_millimeters(), 0.0, epsilon = 0.000001);
+        assert_eq!(skew_factor(mm(10.0), mm(10.0), mm(100.0)), None);
+        assert_eq!(skew_factor(mm(ac), mm(bd), mm(0.0)), None);
+    }
+}
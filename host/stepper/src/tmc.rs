@@ -0,0 +1,1091 @@
+use core::f64::consts::SQRT_2;
+use core::fmt::Display;
+
+use common::HalfDuplexSerialBase;
+use math::measurements::{Current, Distance, Resistance, Speed};
+use math::Axis;
+use parser::gcode::GCommand;
+
+use crate::stepper::SteppingMode;
+
+// https://www.analog.com/media/en/technical-documentation/data-sheets/TMC2209_datasheet_rev1.09.pdf
+pub mod register {
+    pub const GCONF: u8 = 0x00;
+    pub const GSTAT: u8 = 0x01;
+    pub const IFCNT: u8 = 0x02;
+    pub const IOIN: u8 = 0x06;
+    pub const IHOLD_IRUN: u8 = 0x10;
+    pub const TPOWERDOWN: u8 = 0x11;
+    pub const TSTEP: u8 = 0x12;
+    pub const TPWMTHRS: u8 = 0x13;
+    pub const TCOOLTHRS: u8 = 0x14;
+    pub const SGTHRS: u8 = 0x40;
+    pub const SG_RESULT: u8 = 0x41;
+    pub const CHOPCONF: u8 = 0x6C;
+    pub const DRV_STATUS: u8 = 0x6F;
+    pub const PWMCONF: u8 = 0x70;
+}
+
+const SYNC: u8 = 0x05;
+const MASTER_ADDRESS: u8 = 0xFF;
+const WRITE_FLAG: u8 = 0x80;
+
+// internal clock frequency
+const CLOCK_FREQUENCY: f64 = 12_000_000.0;
+
+const GCONF_EN_SPREADCYCLE: u32 = 1 << 2;
+const GCONF_PDN_DISABLE: u32 = 1 << 6;
+const GCONF_MSTEP_REG_SELECT: u32 = 1 << 7;
+const GCONF_MULTISTEP_FILT: u32 = 1 << 8;
+
+const CHOPCONF_VSENSE: u32 = 1 << 17;
+const CHOPCONF_MRES_SHIFT: u32 = 24;
+const CHOPCONF_MRES_MASK: u32 = 0x0F << CHOPCONF_MRES_SHIFT;
+const CHOPCONF_INTPOL: u32 = 1 << 28;
+// toff = 3, hstrt = 4, hend = 1, tbl = 2 (power-on defaults suggested by the datasheet)
+const CHOPCONF_DEFAULT: u32 = 3 | (4 << 4) | (1 << 7) | (2 << 15);
+
+const IHOLDDELAY: u32 = 8;
+const TPWMTHRS_MAX: u32 = (1 << 20) - 1;
+const TCOOLTHRS_MAX: u32 = (1 << 20) - 1;
+
+// full scale sense voltage, with vsense cleared or set
+const VFS_LOW_SENSITIVITY: f64 = 0.325;
+const VFS_HIGH_SENSITIVITY: f64 = 0.180;
+// the sense resistor sees an additional ~20mOhm from the internal wiring
+const R_SENSE_OFFSET: f64 = 0.02;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TmcError {
+    Serial,
+    Crc,
+    InvalidResponse,
+    NotSupported,
+}
+
+impl Display for TmcError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match &self {
+            TmcError::Serial => core::write!(f, "Serial communication failed"),
+            TmcError::Crc => core::write!(f, "CRC mismatch"),
+            TmcError::InvalidResponse => core::write!(f, "Invalid response"),
+            TmcError::NotSupported => core::write!(f, "Command not supported"),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct TmcConfig {
+    // slave address selected through MS1/MS2 (0..3)
+    pub address: u8,
+    pub r_sense: Resistance,
+    pub run_current: Current,
+    // hold current as a fraction of the run current
+    pub hold_multiplier: f64,
+    pub stepping_mode: SteppingMode,
+    pub stealth_chop: bool,
+    // speed above which the driver switches from StealthChop to SpreadCycle
+    pub hybrid_threshold: Option<Speed>,
+    // distance covered by a full step, used to convert the hybrid threshold
+    pub distance_per_step: Distance,
+    // StallGuard threshold (SGTHRS), the higher the value the more sensitive the stall detection
+    pub stall_threshold: u8,
+    // run current used while the stall detection is enabled
+    pub homing_current: Option<Current>,
+}
+
+impl Default for TmcConfig {
+    fn default() -> Self {
+        Self {
+            address: 0,
+            r_sense: Resistance::from_ohms(0.11),
+            run_current: Current::from_milliamperes(800.0),
+            hold_multiplier: 0.5,
+            stepping_mode: SteppingMode::SixteenthStep,
+            stealth_chop: true,
+            hybrid_threshold: None,
+            distance_per_step: Distance::from_millimeters(1.0),
+            stall_threshold: 0,
+            homing_current: None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct DriverStatus {
+    pub overtemperature_warning: bool,
+    pub overtemperature: bool,
+    pub short_to_ground_a: bool,
+    pub short_to_ground_b: bool,
+    pub short_to_supply_a: bool,
+    pub short_to_supply_b: bool,
+    pub open_load_a: bool,
+    pub open_load_b: bool,
+    pub stealth_chop: bool,
+    pub standstill: bool,
+    // actual current scale (0..31)
+    pub current_scale: u8,
+}
+
+impl DriverStatus {
+    pub fn is_ok(&self) -> bool {
+        !(self.overtemperature_warning
+            || self.overtemperature
+            || self.short_to_ground_a
+            || self.short_to_ground_b
+            || self.short_to_supply_a
+            || self.short_to_supply_b
+            || self.open_load_a
+            || self.open_load_b)
+    }
+}
+
+impl From<u32> for DriverStatus {
+    fn from(value: u32) -> Self {
+        let bit = |n: u32| value & (1 << n) != 0;
+        Self {
+            overtemperature_warning: bit(0),
+            overtemperature: bit(1),
+            short_to_ground_a: bit(2),
+            short_to_ground_b: bit(3),
+            short_to_supply_a: bit(4),
+            short_to_supply_b: bit(5),
+            open_load_a: bit(6),
+            open_load_b: bit(7),
+            current_scale: ((value >> 16) & 0x1F) as u8,
+            stealth_chop: bit(30),
+            standstill: bit(31),
+        }
+    }
+}
+
+impl Display for DriverStatus {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mode = if self.stealth_chop {
+            "stealthChop"
+        } else {
+            "spreadCycle"
+        };
+        core::write!(f, "[{}] [CS:{}]", mode, self.current_scale)?;
+        if self.standstill {
+            core::write!(f, " [standstill]")?;
+        }
+        if self.overtemperature {
+            core::write!(f, " [overtemperature]")?;
+        } else if self.overtemperature_warning {
+            core::write!(f, " [overtemperature warning]")?;
+        }
+        if self.short_to_ground_a || self.short_to_ground_b {
+            core::write!(f, " [short to ground]")?;
+        }
+        if self.short_to_supply_a || self.short_to_supply_b {
+            core::write!(f, " [short to supply]")?;
+        }
+        if self.open_load_a || self.open_load_b {
+            core::write!(f, " [open load]")?;
+        }
+        Ok(())
+    }
+}
+
+// CRC8 with polynomial x^8 + x^2 + x + 1, bytes are processed LSB first
+fn crc(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for byte in data {
+        let mut byte = *byte;
+        for _ in 0..8 {
+            if ((crc >> 7) ^ (byte & 0x01)) != 0 {
+                crc = (crc << 1) ^ 0x07;
+            } else {
+                crc <<= 1;
+            }
+            byte >>= 1;
+        }
+    }
+    crc
+}
+
+// the registers that are written by the driver are cached, some of them
+// (e.g. IHOLD_IRUN, TPWMTHRS) are write-only and can't be read back
+pub struct Tmc2209 {
+    address: u8,
+    r_sense: Resistance,
+    hold_multiplier: f64,
+    distance_per_step: Distance,
+    run_current: Current,
+    homing_current: Option<Current>,
+    stall_threshold: u8,
+    // GCONF to restore once the stall detection is disabled
+    stall_detection_gconf: Option<u32>,
+    gconf: u32,
+    chopconf: u32,
+    ihold_irun: u32,
+    tpwmthrs: u32,
+}
+
+impl Tmc2209 {
+    pub fn new(config: TmcConfig) -> Self {
+        let mut gconf = GCONF_PDN_DISABLE | GCONF_MSTEP_REG_SELECT | GCONF_MULTISTEP_FILT;
+        if !config.stealth_chop {
+            gconf |= GCONF_EN_SPREADCYCLE;
+        }
+        let mut driver = Self {
+            address: config.address,
+            r_sense: config.r_sense,
+            hold_multiplier: config.hold_multiplier,
+            distance_per_step: config.distance_per_step,
+            run_current: config.run_current,
+            homing_current: config.homing_current,
+            stall_threshold: config.stall_threshold,
+            stall_detection_gconf: None,
+            gconf,
+            chopconf: CHOPCONF_DEFAULT | CHOPCONF_INTPOL,
+            ihold_irun: 0,
+            tpwmthrs: 0,
+        };
+        driver.chopconf = driver.chopconf_with_microsteps(config.stepping_mode);
+        (driver.chopconf, driver.ihold_irun) = driver.current_registers(config.run_current);
+        if let Some(speed) = config.hybrid_threshold {
+            driver.tpwmthrs = driver.tpwmthrs_from_speed(speed);
+        }
+        driver
+    }
+
+    pub fn get_address(&self) -> u8 {
+        self.address
+    }
+
+    // write the whole configuration to the driver
+    pub async fn init<S: HalfDuplexSerialBase>(&mut self, serial: &mut S) -> Result<(), TmcError> {
+        self.write_register(serial, register::GCONF, self.gconf)
+            .await?;
+        self.write_register(serial, register::CHOPCONF, self.chopconf)
+            .await?;
+        self.write_register(serial, register::IHOLD_IRUN, self.ihold_irun)
+            .await?;
+        self.write_register(serial, register::TPWMTHRS, self.tpwmthrs)
+            .await?;
+        self.write_register(serial, register::SGTHRS, u32::from(self.stall_threshold))
+            .await
+    }
+
+    pub async fn write_register<S: HalfDuplexSerialBase>(
+        &mut self,
+        serial: &mut S,
+        register: u8,
+        value: u32,
+    ) -> Result<(), TmcError> {
+        let mut datagram = [0u8; 8];
+        datagram[0] = SYNC;
+        datagram[1] = self.address;
+        datagram[2] = register | WRITE_FLAG;
+        datagram[3..7].copy_from_slice(&value.to_be_bytes());
+        datagram[7] = crc(&datagram[..7]);
+        serial.write(&datagram).await.map_err(|_| TmcError::Serial)
+    }
+
+    pub async fn read_register<S: HalfDuplexSerialBase>(
+        &mut self,
+        serial: &mut S,
+        register: u8,
+    ) -> Result<u32, TmcError> {
+        let mut request = [SYNC, self.address, register & !WRITE_FLAG, 0];
+        request[3] = crc(&request[..3]);
+        serial.write(&request).await.map_err(|_| TmcError::Serial)?;
+
+        let mut response = [0u8; 8];
+        serial
+            .read(&mut response)
+            .await
+            .map_err(|_| TmcError::Serial)?;
+        if crc(&response[..7]) != response[7] {
+            return Err(TmcError::Crc);
+        }
+        if response[0] & 0x0F != SYNC
+            || response[1] != MASTER_ADDRESS
+            || response[2] != register & !WRITE_FLAG
+        {
+            return Err(TmcError::InvalidResponse);
+        }
+        let mut value = [0u8; 4];
+        value.copy_from_slice(&response[3..7]);
+        Ok(u32::from_be_bytes(value))
+    }
+
+    // the current scale CS is computed as:
+    // I_rms = (CS + 1) / 32 * V_fs / (R_sense + 20mOhm) / sqrt(2)
+    // if the resulting CS is too low the high sensitivity range (vsense) is used
+    fn current_registers(&self, run_current: Current) -> (u32, u32) {
+        let r = self.r_sense.as_ohms() + R_SENSE_OFFSET;
+        let scale = |vfs: f64| 32.0 * SQRT_2 * run_current.as_amperes() * r / vfs - 1.0;
+        let mut chopconf = self.chopconf & !CHOPCONF_VSENSE;
+        let mut cs = scale(VFS_LOW_SENSITIVITY);
+        if cs < 16.0 {
+            chopconf |= CHOPCONF_VSENSE;
+            cs = scale(VFS_HIGH_SENSITIVITY);
+        }
+        let irun = cs.clamp(0.0, 31.0) as u32;
+        let ihold = (irun as f64 * self.hold_multiplier).clamp(0.0, 31.0) as u32;
+        (chopconf, ihold | (irun << 8) | (IHOLDDELAY << 16))
+    }
+
+    pub async fn set_current<S: HalfDuplexSerialBase>(
+        &mut self,
+        serial: &mut S,
+        run_current: Current,
+    ) -> Result<(), TmcError> {
+        self.write_current(serial, run_current).await?;
+        self.run_current = run_current;
+        Ok(())
+    }
+
+    async fn write_current<S: HalfDuplexSerialBase>(
+        &mut self,
+        serial: &mut S,
+        run_current: Current,
+    ) -> Result<(), TmcError> {
+        let (chopconf, ihold_irun) = self.current_registers(run_current);
+        if chopconf != self.chopconf {
+            self.write_register(serial, register::CHOPCONF, chopconf)
+                .await?;
+            self.chopconf = chopconf;
+        }
+        self.write_register(serial, register::IHOLD_IRUN, ihold_irun)
+            .await?;
+        self.ihold_irun = ihold_irun;
+        Ok(())
+    }
+
+    fn current_from_scale(&self, cs: u32) -> Current {
+        let vfs = if self.chopconf & CHOPCONF_VSENSE != 0 {
+            VFS_HIGH_SENSITIVITY
+        } else {
+            VFS_LOW_SENSITIVITY
+        };
+        let r = self.r_sense.as_ohms() + R_SENSE_OFFSET;
+        Current::from_amperes((cs as f64 + 1.0) / 32.0 * vfs / r / SQRT_2)
+    }
+
+    pub fn get_run_current(&self) -> Current {
+        self.current_from_scale((self.ihold_irun >> 8) & 0x1F)
+    }
+
+    pub fn get_hold_current(&self) -> Current {
+        self.current_from_scale(self.ihold_irun & 0x1F)
+    }
+
+    fn chopconf_with_microsteps(&self, mode: SteppingMode) -> u32 {
+        // MRES = 0 -> 256 microsteps, MRES = 8 -> full step
+        let mres = 8 - u8::from(mode).trailing_zeros();
+        (self.chopconf & !CHOPCONF_MRES_MASK) | (mres << CHOPCONF_MRES_SHIFT)
+    }
+
+    pub async fn set_stepping_mode<S: HalfDuplexSerialBase>(
+        &mut self,
+        serial: &mut S,
+        mode: SteppingMode,
+    ) -> Result<(), TmcError> {
+        let chopconf = self.chopconf_with_microsteps(mode);
+        self.write_register(serial, register::CHOPCONF, chopconf)
+            .await?;
+        self.chopconf = chopconf;
+        Ok(())
+    }
+
+    pub fn get_microsteps(&self) -> u16 {
+        let mres = (self.chopconf & CHOPCONF_MRES_MASK) >> CHOPCONF_MRES_SHIFT;
+        256 >> mres
+    }
+
+    pub async fn set_stealth_chop<S: HalfDuplexSerialBase>(
+        &mut self,
+        serial: &mut S,
+        enabled: bool,
+    ) -> Result<(), TmcError> {
+        let gconf = if enabled {
+            self.gconf & !GCONF_EN_SPREADCYCLE
+        } else {
+            self.gconf | GCONF_EN_SPREADCYCLE
+        };
+        self.write_register(serial, register::GCONF, gconf).await?;
+        self.gconf = gconf;
+        Ok(())
+    }
+
+    pub fn is_stealth_chop_enabled(&self) -> bool {
+        self.gconf & GCONF_EN_SPREADCYCLE == 0
+    }
+
+    // TSTEP is the time between two 1/256 microsteps in clock cycles, so it doesn't
+    // depend on the microstepping resolution:
+    // TSTEP = f_clk * distance_per_step / (256 * speed)
+    fn tpwmthrs_from_speed(&self, speed: Speed) -> u32 {
+        let speed = speed.as_meters_per_second();
+        if speed <= 0.0 {
+            return 0;
+        }
+        let tstep = CLOCK_FREQUENCY * self.distance_per_step.as_meters() / (256.0 * speed);
+        (tstep as u32).min(TPWMTHRS_MAX)
+    }
+
+    pub async fn set_hybrid_threshold<S: HalfDuplexSerialBase>(
+        &mut self,
+        serial: &mut S,
+        speed: Speed,
+    ) -> Result<(), TmcError> {
+        let tpwmthrs = self.tpwmthrs_from_speed(speed);
+        self.write_register(serial, register::TPWMTHRS, tpwmthrs)
+            .await?;
+        self.tpwmthrs = tpwmthrs;
+        Ok(())
+    }
+
+    pub fn get_hybrid_threshold(&self) -> Option<Speed> {
+        if self.tpwmthrs == 0 {
+            return None;
+        }
+        let speed =
+            CLOCK_FREQUENCY * self.distance_per_step.as_meters() / (256.0 * self.tpwmthrs as f64);
+        Some(Speed::from_meters_per_second(speed))
+    }
+
+    pub async fn set_stall_threshold<S: HalfDuplexSerialBase>(
+        &mut self,
+        serial: &mut S,
+        threshold: u8,
+    ) -> Result<(), TmcError> {
+        self.write_register(serial, register::SGTHRS, u32::from(threshold))
+            .await?;
+        self.stall_threshold = threshold;
+        Ok(())
+    }
+
+    pub fn get_stall_threshold(&self) -> u8 {
+        self.stall_threshold
+    }
+
+    pub async fn get_stall_result<S: HalfDuplexSerialBase>(
+        &mut self,
+        serial: &mut S,
+    ) -> Result<u16, TmcError> {
+        let value = self.read_register(serial, register::SG_RESULT).await?;
+        Ok((value & 0x3FF) as u16)
+    }
+
+    pub fn is_stall_detection_enabled(&self) -> bool {
+        self.stall_detection_gconf.is_some()
+    }
+
+    // StallGuard4 works only in StealthChop mode, and DIAG is raised only when TSTEP is
+    // below TCOOLTHRS, so the threshold is set to the maximum while homing
+    pub async fn enable_stall_detection<S: HalfDuplexSerialBase>(
+        &mut self,
+        serial: &mut S,
+    ) -> Result<(), TmcError> {
+        if self.is_stall_detection_enabled() {
+            return Ok(());
+        }
+        let gconf = self.gconf;
+        if let Some(current) = self.homing_current {
+            self.write_current(serial, current).await?;
+        }
+        self.write_register(serial, register::SGTHRS, u32::from(self.stall_threshold))
+            .await?;
+        self.write_register(serial, register::TCOOLTHRS, TCOOLTHRS_MAX)
+            .await?;
+        self.set_stealth_chop(serial, true).await?;
+        self.stall_detection_gconf = Some(gconf);
+        Ok(())
+    }
+
+    pub async fn disable_stall_detection<S: HalfDuplexSerialBase>(
+        &mut self,
+        serial: &mut S,
+    ) -> Result<(), TmcError> {
+        let gconf = match self.stall_detection_gconf {
+            Some(gconf) => gconf,
+            None => return Ok(()),
+        };
+        self.write_register(serial, register::TCOOLTHRS, 0).await?;
+        self.write_register(serial, register::GCONF, gconf).await?;
+        self.gconf = gconf;
+        self.write_current(serial, self.run_current).await?;
+        self.stall_detection_gconf = None;
+        Ok(())
+    }
+
+    pub async fn get_status<S: HalfDuplexSerialBase>(
+        &mut self,
+        serial: &mut S,
+    ) -> Result<DriverStatus, TmcError> {
+        let value = self.read_register(serial, register::DRV_STATUS).await?;
+        Ok(DriverStatus::from(value))
+    }
+}
+
+// the drivers of the machine, sharing the same single-wire UART
+pub struct TmcDrivers<S: HalfDuplexSerialBase> {
+    serial: S,
+    x: Option<Tmc2209>,
+    y: Option<Tmc2209>,
+    z: Option<Tmc2209>,
+    e: Option<Tmc2209>,
+}
+
+impl<S: HalfDuplexSerialBase> TmcDrivers<S> {
+    pub fn new(
+        serial: S,
+        drivers: (
+            Option<Tmc2209>,
+            Option<Tmc2209>,
+            Option<Tmc2209>,
+            Option<Tmc2209>,
+        ),
+    ) -> Self {
+        Self {
+            serial,
+            x: drivers.0,
+            y: drivers.1,
+            z: drivers.2,
+            e: drivers.3,
+        }
+    }
+
+    pub async fn init(&mut self) -> Result<(), TmcError> {
+        for driver in [&mut self.x, &mut self.y, &mut self.z, &mut self.e]
+            .into_iter()
+            .flatten()
+        {
+            driver.init(&mut self.serial).await?;
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, axis: Axis) -> Option<&Tmc2209> {
+        match axis {
+            Axis::X => self.x.as_ref(),
+            Axis::Y => self.y.as_ref(),
+            Axis::Z => self.z.as_ref(),
+            Axis::E => self.e.as_ref(),
+        }
+    }
+
+    // the driver of the axis together with the shared serial line
+    pub fn get_mut(&mut self, axis: Axis) -> Option<(&mut Tmc2209, &mut S)> {
+        let driver = match axis {
+            Axis::X => self.x.as_mut(),
+            Axis::Y => self.y.as_mut(),
+            Axis::Z => self.z.as_mut(),
+            Axis::E => self.e.as_mut(),
+        }?;
+        Some((driver, &mut self.serial))
+    }
+
+    pub async fn execute(&mut self, command: GCommand) -> Result<(), TmcError> {
+        match command {
+            GCommand::M906 { x, y, z, e } => self.m906((x, y, z, e)).await,
+            GCommand::M569 { s, x, y, z, e } => self.m569(s, (x, y, z, e)).await,
+            GCommand::M913 { x, y, z, e } => self.m913((x, y, z, e)).await,
+            GCommand::M914 { x, y, z } => self.m914((x, y, z)).await,
+            _ => Err(TmcError::NotSupported),
+        }
+    }
+
+    // set run current
+    async fn m906(
+        &mut self,
+        currents: (
+            Option<Current>,
+            Option<Current>,
+            Option<Current>,
+            Option<Current>,
+        ),
+    ) -> Result<(), TmcError> {
+        for (driver, current) in [
+            (&mut self.x, currents.0),
+            (&mut self.y, currents.1),
+            (&mut self.z, currents.2),
+            (&mut self.e, currents.3),
+        ] {
+            if let (Some(driver), Some(current)) = (driver, current) {
+                driver.set_current(&mut self.serial, current).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // set StealthChop (s = true) or SpreadCycle (s = false)
+    async fn m569(
+        &mut self,
+        stealth_chop: bool,
+        enabled: (bool, bool, bool, bool),
+    ) -> Result<(), TmcError> {
+        for (driver, enabled) in [
+            (&mut self.x, enabled.0),
+            (&mut self.y, enabled.1),
+            (&mut self.z, enabled.2),
+            (&mut self.e, enabled.3),
+        ] {
+            if let (Some(driver), true) = (driver, enabled) {
+                driver
+                    .set_stealth_chop(&mut self.serial, stealth_chop)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    // set hybrid threshold
+    async fn m913(
+        &mut self,
+        speeds: (Option<Speed>, Option<Speed>, Option<Speed>, Option<Speed>),
+    ) -> Result<(), TmcError> {
+        for (driver, speed) in [
+            (&mut self.x, speeds.0),
+            (&mut self.y, speeds.1),
+            (&mut self.z, speeds.2),
+            (&mut self.e, speeds.3),
+        ] {
+            if let (Some(driver), Some(speed)) = (driver, speed) {
+                driver.set_hybrid_threshold(&mut self.serial, speed).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // set stall sensitivity
+    async fn m914(
+        &mut self,
+        thresholds: (Option<u8>, Option<u8>, Option<u8>),
+    ) -> Result<(), TmcError> {
+        for (driver, threshold) in [
+            (&mut self.x, thresholds.0),
+            (&mut self.y, thresholds.1),
+            (&mut self.z, thresholds.2),
+        ] {
+            if let (Some(driver), Some(threshold)) = (driver, threshold) {
+                driver
+                    .set_stall_threshold(&mut self.serial, threshold)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    // DRV_STATUS of every configured driver (M122)
+    pub async fn get_status(
+        &mut self,
+    ) -> (
+        Option<Result<DriverStatus, TmcError>>,
+        Option<Result<DriverStatus, TmcError>>,
+        Option<Result<DriverStatus, TmcError>>,
+        Option<Result<DriverStatus, TmcError>>,
+    ) {
+        let mut status = [None; 4];
+        for (i, driver) in [&mut self.x, &mut self.y, &mut self.z, &mut self.e]
+            .into_iter()
+            .enumerate()
+        {
+            if let Some(driver) = driver {
+                status[i] = Some(driver.get_status(&mut self.serial).await);
+            }
+        }
+        (status[0], status[1], status[2], status[3])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use sim::block_on;
+
+    // emulates the drivers attached to the line: write datagrams update the registers,
+    // read requests prepare the response that is returned by the next read
+    struct SerialMock {
+        datagrams: Vec<Vec<u8>>,
+        registers: [[u32; 128]; 4],
+        response: Option<[u8; 8]>,
+        corrupt_response: bool,
+    }
+
+    impl SerialMock {
+        fn new() -> Self {
+            Self {
+                datagrams: Vec::new(),
+                registers: [[0; 128]; 4],
+                response: None,
+                corrupt_response: false,
+            }
+        }
+
+        fn register(&self, address: u8, register: u8) -> u32 {
+            self.registers[address as usize][register as usize]
+        }
+    }
+
+    impl HalfDuplexSerialBase for SerialMock {
+        type Error = ();
+
+        async fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            let crc_index = data.len() - 1;
+            assert_eq!(crc(&data[..crc_index]), data[crc_index]);
+            assert_eq!(data[0], SYNC);
+            self.datagrams.push(data.to_vec());
+            let address = data[1] as usize;
+            let register = data[2] & !WRITE_FLAG;
+            match data.len() {
+                8 => {
+                    assert!(data[2] & WRITE_FLAG != 0);
+                    let value = u32::from_be_bytes([data[3], data[4], data[5], data[6]]);
+                    self.registers[address][register as usize] = value;
+                }
+                4 => {
+                    let value = self.registers[address][register as usize];
+                    let mut response = [0u8; 8];
+                    response[0] = SYNC;
+                    response[1] = MASTER_ADDRESS;
+                    response[2] = register;
+                    response[3..7].copy_from_slice(&value.to_be_bytes());
+                    response[7] = crc(&response[..7]);
+                    if self.corrupt_response {
+                        response[7] = !response[7];
+                    }
+                    self.response = Some(response);
+                }
+                _ => panic!("Invalid datagram length"),
+            }
+            Ok(())
+        }
+
+        async fn read(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
+            let response = self.response.take().ok_or(())?;
+            data.copy_from_slice(&response);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_crc() {
+        // read request of IFCNT from slave 0
+        assert_eq!(crc(&[0x05, 0x00, 0x02]), 0x8F);
+        assert_eq!(crc(&[]), 0);
+    }
+
+    #[test]
+    fn test_write_register() {
+        block_on(async {
+            let mut serial = SerialMock::new();
+            let mut driver = Tmc2209::new(TmcConfig {
+                address: 2,
+                ..Default::default()
+            });
+            driver
+                .write_register(&mut serial, register::TPOWERDOWN, 0x14)
+                .await
+                .unwrap();
+            assert_eq!(serial.datagrams.len(), 1);
+            let datagram = &serial.datagrams[0];
+            assert_eq!(datagram[..7], [0x05, 0x02, 0x91, 0x00, 0x00, 0x00, 0x14]);
+            assert_eq!(serial.register(2, register::TPOWERDOWN), 0x14);
+        });
+    }
+
+    #[test]
+    fn test_read_register() {
+        block_on(async {
+            let mut serial = SerialMock::new();
+            serial.registers[1][register::IOIN as usize] = 0x2100_0040;
+            let mut driver = Tmc2209::new(TmcConfig {
+                address: 1,
+                ..Default::default()
+            });
+            let value = driver
+                .read_register(&mut serial, register::IOIN)
+                .await
+                .unwrap();
+            assert_eq!(value, 0x2100_0040);
+            assert_eq!(serial.datagrams[0].len(), 4);
+        });
+    }
+
+    #[test]
+    fn test_read_register_crc_error() {
+        block_on(async {
+            let mut serial = SerialMock::new();
+            serial.corrupt_response = true;
+            let mut driver = Tmc2209::new(TmcConfig::default());
+            let res = driver.read_register(&mut serial, register::GCONF).await;
+            assert_eq!(res, Err(TmcError::Crc));
+        });
+    }
+
+    #[test]
+    fn test_init() {
+        block_on(async {
+            let mut serial = SerialMock::new();
+            let mut driver = Tmc2209::new(TmcConfig::default());
+            driver.init(&mut serial).await.unwrap();
+            assert_eq!(serial.datagrams.len(), 5);
+            let gconf = serial.register(0, register::GCONF);
+            assert!(gconf & GCONF_PDN_DISABLE != 0);
+            assert!(gconf & GCONF_MSTEP_REG_SELECT != 0);
+            assert!(gconf & GCONF_EN_SPREADCYCLE == 0);
+            let chopconf = serial.register(0, register::CHOPCONF);
+            // 16 microsteps -> MRES = 4
+            assert_eq!((chopconf & CHOPCONF_MRES_MASK) >> CHOPCONF_MRES_SHIFT, 4);
+            assert_eq!(serial.register(0, register::TPWMTHRS), 0);
+        });
+    }
+
+    #[test]
+    fn test_set_current() {
+        block_on(async {
+            let mut serial = SerialMock::new();
+            let mut driver = Tmc2209::new(TmcConfig::default());
+            driver
+                .set_current(&mut serial, Current::from_milliamperes(800.0))
+                .await
+                .unwrap();
+            let ihold_irun = serial.register(0, register::IHOLD_IRUN);
+            // 32 * sqrt(2) * 0.8 * 0.13 / 0.325 - 1 = 13.48 -> vsense is set
+            // 32 * sqrt(2) * 0.8 * 0.13 / 0.180 - 1 = 25.14
+            assert_eq!((ihold_irun >> 8) & 0x1F, 25);
+            assert_eq!(ihold_irun & 0x1F, 12);
+            assert_eq!((ihold_irun >> 16) & 0x0F, IHOLDDELAY);
+            assert!(driver.chopconf & CHOPCONF_VSENSE != 0);
+            assert_abs_diff_eq!(
+                driver.get_run_current().as_milliamperes(),
+                800.0,
+                epsilon = 30.0
+            );
+            assert_abs_diff_eq!(
+                driver.get_hold_current().as_milliamperes(),
+                400.0,
+                epsilon = 30.0
+            );
+        });
+    }
+
+    #[test]
+    fn test_set_current_high() {
+        block_on(async {
+            let mut serial = SerialMock::new();
+            let mut driver = Tmc2209::new(TmcConfig::default());
+            driver
+                .set_current(&mut serial, Current::from_milliamperes(1700.0))
+                .await
+                .unwrap();
+            let ihold_irun = serial.register(0, register::IHOLD_IRUN);
+            // 32 * sqrt(2) * 1.7 * 0.13 / 0.325 - 1 = 29.77
+            assert_eq!((ihold_irun >> 8) & 0x1F, 29);
+            assert_eq!(serial.register(0, register::CHOPCONF) & CHOPCONF_VSENSE, 0);
+        });
+    }
+
+    #[test]
+    fn test_set_current_saturated() {
+        block_on(async {
+            let mut serial = SerialMock::new();
+            let mut driver = Tmc2209::new(TmcConfig::default());
+            driver
+                .set_current(&mut serial, Current::from_milliamperes(5000.0))
+                .await
+                .unwrap();
+            let ihold_irun = serial.register(0, register::IHOLD_IRUN);
+            assert_eq!((ihold_irun >> 8) & 0x1F, 31);
+        });
+    }
+
+    #[test]
+    fn test_set_stepping_mode() {
+        block_on(async {
+            let mut serial = SerialMock::new();
+            let mut driver = Tmc2209::new(TmcConfig::default());
+            driver
+                .set_stepping_mode(&mut serial, SteppingMode::FullStep)
+                .await
+                .unwrap();
+            assert_eq!(driver.get_microsteps(), 1);
+            let chopconf = serial.register(0, register::CHOPCONF);
+            assert_eq!((chopconf & CHOPCONF_MRES_MASK) >> CHOPCONF_MRES_SHIFT, 8);
+            driver
+                .set_stepping_mode(&mut serial, SteppingMode::EighthStep)
+                .await
+                .unwrap();
+            assert_eq!(driver.get_microsteps(), 8);
+        });
+    }
+
+    #[test]
+    fn test_set_stealth_chop() {
+        block_on(async {
+            let mut serial = SerialMock::new();
+            let mut driver = Tmc2209::new(TmcConfig::default());
+            driver.set_stealth_chop(&mut serial, false).await.unwrap();
+            assert!(!driver.is_stealth_chop_enabled());
+            assert!(serial.register(0, register::GCONF) & GCONF_EN_SPREADCYCLE != 0);
+            driver.set_stealth_chop(&mut serial, true).await.unwrap();
+            assert!(driver.is_stealth_chop_enabled());
+            assert!(serial.register(0, register::GCONF) & GCONF_EN_SPREADCYCLE == 0);
+        });
+    }
+
+    #[test]
+    fn test_set_hybrid_threshold() {
+        block_on(async {
+            let mut serial = SerialMock::new();
+            let mut driver = Tmc2209::new(TmcConfig {
+                distance_per_step: Distance::from_millimeters(0.2),
+                ..Default::default()
+            });
+            driver
+                .set_hybrid_threshold(&mut serial, Speed::from_meters_per_second(0.1))
+                .await
+                .unwrap();
+            // 12MHz * 0.2mm / (256 * 100mm/s) = 93.75
+            assert_eq!(serial.register(0, register::TPWMTHRS), 93);
+            assert_abs_diff_eq!(
+                driver
+                    .get_hybrid_threshold()
+                    .unwrap()
+                    .as_meters_per_second(),
+                0.1,
+                epsilon = 0.001
+            );
+            driver
+                .set_hybrid_threshold(&mut serial, Speed::from_meters_per_second(0.0))
+                .await
+                .unwrap();
+            assert!(driver.get_hybrid_threshold().is_none());
+        });
+    }
+
+    #[test]
+    fn test_stall_detection() {
+        block_on(async {
+            let mut serial = SerialMock::new();
+            let mut driver = Tmc2209::new(TmcConfig {
+                stealth_chop: false,
+                stall_threshold: 80,
+                homing_current: Some(Current::from_milliamperes(400.0)),
+                ..Default::default()
+            });
+            driver.init(&mut serial).await.unwrap();
+            let ihold_irun = serial.register(0, register::IHOLD_IRUN);
+
+            driver.enable_stall_detection(&mut serial).await.unwrap();
+            assert!(driver.is_stall_detection_enabled());
+            assert_eq!(serial.register(0, register::SGTHRS), 80);
+            assert_eq!(serial.register(0, register::TCOOLTHRS), TCOOLTHRS_MAX);
+            assert!(serial.register(0, register::GCONF) & GCONF_EN_SPREADCYCLE == 0);
+            assert_abs_diff_eq!(
+                driver.get_run_current().as_milliamperes(),
+                400.0,
+                epsilon = 30.0
+            );
+
+            driver.disable_stall_detection(&mut serial).await.unwrap();
+            assert!(!driver.is_stall_detection_enabled());
+            assert_eq!(serial.register(0, register::TCOOLTHRS), 0);
+            assert!(serial.register(0, register::GCONF) & GCONF_EN_SPREADCYCLE != 0);
+            assert_eq!(serial.register(0, register::IHOLD_IRUN), ihold_irun);
+            assert!(!driver.is_stealth_chop_enabled());
+        });
+    }
+
+    #[test]
+    fn test_get_status() {
+        block_on(async {
+            let mut serial = SerialMock::new();
+            // otpw, s2ga, ola, CS = 20, stealth
+            serial.registers[0][register::DRV_STATUS as usize] =
+                (1 << 0) | (1 << 2) | (1 << 6) | (20 << 16) | (1 << 30);
+            let mut driver = Tmc2209::new(TmcConfig::default());
+            let status = driver.get_status(&mut serial).await.unwrap();
+            assert!(status.overtemperature_warning);
+            assert!(!status.overtemperature);
+            assert!(status.short_to_ground_a);
+            assert!(!status.short_to_ground_b);
+            assert!(status.open_load_a);
+            assert!(!status.open_load_b);
+            assert!(status.stealth_chop);
+            assert!(!status.standstill);
+            assert_eq!(status.current_scale, 20);
+            assert!(!status.is_ok());
+        });
+    }
+
+    #[test]
+    fn test_drivers_execute() {
+        block_on(async {
+            let serial = SerialMock::new();
+            let mut drivers = TmcDrivers::new(
+                serial,
+                (
+                    Some(Tmc2209::new(TmcConfig {
+                        address: 0,
+                        ..Default::default()
+                    })),
+                    None,
+                    Some(Tmc2209::new(TmcConfig {
+                        address: 2,
+                        ..Default::default()
+                    })),
+                    None,
+                ),
+            );
+            drivers
+                .execute(GCommand::M569 {
+                    s: false,
+                    x: false,
+                    y: true,
+                    z: true,
+                    e: false,
+                })
+                .await
+                .unwrap();
+            assert!(drivers.get(Axis::X).unwrap().is_stealth_chop_enabled());
+            assert!(!drivers.get(Axis::Z).unwrap().is_stealth_chop_enabled());
+            assert!(drivers.get(Axis::Y).is_none());
+            assert_eq!(drivers.serial.datagrams.len(), 1);
+            assert_eq!(drivers.serial.datagrams[0][1], 2);
+
+            drivers
+                .execute(GCommand::M906 {
+                    x: Some(Current::from_milliamperes(1700.0)),
+                    y: None,
+                    z: None,
+                    e: Some(Current::from_milliamperes(1000.0)),
+                })
+                .await
+                .unwrap();
+            assert_eq!(
+                (drivers.serial.register(0, register::IHOLD_IRUN) >> 8) & 0x1F,
+                29
+            );
+            assert_eq!(drivers.serial.register(2, register::IHOLD_IRUN), 0);
+
+            drivers
+                .execute(GCommand::M914 {
+                    x: Some(100),
+                    y: Some(20),
+                    z: None,
+                })
+                .await
+                .unwrap();
+            assert_eq!(drivers.get(Axis::X).unwrap().get_stall_threshold(), 100);
+            assert_eq!(drivers.serial.register(0, register::SGTHRS), 100);
+            assert_eq!(drivers.get(Axis::Z).unwrap().get_stall_threshold(), 0);
+
+            let res = drivers.execute(GCommand::M114).await;
+            assert_eq!(res, Err(TmcError::NotSupported));
+
+            let status = drivers.get_status().await;
+            assert!(status.0.unwrap().unwrap().is_ok());
+            assert!(status.1.is_none());
+            assert!(status.2.is_some());
+            assert!(status.3.is_none());
+        });
+    }
+}
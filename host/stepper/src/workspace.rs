@@ -0,0 +1,94 @@
+use math::measurements::Distance;
+
+// the coordinate systems selected by G54 to G59
+pub const WORKSPACES: usize = 6;
+
+// offsets of X, Y and Z from machine to G-code coordinates, on top of the home offsets. Each
+// coordinate system keeps its own, set by G10 L2 and L20. The shift of G92 is applied on top of
+// them whichever is in use. The steppers and their bounds stay in machine coordinates, homing
+// doesn't change the offsets
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Workspaces {
+    offsets: [[Distance; 3]; WORKSPACES],
+    active: usize,
+}
+
+impl Default for Workspaces {
+    fn default() -> Self {
+        Self {
+            offsets: [[Distance::from_millimeters(0.0); 3]; WORKSPACES],
+            active: 0,
+        }
+    }
+}
+
+impl Workspaces {
+    // from 0 (G54) to 5 (G59), the others are ignored
+    pub fn select(&mut self, index: usize) {
+        if index < WORKSPACES {
+            self.active = index;
+        }
+    }
+
+    pub fn get_active(&self) -> usize {
+        self.active
+    }
+
+    // the coordinate system P of G10, from 1 (G54) to 6 (G59) or 0 for the one in use
+    pub fn index(&self, p: Option<u8>) -> Option<usize> {
+        match p {
+            None | Some(0) => Some(self.active),
+            Some(p) if (p as usize) <= WORKSPACES => Some(p as usize - 1),
+            Some(_) => None,
+        }
+    }
+
+    pub fn offset(&self) -> [Distance; 3] {
+        self.offsets[self.active]
+    }
+
+    pub fn get_offset(&self, index: usize) -> Option<[Distance; 3]> {
+        self.offsets.get(index).copied()
+    }
+
+    // from 0 (G54) to 5 (G59), the others are ignored
+    pub fn set_offset(&mut self, index: usize, offset: [Distance; 3]) {
+        if let Some(o) = self.offsets.get_mut(index) {
+            *o = offset;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mm(value: f64) -> Distance {
+        Distance::from_millimeters(value)
+    }
+
+    #[test]
+    fn test_workspaces() {
+        let mut workspaces = Workspaces::default();
+        assert_eq!(workspaces.get_active(), 0);
+        assert_eq!(workspaces.offset(), [mm(0.0); 3]);
+        workspaces.set_offset(0, [mm(10.0), mm(-5.0), mm(0.0)]);
+        // each coordinate system keeps its own offset
+        workspaces.set_offset(2, [mm(1.0), mm(2.0), mm(3.0)]);
+        workspaces.set_offset(6, [mm(4.0); 3]);
+        assert_eq!(workspaces.offset(), [mm(10.0), mm(-5.0), mm(0.0)]);
+        workspaces.select(2);
+        assert_eq!(workspaces.offset(), [mm(1.0), mm(2.0), mm(3.0)]);
+        workspaces.select(6);
+        assert_eq!(workspaces.get_active(), 2);
+        assert_eq!(workspaces.get_offset(1), Some([mm(0.0); 3]));
+        assert_eq!(workspaces.get_offset(6), None);
+
+        // P of G10
+        assert_eq!(workspaces.index(None), Some(2));
+        assert_eq!(workspaces.index(Some(0)), Some(2));
+        assert_eq!(workspaces.index(Some(1)), Some(0));
+        assert_eq!(workspaces.index(Some(6)), Some(5));
+        assert_eq!(workspaces.index(Some(7)), None);
+    }
+}
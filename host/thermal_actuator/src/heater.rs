@@ -1,7 +1,10 @@
 use core::time::Duration;
 
 use common::{PidConfig, PwmBase};
-use math::{measurements::Temperature, pid::PID};
+use math::{
+    measurements::Temperature,
+    pid::{PidError, PID},
+};
 
 pub struct Heater<P: PwmBase> {
     ch: P::Channel,
@@ -52,7 +55,12 @@ impl<P: PwmBase> Heater<P> {
         pwm.set_duty(self.ch, duty_cycle as u64);
     }
 
-    pub fn update(&mut self, tmp: Temperature, dt: Duration, pwm: &mut P) -> Result<f64, ()> {
+    pub fn update(
+        &mut self,
+        tmp: Temperature,
+        dt: Duration,
+        pwm: &mut P,
+    ) -> Result<f64, PidError> {
         self.pid.set_output_bounds(0f64, self.max_strength);
         let strength = self.pid.update(tmp.as_celsius(), dt)?;
         self.set_strength(strength, pwm);
@@ -63,97 +71,29 @@ impl<P: PwmBase> Heater<P> {
 #[cfg(test)]
 mod tests {
     use super::*;
-
-    #[derive(Default)]
-    struct PwmChannel {
-        enabled: bool,
-        duty_cycle: u64,
-    }
-
-    #[derive(Clone, Copy)]
-    enum Channel {
-        Ch1,
-        Ch2,
-        Ch3,
-        Ch4,
-    }
-
-    struct PwmWrapper {
-        pub ch1: PwmChannel,
-        pub ch2: PwmChannel,
-        pub ch3: PwmChannel,
-        pub ch4: PwmChannel,
-        pub max_duty: u64,
-    }
-
-    impl PwmWrapper {
-        fn new() -> Self {
-            Self {
-                ch1: PwmChannel::default(),
-                ch2: PwmChannel::default(),
-                ch3: PwmChannel::default(),
-                ch4: PwmChannel::default(),
-                max_duty: 4096,
-            }
-        }
-    }
-
-    impl PwmBase for PwmWrapper {
-        type Channel = Channel;
-
-        fn enable(&mut self, channel: Self::Channel) {
-            match channel {
-                Channel::Ch1 => self.ch1.enabled = true,
-                Channel::Ch2 => self.ch2.enabled = true,
-                Channel::Ch3 => self.ch3.enabled = true,
-                Channel::Ch4 => self.ch4.enabled = true,
-            }
-        }
-
-        fn disable(&mut self, channel: Self::Channel) {
-            match channel {
-                Channel::Ch1 => self.ch1.enabled = false,
-                Channel::Ch2 => self.ch2.enabled = false,
-                Channel::Ch3 => self.ch3.enabled = false,
-                Channel::Ch4 => self.ch4.enabled = false,
-            }
-        }
-
-        fn get_max_duty(&self) -> u64 {
-            self.max_duty
-        }
-
-        fn set_duty(&mut self, channel: Self::Channel, duty_cycle: u64) {
-            match channel {
-                Channel::Ch1 => self.ch1.duty_cycle = duty_cycle,
-                Channel::Ch2 => self.ch2.duty_cycle = duty_cycle,
-                Channel::Ch3 => self.ch3.duty_cycle = duty_cycle,
-                Channel::Ch4 => self.ch4.duty_cycle = duty_cycle,
-            }
-        }
-    }
+    use sim::SimPwm;
 
     #[test]
     fn test_heater_enable() {
-        let mut pwm = PwmWrapper::new();
-        let mut heater: Heater<PwmWrapper> = Heater::new(
-            Channel::Ch2,
+        let mut pwm = SimPwm::new(4, 4096);
+        let mut heater: Heater<SimPwm> = Heater::new(
+            1,
             PidConfig {
                 k_p: 30.0,
                 k_i: 0.0,
                 k_d: 3.0,
             },
         );
-        assert!(!pwm.ch2.enabled);
+        assert!(!pwm.is_enabled(1));
         heater.enable(&mut pwm);
-        assert!(pwm.ch2.enabled);
+        assert!(pwm.is_enabled(1));
     }
 
     #[test]
     fn test_heater_disable() {
-        let mut pwm = PwmWrapper::new();
-        let mut heater: Heater<PwmWrapper> = Heater::new(
-            Channel::Ch2,
+        let mut pwm = SimPwm::new(4, 4096);
+        let mut heater: Heater<SimPwm> = Heater::new(
+            1,
             PidConfig {
                 k_p: 30.0,
                 k_i: 0.0,
@@ -161,16 +101,16 @@ mod tests {
             },
         );
         heater.enable(&mut pwm);
-        assert!(pwm.ch2.enabled);
+        assert!(pwm.is_enabled(1));
         heater.disable(&mut pwm);
-        assert!(!pwm.ch2.enabled);
+        assert!(!pwm.is_enabled(1));
     }
 
     #[test]
     fn test_heater_set_target_temperature() {
         let target = Temperature::from_celsius(150.0);
-        let mut heater: Heater<PwmWrapper> = Heater::new(
-            Channel::Ch2,
+        let mut heater: Heater<SimPwm> = Heater::new(
+            1,
             PidConfig {
                 k_p: 30.0,
                 k_i: 0.0,
@@ -188,8 +128,8 @@ mod tests {
     #[test]
     fn test_heater_reset_target_temperature() {
         let target = Temperature::from_celsius(150.0);
-        let mut heater: Heater<PwmWrapper> = Heater::new(
-            Channel::Ch2,
+        let mut heater: Heater<SimPwm> = Heater::new(
+            1,
             PidConfig {
                 k_p: 30.0,
                 k_i: 0.0,
@@ -205,11 +145,11 @@ mod tests {
 
     #[test]
     fn test_heater_update() {
-        let mut pwm = PwmWrapper::new();
+        let mut pwm = SimPwm::new(4, 4096);
         let target_temp = Temperature::from_celsius(150.0);
         let current_temp = Temperature::from_celsius(110.0);
-        let mut heater: Heater<PwmWrapper> = Heater::new(
-            Channel::Ch2,
+        let mut heater: Heater<SimPwm> = Heater::new(
+            1,
             PidConfig {
                 k_p: 30.0,
                 k_i: 0.0,
@@ -60,66 +60,15 @@ impl<'a, A: AdcBase> Thermistor<'a, A> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use sim::{block_on, AdcSource, SimAdc, SimAdcResolution};
 
-    #[derive(Copy, Clone)]
-    pub enum Resolution {
-        BITS12,
-    }
-
-    impl From<Resolution> for u64 {
-        fn from(val: Resolution) -> Self {
-            match val {
-                Resolution::BITS12 => 1 << 12,
-            }
-        }
-    }
-
-    struct AdcWrapper {
-        resolution: Resolution,
-        value: u16,
-    }
-
-    impl AdcWrapper {
-        pub fn new() -> Self {
-            Self {
-                resolution: Resolution::BITS12,
-                value: 2048,
-            }
-        }
-    }
-
-    struct Pin;
-
-    impl AdcBase for AdcWrapper {
-        type SampleTime = ();
-
-        type Resolution = Resolution;
-
-        type PinType = ();
-
-        fn set_sample_time(&mut self, _sample_time: Self::SampleTime) {}
-
-        fn sample_time(&self) -> Self::SampleTime {}
-
-        fn set_resolution(&mut self, resolution: Self::Resolution) {
-            self.resolution = resolution;
-        }
-
-        async fn read(&mut self, _pin: &mut (), readings: &mut [u16]) {
-            readings[0] = self.value
-        }
-
-        fn resolution(&self) -> Self::Resolution {
-            self.resolution
-        }
-    }
-
-    #[tokio::test]
-    async fn test_thermistor() {
+    #[test]
+    fn test_thermistor() {
         let mut readings = [0u16; 1];
-        let mut adc = AdcWrapper::new();
+        let mut adc = SimAdc::new(SimAdcResolution(12));
+        let pin = adc.add_source(AdcSource::Constant(2048));
         let mut thermistor: Thermistor<'_, _> = Thermistor::new(
-            (),
+            pin,
             &mut readings,
             ThermistorConfig {
                 r_series: Resistance::from_ohms(10_000.0),
@@ -128,7 +77,7 @@ mod tests {
                 samples: 1,
             },
         );
-        let t = thermistor.read_temperature(&mut adc).await;
+        let t = block_on(thermistor.read_temperature(&mut adc));
         assert_eq!(25.0, t.as_celsius());
     }
 }